@@ -0,0 +1,51 @@
+#!/bin/bash
+
+# copies the plugin libraries built with "cargo build --release" as CLAP plugins in artefact.
+# On Linux a CLAP plugin is the library renamed to .clap, on Mac OS X it's a bundle like the VSTs.
+
+ARTEFACT_DIRECTORY=artefact
+LIB_PATH_PREFIX=target/release/
+
+mkdir -p "${ARTEFACT_DIRECTORY}"
+
+for BUNDLE_LIB in "NoteGenerator note_generator" "NoteOffDelay note_off_delay" "FilterOutNonNote filter_out_non_note" \
+ "NoteFanOut note_fan_out" "MidiDelay midi_delay" "MaxNoteDuration max_note_duration"; do
+  BUNDLE_NAME=$(echo $BUNDLE_LIB | cut -f1 -d" ")
+  LIBNAME=$(echo $BUNDLE_LIB | cut -f2 -d" ")
+
+  if [ "$(uname)" == "Darwin" ]; then
+    mkdir -p "${ARTEFACT_DIRECTORY}/${BUNDLE_NAME}.clap/Contents/MacOS"
+    echo "BNDL????" > "${ARTEFACT_DIRECTORY}/${BUNDLE_NAME}.clap/Contents/PkgInfo"
+    echo "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+  <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+  <plist version=\"1.0\">
+  <dict>
+      <key>CFBundleDevelopmentRegion</key>
+      <string>English</string>
+
+      <key>CFBundleExecutable</key>
+      <string>${BUNDLE_NAME}</string>
+
+      <key>CFBundleIdentifier</key>
+      <string>com.djcrontab.${LIBNAME}</string>
+
+      <key>CFBundleInfoDictionaryVersion</key>
+      <string>6.0</string>
+
+      <key>CFBundleName</key>
+      <string>${BUNDLE_NAME}</string>
+
+      <key>CFBundlePackageType</key>
+      <string>BNDL</string>
+
+      <key>CFBundleSignature</key>
+      <string>????</string>
+  </dict>
+  </plist>" > "${ARTEFACT_DIRECTORY}/${BUNDLE_NAME}.clap/Contents/Info.plist"
+    cp "${LIB_PATH_PREFIX}lib${LIBNAME}.dylib" "${ARTEFACT_DIRECTORY}/${BUNDLE_NAME}.clap/Contents/MacOS/${BUNDLE_NAME}"
+  else
+    cp "${LIB_PATH_PREFIX}lib${LIBNAME}.so" "${ARTEFACT_DIRECTORY}/${BUNDLE_NAME}.clap"
+  fi
+
+  echo "Created ${BUNDLE_NAME}.clap"
+done
@@ -1,66 +1,48 @@
 #[macro_use]
 extern crate vst;
 
-use vst::api;
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::event::{Event, MidiEvent};
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin};
+use std::sync::Arc;
 
-plugin_main!(FilterOutNonNote);
+use vst::api::Supported;
+use vst::plugin::CanDo;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
+use util::vst_wrapper::VstWrapper;
 
-pub struct FilterOutNonNote {
-    events: Vec<MidiEvent>,
-    send_buffer: SendEventBuffer,
-    host: HostCallback,
-}
+plugin_main!(VstWrapper<FilterOutNonNote>);
+util::clap_main!(FilterOutNonNote);
+util::lv2_main!(FilterOutNonNote);
 
-impl Default for FilterOutNonNote {
-    fn default() -> Self {
-        FilterOutNonNote {
-            events: vec![],
-            send_buffer: Default::default(),
-            host: Default::default(),
-        }
-    }
-}
+// no parameters, the defaults of the trait are enough
+#[derive(Default)]
+pub struct FilterOutNonNoteParameters;
 
-impl FilterOutNonNote {
-    fn send_midi(&mut self) {
-        self.send_buffer.send_events(&self.events, &mut self.host);
-        self.events.clear();
-    }
+impl vst::plugin::PluginParameters for FilterOutNonNoteParameters {}
+
+#[derive(Default)]
+pub struct FilterOutNonNote {
+    parameters: Arc<FilterOutNonNoteParameters>,
 }
 
-impl Plugin for FilterOutNonNote {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Filter out non-note".to_string(),
-            vendor: "DJ Crontab".to_string(),
+impl MidiProcessor for FilterOutNonNote {
+    type Parameters = FilterOutNonNoteParameters;
+
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Filter out non-note",
+            id: "filter_out_non_note",
+            vendor: "DJ Crontab",
             unique_id: 342131710,
-            parameters: 0,
-            category: Category::Effect,
-            initial_delay: 0,
             version: 7,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
+            parameters: 0,
+            parameter_definitions: &[],
         }
     }
 
-    fn new(host: HostCallback) -> Self {
-        FilterOutNonNote {
-            events: vec![],
-            send_buffer: Default::default(),
-            host,
-        }
+    fn parameters(&self) -> &Arc<FilterOutNonNoteParameters> {
+        &self.parameters
     }
 
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
+    fn can_do(can_do: CanDo) -> Supported {
         use vst::api::Supported::*;
         use vst::plugin::CanDo::*;
 
@@ -70,17 +52,37 @@ impl Plugin for FilterOutNonNote {
         }
     }
 
-    fn process(&mut self, _: &mut AudioBuffer<f32>) {
-        self.send_midi();
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        _samples: usize,
+        _transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        outputs.extend(
+            inputs
+                .iter()
+                .filter(|message| message.data[0] >= 0x80 && message.data[0] <= 0x9F),
+        );
     }
+}
 
-    fn process_events(&mut self, events: &api::Events) {
-        for e in events.events() {
-            if let Event::Midi(e) = e {
-                if e.data[0] >= 0x80 && e.data[0] <= 0x9F {
-                    self.events.push(e);
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_notes_pass() {
+        let inputs = [
+            TimedMidiMessage::new([0x90, 60, 100], 0),
+            TimedMidiMessage::new([0xB0, 1, 64], 1),
+            TimedMidiMessage::new([0xE1, 0, 64], 2),
+            TimedMidiMessage::new([0x8F, 60, 0], 3),
+            TimedMidiMessage::new([0xC0, 5, 0], 4),
+            TimedMidiMessage::new([0xA0, 60, 10], 5),
+        ];
+        let mut outputs = vec![];
+        FilterOutNonNote::default().process(&inputs, 64, &TransportInfo::default(), &mut outputs);
+        assert_eq!(outputs, vec![inputs[0], inputs[3]]);
     }
 }
@@ -5,19 +5,20 @@ extern crate vst;
 
 use std::sync::Arc;
 
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin};
-use vst::event::{Event, MidiEvent};
-use vst::api::Events;
-
 use parameters::MaxNoteDurationPluginParameters;
-use parameters::Parameter;
+use parameters::{Parameter, PARAMETER_DEFINITIONS};
 use util::midi_message_type::MidiMessageType;
+use vst::api::Supported;
+use vst::plugin::CanDo;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
 use util::parameters::ParameterConversion;
+use util::vst_wrapper::VstWrapper;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 
-plugin_main!(MaxNoteDurationPlugin);
+plugin_main!(VstWrapper<MaxNoteDurationPlugin>);
+util::clap_main!(MaxNoteDurationPlugin);
+util::lv2_main!(MaxNoteDurationPlugin);
 
 #[derive(Eq, Clone, Copy)]
 struct PlayingNote {
@@ -45,24 +46,16 @@ pub struct MaxNoteDurationPlugin {
     current_time_in_samples: usize,
     parameters: Arc<MaxNoteDurationPluginParameters>,
     sample_rate: f32,
-    send_buffer: SendEventBuffer,
     current_playing_notes: HashSet<PlayingNote>,
-    host: HostCallback,
-    block_size: usize,
-    events: Vec<MidiEvent>,
 }
 
 impl Default for MaxNoteDurationPlugin {
     fn default() -> Self {
         MaxNoteDurationPlugin {
-            send_buffer: Default::default(),
             parameters: Arc::new(Default::default()),
             sample_rate: 44100.0,
             current_time_in_samples: 0,
             current_playing_notes: Default::default(),
-            host: Default::default(),
-            block_size: 0,
-            events: vec![],
         }
     }
 }
@@ -82,54 +75,76 @@ impl MaxNoteDurationPlugin {
         let new_time_in_samples = self.current_time_in_samples + samples;
         self.current_time_in_samples = new_time_in_samples;
     }
-}
 
+    fn track_notes(&mut self, inputs: &[TimedMidiMessage], outputs: &mut Vec<TimedMidiMessage>) {
+        let maximum_duration = self.seconds_to_samples(self.parameters.get_exponential_scale_parameter(Parameter::MaxDuration,
+                                                                                                       10., 20.));
 
-impl Plugin for MaxNoteDurationPlugin {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Max note duration".to_string(),
-            vendor: "DJ Crontab".to_string(),
-            unique_id: 231213173,
-            parameters: 1,
-            category: Category::Effect,
-            initial_delay: 0,
-            version: 1,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
+        for midi_event in inputs {
+            match MidiMessageType::from(&midi_event.data) {
+                MidiMessageType::NoteOffMessage(note) => {
+                    self.current_playing_notes.remove(&PlayingNote {
+                        channel: note.channel,
+                        pitch: note.pitch,
+                        deadline: 0,  // ignored for comparisons
+                    });
+                }
+                MidiMessageType::NoteOnMessage(note) => {
+                    self.current_playing_notes.insert(PlayingNote {
+                        channel: note.channel,
+                        pitch: note.pitch,
+                        deadline: self.current_time_in_samples + maximum_duration,
+                    });
+                }
+                MidiMessageType::Unsupported => {
+                    continue;
+                }
+                _ => {}
+            }
+            outputs.push(*midi_event);
         }
     }
 
-    fn set_block_size(&mut self, block_size: i64) {
-        self.block_size = block_size as usize
+    fn stop_expired_notes(&mut self, samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        if !self.current_playing_notes.is_empty() {
+            let mut next_playing_notes = HashSet::new(); // can't iterate and modify, copy to a new one
+
+            for playing_note in self.current_playing_notes.drain() {
+                if playing_note.deadline < self.current_time_in_samples + samples {
+                    outputs.push(TimedMidiMessage::new(
+                        [0x80 + playing_note.channel, playing_note.pitch, 0],
+                        playing_note.deadline.saturating_sub(self.current_time_in_samples),
+                    ));
+                } else {
+                    next_playing_notes.insert(playing_note);
+                }
+            }
+            self.current_playing_notes = next_playing_notes
+        }
     }
+}
 
-    fn new(host: HostCallback) -> Self {
-        let parameters = MaxNoteDurationPluginParameters::new(host);
 
-        MaxNoteDurationPlugin {
-            current_time_in_samples: 0,
-            parameters: Arc::new(parameters),
-            sample_rate: 44100.0,
-            send_buffer: Default::default(),
-            current_playing_notes: Default::default(),
-            host,
-            block_size: 0,
-            events: vec![],
+impl MidiProcessor for MaxNoteDurationPlugin {
+    type Parameters = MaxNoteDurationPluginParameters;
+
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Max note duration",
+            id: "max_note_duration",
+            vendor: "DJ Crontab",
+            unique_id: 231213173,
+            version: 1,
+            parameters: 1,
+            parameter_definitions: &PARAMETER_DEFINITIONS,
         }
     }
 
-    fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = rate
+    fn parameters(&self) -> &Arc<MaxNoteDurationPluginParameters> {
+        &self.parameters
     }
 
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
+    fn can_do(can_do: CanDo) -> Supported {
         use vst::api::Supported::*;
         use vst::plugin::CanDo::*;
 
@@ -144,70 +159,75 @@ impl Plugin for MaxNoteDurationPlugin {
         }
     }
 
-    fn process(&mut self, audio_buffer: &mut AudioBuffer<f32>) {
-        if !self.current_playing_notes.is_empty() {
-            let mut next_playing_notes = HashSet::new(); // can't iterate and modify, copy to a new one
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate
+    }
 
-            for playing_note in self.current_playing_notes.drain() {
-                if playing_note.deadline < self.current_time_in_samples + self.block_size {
-                    self.events.push(MidiEvent {
-                        data: [0x80 + playing_note.channel, playing_note.pitch, 0],
-                        delta_frames: (playing_note.deadline - self.current_time_in_samples) as i32,
-                        live: false,
-                        note_length: None,
-                        note_offset: None,
-                        detune: 0,
-                        note_off_velocity: 0,
-                    });
-                } else {
-                    next_playing_notes.insert(playing_note);
-                }
-            }
-            self.current_playing_notes = next_playing_notes
-        }
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        _transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        self.track_notes(inputs, outputs);
+        self.stop_expired_notes(samples, outputs);
+        self.increase_time_in_samples(samples);
+    }
+}
 
-        self.send_buffer.send_events(&self.events, &mut self.host);
-        self.events.clear();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vst::plugin::PluginParameters;
 
-        self.increase_time_in_samples(audio_buffer.samples());
-    }
+    const BLOCK_SIZE: usize = 64;
 
-    fn process_events(&mut self, events: &Events) {
-        let maximum_duration = self.seconds_to_samples(self.parameters.get_exponential_scale_parameter(Parameter::MaxDuration,
-                                                                                                       10., 20.));
+    fn process(plugin: &mut MaxNoteDurationPlugin, inputs: &[TimedMidiMessage]) -> Vec<TimedMidiMessage> {
+        let mut outputs = vec![];
+        plugin.process(inputs, BLOCK_SIZE, &TransportInfo::default(), &mut outputs);
+        outputs
+    }
 
-        for event in events.events() {
-            let midi_event = if let Event::Midi(midi_event) = event {
-                midi_event
-            } else {
-                continue;
-            };
+    // the maximum duration in samples, at 1000 Hz
+    fn plugin_with_max_duration(value: f32) -> (MaxNoteDurationPlugin, usize) {
+        let mut plugin = MaxNoteDurationPlugin::default();
+        plugin.set_sample_rate(1000.);
+        plugin.parameters.set_parameter(Parameter::MaxDuration.into(), value);
+        let duration = plugin
+            .seconds_to_samples(plugin.parameters.get_exponential_scale_parameter(Parameter::MaxDuration, 10., 20.));
+        (plugin, duration)
+    }
 
-            match MidiMessageType::from(&midi_event.data) {
-                MidiMessageType::NoteOffMessage(note) => {
-                    self.current_playing_notes.remove(&PlayingNote {
-                        channel: note.channel,
-                        pitch: note.pitch,
-                        deadline: 0,  // ignored for comparisons
-                    });
-                }
-                MidiMessageType::NoteOnMessage(note) => {
-                    self.current_playing_notes.insert(PlayingNote {
-                        channel: note.channel,
-                        pitch: note.pitch,
-                        deadline: self.current_time_in_samples + maximum_duration,
-                    });
-                }
-                MidiMessageType::Unsupported => {
-                    continue;
-                }
-                _ => {}
-            }
-            self.events.push(midi_event);
+    #[test]
+    fn long_notes_are_stopped() {
+        let (mut plugin, duration) = plugin_with_max_duration(0.1);
+        let note_on = TimedMidiMessage::new([0x91, 60, 100], 10);
+        assert_eq!(process(&mut plugin, &[note_on]), vec![note_on]);
+
+        // the deadline counts from the start of the block of the note on
+        let mut block_start = BLOCK_SIZE;
+        let mut outputs = process(&mut plugin, &[]);
+        while outputs.is_empty() && block_start < 10 * duration {
+            block_start += BLOCK_SIZE;
+            outputs = process(&mut plugin, &[]);
         }
+        assert_eq!(outputs, vec![TimedMidiMessage::new([0x81, 60, 0], duration - block_start)]);
+
+        // the note off that follows is passed as is
+        let note_off = TimedMidiMessage::new([0x81, 60, 0], 0);
+        assert_eq!(process(&mut plugin, &[note_off]), vec![note_off]);
     }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn vst::plugin::PluginParameters> {
-        Arc::clone(&self.parameters) as Arc<dyn vst::plugin::PluginParameters>
+    #[test]
+    fn short_notes_are_kept() {
+        let (mut plugin, duration) = plugin_with_max_duration(0.1);
+        let note_on = TimedMidiMessage::new([0x90, 60, 100], 0);
+        let note_off = TimedMidiMessage::new([0x80, 60, 0], 20);
+
+        assert_eq!(process(&mut plugin, &[note_on, note_off]), vec![note_on, note_off]);
+        for _ in 0..=duration / BLOCK_SIZE {
+            assert_eq!(process(&mut plugin, &[]), vec![]);
+        }
     }
 }
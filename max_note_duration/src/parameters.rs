@@ -1,15 +1,16 @@
-use std::sync::Mutex;
-
-use vst::plugin::HostCallback ;
 use vst::util::ParameterTransfer;
 
-use util::{HostCallbackLock, duration_display};
+use util::duration_display;
+use util::parameter_definition::{ParameterDefinition, ParameterScale};
 use util::parameters::ParameterConversion;
 
-const PARAMETER_COUNT: usize = 1;
+pub const PARAMETER_COUNT: usize = 1;
+
+pub const PARAMETER_DEFINITIONS: [ParameterDefinition; PARAMETER_COUNT] = [
+    ParameterDefinition::new("Maximum duration", ParameterScale::Exponential { max: 10., factor: 20. }),
+];
 
 pub struct MaxNoteDurationPluginParameters {
-    pub host_mutex: Mutex<HostCallbackLock>,
     pub transfer: ParameterTransfer,
 }
 
@@ -46,20 +47,9 @@ impl ParameterConversion<Parameter> for MaxNoteDurationPluginParameters {
 }
 
 
-impl MaxNoteDurationPluginParameters {
-    pub fn new(host: HostCallback) -> Self {
-        MaxNoteDurationPluginParameters {
-            host_mutex: Mutex::new(HostCallbackLock { host }),
-            ..Default::default()
-        }
-    }
-}
-
-
 impl Default for MaxNoteDurationPluginParameters {
     fn default() -> Self {
         MaxNoteDurationPluginParameters {
-            host_mutex: Default::default(),
             transfer: ParameterTransfer::new(PARAMETER_COUNT),
         }
     }
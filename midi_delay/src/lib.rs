@@ -4,21 +4,22 @@ mod parameters;
 extern crate vst;
 
 use std::sync::Arc;
-use std::cell::RefCell;
-use vst::api::Events;
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::event::Event;
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters};
 
-use parameters::{MidiDelayParameters, Parameter};
+use vst::plugin::PluginParameters;
+
+use parameters::{MidiDelayParameters, Parameter, PARAMETER_DEFINITIONS, STORED_PARAMETER_COUNT};
 use util::absolute_time_midi_message_vector::AbsoluteTimeMidiMessageVector;
 use util::delayed_message_consumer::{process_scheduled_events, MessageReason};
 use util::midi_message_type::MidiMessageType;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
 use util::parameters::ParameterConversion;
+use util::vst_wrapper::VstWrapper;
 
 
 
-plugin_main!(MidiDelay);
+plugin_main!(VstWrapper<MidiDelay>);
+util::clap_main!(MidiDelay);
+util::lv2_main!(MidiDelay);
 
 
 pub struct MidiDelay {
@@ -26,7 +27,6 @@ pub struct MidiDelay {
     message_queue: AbsoluteTimeMidiMessageVector,
     parameters: Arc<MidiDelayParameters>,
     sample_rate: f32,
-    send_buffer: RefCell<SendEventBuffer>,
 }
 
 
@@ -37,7 +37,6 @@ impl Default for MidiDelay {
             message_queue: Default::default(),
             parameters: Arc::new(Default::default()),
             sample_rate: 44100.0,
-            send_buffer: Default::default(),
         }
     }
 }
@@ -58,95 +57,29 @@ impl MidiDelay {
         (seconds * self.sample_rate) as usize
     }
 
-    fn send_events(&mut self, samples: usize) {
-        if let Ok(mut host_callback_lock) = self.parameters.host.lock() {
-            let (next_message_queue, events)
-                = process_scheduled_events(
-                samples,
-                self.current_time_in_samples,
-                &self.message_queue,
-                0,
-                false,
-                self.parameters.get_parameter(Parameter::Delay.into()) > 0.0
-                );
-
-            self.message_queue = next_message_queue;
-            self.send_buffer.borrow_mut().send_events(events, &mut host_callback_lock.host);
-        }
-    }
-}
-
-
-impl Plugin for MidiDelay {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Midi Delay".to_string(),
-            vendor: "DJ Crontab".to_string(),
-            unique_id: 133498,
-            parameters: 1,
-            category: Category::Effect,
-            initial_delay: 0,
-            version: 2,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
-        }
-    }
-
-    fn new(host: HostCallback) -> Self {
-        let parameters = MidiDelayParameters::new(host);
-
-        MidiDelay {
-            current_time_in_samples: 0,
-            message_queue: Default::default(),
-            parameters: Arc::new(parameters),
-            sample_rate: 44100.0,
-            send_buffer: Default::default(),
-        }
-    }
-
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
-        use vst::api::Supported::*;
-        use vst::plugin::CanDo::*;
-
-        match can_do {
-            SendEvents | SendMidiEvent | ReceiveEvents | ReceiveMidiEvent | Offline | Bypass => Yes,
-            MidiProgramNames | ReceiveSysExEvent | MidiSingleNoteTuningChange => No,
-            Other(_) => Maybe,
-            _ => Maybe
-        }
+    fn send_events(&mut self, samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let (next_message_queue, events)
+            = process_scheduled_events(
+            samples,
+            self.current_time_in_samples,
+            &self.message_queue,
+            0,
+            false,
+            self.parameters.get_parameter(Parameter::Delay.into()) > 0.0,
+            &self.parameters.statistics
+            );
+
+        self.message_queue = next_message_queue;
+        self.parameters.statistics.report(self.current_time_in_samples, self.seconds_to_samples(1.0));
+        outputs.extend(events);
     }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn vst::plugin::PluginParameters> {
-        Arc::clone(&self.parameters) as Arc<dyn vst::plugin::PluginParameters>
-    }
-
-    fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = rate
-    }
-
-    fn process(&mut self, audio_buffer: &mut AudioBuffer<f32>) {
-        self.send_events(audio_buffer.samples());
-        self.increase_time_in_samples(audio_buffer.samples());
-    }
-
-    fn process_events(&mut self, events: &Events) {
+    fn queue_events(&mut self, inputs: &[TimedMidiMessage]) {
         let midi_delay = self.seconds_to_samples(
             self.parameters.get_exponential_scale_parameter(Parameter::Delay, 1., 80.)
         );
 
-        for event in events.events() {
-            let midi_event = if let Event::Midi(midi_event) = event {
-                midi_event
-            } else {
-                continue
-            };
-
+        for midi_event in inputs {
             if let MidiMessageType::NoteOffMessage(_) = MidiMessageType::from(&midi_event.data) {
                 // TODO because of changes in process_scheduled_events specific to delay note off plugin,
                 // note offs need a special handling ; not all usages of process_scheduled_events should have to care
@@ -154,21 +87,21 @@ impl Plugin for MidiDelay {
 
                 self.message_queue.insert_message(
                     midi_event.data,
-                    midi_event.delta_frames as usize + self.current_time_in_samples,
+                    midi_event.delta_frames + self.current_time_in_samples,
                     MessageReason::Live,
                 );
 
                 if midi_delay > 0 {
                     self.message_queue.insert_message(
                         midi_event.data,
-                        midi_delay + midi_event.delta_frames as usize + self.current_time_in_samples,
+                        midi_delay + midi_event.delta_frames + self.current_time_in_samples,
                         MessageReason::Delayed,
                     );
                 }
             } else {
                 self.message_queue.insert_message(
                     midi_event.data,
-                    midi_delay + midi_event.delta_frames as usize + self.current_time_in_samples, MessageReason::Live
+                    midi_delay + midi_event.delta_frames + self.current_time_in_samples, MessageReason::Live
                 );
             }
 
@@ -176,3 +109,104 @@ impl Plugin for MidiDelay {
         }
     }
 }
+
+
+impl MidiProcessor for MidiDelay {
+    type Parameters = MidiDelayParameters;
+
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Midi Delay",
+            id: "midi_delay",
+            vendor: "DJ Crontab",
+            unique_id: 133498,
+            version: 2,
+            parameters: STORED_PARAMETER_COUNT as i32,
+            parameter_definitions: &PARAMETER_DEFINITIONS,
+        }
+    }
+
+    fn parameters(&self) -> &Arc<MidiDelayParameters> {
+        &self.parameters
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate
+    }
+
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        _transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        self.queue_events(inputs);
+        self.send_events(samples, outputs);
+        self.increase_time_in_samples(samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 64;
+
+    // inputs and outputs delta frames are from the start of the first block
+    fn play(plugin: &mut MidiDelay, inputs: &[TimedMidiMessage], blocks: usize) -> Vec<TimedMidiMessage> {
+        let mut played = vec![];
+        for block in 0..blocks {
+            let block_start = block * BLOCK_SIZE;
+            let block_inputs: Vec<TimedMidiMessage> = inputs
+                .iter()
+                .filter(|input| (block_start..block_start + BLOCK_SIZE).contains(&input.delta_frames))
+                .map(|input| TimedMidiMessage::new(input.data, input.delta_frames - block_start))
+                .collect();
+            let mut outputs = vec![];
+            plugin.process(&block_inputs, BLOCK_SIZE, &TransportInfo::default(), &mut outputs);
+            played.extend(
+                outputs
+                    .iter()
+                    .map(|output| TimedMidiMessage::new(output.data, output.delta_frames + block_start)),
+            );
+        }
+        played
+    }
+
+    #[test]
+    fn messages_are_delayed() {
+        let mut plugin = MidiDelay::default();
+        plugin.set_sample_rate(1000.);
+        plugin.parameters.set_parameter(Parameter::Delay.into(), 0.5);
+        let delay =
+            plugin.seconds_to_samples(plugin.parameters.get_exponential_scale_parameter(Parameter::Delay, 1., 80.));
+        assert!(delay > BLOCK_SIZE);
+
+        let inputs = [
+            TimedMidiMessage::new([0x90, 60, 100], 0),
+            TimedMidiMessage::new([0xB0, 1, 64], 5),
+            TimedMidiMessage::new([0x80, 60, 0], 200),
+        ];
+        let delayed: Vec<TimedMidiMessage> = inputs
+            .iter()
+            .map(|input| TimedMidiMessage::new(input.data, input.delta_frames + delay))
+            .collect();
+        assert_eq!(play(&mut plugin, &inputs, (200 + delay) / BLOCK_SIZE + 2), delayed);
+    }
+
+    #[test]
+    fn without_delay_messages_pass() {
+        let mut plugin = MidiDelay::default();
+        let inputs = [TimedMidiMessage::new([0x90, 60, 100], 0), TimedMidiMessage::new([0x80, 60, 0], 70)];
+        assert_eq!(play(&mut plugin, &inputs, 2), inputs.to_vec());
+    }
+
+    #[test]
+    fn statistics_are_not_stored() {
+        let plugin = MidiDelay::default();
+        plugin.parameters.statistics.count_late_event();
+        assert_eq!(plugin.parameters.get_preset_data().len(), STORED_PARAMETER_COUNT);
+        assert_eq!(MidiDelay::info().parameters, STORED_PARAMETER_COUNT as i32);
+    }
+}
@@ -1,17 +1,29 @@
-use std::sync::Mutex;
-use util::{HostCallbackLock, duration_display};
-use vst::plugin::{HostCallback, PluginParameters};
+use util::duration_display;
+use vst::plugin::PluginParameters;
 use vst::util::ParameterTransfer;
+use util::parameter_definition::{ParameterDefinition, ParameterScale};
 use util::parameters::ParameterConversion;
+use util::scheduler_statistics::SchedulerStatistics;
+
+pub const PARAMETER_COUNT: usize = 2;
+// the statistics come last, they are not stored in presets nor shown to VST2 hosts, which have no
+// read-only parameters
+pub const STORED_PARAMETER_COUNT: usize = PARAMETER_COUNT - 1;
+
+pub const PARAMETER_DEFINITIONS: [ParameterDefinition; PARAMETER_COUNT] = [
+    ParameterDefinition::new("Delay", ParameterScale::Exponential { max: 1., factor: 80. }),
+    ParameterDefinition::new("Statistics", ParameterScale::Normalized).read_only(),
+];
 
 pub struct MidiDelayParameters {
-    pub host: Mutex<HostCallbackLock>,
     pub transfer: ParameterTransfer,
+    pub statistics: SchedulerStatistics,
 }
 
 #[repr(i32)]
 pub enum Parameter {
     Delay = 0,
+    Statistics,
 }
 
 
@@ -19,6 +31,7 @@ impl From<i32> for Parameter {
     fn from(i: i32) -> Self {
         match i {
             0 => Parameter::Delay,
+            1 => Parameter::Statistics,
             _ => panic!(format!("No such Parameter {}", i)),
         }
     }
@@ -37,20 +50,10 @@ impl ParameterConversion<Parameter> for MidiDelayParameters {
     }
 
     fn get_parameter_count() -> usize {
-        1
-    }
-}
-
-impl MidiDelayParameters {
-    pub fn new(host: HostCallback) -> Self {
-        MidiDelayParameters {
-            host: Mutex::new(HostCallbackLock { host }),
-            transfer: ParameterTransfer::new(1),
-        }
+        STORED_PARAMETER_COUNT
     }
 }
 
-
 impl PluginParameters for MidiDelayParameters {
     fn get_parameter_text(&self, index: i32) -> String {
         match index.into() {
@@ -62,12 +65,14 @@ impl PluginParameters for MidiDelayParameters {
                     "Off".to_string()
                 }
             }
+            Parameter::Statistics => self.statistics.snapshot().summary(),
         }
     }
 
     fn get_parameter_name(&self, index: i32) -> String {
         match Parameter::from(index as i32) {
-            Parameter::Delay => "Delay"
+            Parameter::Delay => "Delay",
+            Parameter::Statistics => "Statistics",
         }.to_string()
     }
 
@@ -83,6 +88,9 @@ impl PluginParameters for MidiDelayParameters {
                     self.transfer.set_parameter(index as usize, value)
                 }
             }
+            Parameter::Statistics => {
+                // read-only, only its text is meaningful
+            }
         }
     }
 
@@ -106,8 +114,8 @@ impl PluginParameters for MidiDelayParameters {
 impl Default for MidiDelayParameters {
     fn default() -> Self {
         let parameters = MidiDelayParameters {
-            host: Default::default(),
-            transfer: ParameterTransfer::new(1),
+            transfer: ParameterTransfer::new(PARAMETER_COUNT),
+            statistics: Default::default(),
         };
         parameters.set_byte_parameter(Parameter::Delay, 0);
         parameters
@@ -0,0 +1,59 @@
+// channels of the channel distribution: a note gets the free channel released the longest time
+// ago, so the release of the previous note on it can ring. When every channel holds notes, the one
+// whose last note started the longest time ago is stolen, the caller stopping its notes.
+
+use crate::parameters::CHANNEL_COUNT;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Allocation {
+    Free(u8),
+    // the notes playing on the channel have to be stopped
+    Stolen(u8),
+}
+
+#[derive(Default)]
+pub struct ChannelAllocator {
+    // notes playing on each channel
+    playing: [usize; CHANNEL_COUNT],
+    // when each channel was last released or given a note, in allocations and releases
+    last_used: [u64; CHANNEL_COUNT],
+    clock: u64,
+}
+
+impl ChannelAllocator {
+    // a channel for a new note among the channels of the mask, None when the mask is empty
+    pub fn allocate(&mut self, mask: u16) -> Option<Allocation> {
+        let channels = || (0..CHANNEL_COUNT).filter(|&channel| mask & (1 << channel) != 0);
+        let least_recently_used = |channels: &mut dyn Iterator<Item = usize>| {
+            channels.min_by_key(|&channel| self.last_used[channel])
+        };
+
+        let allocation = match least_recently_used(&mut channels().filter(|&channel| self.playing[channel] == 0)) {
+            Some(channel) => Allocation::Free(channel as u8),
+            None => Allocation::Stolen(least_recently_used(&mut channels())? as u8),
+        };
+        let channel = match allocation {
+            Allocation::Free(channel) | Allocation::Stolen(channel) => channel as usize,
+        };
+        // free, or the stolen notes are stopped
+        self.playing[channel] = 1;
+        self.touch(channel);
+        Some(allocation)
+    }
+
+    // a note of the channel stopped, not called for the notes of a stolen channel
+    pub fn release(&mut self, channel: u8) {
+        let channel = channel as usize % CHANNEL_COUNT;
+        if self.playing[channel] > 0 {
+            self.playing[channel] -= 1;
+            if self.playing[channel] == 0 {
+                self.touch(channel);
+            }
+        }
+    }
+
+    fn touch(&mut self, channel: usize) {
+        self.clock += 1;
+        self.last_used[channel] = self.clock;
+    }
+}
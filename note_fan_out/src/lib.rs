@@ -1,199 +1,671 @@
+mod channel_allocator;
 mod parameters;
 
 #[macro_use]
 extern crate vst;
 
-use std::collections::HashSet;
-use std::hash::{Hasher, Hash};
 use std::sync::Arc;
-use vst::api;
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::event::{Event, MidiEvent};
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin};
-
-use parameters::ChannelDistribution;
-use parameters::{NoteFanoutParameters, Parameter};
-use util::messages::{ChannelMessage, GenericChannelMessage, NoteMessage, NoteOff, NoteOn};
+
+use channel_allocator::{Allocation, ChannelAllocator};
+use parameters::Distribution;
+use parameters::{NoteFanoutParameters, Parameter, PARAMETER_DEFINITIONS};
+use util::constants::{AFTERTOUCH, CC};
+use util::messages::{ChannelMessage, NoteMessage, NoteOff, NoteOn};
 use util::midi_message_type::MidiMessageType;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
 use util::parameters::ParameterConversion;
+use util::random::Random;
 use util::raw_message::RawMessage;
+use util::step_counter::{StepCounter, LOCAL_GROUP};
+use util::vst_wrapper::VstWrapper;
 
-plugin_main!(NoteFanOut);
+plugin_main!(VstWrapper<NoteFanOut>);
+util::clap_main!(NoteFanOut);
+util::lv2_main!(NoteFanOut);
 
 
 #[derive(Default)]
 pub struct NoteFanOut {
-    events: Vec<MidiEvent>,
-    current_playing_notes: HashSet<PlayingNote>,
-    send_buffer: SendEventBuffer,
+    // in the order they started, so the same pitch held twice is stopped in the same order
+    current_playing_notes: Vec<PlayingNote>,
     parameters: Arc<NoteFanoutParameters>,
-    current_step: u8,
-    notes_counter: usize
+    // the position of the note ons, shared with the copies of the same group
+    step_counter: StepCounter,
+    channel_allocator: ChannelAllocator,
+    // per channel, the last expression messages received while no note of the channel was playing
+    pending_expression: [Vec<[u8; 3]>; 16],
+    // copies with the same seed draw the same steps, so each note goes to one of them
+    random: Random,
+    seed: Option<u8>,
+    // the transport, the random steps start over when it starts
+    playing: bool,
 }
 
+// release velocity of the notes stopped when their channel is stolen
+const STOLEN_NOTE_OFF_VELOCITY: u8 = 64;
 
-impl NoteFanOut {
-    fn send_midi(&mut self) {
-        if let Ok(mut host_callback_lock) = self.parameters.host.lock() {
-            self.send_buffer
-                .send_events(&self.events, &mut host_callback_lock.host);
-        }
-        self.events.clear();
-    }
-}
+// while the channel distribution is on, expression on the other channels is for the notes of the
+// channel, as in MPE: it goes to the mapped channels of the notes played from it, and when there
+// are none, is kept for the next note on of the channel, MPE controllers sending the initial
+// expression just before the note on. The copies that don't take that note drop it. Messages of
+// the master channel are for every note: they are sent unchanged, including when notes played on the
+// master channel are moved to other channels, the synth applying them to all its channels as an MPE
+// synth does
+const MASTER_CHANNEL: u8 = 0;
+
+// messages kept per channel until its next note on, the oldest being dropped
+const MAX_PENDING_EXPRESSION: usize = 16;
+
+// all sound off, reset all controllers, local control, all notes off and the mode changes, sent
+// on the channel itself as well as the mapped channels, and never kept
+const FIRST_CHANNEL_MODE_CC: u8 = 120;
 
-#[derive(Eq,Clone)]
 struct PlayingNote {
     channel: u8,
     pitch: u8,
-    mapped_channel: u8
+    mapped_channel: u8,
+    // given by the channel allocator, that has to be told when it stops
+    allocated: bool,
+    // stopped when its channel was stolen, its note off is dropped
+    stolen: bool,
+    // selected by this copy. The notes of the other copies are followed too, so the channels are
+    // allocated the same way in every copy and the copies don't play on the same channel
+    played: bool,
 }
 
-impl PartialEq for PlayingNote {
-    fn eq(&self, other: &Self) -> bool {
-        self.channel == other.channel && self.pitch == other.pitch
+impl NoteFanOut {
+    // the step of a note on, from 0 to steps - 1, or 0 and 1 for Euclidean hits and rests
+    fn select_step(&mut self, inputs: &[TimedMidiMessage], index: usize, position: u64, steps: u8) -> u8 {
+        let (pitch, velocity) = match MidiMessageType::from(&inputs[index].data) {
+            MidiMessageType::NoteOnMessage(note_on) => (note_on.pitch, note_on.velocity),
+            _ => return 0,
+        };
+        // in a group, the draws depend on the position only, so the copies agree whatever notes
+        // they missed
+        if self.step_counter.group() != LOCAL_GROUP {
+            let seed = self.parameters.get_byte_parameter(Parameter::Seed) as u64;
+            self.random.reseed((seed << 32) | position);
+        }
+
+        match self.parameters.get_distribution() {
+            Distribution::RoundRobin => (position % steps as u64) as u8,
+            Distribution::Random => self.random.below(steps as usize) as u8,
+            Distribution::Weighted => {
+                let parameters = &self.parameters;
+                let weight = |step: u8| parameters.get_byte_parameter(Parameter::Weight(step as usize)) as usize;
+                let total: usize = (0..steps).map(weight).sum();
+                if total == 0 {
+                    return self.random.below(steps as usize) as u8;
+                }
+                let mut draw = self.random.below(total);
+                for step in 0..steps {
+                    if draw < weight(step) {
+                        return step;
+                    }
+                    draw -= weight(step);
+                }
+                steps - 1
+            }
+            Distribution::Euclidean => {
+                let position = (position % steps as u64) as usize;
+                let hits = self.parameters.get_euclidean_hits().min(steps) as usize;
+                if (position * hits) % (steps as usize) < hits {
+                    0
+                } else {
+                    1
+                }
+            }
+            Distribution::PitchZones => self.parameters.get_split_step(pitch, steps),
+            Distribution::VelocityLayers => self.parameters.get_split_step(velocity, steps),
+            Distribution::ChordPosition => {
+                let delta_frames = inputs[index].delta_frames;
+                // the note ons at the same time, lower or before this one
+                let position = inputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(other_index, other)| {
+                        other.delta_frames == delta_frames
+                            && match MidiMessageType::from(&other.data) {
+                                MidiMessageType::NoteOnMessage(other) => {
+                                    other.pitch < pitch || (other.pitch == pitch && *other_index < index)
+                                }
+                                _ => false,
+                            }
+                    })
+                    .count();
+                (position % steps as usize) as u8
+            }
+        }
     }
-}
 
-impl Hash for PlayingNote {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.channel.hash(state);
-        self.pitch.hash(state);
+    // stops the notes of a stolen channel, their note offs will be dropped
+    fn stop_channel(&mut self, channel: u8, delta_frames: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        for note in self
+            .current_playing_notes
+            .iter_mut()
+            .filter(|note| note.allocated && !note.stolen && note.mapped_channel == channel)
+        {
+            if note.played {
+                let raw_message: RawMessage = NoteOff {
+                    channel,
+                    pitch: note.pitch,
+                    velocity: STOLEN_NOTE_OFF_VELOCITY,
+                }
+                .into();
+                outputs.push(TimedMidiMessage::new(raw_message.into(), delta_frames));
+            }
+            note.stolen = true;
+        }
     }
-}
 
-impl Plugin for NoteFanOut {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Note fan-out".to_string(),
-            vendor: "DJ Crontab".to_string(),
-            unique_id: 123458,
-            parameters: 3,
-            category: Category::Effect,
-            initial_delay: 0,
-            version: 5,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
+    // pitch bend, channel pressure and control changes
+    fn forward_expression(&mut self, message: &TimedMidiMessage, outputs: &mut Vec<TimedMidiMessage>) {
+        let channel = message.data[0] & 0x0F;
+        if channel == MASTER_CHANNEL {
+            outputs.push(*message);
+            return;
+        }
+
+        let mut mapped_channels = self
+            .current_playing_notes
+            .iter()
+            .filter(|note| note.channel == channel && note.played && !note.stolen)
+            .fold(0u16, |mask, note| mask | 1 << note.mapped_channel);
+        if message.data[0] & 0xF0 == CC && message.data[1] >= FIRST_CHANNEL_MODE_CC {
+            mapped_channels |= 1 << channel;
+        }
+        if mapped_channels != 0 {
+            for mapped_channel in 0..16 {
+                if mapped_channels & (1 << mapped_channel) != 0 {
+                    let data = with_channel(message.data, mapped_channel);
+                    outputs.push(TimedMidiMessage::new(data, message.delta_frames));
+                }
+            }
+            return;
+        }
+        // for the notes of the other copies
+        if self.current_playing_notes.iter().any(|note| note.channel == channel && !note.stolen) {
+            return;
+        }
+
+        // only the last value of each message, and of each controller, matters
+        let data = message.data;
+        let pending = &mut self.pending_expression[channel as usize];
+        let replaced = pending
+            .iter_mut()
+            .find(|other| other[0] == data[0] && (data[0] & 0xF0 != CC || other[1] == data[1]));
+        match replaced {
+            Some(other) => *other = data,
+            None => {
+                if pending.len() == MAX_PENDING_EXPRESSION {
+                    pending.remove(0);
+                }
+                pending.push(data);
+            }
         }
     }
 
-    fn new(host: HostCallback) -> Self {
-        NoteFanOut {
-            parameters: Arc::new(NoteFanoutParameters::new(host)),
-            ..Default::default()
+    // for the note of the pitch only, dropped when it is not played by this copy
+    fn forward_poly_pressure(&mut self, message: &TimedMidiMessage, outputs: &mut Vec<TimedMidiMessage>) {
+        let channel = message.data[0] & 0x0F;
+        if channel == MASTER_CHANNEL {
+            outputs.push(*message);
+            return;
+        }
+
+        let pitch = message.data[1];
+        let note = self
+            .current_playing_notes
+            .iter()
+            .rev()
+            .find(|note| note.channel == channel && note.pitch == pitch && note.played && !note.stolen);
+        if let Some(note) = note {
+            let data = with_channel(message.data, note.mapped_channel);
+            outputs.push(TimedMidiMessage::new(data, message.delta_frames));
         }
     }
+}
+
+fn with_channel(data: [u8; 3], channel: u8) -> [u8; 3] {
+    [(data[0] & 0xF0) | (channel & 0x0F), data[1], data[2]]
+}
 
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
-        use vst::api::Supported::*;
-        use vst::plugin::CanDo::*;
+impl MidiProcessor for NoteFanOut {
+    type Parameters = NoteFanoutParameters;
 
-        match can_do {
-            SendEvents | SendMidiEvent | ReceiveEvents | ReceiveMidiEvent | Offline | Bypass => Yes,
-            MidiProgramNames | ReceiveSysExEvent | MidiSingleNoteTuningChange => No,
-            Other(_) => Maybe,
-            _ => Maybe
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Note fan-out",
+            id: "note_fan_out",
+            vendor: "DJ Crontab",
+            unique_id: 123458,
+            version: 8,
+            parameters: 57,
+            parameter_definitions: &PARAMETER_DEFINITIONS,
         }
     }
 
-    fn process(&mut self, _: &mut AudioBuffer<f32>) {
-        self.send_midi();
+    fn parameters(&self) -> &Arc<NoteFanoutParameters> {
+        &self.parameters
     }
 
-    fn process_events(&mut self, events: &api::Events) {
-        let steps = self.parameters.get_byte_parameter(Parameter::Steps) / 8;
-        let selection = self.parameters.get_byte_parameter(Parameter::Selection) / 8;
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        _samples: usize,
+        transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        let steps = self.parameters.get_steps();
+        let selection = self.parameters.get_selection();
+
+        let started = transport.playing && !self.playing;
+        let seed = self.parameters.get_byte_parameter(Parameter::Seed);
+        if self.seed != Some(seed) || started {
+            self.random.reseed(seed as u64);
+            self.seed = Some(seed);
+        }
+        self.playing = transport.playing;
 
-        for e in events.events() {
-            if let Event::Midi(e) = e {
-                let midi_message = MidiMessageType::from(&e.data);
+        self.step_counter.set_group(self.parameters.get_byte_parameter(Parameter::Group));
+        // hosts keep the sample position while stopped, it only tells cycles apart while playing
+        let sample_position = transport.sample_position.filter(|_| transport.playing);
+        self.step_counter.start_block(started, sample_position);
 
-                match midi_message {
-                    MidiMessageType::NoteOnMessage(midi_message) => {
-                        let target_channel = match self.parameters.get_channel_distribution(Parameter::ChannelDistribute) {
-                            ChannelDistribution::Channels(distribution) => {
-                                let target_channel = (self.notes_counter % (distribution as usize)) as u8 + 1;
-                                self.notes_counter += 1;
-                                target_channel
-                            }
-                            ChannelDistribution::Off => {
-                                GenericChannelMessage::from(&e.data).get_channel()
+        // without it, the notes keep their channel and everything else passes
+        let distributing = self.parameters.get_channel_mask() != 0;
+        if !distributing {
+            self.pending_expression.iter_mut().for_each(Vec::clear);
+        }
+
+        for (index, e) in inputs.iter().enumerate() {
+            let midi_message = MidiMessageType::from(&e.data);
+
+            match midi_message {
+                MidiMessageType::NoteOnMessage(midi_message) => {
+                    let channel = midi_message.get_channel();
+                    let position = self.step_counter.count(e.delta_frames, channel, midi_message.pitch);
+                    let selected = steps == 0 || self.select_step(inputs, index, position, steps) == selection;
+
+                    let (mapped_channel, allocated) =
+                        match self.channel_allocator.allocate(self.parameters.get_channel_mask()) {
+                            Some(Allocation::Free(mapped_channel)) => (mapped_channel, true),
+                            Some(Allocation::Stolen(mapped_channel)) => {
+                                self.stop_channel(mapped_channel, e.delta_frames, outputs);
+                                (mapped_channel, true)
                             }
+                            None => (channel, false),
                         };
 
-                        if steps == 0 || selection == self.current_step {
-                            let raw_message : RawMessage = NoteOn {
-                                channel: target_channel,
-                                pitch: midi_message.pitch,
-                                velocity: midi_message.velocity
-                            }.into();
-
-                            self.events.push(MidiEvent {
-                                data: raw_message.into(),
-                                delta_frames: e.delta_frames,
-                                live: e.live,
-                                note_length: e.note_length,
-                                note_offset: e.note_offset,
-                                detune: e.detune,
-                                note_off_velocity: e.note_off_velocity
-                            });
-
-                            self.current_playing_notes.insert(PlayingNote {
-                                channel: midi_message.get_channel(),
-                                pitch: midi_message.get_pitch(),
-                                mapped_channel: target_channel
-                            });
-                        }
+                    if !selected {
+                        self.pending_expression[channel as usize].clear();
+                        self.current_playing_notes.push(PlayingNote {
+                            channel,
+                            pitch: midi_message.get_pitch(),
+                            mapped_channel,
+                            allocated,
+                            stolen: false,
+                            played: false,
+                        });
+                        continue;
+                    }
 
-                        if steps > 0 {
-                            self.current_step = (self.current_step + 1) % steps ;
-                        }
+                    for data in self.pending_expression[channel as usize].drain(..) {
+                        let data = with_channel(data, mapped_channel);
+                        outputs.push(TimedMidiMessage::new(data, e.delta_frames));
                     }
-                    MidiMessageType::NoteOffMessage(midi_message) => {
-                        let lookup = PlayingNote {
-                            channel: midi_message.get_channel(),
-                            pitch: midi_message.get_pitch(),
-                            mapped_channel: midi_message.get_channel()
-                        };
 
-                        match self.current_playing_notes.take(&lookup) {
-                            Some(note) => {
-                                let raw_message : RawMessage = NoteOff {
-                                    channel: note.mapped_channel,
-                                    pitch: midi_message.pitch,
-                                    velocity: midi_message.velocity
-                                }.into();
-
-                                self.events.push(MidiEvent {
-                                    data: raw_message.into(),
-                                    delta_frames: e.delta_frames,
-                                    live: e.live,
-                                    note_length: e.note_length,
-                                    note_offset: e.note_offset,
-                                    detune: e.detune,
-                                    note_off_velocity: e.note_off_velocity
-                                });
+                    let raw_message : RawMessage = NoteOn {
+                        channel: mapped_channel,
+                        pitch: midi_message.pitch,
+                        velocity: midi_message.velocity
+                    }.into();
+
+                    outputs.push(TimedMidiMessage::new(raw_message.into(), e.delta_frames));
+
+                    self.current_playing_notes.push(PlayingNote {
+                        channel,
+                        pitch: midi_message.get_pitch(),
+                        mapped_channel,
+                        allocated,
+                        stolen: false,
+                        played: true,
+                    });
+                }
+                MidiMessageType::NoteOffMessage(midi_message) => {
+                    let channel = midi_message.get_channel();
+                    let pitch = midi_message.get_pitch();
+                    let index = self
+                        .current_playing_notes
+                        .iter()
+                        .position(|note| note.channel == channel && note.pitch == pitch);
+
+                    match index.map(|index| self.current_playing_notes.remove(index)) {
+                        Some(note) if note.stolen => {}
+                        Some(note) => {
+                            if note.allocated {
+                                self.channel_allocator.release(note.mapped_channel);
                             }
-                            None => {
-                                self.events.push(e);
+                            if !note.played {
+                                continue;
                             }
+                            let raw_message : RawMessage = NoteOff {
+                                channel: note.mapped_channel,
+                                pitch: midi_message.pitch,
+                                velocity: midi_message.velocity
+                            }.into();
+
+                            outputs.push(TimedMidiMessage::new(raw_message.into(), e.delta_frames));
+                        }
+                        None => {
+                            outputs.push(*e);
                         }
-                    }
-                    _ => {
-                        self.events.push(e);
                     }
                 }
+                MidiMessageType::CCMessage(_)
+                | MidiMessageType::PressureMessage(_)
+                | MidiMessageType::PitchBendMessage(_)
+                    if distributing =>
+                {
+                    self.forward_expression(e, outputs);
+                }
+                MidiMessageType::UnsupportedChannelMessage(_) if distributing && e.data[0] & 0xF0 == AFTERTOUCH => {
+                    self.forward_poly_pressure(e, outputs);
+                }
+                _ => {
+                    outputs.push(*e);
+                }
             }
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameters::{ChannelDistribution, DISTRIBUTION_NAMES};
+    use vst::plugin::PluginParameters;
+
+    fn process(plugin: &mut NoteFanOut, inputs: &[TimedMidiMessage]) -> Vec<TimedMidiMessage> {
+        let mut outputs = vec![];
+        plugin.process(inputs, 64, &TransportInfo::default(), &mut outputs);
+        outputs
+    }
+
+    fn note_on(pitch: u8, delta_frames: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new([0x90, pitch, 100], delta_frames)
+    }
+
+    fn note_off(pitch: u8, delta_frames: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new([0x80, pitch, 0], delta_frames)
+    }
+
+    // one of two steps
+    fn copy(selection: u8) -> NoteFanOut {
+        let plugin = NoteFanOut::default();
+        plugin.parameters.set_byte_parameter(Parameter::Steps, 2 * 8);
+        plugin.parameters.set_byte_parameter(Parameter::Selection, selection * 8);
+        plugin
+    }
+
+    fn second_copy() -> NoteFanOut {
+        copy(1)
+    }
+
+    #[test]
+    fn round_robin_takes_one_note_out_of_steps() {
+        let mut plugin = second_copy();
+        let inputs = [note_on(60, 0), note_on(62, 1), note_on(64, 2), note_on(65, 3), note_on(67, 4)];
+        assert_eq!(process(&mut plugin, &inputs), vec![inputs[1], inputs[3]]);
+        // the count goes on in the next blocks
+        assert_eq!(process(&mut plugin, &[note_on(69, 0), note_on(71, 1)]), vec![note_on(69, 0)]);
+    }
+
+    #[test]
+    fn without_steps_every_note_passes() {
+        let mut plugin = NoteFanOut::default();
+        let inputs = [note_on(60, 0), TimedMidiMessage::new([0xB0, 64, 127], 1), note_off(60, 2)];
+        assert_eq!(process(&mut plugin, &inputs), inputs.to_vec());
+    }
+
+    fn distribute(plugin: &NoteFanOut) {
+        plugin
+            .parameters
+            .set_parameter(Parameter::ChannelDistribute.into(), ChannelDistribution::Channels(3).into());
+    }
+
+    #[test]
+    fn copies_allocate_different_channels() {
+        let mut copies = [copy(0), copy(1)];
+        copies.iter().for_each(distribute);
+        let mut outputs = |inputs: &[TimedMidiMessage]| -> Vec<Vec<TimedMidiMessage>> {
+            copies.iter_mut().map(|plugin| process(plugin, inputs)).collect()
+        };
+
+        let on = TimedMidiMessage::new;
+        let off = TimedMidiMessage::new;
+        assert_eq!(
+            outputs(&[note_on(60, 0), note_on(62, 1), note_on(64, 2)]),
+            vec![
+                vec![on([0x91, 60, 100], 0), on([0x93, 64, 100], 2)],
+                vec![on([0x92, 62, 100], 1)]
+            ]
+        );
+        // both copies give the channel released first to the next note, whichever copy plays it
+        assert_eq!(
+            outputs(&[note_off(62, 0), note_off(60, 1), note_on(65, 2), note_on(67, 3)]),
+            vec![
+                vec![off([0x81, 60, 0], 1), on([0x91, 67, 100], 3)],
+                vec![off([0x82, 62, 0], 0), on([0x92, 65, 100], 2)]
+            ]
+        );
+    }
+
+    #[test]
+    fn without_distribution_expression_passes() {
+        let mut plugin = second_copy();
+        let inputs = [
+            TimedMidiMessage::new([0xB1, 64, 127], 0),
+            TimedMidiMessage::new([0xB1, 1, 20], 1),
+            TimedMidiMessage::new([0xD1, 30, 0], 2),
+            TimedMidiMessage::new([0xE1, 0, 70], 3),
+            TimedMidiMessage::new([0xA1, 60, 10], 4),
+            TimedMidiMessage::new([0xB1, 123, 0], 5),
+        ];
+        assert_eq!(process(&mut plugin, &inputs), inputs.to_vec());
+    }
+
+    #[test]
+    fn channel_mode_messages_are_not_kept() {
+        let mut plugin = NoteFanOut::default();
+        distribute(&plugin);
+        let all_notes_off = TimedMidiMessage::new([0xB3, 123, 0], 0);
+        assert_eq!(process(&mut plugin, &[all_notes_off]), vec![all_notes_off]);
+
+        // also sent to the mapped channel of the note
+        assert_eq!(
+            process(&mut plugin, &[TimedMidiMessage::new([0x93, 60, 100], 0)]),
+            vec![TimedMidiMessage::new([0x91, 60, 100], 0)]
+        );
+        assert_eq!(
+            process(&mut plugin, &[all_notes_off]),
+            vec![TimedMidiMessage::new([0xB1, 123, 0], 0), all_notes_off]
+        );
+    }
+
+    #[test]
+    fn pending_expression_is_bounded() {
+        let mut plugin = NoteFanOut::default();
+        distribute(&plugin);
+        let controllers: Vec<TimedMidiMessage> =
+            (0..40).map(|controller| TimedMidiMessage::new([0xB1, controller, 1], 0)).collect();
+        assert!(process(&mut plugin, &controllers).is_empty());
+
+        let outputs = process(&mut plugin, &[TimedMidiMessage::new([0x91, 60, 100], 0)]);
+        let expected: Vec<TimedMidiMessage> = (40 - MAX_PENDING_EXPRESSION as u8..40)
+            .map(|controller| TimedMidiMessage::new([0xB1, controller, 1], 0))
+            .chain([TimedMidiMessage::new([0x91, 60, 100], 0)])
+            .collect();
+        assert_eq!(outputs, expected);
+    }
+
+    #[test]
+    fn expression_of_the_notes_of_other_copies_is_dropped() {
+        let mut copies = [copy(0), copy(1)];
+        copies.iter().for_each(distribute);
+        let mut outputs = |inputs: &[TimedMidiMessage]| -> Vec<Vec<TimedMidiMessage>> {
+            copies.iter_mut().map(|plugin| process(plugin, inputs)).collect()
+        };
+
+        // the first copy takes the note
+        let pressure = TimedMidiMessage::new([0xD3, 90, 0], 1);
+        assert_eq!(
+            outputs(&[TimedMidiMessage::new([0x93, 60, 100], 0), pressure]),
+            vec![
+                vec![TimedMidiMessage::new([0x91, 60, 100], 0), TimedMidiMessage::new([0xD1, 90, 0], 1)],
+                vec![]
+            ]
+        );
+        // the second copy doesn't send it with its own note on the channel
+        assert_eq!(
+            outputs(&[TimedMidiMessage::new([0x93, 62, 100], 0)]),
+            vec![vec![], vec![TimedMidiMessage::new([0x92, 62, 100], 0)]]
+        );
+    }
+
+    fn copies(distribution: Distribution, steps: u8) -> Vec<NoteFanOut> {
+        (0..steps)
+            .map(|selection| {
+                let plugin = copy(selection);
+                plugin.parameters.set_byte_parameter(Parameter::Steps, steps * 8);
+                let name = DISTRIBUTION_NAMES[distribution as usize].to_string();
+                assert!(plugin.parameters.string_to_parameter(Parameter::Distribution.into(), name));
+                plugin
+            })
+            .collect()
+    }
+
+    // the pitches of the note ons each copy plays
+    fn taken(copies: &mut [NoteFanOut], inputs: &[TimedMidiMessage]) -> Vec<Vec<u8>> {
+        copies
+            .iter_mut()
+            .map(|plugin| {
+                process(plugin, inputs)
+                    .iter()
+                    .filter(|output| output.data[0] & 0xF0 == 0x90)
+                    .map(|output| output.data[1])
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn scale(notes: usize) -> Vec<TimedMidiMessage> {
+        (0..notes).map(|note| note_on(30 + note as u8, note)).collect()
+    }
+
+    // every note is taken by one copy exactly
+    fn assert_each_note_taken_once(taken: &[Vec<u8>], inputs: &[TimedMidiMessage]) {
+        let mut pitches: Vec<u8> = taken.concat();
+        pitches.sort_unstable();
+        assert_eq!(pitches, inputs.iter().map(|input| input.data[1]).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn random_steps_follow_the_seed() {
+        let inputs = scale(32);
+        let mut random_copies = copies(Distribution::Random, 3);
+        random_copies.iter().for_each(|plugin| plugin.parameters.set_byte_parameter(Parameter::Seed, 7));
+        let taken = taken(&mut random_copies, &inputs);
+        assert_each_note_taken_once(&taken, &inputs);
+
+        let mut random = Random::new(7);
+        let mut expected = vec![vec![]; 3];
+        for input in &inputs {
+            expected[random.below(3)].push(input.data[1]);
+        }
+        assert_eq!(taken, expected);
+    }
+
+    #[test]
+    fn weighted_steps_follow_the_weights() {
+        let inputs = scale(64);
+        let weighted = || {
+            let mut weighted_copies = copies(Distribution::Weighted, 3);
+            for plugin in &weighted_copies {
+                plugin.parameters.set_byte_parameter(Parameter::Seed, 3);
+                plugin.parameters.set_byte_parameter(Parameter::Weight(0), 0);
+                plugin.parameters.set_byte_parameter(Parameter::Weight(1), 20);
+                plugin.parameters.set_byte_parameter(Parameter::Weight(2), 100);
+            }
+            taken(&mut weighted_copies, &inputs)
+        };
+        let taken = weighted();
+        assert_each_note_taken_once(&taken, &inputs);
+        assert!(taken[0].is_empty());
+        assert!(taken[1].len() < taken[2].len());
+        // the same seed takes the same notes
+        assert_eq!(weighted(), taken);
+    }
+
+    #[test]
+    fn euclidean_hits_go_to_the_first_step() {
+        let mut euclidean_copies = copies(Distribution::Euclidean, 2);
+        for plugin in &euclidean_copies {
+            plugin.parameters.set_byte_parameter(Parameter::Steps, 8 * 8);
+            plugin.parameters.set_byte_parameter(Parameter::EuclideanHits, 3 * 8);
+        }
+        let inputs = scale(16);
+        assert_eq!(
+            taken(&mut euclidean_copies, &inputs),
+            vec![
+                vec![30, 33, 36, 38, 41, 44],
+                vec![31, 32, 34, 35, 37, 39, 40, 42, 43, 45]
+            ]
+        );
+    }
+
+    #[test]
+    fn pitch_zones_start_at_the_splits() {
+        let mut zone_copies = copies(Distribution::PitchZones, 3);
+        for plugin in &zone_copies {
+            plugin.parameters.set_byte_parameter(Parameter::Split(1), 60);
+            plugin.parameters.set_byte_parameter(Parameter::Split(2), 72);
+        }
+        let inputs = [note_on(40, 0), note_on(60, 1), note_on(71, 2), note_on(72, 3), note_on(127, 4)];
+        assert_eq!(taken(&mut zone_copies, &inputs), vec![vec![40], vec![60, 71], vec![72, 127]]);
+    }
+
+    #[test]
+    fn velocity_layers_start_at_the_splits() {
+        let mut layer_copies = copies(Distribution::VelocityLayers, 2);
+        for plugin in &layer_copies {
+            plugin.parameters.set_byte_parameter(Parameter::Split(1), 64);
+        }
+        let inputs: Vec<TimedMidiMessage> = [(60, 1), (62, 63), (64, 64), (65, 127)]
+            .iter()
+            .enumerate()
+            .map(|(delta_frames, &(pitch, velocity))| TimedMidiMessage::new([0x90, pitch, velocity], delta_frames))
+            .collect();
+        assert_eq!(taken(&mut layer_copies, &inputs), vec![vec![60, 62], vec![64, 65]]);
+    }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn vst::plugin::PluginParameters> {
-        Arc::clone(&self.parameters) as Arc<dyn vst::plugin::PluginParameters>
+    #[test]
+    fn chord_positions_count_from_the_lowest_note() {
+        let mut chord_copies = copies(Distribution::ChordPosition, 3);
+        // the chord in any order, then a note alone and a chord of four notes
+        let inputs = [
+            note_on(67, 0),
+            note_on(60, 0),
+            note_on(64, 0),
+            note_on(72, 5),
+            note_on(50, 9),
+            note_on(55, 9),
+            note_on(53, 9),
+            note_on(57, 9),
+        ];
+        assert_eq!(
+            taken(&mut chord_copies, &inputs),
+            vec![vec![60, 72, 50, 57], vec![64, 53], vec![67, 55]]
+        );
     }
 }
@@ -1,34 +1,161 @@
-use std::sync::Mutex;
 use util::parameter_value_conversion::f32_to_byte;
-use util::HostCallbackLock;
-use vst::plugin::{HostCallback, PluginParameters};
+use vst::plugin::PluginParameters;
 use vst::util::ParameterTransfer;
+use util::parameter_definition::{ParameterDefinition, ParameterScale};
 use util::parameters::ParameterConversion;
 
+pub const MAX_STEPS: usize = 16;
+const FIRST_WEIGHT_PARAMETER: usize = 6;
+const FIRST_SPLIT_PARAMETER: usize = FIRST_WEIGHT_PARAMETER + MAX_STEPS;
+// between the steps
+const SPLIT_COUNT: usize = MAX_STEPS - 1;
+const GROUP_PARAMETER: usize = FIRST_SPLIT_PARAMETER + SPLIT_COUNT;
+const CHANNEL_SELECTION_PARAMETER: usize = GROUP_PARAMETER + 1;
+const FIRST_LISTED_CHANNEL_PARAMETER: usize = CHANNEL_SELECTION_PARAMETER + 3;
+pub const CHANNEL_COUNT: usize = 16;
+pub const PARAMETER_COUNT: usize = FIRST_LISTED_CHANNEL_PARAMETER + CHANNEL_COUNT;
+
+// the steps are named from 1, the first step going to the selection 0
+const WEIGHT_NAMES: [&str; MAX_STEPS] = [
+    "Step 1 weight",
+    "Step 2 weight",
+    "Step 3 weight",
+    "Step 4 weight",
+    "Step 5 weight",
+    "Step 6 weight",
+    "Step 7 weight",
+    "Step 8 weight",
+    "Step 9 weight",
+    "Step 10 weight",
+    "Step 11 weight",
+    "Step 12 weight",
+    "Step 13 weight",
+    "Step 14 weight",
+    "Step 15 weight",
+    "Step 16 weight",
+];
+
+const LISTED_CHANNEL_NAMES: [&str; CHANNEL_COUNT] = [
+    "Channel 1 listed",
+    "Channel 2 listed",
+    "Channel 3 listed",
+    "Channel 4 listed",
+    "Channel 5 listed",
+    "Channel 6 listed",
+    "Channel 7 listed",
+    "Channel 8 listed",
+    "Channel 9 listed",
+    "Channel 10 listed",
+    "Channel 11 listed",
+    "Channel 12 listed",
+    "Channel 13 listed",
+    "Channel 14 listed",
+    "Channel 15 listed",
+    "Channel 16 listed",
+];
+
+// the first step has no split
+const SPLIT_NAMES: [&str; SPLIT_COUNT] = [
+    "Step 2 split",
+    "Step 3 split",
+    "Step 4 split",
+    "Step 5 split",
+    "Step 6 split",
+    "Step 7 split",
+    "Step 8 split",
+    "Step 9 split",
+    "Step 10 split",
+    "Step 11 split",
+    "Step 12 split",
+    "Step 13 split",
+    "Step 14 split",
+    "Step 15 split",
+    "Step 16 split",
+];
+
+pub const PARAMETER_DEFINITIONS: [ParameterDefinition; PARAMETER_COUNT] = parameter_definitions();
+
+const fn parameter_definitions() -> [ParameterDefinition; PARAMETER_COUNT] {
+    let mut definitions = [ParameterDefinition::new("", ParameterScale::Normalized); PARAMETER_COUNT];
+    definitions[0] = ParameterDefinition::new("Steps", ParameterScale::Byte { divisor: 8 });
+    definitions[1] = ParameterDefinition::new("Selection", ParameterScale::Byte { divisor: 8 });
+    // see ChannelDistribution for the mapping
+    definitions[2] = ParameterDefinition::new("Channel distribution", ParameterScale::Normalized);
+    // parameters added after the channel distribution, so presets saved before still load
+    definitions[3] = ParameterDefinition::new("Distribution", ParameterScale::Byte { divisor: DISTRIBUTION_DIVISOR });
+    definitions[4] = ParameterDefinition::new("Seed", ParameterScale::Byte { divisor: 1 });
+    definitions[5] = ParameterDefinition::new("Euclidean hits", ParameterScale::Byte { divisor: 8 })
+        .with_default(8. / 127.);
+    let mut step = 0;
+    while step < MAX_STEPS {
+        definitions[FIRST_WEIGHT_PARAMETER + step] =
+            ParameterDefinition::new(WEIGHT_NAMES[step], ParameterScale::Byte { divisor: 1 }).with_default(1.0);
+        step += 1;
+    }
+    let mut split = 0;
+    while split < SPLIT_COUNT {
+        definitions[FIRST_SPLIT_PARAMETER + split] =
+            ParameterDefinition::new(SPLIT_NAMES[split], ParameterScale::Byte { divisor: 1 })
+                .with_default(default_split(split) as f32 / 127.);
+        split += 1;
+    }
+    // copies in the same group share their note counter, see util::step_counter
+    definitions[GROUP_PARAMETER] = ParameterDefinition::new("Group", ParameterScale::Byte { divisor: 1 });
+    // the channels the channel distribution allocates, see ChannelSelection
+    definitions[CHANNEL_SELECTION_PARAMETER] = ParameterDefinition::new(
+        "Channel selection",
+        ParameterScale::Byte {
+            divisor: CHANNEL_SELECTION_DIVISOR,
+        },
+    );
+    definitions[CHANNEL_SELECTION_PARAMETER + 1] =
+        ParameterDefinition::new("First channel", ParameterScale::Byte { divisor: 8 }).with_default(8. / 127.);
+    definitions[CHANNEL_SELECTION_PARAMETER + 2] =
+        ParameterDefinition::new("Last channel", ParameterScale::Byte { divisor: 8 }).with_default(120. / 127.);
+    let mut channel = 0;
+    while channel < CHANNEL_COUNT {
+        // all but the first channel, reserved for MPE
+        let default = if channel == 0 { 0. } else { 1. };
+        definitions[FIRST_LISTED_CHANNEL_PARAMETER + channel] =
+            ParameterDefinition::new(LISTED_CHANNEL_NAMES[channel], ParameterScale::Bool).with_default(default);
+        channel += 1;
+    }
+    definitions
+}
+
+// evenly spread over the 128 values
+const fn default_split(split: usize) -> u8 {
+    ((split + 1) * 128 / MAX_STEPS) as u8
+}
+
+// 7 distributions
+const DISTRIBUTION_DIVISOR: u8 = 18;
+// 3 channel selections
+const CHANNEL_SELECTION_DIVISOR: u8 = 43;
+
 pub struct NoteFanoutParameters {
-    pub host: Mutex<HostCallbackLock>,
     pub transfer: ParameterTransfer,
 }
 
-#[repr(i32)]
-#[derive(Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Parameter {
-    Steps = 0,
+    Steps,
     Selection,
     ChannelDistribute,
-}
-
-
-impl Clone for Parameter {
-    fn clone(&self) -> Self {
-        let value = *self as i32;
-        Parameter::from(value)
-    }
-
-    fn clone_from(&mut self, source: &Self) {
-        let value = *source as i32;
-        *self = Parameter::from(value)
-    }
+    Distribution,
+    Seed,
+    EuclideanHits,
+    // step from 0, named from 1
+    Weight(usize),
+    // the lowest pitch or velocity of the step, from 1, named from 2
+    Split(usize),
+    // 0 when the note counter is not shared
+    Group,
+    ChannelSelection,
+    // channels from 0, displayed from 1
+    FirstChannel,
+    LastChannel,
+    ListedChannel(usize),
 }
 
 impl From<i32> for Parameter {
@@ -37,6 +164,22 @@ impl From<i32> for Parameter {
             0 => Parameter::Steps,
             1 => Parameter::Selection,
             2 => Parameter::ChannelDistribute,
+            3 => Parameter::Distribution,
+            4 => Parameter::Seed,
+            5 => Parameter::EuclideanHits,
+            i if (FIRST_WEIGHT_PARAMETER..FIRST_SPLIT_PARAMETER).contains(&(i as usize)) => {
+                Parameter::Weight(i as usize - FIRST_WEIGHT_PARAMETER)
+            }
+            i if (FIRST_SPLIT_PARAMETER..GROUP_PARAMETER).contains(&(i as usize)) => {
+                Parameter::Split(i as usize - FIRST_SPLIT_PARAMETER + 1)
+            }
+            i if i as usize == GROUP_PARAMETER => Parameter::Group,
+            i if i as usize == CHANNEL_SELECTION_PARAMETER => Parameter::ChannelSelection,
+            i if i as usize == CHANNEL_SELECTION_PARAMETER + 1 => Parameter::FirstChannel,
+            i if i as usize == CHANNEL_SELECTION_PARAMETER + 2 => Parameter::LastChannel,
+            i if (FIRST_LISTED_CHANNEL_PARAMETER..PARAMETER_COUNT).contains(&(i as usize)) => {
+                Parameter::ListedChannel(i as usize - FIRST_LISTED_CHANNEL_PARAMETER)
+            }
             _ => panic!(format!("No such Parameter {}", i)),
         }
     }
@@ -44,13 +187,162 @@ impl From<i32> for Parameter {
 
 impl Into<i32> for Parameter {
     fn into(self) -> i32 {
-        self as i32
+        match self {
+            Parameter::Steps => 0,
+            Parameter::Selection => 1,
+            Parameter::ChannelDistribute => 2,
+            Parameter::Distribution => 3,
+            Parameter::Seed => 4,
+            Parameter::EuclideanHits => 5,
+            Parameter::Weight(step) => (FIRST_WEIGHT_PARAMETER + step) as i32,
+            Parameter::Split(step) => (FIRST_SPLIT_PARAMETER + step - 1) as i32,
+            Parameter::Group => GROUP_PARAMETER as i32,
+            Parameter::ChannelSelection => CHANNEL_SELECTION_PARAMETER as i32,
+            Parameter::FirstChannel => (CHANNEL_SELECTION_PARAMETER + 1) as i32,
+            Parameter::LastChannel => (CHANNEL_SELECTION_PARAMETER + 2) as i32,
+            Parameter::ListedChannel(channel) => (FIRST_LISTED_CHANNEL_PARAMETER + channel) as i32,
+        }
+    }
+}
+
+impl Parameter {
+    #[inline]
+    fn index(self) -> usize {
+        let index: i32 = self.into();
+        index as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Distribution {
+    // one step after the other
+    RoundRobin = 0,
+    Random,
+    // random, each step having its weight
+    Weighted,
+    // Euclidean hits over the steps go to the selection 0, the rests to the selection 1
+    Euclidean,
+    // by the split pitches
+    PitchZones,
+    // by the split velocities
+    VelocityLayers,
+    // the lowest of the notes starting together goes to the step 0, the next one to the step 1...
+    ChordPosition,
+}
+
+pub const DISTRIBUTION_NAMES: [&str; 7] = [
+    "Round robin",
+    "Random",
+    "Weighted",
+    "Euclidean",
+    "Pitch zones",
+    "Velocity layers",
+    "Chord position",
+];
+
+impl From<u8> for Distribution {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Distribution::RoundRobin,
+            1 => Distribution::Random,
+            2 => Distribution::Weighted,
+            3 => Distribution::Euclidean,
+            4 => Distribution::PitchZones,
+            5 => Distribution::VelocityLayers,
+            _ => Distribution::ChordPosition,
+        }
+    }
+}
+
+// the mask of the channels from first to last, in any order
+fn channel_range(first: u8, last: u8) -> u16 {
+    let highest = first.max(last).min(CHANNEL_COUNT as u8 - 1);
+    (first.min(last)..=highest).fold(0, |mask, channel| mask | 1 << channel)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChannelSelection {
+    // the channel distribution gives the number of channels, from the channel 2
+    Count = 0,
+    // from the first to the last channel
+    Range,
+    // the listed channels
+    List,
+}
+
+pub const CHANNEL_SELECTION_NAMES: [&str; 3] = ["Count", "Range", "List"];
+
+impl From<u8> for ChannelSelection {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ChannelSelection::Count,
+            1 => ChannelSelection::Range,
+            _ => ChannelSelection::List,
+        }
     }
 }
 
 impl NoteFanoutParameters {
     pub fn get_channel_distribution(&self, parameter: Parameter) -> ChannelDistribution {
-        ChannelDistribution::from(self.transfer.get_parameter(parameter as usize))
+        ChannelDistribution::from(self.transfer.get_parameter(parameter.index()))
+    }
+
+    #[inline]
+    pub fn get_steps(&self) -> u8 {
+        self.get_byte_parameter(Parameter::Steps) / 8
+    }
+
+    #[inline]
+    pub fn get_selection(&self) -> u8 {
+        self.get_byte_parameter(Parameter::Selection) / 8
+    }
+
+    #[inline]
+    pub fn get_distribution(&self) -> Distribution {
+        Distribution::from(self.get_byte_parameter(Parameter::Distribution) / DISTRIBUTION_DIVISOR)
+    }
+
+    #[inline]
+    pub fn get_euclidean_hits(&self) -> u8 {
+        self.get_byte_parameter(Parameter::EuclideanHits) / 8
+    }
+
+    #[inline]
+    pub fn get_channel_selection(&self) -> ChannelSelection {
+        let value = self.get_byte_parameter(Parameter::ChannelSelection);
+        ChannelSelection::from(value / CHANNEL_SELECTION_DIVISOR)
+    }
+
+    // from 0
+    #[inline]
+    pub fn get_channel(&self, parameter: Parameter) -> u8 {
+        self.get_byte_parameter(parameter) / 8
+    }
+
+    // bit n is set when the channel n, from 0, can be allocated. 0 when the channel distribution is
+    // off
+    pub fn get_channel_mask(&self) -> u16 {
+        let count = match self.get_channel_distribution(Parameter::ChannelDistribute) {
+            ChannelDistribution::Channels(count) => count,
+            ChannelDistribution::Off => return 0,
+        };
+        match self.get_channel_selection() {
+            ChannelSelection::Count => channel_range(1, count),
+            ChannelSelection::Range => {
+                channel_range(self.get_channel(Parameter::FirstChannel), self.get_channel(Parameter::LastChannel))
+            }
+            ChannelSelection::List => (0..CHANNEL_COUNT)
+                .filter(|&channel| self.get_bool_parameter(Parameter::ListedChannel(channel)))
+                .fold(0, |mask, channel| mask | 1 << channel),
+        }
+    }
+
+    // the splits between the steps are expected to go up, a step taking the values from its split
+    // to the next one
+    pub fn get_split_step(&self, value: u8, steps: u8) -> u8 {
+        (1..steps as usize)
+            .filter(|&step| value >= self.get_byte_parameter(Parameter::Split(step)))
+            .count() as u8
     }
 }
 
@@ -60,16 +352,7 @@ impl ParameterConversion<Parameter> for NoteFanoutParameters {
     }
 
     fn get_parameter_count() -> usize {
-        3
-    }
-}
-
-impl NoteFanoutParameters {
-    pub fn new(host: HostCallback) -> Self {
-        NoteFanoutParameters {
-            host: Mutex::new(HostCallbackLock { host }),
-            transfer: ParameterTransfer::new(3),
-        }
+        PARAMETER_COUNT
     }
 }
 
@@ -107,7 +390,7 @@ impl PluginParameters for NoteFanoutParameters {
     fn get_parameter_text(&self, index: i32) -> String {
         match Parameter::from(index ) {
             Parameter::Steps => {
-                let value = self.get_byte_parameter(Parameter::Steps) / 8;
+                let value = self.get_steps();
                 if value == 0 {
                     "off".to_string()
                 } else {
@@ -115,7 +398,7 @@ impl PluginParameters for NoteFanoutParameters {
                 }
             }
             Parameter::Selection => {
-                format!("{}", self.get_byte_parameter(Parameter::Selection) / 8)
+                format!("{}", self.get_selection())
             }
             Parameter::ChannelDistribute => {
                 match self.get_channel_distribution(Parameter::ChannelDistribute) {
@@ -123,16 +406,36 @@ impl PluginParameters for NoteFanoutParameters {
                     ChannelDistribution::Off => "Off".to_string()
                 }
             }
+            Parameter::Distribution => DISTRIBUTION_NAMES[self.get_distribution() as usize].to_string(),
+            Parameter::EuclideanHits => format!("{}", self.get_euclidean_hits()),
+            Parameter::Group => match self.get_byte_parameter(Parameter::Group) {
+                0 => "Off".to_string(),
+                group => format!("{}", group),
+            },
+            Parameter::ChannelSelection => {
+                CHANNEL_SELECTION_NAMES[self.get_channel_selection() as usize].to_string()
+            }
+            parameter @ (Parameter::FirstChannel | Parameter::LastChannel) => {
+                format!("{}", self.get_channel(parameter) + 1)
+            }
+            Parameter::ListedChannel(channel) => {
+                if self.get_bool_parameter(Parameter::ListedChannel(channel)) {
+                    "On".to_string()
+                } else {
+                    "Off".to_string()
+                }
+            }
+            parameter @ (Parameter::Seed | Parameter::Weight(_) | Parameter::Split(_)) => {
+                format!("{}", self.get_byte_parameter(parameter))
+            }
         }
     }
 
     fn get_parameter_name(&self, index: i32) -> String {
-        match Parameter::from(index as i32) {
-            Parameter::Steps => "Steps",
-            Parameter::Selection => "Selection",
-            Parameter::ChannelDistribute => "Channel distribution"
+        match PARAMETER_DEFINITIONS.get(index as usize) {
+            Some(definition) => definition.name.to_string(),
+            None => "".to_string(),
         }
-        .to_string()
     }
 
     fn get_parameter(&self, index: i32) -> f32 {
@@ -142,7 +445,11 @@ impl PluginParameters for NoteFanoutParameters {
     fn set_parameter(&self, index: i32, value: f32) {
         let parameter = Parameter::from(index);
         match parameter {
-            Parameter::Steps | Parameter::Selection => {
+            Parameter::Steps
+            | Parameter::Selection
+            | Parameter::EuclideanHits
+            | Parameter::FirstChannel
+            | Parameter::LastChannel => {
                 let new_value = f32_to_byte(value) / 8;
                 let old_value = self.get_byte_parameter(parameter) / 8;
 
@@ -153,9 +460,58 @@ impl PluginParameters for NoteFanoutParameters {
             Parameter::ChannelDistribute => {
                 self.transfer.set_parameter(index as usize, value);
             }
+            _ => {
+                let new_value = f32_to_byte(value);
+                if new_value != self.get_byte_parameter(parameter) {
+                    self.set_byte_parameter(parameter, new_value)
+                }
+            }
         }
     }
 
+    fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        let parameter = Parameter::from(index);
+        let value = match parameter {
+            Parameter::Steps if text.eq_ignore_ascii_case("off") => 0,
+            Parameter::Steps | Parameter::Selection | Parameter::EuclideanHits => match text.parse::<u8>() {
+                Ok(n) if n < 16 => n * 8,
+                _ => return false,
+            },
+            Parameter::ChannelDistribute => return false,
+            Parameter::Distribution => {
+                match DISTRIBUTION_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                    Some(position) => position as u8 * DISTRIBUTION_DIVISOR,
+                    None => return false,
+                }
+            }
+            Parameter::ChannelSelection => {
+                match CHANNEL_SELECTION_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                    Some(position) => position as u8 * CHANNEL_SELECTION_DIVISOR,
+                    None => return false,
+                }
+            }
+            Parameter::FirstChannel | Parameter::LastChannel => match text.trim().parse::<u8>() {
+                Ok(n) if (1..=CHANNEL_COUNT as u8).contains(&n) => (n - 1) * 8,
+                _ => return false,
+            },
+            Parameter::ListedChannel(_) => match text.trim().to_ascii_lowercase().as_ref() {
+                "0" | "off" | "false" | "" => 0,
+                "1" | "on" | "true" => 127,
+                _ => return false,
+            },
+            Parameter::Group if text.trim().eq_ignore_ascii_case("off") => 0,
+            Parameter::Seed | Parameter::Weight(_) | Parameter::Split(_) | Parameter::Group => {
+                match text.trim().parse::<u8>() {
+                    Ok(n) if n < 128 => n,
+                    _ => return false,
+                }
+            }
+        };
+
+        self.set_byte_parameter(parameter, value);
+        true
+    }
+
     fn get_preset_data(&self) -> Vec<u8> {
         self.serialize_state()
     }
@@ -176,12 +532,23 @@ impl PluginParameters for NoteFanoutParameters {
 impl Default for NoteFanoutParameters {
     fn default() -> Self {
         let parameters = NoteFanoutParameters {
-            host: Default::default(),
-            transfer: ParameterTransfer::new(3),
+            transfer: ParameterTransfer::new(PARAMETER_COUNT),
         };
         parameters.set_byte_parameter(Parameter::Steps, 0);
         parameters.set_byte_parameter(Parameter::Selection, 0);
         parameters.set_byte_parameter(Parameter::ChannelDistribute, 0);
+        parameters.set_byte_parameter(Parameter::EuclideanHits, 8);
+        for step in 0..MAX_STEPS {
+            parameters.set_byte_parameter(Parameter::Weight(step), 127);
+        }
+        for split in 1..MAX_STEPS {
+            parameters.set_byte_parameter(Parameter::Split(split), default_split(split - 1));
+        }
+        parameters.set_byte_parameter(Parameter::FirstChannel, 8);
+        parameters.set_byte_parameter(Parameter::LastChannel, 120);
+        for channel in 1..CHANNEL_COUNT {
+            parameters.set_bool_parameter(Parameter::ListedChannel(channel), true);
+        }
         parameters
     }
 }
@@ -0,0 +1,129 @@
+// chords played when the generated note is triggered: the intervals of the chord type above the
+// pitch parameter, voiced with the inversion and spread parameters.
+
+// the root and up to 4 custom intervals
+pub const MAX_CHORD_NOTES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChordType {
+    // only the pitch parameter, as before chords existed
+    Single = 0,
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Major7,
+    Minor7,
+    Dominant7,
+    Power,
+    // the custom interval parameters
+    Custom,
+}
+
+pub const CHORD_TYPE_NAMES: [&str; 12] = [
+    "Single note",
+    "Major",
+    "Minor",
+    "Diminished",
+    "Augmented",
+    "Sus2",
+    "Sus4",
+    "Major 7th",
+    "Minor 7th",
+    "Dominant 7th",
+    "Power",
+    "Custom",
+];
+
+impl From<u8> for ChordType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ChordType::Single,
+            1 => ChordType::Major,
+            2 => ChordType::Minor,
+            3 => ChordType::Diminished,
+            4 => ChordType::Augmented,
+            5 => ChordType::Sus2,
+            6 => ChordType::Sus4,
+            7 => ChordType::Major7,
+            8 => ChordType::Minor7,
+            9 => ChordType::Dominant7,
+            10 => ChordType::Power,
+            _ => ChordType::Custom,
+        }
+    }
+}
+
+impl ChordType {
+    pub fn name(self) -> &'static str {
+        CHORD_TYPE_NAMES[self as usize]
+    }
+
+    // semitones above the root, empty for custom chords
+    pub fn intervals(self) -> &'static [u8] {
+        match self {
+            ChordType::Single | ChordType::Custom => &[],
+            ChordType::Major => &[4, 7],
+            ChordType::Minor => &[3, 7],
+            ChordType::Diminished => &[3, 6],
+            ChordType::Augmented => &[4, 8],
+            ChordType::Sus2 => &[2, 7],
+            ChordType::Sus4 => &[5, 7],
+            ChordType::Major7 => &[4, 7, 11],
+            ChordType::Minor7 => &[3, 7, 10],
+            ChordType::Dominant7 => &[4, 7, 10],
+            ChordType::Power => &[7, 12],
+        }
+    }
+}
+
+// distinct pitches, lowest first
+pub struct Chord {
+    pitches: [u8; MAX_CHORD_NOTES],
+    count: usize,
+}
+
+impl Chord {
+    // each inversion moves the lowest note an octave up, so inverting as many times as there are
+    // notes moves the whole chord an octave up. Spread then moves every other note, starting with
+    // the second lowest, that many octaves up. Pitches above 127 are left out
+    pub fn new(root: u8, intervals: &[u8], inversion: u8, spread: u8) -> Chord {
+        let mut offsets = [0u16; MAX_CHORD_NOTES];
+        let mut count = 1;
+        for &interval in intervals.iter().take(MAX_CHORD_NOTES - 1) {
+            offsets[count] = interval as u16;
+            count += 1;
+        }
+        let offsets = &mut offsets[..count];
+        offsets.sort_unstable();
+
+        for _ in 0..inversion {
+            offsets[0] += 12;
+            offsets.sort_unstable();
+        }
+        for offset in offsets.iter_mut().skip(1).step_by(2) {
+            *offset += spread as u16 * 12;
+        }
+        offsets.sort_unstable();
+
+        let mut chord = Chord {
+            pitches: [0; MAX_CHORD_NOTES],
+            count: 0,
+        };
+        for &offset in offsets.iter() {
+            let pitch = root as u16 + offset;
+            if pitch > 127 || chord.pitches().last() == Some(&(pitch as u8)) {
+                continue;
+            }
+            chord.pitches[chord.count] = pitch as u8;
+            chord.count += 1;
+        }
+        chord
+    }
+
+    pub fn pitches(&self) -> &[u8] {
+        &self.pitches[..self.count]
+    }
+}
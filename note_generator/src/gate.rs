@@ -0,0 +1,69 @@
+// one-shot notes: with a gate, a rising trigger starts notes that are stopped after the gate
+// length, in seconds or tempo synced, whatever the trigger does in between. Triggering again while
+// they play stops them and starts the gate over.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GateMode {
+    // the notes last as long as the trigger
+    Off = 0,
+    Time,
+    Synced,
+}
+
+pub const GATE_MODE_NAMES: [&str; 3] = ["Off", "Time", "Tempo synced"];
+
+impl From<u8> for GateMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => GateMode::Off,
+            1 => GateMode::Time,
+            _ => GateMode::Synced,
+        }
+    }
+}
+
+impl GateMode {
+    pub fn name(self) -> &'static str {
+        GATE_MODE_NAMES[self as usize]
+    }
+}
+
+#[derive(Default)]
+pub struct Gate {
+    // samples until the notes are stopped, from the start of the current block
+    remaining: Option<usize>,
+}
+
+impl Gate {
+    // samples from the start of the block to when the notes are stopped
+    pub fn open(&mut self, samples: usize) {
+        self.remaining = Some(samples);
+    }
+
+    pub fn close(&mut self) {
+        self.remaining = None;
+    }
+
+    // notes started without a gate are stopped by the trigger
+    pub fn is_open(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    // the delta at which the notes end when it is before the given one, e.g. when they are
+    // triggered again later in the block
+    pub fn end_before(&self, delta: usize) -> Option<usize> {
+        self.remaining.filter(|&remaining| remaining < delta)
+    }
+
+    // the delta at which the notes have to be stopped, if in this block
+    pub fn process(&mut self, samples: usize) -> Option<usize> {
+        let remaining = self.remaining?;
+        if remaining < samples {
+            self.remaining = None;
+            Some(remaining)
+        } else {
+            self.remaining = Some(remaining - samples);
+            None
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// input-driven mode: incoming notes, or a CC crossing a threshold, trigger the notes of the
+// generator instead of the trigger parameter. The events used are not passed through, so the
+// generator maps them to its own channel, pitch, velocity and expressions.
+
+use util::constants::{CC, NOTE_OFF, NOTE_ON};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputMode {
+    Off = 0,
+    Notes,
+    Cc,
+}
+
+pub const INPUT_MODE_NAMES: [&str; 3] = ["Off", "Notes", "CC"];
+
+impl From<u8> for InputMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => InputMode::Off,
+            1 => InputMode::Notes,
+            _ => InputMode::Cc,
+        }
+    }
+}
+
+impl InputMode {
+    pub fn name(self) -> &'static str {
+        INPUT_MODE_NAMES[self as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputAction {
+    // not used by the mode, passed through
+    Pass,
+    // used, but does not change the notes
+    Consume,
+    Start,
+    Stop,
+}
+
+// allocated up front for the audio thread, notes held beyond are not tracked
+const MAX_HELD_NOTES: usize = 128;
+
+pub struct InputTrigger {
+    // channel and pitch of the incoming notes held, the notes stop when the last one is released
+    held_notes: Vec<(u8, u8)>,
+    cc_high: bool,
+}
+
+impl Default for InputTrigger {
+    fn default() -> Self {
+        InputTrigger {
+            held_notes: Vec::with_capacity(MAX_HELD_NOTES),
+            cc_high: false,
+        }
+    }
+}
+
+impl InputTrigger {
+    // forgets the notes held and the CC position, when the mode changes
+    pub fn reset(&mut self) {
+        self.held_notes.clear();
+        self.cc_high = false;
+    }
+
+    pub fn event(&mut self, mode: InputMode, cc: u8, threshold: u8, data: [u8; 3]) -> InputAction {
+        let channel = data[0] & 0x0F;
+        match (mode, data[0] & 0xF0) {
+            (InputMode::Notes, NOTE_ON) if data[2] > 0 => {
+                if self.held_notes.len() < MAX_HELD_NOTES {
+                    self.held_notes.push((channel, data[1]));
+                }
+                InputAction::Start
+            }
+            (InputMode::Notes, NOTE_ON) | (InputMode::Notes, NOTE_OFF) => {
+                match self.held_notes.iter().position(|&note| note == (channel, data[1])) {
+                    Some(position) => {
+                        self.held_notes.remove(position);
+                        if self.held_notes.is_empty() {
+                            InputAction::Stop
+                        } else {
+                            InputAction::Consume
+                        }
+                    }
+                    // started before the mode was selected
+                    None => InputAction::Pass,
+                }
+            }
+            (InputMode::Cc, CC) if data[1] == cc => {
+                let high = data[2] >= threshold;
+                if high == self.cc_high {
+                    return InputAction::Consume;
+                }
+                self.cc_high = high;
+                if high {
+                    InputAction::Start
+                } else {
+                    InputAction::Stop
+                }
+            }
+            _ => InputAction::Pass,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// legato for the triggered notes: when the pitch changes while they play, the new notes are either
+// started before the previous ones are stopped ( re-articulation ), or the notes keep playing and
+// pitch bends glide to the new pitch, within the bend range of the synth.
+
+use crate::chord::MAX_CHORD_NOTES;
+use util::constants::PITCHBEND;
+use util::midi_processor::TimedMidiMessage;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LegatoMode {
+    Off = 0,
+    Rearticulate,
+    Glide,
+}
+
+pub const LEGATO_MODE_NAMES: [&str; 3] = ["Off", "Re-articulate", "Glide"];
+
+impl From<u8> for LegatoMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => LegatoMode::Off,
+            1 => LegatoMode::Rearticulate,
+            _ => LegatoMode::Glide,
+        }
+    }
+}
+
+impl LegatoMode {
+    pub fn name(self) -> &'static str {
+        LEGATO_MODE_NAMES[self as usize]
+    }
+}
+
+// samples between two pitch bends of a glide
+const GLIDE_INTERVAL: usize = 64;
+
+struct DelayedNoteOff {
+    remaining: usize,
+    data: [u8; 3],
+}
+
+pub struct Legato {
+    note_offs: Vec<DelayedNoteOff>,
+    // in semitones from the pitch the notes were started with
+    bend: f64,
+    gliding: bool,
+    glide_start: f64,
+    glide_target: f64,
+    glide_elapsed: usize,
+    glide_length: usize,
+    last_glide_value: Option<u16>,
+}
+
+impl Default for Legato {
+    fn default() -> Self {
+        Legato {
+            note_offs: Vec::with_capacity(2 * MAX_CHORD_NOTES),
+            bend: 0.,
+            gliding: false,
+            glide_start: 0.,
+            glide_target: 0.,
+            glide_elapsed: 0,
+            glide_length: 0,
+            last_glide_value: None,
+        }
+    }
+}
+
+impl Legato {
+    pub fn delay_note_off(&mut self, data: [u8; 3], samples: usize) {
+        self.note_offs.push(DelayedNoteOff {
+            remaining: samples,
+            data,
+        });
+    }
+
+    // returns the note off of a note still overlapping, so it is sent before the note is started
+    // again
+    pub fn take_note_off(&mut self, channel: u8, pitch: u8) -> Option<[u8; 3]> {
+        let position = self
+            .note_offs
+            .iter()
+            .position(|note_off| note_off.data[0] & 0x0F == channel && note_off.data[1] == pitch)?;
+        Some(self.note_offs.remove(position).data)
+    }
+
+    pub fn flush_note_offs(&mut self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        for note_off in self.note_offs.drain(..) {
+            outputs.push(TimedMidiMessage::new(note_off.data, delta));
+        }
+    }
+
+    // relative to the pitch the notes were started with, a length of 0 jumps there
+    pub fn glide_to(&mut self, semitones: f64, samples: usize) {
+        self.gliding = true;
+        self.glide_start = self.bend;
+        self.glide_target = semitones;
+        self.glide_elapsed = 0;
+        self.glide_length = samples;
+        self.last_glide_value = None;
+    }
+
+    // when notes are started
+    pub fn reset_glide(&mut self) {
+        self.bend = 0.;
+        self.gliding = false;
+    }
+
+    // the pitch bend parameter, moved by the glide
+    pub fn pitch_bend(&self, base: u16, bend_range: u8) -> u16 {
+        let offset = self.bend * 8192. / bend_range.max(1) as f64;
+        (base as f64 + offset).round().clamp(0., 0x3FFF as f64) as u16
+    }
+
+    pub fn process(
+        &mut self,
+        samples: usize,
+        channel: u8,
+        base_bend: u16,
+        bend_range: u8,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        self.note_offs.retain_mut(|note_off| {
+            if note_off.remaining < samples {
+                outputs.push(TimedMidiMessage::new(note_off.data, note_off.remaining));
+                false
+            } else {
+                note_off.remaining -= samples;
+                true
+            }
+        });
+
+        if !self.gliding {
+            return;
+        }
+        let mut delta = 0;
+        while delta < samples {
+            let elapsed = self.glide_elapsed + delta;
+            let progress = if elapsed >= self.glide_length {
+                1.
+            } else {
+                elapsed as f64 / self.glide_length as f64
+            };
+            self.bend = self.glide_start + (self.glide_target - self.glide_start) * progress;
+
+            let value = self.pitch_bend(base_bend, bend_range);
+            if self.last_glide_value != Some(value) {
+                outputs.push(TimedMidiMessage::new(
+                    [PITCHBEND + channel, (value & 0x7F) as u8, (value >> 7) as u8],
+                    delta,
+                ));
+                self.last_glide_value = Some(value);
+            }
+            if progress >= 1. {
+                self.gliding = false;
+                return;
+            }
+            delta += GLIDE_INTERVAL;
+        }
+        self.glide_elapsed += samples;
+    }
+}
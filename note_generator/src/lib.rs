@@ -3,73 +3,191 @@ extern crate vst;
 
 use std::sync::Arc;
 
-use vst::api;
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::event::{Event, MidiEvent};
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin};
-
+mod chord;
+mod gate;
+mod input;
+mod legato;
 mod parameters;
+mod randomizer;
+mod sequencer;
 
-use crate::parameters::{NoteGeneratorPluginParameters, Parameter};
-use util::constants::{CC, NOTE_OFF, NOTE_ON, PITCHBEND, PRESSURE, TIMBRECC, ZEROVALUE};
-use util::make_midi_message;
+use crate::chord::MAX_CHORD_NOTES;
+use crate::gate::{Gate, GateMode};
+use crate::input::{InputAction, InputMode, InputTrigger};
+use crate::legato::{Legato, LegatoMode};
+use crate::parameters::{CcParameter, NoteGeneratorPluginParameters, Parameter, PARAMETER_DEFINITIONS};
+use crate::randomizer::Randomizer;
+use crate::sequencer::Sequencer;
+use util::constants::{AFTERTOUCH, CC, NOTE_OFF, NOTE_ON, PITCHBEND, PRESSURE, PROGRAM_CHANGE, TIMBRECC};
+use vst::api::Supported;
+use vst::plugin::CanDo;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
 use util::parameter_value_conversion::f32_to_bool;
 use util::parameters::ParameterConversion;
+use util::vst_wrapper::VstWrapper;
 
-plugin_main!(NoteGeneratorPlugin);
+plugin_main!(VstWrapper<NoteGeneratorPlugin>);
+util::clap_main!(NoteGeneratorPlugin);
+util::lv2_main!(NoteGeneratorPlugin);
 
-#[derive(Default)]
 pub struct NoteGeneratorPlugin {
-    events: Vec<MidiEvent>,
-    send_buffer: SendEventBuffer,
-    parameters: Arc<parameters::NoteGeneratorPluginParameters>,
+    parameters: Arc<NoteGeneratorPluginParameters>,
+    // channel and pitch of the notes started by the trigger, so the same ones are stopped even if
+    // the pitch, channel or chord changed in between
+    started_notes: Vec<(u8, u8)>,
+    // the pitch parameter when they were started, glides are relative to it
+    started_pitch: u8,
+    legato: Legato,
+    gate: Gate,
+    input: InputTrigger,
+    sequencer: Sequencer,
+    randomizer: Randomizer,
+    sample_rate: f32,
+    // of the last block, synced gates use 120 bpm until the host gives one
+    tempo: f64,
+    // the transport, the randomizer is seeded again when it starts
+    playing: bool,
+    // false until the first block, the values set before are restored by the host
+    // and not sent
+    running: bool,
+}
+
+impl Default for NoteGeneratorPlugin {
+    fn default() -> Self {
+        NoteGeneratorPlugin {
+            parameters: Default::default(),
+            started_notes: Vec::with_capacity(MAX_CHORD_NOTES),
+            started_pitch: 0,
+            legato: Default::default(),
+            gate: Default::default(),
+            input: Default::default(),
+            sequencer: Default::default(),
+            randomizer: Default::default(),
+            sample_rate: 44100.,
+            tempo: 120.,
+            playing: false,
+            running: false,
+        }
+    }
 }
 
 impl NoteGeneratorPlugin {
-    fn get_midi_channel_event(
-        &self,
-        event_type: u8,
-        channel_parameter: Parameter,
-        pitch_parameter: Parameter,
-        velocity_parameter: Parameter,
-        delta: i32,
-    ) -> MidiEvent {
-        make_midi_message(
-            [
-                event_type + self.parameters.get_byte_parameter(channel_parameter) / 8,
-                self.parameters.get_byte_parameter(pitch_parameter),
-                self.parameters.get_byte_parameter(velocity_parameter),
-            ],
-            delta,
-        )
+    // stops the notes still playing, then starts the new ones with their expressions
+    fn trigger_notes(&mut self, delta: usize, samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        // the notes of a gate ending earlier in the block stop there
+        let stop_delta = self.gate.end_before(delta).unwrap_or(delta);
+        self.stop_notes(stop_delta, outputs);
+        self.start_notes(delta, outputs);
+
+        // delay those expressions with delta frames, seem to do the trick
+        // even though bitwig always inserts zero values for those before the
+        // note, so it always need to be sent right after to obtain the
+        // desired state. A note at the end of the block gets them at the same time
+        let expression_delta = if delta + 1 < samples { delta + 1 } else { delta };
+        outputs.push(self.get_current_pitchbend(expression_delta));
+        outputs.push(self.get_current_timber(expression_delta));
+        outputs.push(self.get_current_pressure(expression_delta));
+        self.push_poly_aftertouch(expression_delta, outputs);
     }
 
-    fn get_current_note_on(&self, delta: i32) -> MidiEvent {
-        self.get_midi_channel_event(
-            NOTE_ON,
-            Parameter::Channel,
-            Parameter::Pitch,
-            Parameter::Velocity,
-            delta,
-        )
+    // the notes of a gate are stopped by it
+    fn release_notes(&mut self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        if !self.gate.is_open() {
+            self.stop_notes(delta, outputs);
+        }
     }
 
-    fn get_current_note_off(&self, delta: i32) -> MidiEvent {
-        self.get_midi_channel_event(
-            NOTE_OFF,
-            Parameter::TriggeredChannel,
-            Parameter::TriggeredPitch,
-            Parameter::NoteOffVelocity,
-            delta,
-        )
+    fn start_notes(&mut self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        self.legato.reset_glide();
+        self.start_chord(delta, outputs);
+        // the gate counts from the start of the block
+        match self.parameters.get_gate() {
+            GateMode::Off => self.gate.close(),
+            GateMode::Time => {
+                let samples = (self.parameters.get_gate_time() * self.sample_rate) as usize;
+                self.gate.open(delta + samples.max(1))
+            }
+            GateMode::Synced => {
+                let seconds = self.parameters.get_gate_length() * 60. / self.tempo;
+                self.gate.open(delta + ((seconds * self.sample_rate as f64) as usize).max(1))
+            }
+        }
+    }
+
+    fn start_chord(&mut self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let channel = self.parameters.get_byte_parameter(Parameter::Channel) / 8;
+        let velocity = self
+            .randomizer
+            .velocity(&self.parameters, self.parameters.get_byte_parameter(Parameter::Velocity));
+        let pitch = self
+            .randomizer
+            .pitch(&self.parameters, self.parameters.get_pitch());
+        self.started_pitch = pitch;
+        for &pitch in self.parameters.get_chord(pitch).pitches() {
+            // a previous note with the same pitch still overlapping has to stop first
+            if let Some(note_off) = self.legato.take_note_off(channel, pitch) {
+                outputs.push(TimedMidiMessage::new(note_off, delta));
+            }
+            outputs.push(TimedMidiMessage::new([NOTE_ON + channel, pitch, velocity], delta));
+            self.started_notes.push((channel, pitch));
+        }
+    }
+
+    fn get_note_off_velocity(&mut self) -> u8 {
+        self.randomizer
+            .note_off_velocity(&self.parameters, self.parameters.get_byte_parameter(Parameter::NoteOffVelocity))
+    }
+
+    fn stop_notes(&mut self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let velocity = self.get_note_off_velocity();
+        for (channel, pitch) in self.started_notes.drain(..) {
+            outputs.push(TimedMidiMessage::new([NOTE_OFF + channel, pitch, velocity], delta));
+        }
+        self.legato.flush_note_offs(delta, outputs);
+        self.gate.close();
+    }
+
+    // without legato, a new pitch is only played at the next trigger
+    fn change_pitch(&mut self, outputs: &mut Vec<TimedMidiMessage>) {
+        if self.started_notes.is_empty() {
+            return;
+        }
+        match self.parameters.get_legato() {
+            LegatoMode::Off => (),
+            LegatoMode::Rearticulate => {
+                let overlap = (self.parameters.get_legato_overlap() * self.sample_rate) as usize;
+                let velocity = self.get_note_off_velocity();
+                for (channel, pitch) in self.started_notes.drain(..) {
+                    self.legato.delay_note_off([NOTE_OFF + channel, pitch, velocity], overlap);
+                }
+                self.start_chord(0, outputs);
+            }
+            LegatoMode::Glide => {
+                let pitch = self.parameters.get_pitch();
+                let glide_time = (self.parameters.get_glide_time() * self.sample_rate) as usize;
+                self.legato
+                    .glide_to(pitch as f64 - self.started_pitch as f64, glide_time);
+            }
+        }
+    }
+
+    // where pitch bends go: the notes playing, or the channel parameter
+    fn get_playing_channel(&self) -> u8 {
+        match self.started_notes.first() {
+            Some((channel, _)) => *channel,
+            None => self.parameters.get_byte_parameter(Parameter::Channel) / 8,
+        }
     }
 
-    fn get_current_pitchbend(&self, delta: i32) -> MidiEvent {
+    fn get_current_pitchbend(&self, delta: usize) -> TimedMidiMessage {
         let channel = self.parameters.get_byte_parameter(Parameter::Channel) / 8;
-        let pitchbend_value = self.parameters.get_u14_parameter(Parameter::PitchBend);
+        let pitchbend_value = self.legato.pitch_bend(
+            self.parameters.get_u14_parameter(Parameter::PitchBend),
+            self.parameters.get_bend_range(),
+        );
         let msb = pitchbend_value >> 7;
         let lsb = pitchbend_value & 0x7F;
-        make_midi_message(
+        TimedMidiMessage::new(
             [
                 channel + PITCHBEND,
                 lsb as u8,
@@ -79,8 +197,8 @@ impl NoteGeneratorPlugin {
         )
     }
 
-    fn get_current_pressure(&self, delta: i32) -> MidiEvent {
-        make_midi_message(
+    fn get_current_pressure(&self, delta: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new(
             [
                 PRESSURE + self.parameters.get_byte_parameter(Parameter::Channel) / 8,
                 self.parameters.get_byte_parameter(Parameter::Pressure),
@@ -90,86 +208,142 @@ impl NoteGeneratorPlugin {
         )
     }
 
-    fn get_current_timber(&self, delta: i32) -> MidiEvent {
-        make_midi_message(
+    fn get_current_timber(&self, delta: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new(
             [
                 CC + self.parameters.get_byte_parameter(Parameter::Channel) / 8,
                 TIMBRECC,
-                ZEROVALUE,
+                self.parameters.get_byte_parameter(Parameter::Timbre),
             ],
             delta,
         )
     }
 
-    fn send_midi(&mut self) {
-        for (index, value) in self.parameters.transfer.iterate(true) {
+    // to each of the triggered notes
+    fn push_poly_aftertouch(&self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let value = self.parameters.get_byte_parameter(Parameter::PolyAftertouch);
+        for &(channel, pitch) in &self.started_notes {
+            outputs.push(TimedMidiMessage::new([AFTERTOUCH + channel, pitch, value], delta));
+        }
+    }
+
+    // nothing when off
+    fn push_program(&self, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        if let Some(program) = self.parameters.get_program() {
+            let status = PROGRAM_CHANGE + self.parameters.get_byte_parameter(Parameter::Channel) / 8;
+            outputs.push(TimedMidiMessage::new([status, program, 0], delta));
+        }
+    }
+
+    // 14-bit values are sent as the most significant 7 bits on the controller, then the least
+    // significant on the controller + 32, which only exists for the first 32 controllers
+    fn push_cc(&self, slot: usize, delta: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let number = match self.parameters.get_cc_number(slot) {
+            Some(number) => number,
+            None => return,
+        };
+        let status = CC + self.parameters.get_byte_parameter(Parameter::Channel) / 8;
+        let value = self.parameters.get_cc_value(slot);
+        if self.parameters.get_cc_high_resolution(slot) {
+            outputs.push(TimedMidiMessage::new([status, number, (value >> 7) as u8], delta));
+            if number < 32 {
+                outputs.push(TimedMidiMessage::new([status, number + 32, (value & 0x7F) as u8], delta));
+            }
+        } else {
+            outputs.push(TimedMidiMessage::new([status, number, value as u8], delta));
+        }
+    }
+
+    // passes the inputs through, except the ones triggering the notes in input-driven mode
+    fn process_inputs(&mut self, inputs: &[TimedMidiMessage], samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let mode = self.parameters.get_input_mode();
+        if mode == InputMode::Off {
+            outputs.extend_from_slice(inputs);
+            return;
+        }
+        let cc = self.parameters.get_byte_parameter(Parameter::InputCc);
+        let threshold = self.parameters.get_byte_parameter(Parameter::InputThreshold);
+        for input in inputs {
+            match self.input.event(mode, cc, threshold, input.data) {
+                InputAction::Pass => outputs.push(*input),
+                InputAction::Consume => (),
+                InputAction::Start => self.trigger_notes(input.delta_frames, samples, outputs),
+                InputAction::Stop => self.release_notes(input.delta_frames, outputs),
+            }
+        }
+    }
+
+    fn generate_midi(&mut self, samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        // the notes started and stopped need self while the changes are iterated
+        let parameters = Arc::clone(&self.parameters);
+        // a loaded preset or the first values of the host restore the state of the instrument,
+        // they are not changes to send. Pressure and pitch bend are sent anyway, as they always were
+        let restored = parameters.take_state_loaded() || !self.running;
+        self.running = true;
+        for (index, value) in parameters.transfer.iterate(true) {
             match Parameter::from(index as i32) {
                 Parameter::Pressure => {
-                    self.events.push(self.get_current_pressure(0));
+                    outputs.push(self.get_current_pressure(0));
                 }
                 Parameter::PitchBend => {
-                    self.events.push(self.get_current_pitchbend(0));
+                    outputs.push(self.get_current_pitchbend(0));
+                }
+                Parameter::Pitch => {
+                    self.change_pitch(outputs);
+                }
+                Parameter::Timbre if !restored => {
+                    outputs.push(self.get_current_timber(0));
+                }
+                Parameter::PolyAftertouch if !restored => {
+                    self.push_poly_aftertouch(0, outputs);
+                }
+                Parameter::Program if !restored => {
+                    self.push_program(0, outputs);
+                }
+                Parameter::Cc(slot, CcParameter::Value) if !restored => {
+                    self.push_cc(slot, 0, outputs);
                 }
                 Parameter::Trigger => {
+                    // the trigger may have gone off and on again since the last block, or the
+                    // notes of a gate may still play
                     if f32_to_bool(value) {
-                        self.parameters
-                            .copy_parameter(Parameter::Channel, Parameter::TriggeredChannel);
-                        self.parameters
-                            .copy_parameter(Parameter::Pitch, Parameter::TriggeredPitch);
-                        self.events.push(self.get_current_note_on(0));
-
-                        // delay those expressions with delta frames, seem to do the trick
-                        // even though bitwig always inserts zero values for those before the
-                        // note, so it always need to be sent right after to obtain the
-                        // desired state
-                        self.events.push(self.get_current_pitchbend(1));
-                        self.events.push(self.get_current_timber(1));
-                        self.events.push(self.get_current_pressure(1));
+                        self.trigger_notes(0, samples, outputs);
                     } else {
-                        self.events.push(self.get_current_note_off(0));
+                        self.release_notes(0, outputs);
                     }
                 }
+                Parameter::InputTrigger | Parameter::InputCc => {
+                    self.input.reset();
+                }
+                Parameter::RandomSeed => {
+                    self.randomizer.reseed(self.parameters.get_byte_parameter(Parameter::RandomSeed));
+                }
                 _ => (),
             }
         }
-
-        if let Ok(mut host_callback_lock) = self.parameters.host.lock() {
-            self.send_buffer
-                .send_events(&self.events, &mut host_callback_lock.host);
-        }
-        self.events.clear();
     }
 }
 
-impl Plugin for NoteGeneratorPlugin {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Note Generator".to_string(),
-            vendor: "DJ Crontab".to_string(),
+impl MidiProcessor for NoteGeneratorPlugin {
+    type Parameters = NoteGeneratorPluginParameters;
+
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Note Generator",
+            id: "note_generator",
+            vendor: "DJ Crontab",
             unique_id: 234213172,
-            parameters: 6,
-            category: Category::Effect,
-            initial_delay: 0,
-            version: 7,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
-        }
-    }
-
-    fn new(host: HostCallback) -> Self {
-        NoteGeneratorPlugin {
-            parameters: Arc::new(NoteGeneratorPluginParameters::new(host)),
-            ..Default::default()
+            version: 15,
+            parameters: 190,
+            parameter_definitions: &PARAMETER_DEFINITIONS,
         }
     }
 
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
+    fn parameters(&self) -> &Arc<NoteGeneratorPluginParameters> {
+        &self.parameters
+    }
+
+    fn can_do(can_do: CanDo) -> Supported {
         use vst::api::Supported::*;
         use vst::plugin::CanDo::*;
 
@@ -179,19 +353,448 @@ impl Plugin for NoteGeneratorPlugin {
         }
     }
 
-    fn process(&mut self, _: &mut AudioBuffer<f32>) {
-        self.send_midi();
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
     }
 
-    fn process_events(&mut self, events: &api::Events) {
-        for e in events.events() {
-            if let Event::Midi(e) = e {
-                self.events.push(e);
-            }
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        let first_output = outputs.len();
+        if let Some(tempo) = transport.tempo.filter(|&tempo| tempo > 0.) {
+            self.tempo = tempo;
+        }
+        if transport.playing && !self.playing {
+            self.randomizer.reseed(self.parameters.get_byte_parameter(Parameter::RandomSeed));
         }
+        self.playing = transport.playing;
+        self.generate_midi(samples, outputs);
+        self.process_inputs(inputs, samples, outputs);
+        self.legato.process(
+            samples,
+            self.get_playing_channel(),
+            self.parameters.get_u14_parameter(Parameter::PitchBend),
+            self.parameters.get_bend_range(),
+            outputs,
+        );
+        // after the legato, so the note offs it still delays are the ones ending later
+        if let Some(delta) = self.gate.process(samples) {
+            self.stop_notes(delta, outputs);
+        }
+        self.sequencer.process(
+            &self.parameters,
+            &mut self.randomizer,
+            samples,
+            self.sample_rate,
+            transport,
+            outputs,
+        );
+        // sequenced notes are anywhere in the block, the sort is stable so note offs stay before
+        // note ons at the same time
+        outputs[first_output..].sort_by_key(|message| message.delta_frames);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::StepParameter;
+    use vst::plugin::PluginParameters;
+
+    fn process(plugin: &mut NoteGeneratorPlugin) -> Vec<TimedMidiMessage> {
+        process_samples(plugin, 64)
+    }
+
+    fn process_samples(plugin: &mut NoteGeneratorPlugin, samples: usize) -> Vec<TimedMidiMessage> {
+        process_with(plugin, &[], samples, &TransportInfo::default())
+    }
+
+    fn process_with(
+        plugin: &mut NoteGeneratorPlugin,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        transport: &TransportInfo,
+    ) -> Vec<TimedMidiMessage> {
+        let mut outputs = vec![];
+        plugin.process(inputs, samples, transport, &mut outputs);
+        outputs
+    }
+
+    fn set(plugin: &NoteGeneratorPlugin, parameter: Parameter, text: &str) {
+        assert!(plugin.parameters.string_to_parameter(parameter.into(), text.to_string()));
+    }
+
+    fn trigger(plugin: &mut NoteGeneratorPlugin, on: bool) -> Vec<TimedMidiMessage> {
+        plugin.parameters.set_parameter(Parameter::Trigger.into(), if on { 1. } else { 0. });
+        process(plugin)
+    }
+
+    // note ons and offs with their delta frames
+    fn timed_notes(outputs: &[TimedMidiMessage]) -> Vec<TimedMidiMessage> {
+        outputs
+            .iter()
+            .filter(|output| output.data[0] & 0xE0 == NOTE_OFF)
+            .copied()
+            .collect()
+    }
+
+    fn pitches(outputs: &[TimedMidiMessage], status: u8) -> Vec<u8> {
+        notes(outputs)
+            .iter()
+            .filter(|note| note[0] & 0xF0 == status)
+            .map(|note| note[1])
+            .collect()
+    }
+
+    // program changes and CCs
+    fn values(outputs: &[TimedMidiMessage]) -> Vec<[u8; 3]> {
+        outputs
+            .iter()
+            .filter(|output| output.data[0] & 0xF0 == PROGRAM_CHANGE || output.data[0] & 0xF0 == CC)
+            .map(|output| output.data)
+            .collect()
+    }
+
+    fn notes(outputs: &[TimedMidiMessage]) -> Vec<[u8; 3]> {
+        outputs
+            .iter()
+            .filter(|output| output.data[0] & 0xE0 == NOTE_OFF)
+            .map(|output| output.data)
+            .collect()
+    }
+
+    #[test]
+    fn trigger_starts_and_stops_the_note() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        let pitch = plugin.parameters.get_pitch();
+        let velocity = plugin.parameters.get_byte_parameter(Parameter::Velocity);
+        let note_off_velocity = plugin.parameters.get_byte_parameter(Parameter::NoteOffVelocity);
+
+        plugin.parameters.set_parameter(Parameter::Trigger.into(), 1.);
+        assert_eq!(notes(&process(&mut plugin)), vec![[NOTE_ON, pitch, velocity]]);
+        assert!(notes(&process(&mut plugin)).is_empty());
+
+        plugin.parameters.set_parameter(Parameter::Trigger.into(), 0.);
+        assert_eq!(notes(&process(&mut plugin)), vec![[NOTE_OFF, pitch, note_off_velocity]]);
+    }
+
+    #[test]
+    fn program_and_ccs_are_off_by_default() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_parameter(Parameter::Program.into(), 0.);
+        plugin.parameters.set_parameter(Parameter::Cc(0, CcParameter::Value).into(), 1.);
+        assert!(values(&process(&mut plugin)).is_empty());
+
+        assert!(plugin.parameters.string_to_parameter(Parameter::Program.into(), "5".to_string()));
+        assert!(plugin.parameters.string_to_parameter(Parameter::Cc(0, CcParameter::Number).into(), "16".to_string()));
+        plugin.parameters.set_parameter(Parameter::Cc(0, CcParameter::Value).into(), 0.);
+        assert_eq!(values(&process(&mut plugin)), vec![[PROGRAM_CHANGE, 5, 0], [CC, 16, 0]]);
+        assert_eq!(plugin.parameters.get_parameter_text(Parameter::Program.into()), "5");
+    }
+
+    #[test]
+    fn loaded_values_are_not_sent() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        plugin.parameters.string_to_parameter(Parameter::Program.into(), "5".to_string());
+        plugin.parameters.string_to_parameter(Parameter::Cc(0, CcParameter::Number).into(), "16".to_string());
+        plugin.parameters.string_to_parameter(Parameter::Cc(0, CcParameter::Value).into(), "100".to_string());
+        // restored before the first block, as LV2 controls are
+        assert!(values(&process(&mut plugin)).is_empty());
+
+        let preset = plugin.parameters.get_preset_data();
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.load_preset_data(&preset);
+        assert!(values(&process(&mut plugin)).is_empty());
+
+        plugin.parameters.string_to_parameter(Parameter::Cc(0, CcParameter::Value).into(), "101".to_string());
+        assert_eq!(values(&process(&mut plugin)), vec![[CC, 16, 101]]);
+    }
+
+    #[test]
+    fn high_resolution_cc_values_are_saved() {
+        let parameters = NoteGeneratorPluginParameters::default();
+        parameters.set_bool_parameter(Parameter::Cc(2, CcParameter::HighResolution), true);
+        for value in [0, 1, 0x1234, 0x3F80, 0x3FFF] {
+            parameters.string_to_parameter(Parameter::Cc(2, CcParameter::Value).into(), value.to_string());
+            let loaded = NoteGeneratorPluginParameters::default();
+            loaded.load_preset_data(&parameters.get_preset_data());
+            assert_eq!(loaded.get_cc_value(2), value);
+        }
+    }
+
+    #[test]
+    fn expressions_stay_in_the_block() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_parameter(Parameter::Trigger.into(), 1.);
+        let outputs = process_samples(&mut plugin, 1);
+        assert_eq!(notes(&outputs).len(), 1);
+        assert!(outputs.len() > 1);
+        assert!(outputs.iter().all(|output| output.delta_frames == 0));
+    }
+
+    #[test]
+    fn parameter_indices_round_trip() {
+        assert_eq!(PARAMETER_DEFINITIONS.len() as i32, NoteGeneratorPlugin::info().parameters);
+        for index in 0..PARAMETER_DEFINITIONS.len() as i32 {
+            let index_back: i32 = Parameter::from(index).into();
+            assert_eq!(index_back, index);
+        }
+        let last: i32 = Parameter::ScalaFile.into();
+        assert_eq!(last as usize, PARAMETER_DEFINITIONS.len() - 1);
+    }
+
+    #[test]
+    fn defaults_are_not_changes() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        assert!(plugin.parameters.transfer.iterate(false).next().is_none());
+        assert!(process(&mut plugin).is_empty());
+    }
+
+    #[test]
+    fn chords_follow_their_type_and_inversion() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 60);
+        set(&plugin, Parameter::ChordType, "Major");
+        set(&plugin, Parameter::ChordInversion, "1");
+        assert_eq!(pitches(&trigger(&mut plugin, true), NOTE_ON), vec![64, 67, 72]);
+
+        // the notes started are stopped, whatever the chord is now
+        set(&plugin, Parameter::ChordType, "Minor 7th");
+        set(&plugin, Parameter::ChordInversion, "0");
+        assert_eq!(pitches(&trigger(&mut plugin, false), NOTE_OFF), vec![64, 67, 72]);
+        assert_eq!(pitches(&trigger(&mut plugin, true), NOTE_ON), vec![60, 63, 67, 70]);
+        trigger(&mut plugin, false);
+
+        set(&plugin, Parameter::ChordType, "Major");
+        set(&plugin, Parameter::ChordSpread, "1");
+        // the third spread an octave up
+        assert_eq!(pitches(&trigger(&mut plugin, true), NOTE_ON), vec![60, 67, 76]);
+    }
+
+    #[test]
+    fn steps_follow_the_tempo() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        // a quarter note every 24000 samples, a 1/16 step every 6000
+        plugin.set_sample_rate(48000.);
+        set(&plugin, Parameter::Sequencer, "on");
+        set(&plugin, Parameter::SequencerSteps, "2");
+        set(&plugin, Parameter::SequencerRate, "1/16");
+        set(&plugin, Parameter::Step(0, StepParameter::Pitch), "C3");
+        set(&plugin, Parameter::Step(1, StepParameter::Pitch), "D3");
+        set(&plugin, Parameter::Step(1, StepParameter::Gate), "25%");
+        let c = plugin.parameters.get_step_pitch(0);
+        let d = plugin.parameters.get_step_pitch(1);
+        let mut transport = TransportInfo {
+            playing: true,
+            tempo: Some(120.),
+            position: Some(0.),
+            ..Default::default()
+        };
+
+        let on = |pitch, delta_frames| TimedMidiMessage::new([NOTE_ON, pitch, 64], delta_frames);
+        let off = |pitch, delta_frames| TimedMidiMessage::new([NOTE_OFF, pitch, 0], delta_frames);
+        assert_eq!(
+            timed_notes(&process_with(&mut plugin, &[], 8000, &transport)),
+            vec![on(c, 0), off(c, 3000), on(d, 6000), off(d, 7500)]
+        );
+        transport.position = Some(8000. / 24000.);
+        assert_eq!(
+            timed_notes(&process_with(&mut plugin, &[], 8000, &transport)),
+            vec![on(c, 4000), off(c, 7000)]
+        );
+
+        // twice as fast, from where the transport is
+        transport.tempo = Some(240.);
+        transport.position = Some(16000. / 24000.);
+        assert_eq!(
+            timed_notes(&process_with(&mut plugin, &[], 6000, &transport)),
+            vec![on(d, 1000), off(d, 1750), on(c, 4000), off(c, 5500)]
+        );
+    }
+
+    #[test]
+    fn rearticulated_notes_overlap() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 60);
+        set(&plugin, Parameter::Legato, "Re-articulate");
+        set(&plugin, Parameter::LegatoOverlap, "10ms");
+        let overlap = (plugin.parameters.get_legato_overlap() * plugin.sample_rate) as usize;
+        assert!(overlap > 0 && overlap < 1024);
+        trigger(&mut plugin, true);
+
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 62);
+        assert_eq!(
+            timed_notes(&process_samples(&mut plugin, 1024)),
+            vec![
+                TimedMidiMessage::new([NOTE_ON, 62, 64], 0),
+                TimedMidiMessage::new([NOTE_OFF, 60, 0], overlap)
+            ]
+        );
+        assert_eq!(pitches(&trigger(&mut plugin, false), NOTE_OFF), vec![62]);
+    }
+
+    #[test]
+    fn glides_bend_to_the_new_pitch() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 60);
+        plugin.parameters.set_u14_parameter(Parameter::PitchBend, 0x2000);
+        set(&plugin, Parameter::Legato, "Glide");
+        set(&plugin, Parameter::GlideTime, "50ms");
+        set(&plugin, Parameter::BendRange, "12");
+        trigger(&mut plugin, true);
+
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 62);
+        let outputs: Vec<TimedMidiMessage> = (0..4).flat_map(|_| process_samples(&mut plugin, 1024)).collect();
+        // the note keeps playing
+        assert!(notes(&outputs).is_empty());
+        let bends: Vec<u16> = outputs
+            .iter()
+            .filter(|output| output.data[0] == PITCHBEND)
+            .map(|output| (output.data[2] as u16) << 7 | output.data[1] as u16)
+            .collect();
+        assert!(bends.len() > 2);
+        assert!(bends.windows(2).all(|pair| pair[0] < pair[1]));
+        // 2 semitones of 12
+        assert_eq!(bends.last(), Some(&(0x2000 + 1365)));
+    }
+
+    fn input_note_on(pitch: u8, delta_frames: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new([NOTE_ON, pitch, 100], delta_frames)
+    }
+
+    fn input_note_off(pitch: u8, delta_frames: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new([NOTE_OFF, pitch, 0], delta_frames)
+    }
+
+    #[test]
+    fn gates_stop_the_notes_after_their_length() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        let pitch = plugin.parameters.get_pitch();
+        set(&plugin, Parameter::Gate, "Time");
+        set(&plugin, Parameter::GateTime, "10ms");
+        let gate = (plugin.parameters.get_gate_time() * plugin.sample_rate) as usize;
+        assert!(gate > 0 && gate < 1024);
+
+        plugin.parameters.set_parameter(Parameter::Trigger.into(), 1.);
+        assert_eq!(
+            timed_notes(&process_samples(&mut plugin, 1024)),
+            vec![TimedMidiMessage::new([NOTE_ON, pitch, 64], 0), TimedMidiMessage::new([NOTE_OFF, pitch, 0], gate)]
+        );
+        // the trigger going off does not stop them again
+        assert!(notes(&trigger(&mut plugin, false)).is_empty());
+    }
+
+    #[test]
+    fn gates_ending_before_a_retrigger_stop_the_notes_at_their_end() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        let pitch = plugin.parameters.get_pitch();
+        set(&plugin, Parameter::InputTrigger, "Notes");
+        set(&plugin, Parameter::Gate, "Time");
+        set(&plugin, Parameter::GateTime, "10ms");
+        let gate = (plugin.parameters.get_gate_time() * plugin.sample_rate) as usize;
+        assert!(gate > 256 && gate < 500);
+
+        let transport = TransportInfo::default();
+        let outputs = process_with(&mut plugin, &[input_note_on(60, 0)], 256, &transport);
+        assert_eq!(timed_notes(&outputs), vec![TimedMidiMessage::new([NOTE_ON, pitch, 64], 0)]);
+
+        // the gate ends in the block the notes are triggered again
+        let outputs = process_with(&mut plugin, &[input_note_on(62, 500)], 1024, &transport);
+        assert_eq!(
+            timed_notes(&outputs),
+            vec![
+                TimedMidiMessage::new([NOTE_OFF, pitch, 0], gate - 256),
+                TimedMidiMessage::new([NOTE_ON, pitch, 64], 500),
+                TimedMidiMessage::new([NOTE_OFF, pitch, 0], 500 + gate)
+            ]
+        );
+    }
+
+    #[test]
+    fn input_notes_play_until_the_last_one_is_released() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        let pitch = plugin.parameters.get_pitch();
+        set(&plugin, Parameter::InputTrigger, "Notes");
+        let on = |delta_frames| TimedMidiMessage::new([NOTE_ON, pitch, 64], delta_frames);
+        let off = |delta_frames| TimedMidiMessage::new([NOTE_OFF, pitch, 0], delta_frames);
+
+        let sustain = TimedMidiMessage::new([CC, 64, 127], 5);
+        let inputs = [
+            input_note_on(60, 0),
+            sustain,
+            input_note_on(64, 10),
+            input_note_off(60, 20),
+            input_note_off(64, 30),
+            // started before the mode was selected
+            input_note_off(67, 40),
+        ];
+        let outputs = process_with(&mut plugin, &inputs, 64, &TransportInfo::default());
+        assert_eq!(timed_notes(&outputs), vec![on(0), off(10), on(10), off(30), input_note_off(67, 40)]);
+        assert!(outputs.contains(&sustain));
+    }
+
+    #[test]
+    fn input_notes_beyond_the_held_ones_still_trigger() {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        set(&plugin, Parameter::InputTrigger, "Notes");
+        let mut inputs: Vec<TimedMidiMessage> = (0..128).map(|pitch| input_note_on(pitch, pitch as usize)).collect();
+        inputs.push(TimedMidiMessage::new([NOTE_ON + 1, 60, 100], 200));
+        let outputs = process_with(&mut plugin, &inputs, 256, &TransportInfo::default());
+        assert_eq!(pitches(&outputs, NOTE_ON).len(), 129);
+        assert_eq!(timed_notes(&outputs).last().map(|note| (note.data[0], note.delta_frames)), Some((NOTE_ON, 200)));
+    }
+
+    // the pitch and velocity of the notes of each trigger
+    fn randomized_notes(seed: &str) -> Vec<(u8, u8)> {
+        let mut plugin = NoteGeneratorPlugin::default();
+        process(&mut plugin);
+        plugin.parameters.set_byte_parameter(Parameter::Pitch, 60);
+        set(&plugin, Parameter::RandomSeed, seed);
+        set(&plugin, Parameter::RandomAmount, "100%");
+        set(&plugin, Parameter::RandomVelocity, "on");
+        set(&plugin, Parameter::RandomPitchRange, "12");
+        (0..50)
+            .map(|_| {
+                let note = notes(&trigger(&mut plugin, true))[0];
+                trigger(&mut plugin, false);
+                (note[1], note[2])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_notes_follow_the_seed() {
+        let notes = randomized_notes("5");
+        assert_eq!(randomized_notes("5"), notes);
+        assert_ne!(randomized_notes("6"), notes);
+        assert!(notes.iter().all(|&(pitch, velocity)| (48..=72).contains(&pitch) && velocity >= 1));
+        assert!(notes.iter().any(|&(pitch, _)| pitch != 60));
+        assert!(notes.iter().any(|&(_, velocity)| velocity != 64));
     }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn vst::plugin::PluginParameters> {
-        Arc::clone(&self.parameters) as Arc<dyn vst::plugin::PluginParameters>
+    #[test]
+    fn random_pitch_range_is_two_octaves_at_most() {
+        let plugin = NoteGeneratorPlugin::default();
+        let index: i32 = Parameter::RandomPitchRange.into();
+        assert!(!plugin.parameters.string_to_parameter(index, "25".to_string()));
+        set(&plugin, Parameter::RandomPitchRange, "24");
+        assert_eq!(plugin.parameters.get_parameter_text(index), "+/-24 semitones");
+        plugin.parameters.set_parameter(index, 1.);
+        assert_eq!(plugin.parameters.get_random_pitch_range(), 24);
     }
 }
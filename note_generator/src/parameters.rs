@@ -1,27 +1,291 @@
-use std::sync::Mutex;
-use util::constants::{C0, NOTE_NAMES};
+use crate::chord::{Chord, ChordType, CHORD_TYPE_NAMES};
+use crate::gate::{GateMode, GATE_MODE_NAMES};
+use crate::input::{InputMode, INPUT_MODE_NAMES};
+use crate::legato::{LegatoMode, LEGATO_MODE_NAMES};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use util::constants::{C0, NOTE_NAMES, ZEROVALUE};
 use util::parameter_value_conversion::{f32_to_bool, f32_to_byte, f32_to_u14};
-use util::HostCallbackLock;
-use vst::plugin::{HostCallback, PluginParameters};
+use util::messages::note_name;
+use vst::plugin::PluginParameters;
 use vst::util::ParameterTransfer;
+use util::parameter_definition::{ParameterDefinition, ParameterScale};
 use util::parameters::ParameterConversion;
+use util::scale::{ScalaScale, Scale, ScaleType, CHROMATIC_MASK, SCALE_TYPE_NAMES};
+use util::duration_display;
+
+pub const STEP_COUNT: usize = 32;
+const STEP_PARAMETER_COUNT: usize = 4;
+const FIRST_STEP_PARAMETER: usize = 17;
+// parameters added after the steps are appended, so presets saved before still load
+const FIRST_APPENDED_PARAMETER: usize = FIRST_STEP_PARAMETER + STEP_COUNT * STEP_PARAMETER_COUNT;
+const APPENDED_PARAMETER_COUNT: usize = 7;
+pub const CC_SLOT_COUNT: usize = 4;
+const CC_PARAMETER_COUNT: usize = 3;
+const FIRST_CC_PARAMETER: usize = FIRST_APPENDED_PARAMETER + APPENDED_PARAMETER_COUNT;
+// parameters added after the CC slots
+const FIRST_LATE_PARAMETER: usize = FIRST_CC_PARAMETER + CC_SLOT_COUNT * CC_PARAMETER_COUNT;
+// after the 13 parameters from the gate to the scale key
+const FIRST_CUSTOM_SCALE_PARAMETER: usize = FIRST_LATE_PARAMETER + 13;
+const SCALA_FILE_PARAMETER: usize = FIRST_CUSTOM_SCALE_PARAMETER + 12;
+const LATE_PARAMETER_COUNT: usize = 26;
+pub const PARAMETER_COUNT: usize = FIRST_LATE_PARAMETER + LATE_PARAMETER_COUNT;
+
+// the presets end with the 14-bit value of each CC slot, as its parameter only keeps 7 bits
+const CC_VALUE_SIZE: usize = 2;
+
+// presets written before chords existed ended with the pitch and channel of the triggered note,
+// they are only loaded up to the trigger
+const TRIGGERED_NOTE_PRESET_SIZE: usize = 9;
+
+pub const PARAMETER_DEFINITIONS: [ParameterDefinition; PARAMETER_COUNT] = parameter_definitions();
+
+const GLOBAL_PARAMETER_DEFINITIONS: [ParameterDefinition; FIRST_STEP_PARAMETER] = [
+    ParameterDefinition::new("Channel", ParameterScale::Byte { divisor: 8 }),
+    ParameterDefinition::new("Pitch", ParameterScale::Byte { divisor: 1 }).with_default(C0 as f32 / 127.),
+    ParameterDefinition::new("Velocity", ParameterScale::Byte { divisor: 1 }).with_default(64. / 127.),
+    ParameterDefinition::new("Note off velocity", ParameterScale::Byte { divisor: 1 }),
+    ParameterDefinition::new("Pressure", ParameterScale::Byte { divisor: 1 }),
+    ParameterDefinition::new("Pitch Bend", ParameterScale::U14),
+    ParameterDefinition::new("Trigger generated note", ParameterScale::Bool),
+    ParameterDefinition::new("Chord type", ParameterScale::Byte { divisor: CHORD_TYPE_DIVISOR }),
+    ParameterDefinition::new("Chord inversion", ParameterScale::Byte { divisor: INVERSION_DIVISOR }),
+    ParameterDefinition::new("Chord spread", ParameterScale::Byte { divisor: SPREAD_DIVISOR }),
+    ParameterDefinition::new("Custom interval 1", ParameterScale::Byte { divisor: INTERVAL_DIVISOR }),
+    ParameterDefinition::new("Custom interval 2", ParameterScale::Byte { divisor: INTERVAL_DIVISOR }),
+    ParameterDefinition::new("Custom interval 3", ParameterScale::Byte { divisor: INTERVAL_DIVISOR }),
+    ParameterDefinition::new("Custom interval 4", ParameterScale::Byte { divisor: INTERVAL_DIVISOR }),
+    ParameterDefinition::new("Sequencer", ParameterScale::Bool),
+    ParameterDefinition::new("Sequencer steps", ParameterScale::Byte { divisor: STEPS_DIVISOR })
+        .with_default((STEP_COUNT - 1) as f32 * STEPS_DIVISOR as f32 / 127.),
+    ParameterDefinition::new("Sequencer rate", ParameterScale::Byte { divisor: RATE_DIVISOR })
+        .with_default(DEFAULT_RATE as f32 * RATE_DIVISOR as f32 / 127.),
+];
+
+const APPENDED_PARAMETER_DEFINITIONS: [ParameterDefinition; APPENDED_PARAMETER_COUNT] = [
+    ParameterDefinition::new("Legato", ParameterScale::Byte { divisor: LEGATO_DIVISOR }),
+    ParameterDefinition::new(
+        "Legato overlap",
+        ParameterScale::Exponential { max: MAX_OVERLAP, factor: DURATION_FACTOR },
+    ),
+    ParameterDefinition::new("Glide time", ParameterScale::Exponential { max: MAX_GLIDE_TIME, factor: DURATION_FACTOR }),
+    ParameterDefinition::new("Bend range", ParameterScale::Byte { divisor: 1 }).with_default(48. / 127.),
+    ParameterDefinition::new("Timbre", ParameterScale::Byte { divisor: 1 }).with_default(ZEROVALUE as f32 / 127.),
+    ParameterDefinition::new("Poly aftertouch", ParameterScale::Byte { divisor: 1 }),
+    ParameterDefinition::new("Program", ParameterScale::Byte { divisor: 1 }),
+];
+
+const LATE_PARAMETER_DEFINITIONS: [ParameterDefinition; LATE_PARAMETER_COUNT] = [
+    ParameterDefinition::new("Gate", ParameterScale::Byte { divisor: GATE_MODE_DIVISOR }),
+    ParameterDefinition::new("Gate time", ParameterScale::Exponential { max: MAX_GATE_TIME, factor: DURATION_FACTOR })
+        .with_default(DEFAULT_GATE_TIME),
+    ParameterDefinition::new("Gate rate", ParameterScale::Byte { divisor: RATE_DIVISOR })
+        .with_default(DEFAULT_RATE as f32 * RATE_DIVISOR as f32 / 127.),
+    ParameterDefinition::new("Input trigger", ParameterScale::Byte { divisor: INPUT_MODE_DIVISOR }),
+    ParameterDefinition::new("Input CC", ParameterScale::Byte { divisor: 1 }).with_default(DEFAULT_INPUT_CC as f32 / 127.),
+    ParameterDefinition::new("Input threshold", ParameterScale::Byte { divisor: 1 }).with_default(64. / 127.),
+    ParameterDefinition::new("Random amount", ParameterScale::Normalized),
+    ParameterDefinition::new("Random velocity", ParameterScale::Bool),
+    ParameterDefinition::new("Random pitch range", ParameterScale::Byte { divisor: RANDOM_PITCH_RANGE_DIVISOR }),
+    ParameterDefinition::new("Random note off velocity", ParameterScale::Bool),
+    ParameterDefinition::new("Random seed", ParameterScale::Byte { divisor: 1 }),
+    ParameterDefinition::new("Scale", ParameterScale::Byte { divisor: SCALE_TYPE_DIVISOR }),
+    ParameterDefinition::new("Scale key", ParameterScale::Byte { divisor: SCALE_KEY_DIVISOR }),
+    ParameterDefinition::new("Custom scale +0", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +1", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +2", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +3", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +4", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +5", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +6", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +7", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +8", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +9", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +10", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Custom scale +11", ParameterScale::Bool).with_default(1.0),
+    ParameterDefinition::new("Scala file", ParameterScale::Byte { divisor: 1 }),
+];
+
+const CC_NAMES: [[&str; CC_PARAMETER_COUNT]; CC_SLOT_COUNT] = [
+    ["CC 1 number", "CC 1 value", "CC 1 14-bit"],
+    ["CC 2 number", "CC 2 value", "CC 2 14-bit"],
+    ["CC 3 number", "CC 3 value", "CC 3 14-bit"],
+    ["CC 4 number", "CC 4 value", "CC 4 14-bit"],
+];
+
+// sustain pedal
+const DEFAULT_INPUT_CC: u8 = 64;
+
+macro_rules! step_names {
+    ($($step:literal)*) => {
+        [$([
+            concat!("Step ", $step, " pitch"),
+            concat!("Step ", $step, " velocity"),
+            concat!("Step ", $step, " gate"),
+            concat!("Step ", $step, " on"),
+        ]),*]
+    };
+}
+
+const STEP_NAMES: [[&str; STEP_PARAMETER_COUNT]; STEP_COUNT] = step_names!(
+    1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+);
+
+const fn parameter_definitions() -> [ParameterDefinition; PARAMETER_COUNT] {
+    let mut definitions = [ParameterDefinition::new("", ParameterScale::Normalized); PARAMETER_COUNT];
+    let mut index = 0;
+    while index < FIRST_STEP_PARAMETER {
+        definitions[index] = GLOBAL_PARAMETER_DEFINITIONS[index];
+        index += 1;
+    }
+    let mut step = 0;
+    while step < STEP_COUNT {
+        let first = FIRST_STEP_PARAMETER + step * STEP_PARAMETER_COUNT;
+        let names = STEP_NAMES[step];
+        definitions[first] = ParameterDefinition::new(names[0], ParameterScale::Byte { divisor: 1 })
+            .with_default(C0 as f32 / 127.);
+        definitions[first + 1] =
+            ParameterDefinition::new(names[1], ParameterScale::Byte { divisor: 1 }).with_default(64. / 127.);
+        definitions[first + 2] = ParameterDefinition::new(names[2], ParameterScale::Normalized).with_default(0.5);
+        definitions[first + 3] = ParameterDefinition::new(names[3], ParameterScale::Bool).with_default(1.0);
+        step += 1;
+    }
+    let mut index = 0;
+    while index < APPENDED_PARAMETER_COUNT {
+        definitions[FIRST_APPENDED_PARAMETER + index] = APPENDED_PARAMETER_DEFINITIONS[index];
+        index += 1;
+    }
+    let mut slot = 0;
+    while slot < CC_SLOT_COUNT {
+        let first = FIRST_CC_PARAMETER + slot * CC_PARAMETER_COUNT;
+        let names = CC_NAMES[slot];
+        definitions[first] = ParameterDefinition::new(names[0], ParameterScale::Byte { divisor: 1 });
+        definitions[first + 1] = ParameterDefinition::new(names[1], ParameterScale::U14);
+        definitions[first + 2] = ParameterDefinition::new(names[2], ParameterScale::Bool);
+        slot += 1;
+    }
+    let mut index = 0;
+    while index < LATE_PARAMETER_COUNT {
+        definitions[FIRST_LATE_PARAMETER + index] = LATE_PARAMETER_DEFINITIONS[index];
+        index += 1;
+    }
+    definitions
+}
+
+// step lengths of the sequencer and synced gates, in quarter notes
+pub const RATES: [(&str, f64); 8] = [
+    ("1/1", 4.),
+    ("1/2", 2.),
+    ("1/4", 1.),
+    ("1/8", 0.5),
+    ("1/16", 0.25),
+    ("1/32", 0.125),
+    ("1/8T", 1. / 3.),
+    ("1/16T", 1. / 6.),
+];
+const DEFAULT_RATE: usize = 4;
+
+// the byte values are divided to get 12 chord types, 0 to 5 inversions, 0 to 3 octaves of spread
+// and 0 ( unused ) to 25 semitones for custom intervals
+const CHORD_TYPE_DIVISOR: u8 = 11;
+const INVERSION_DIVISOR: u8 = 25;
+const SPREAD_DIVISOR: u8 = 42;
+const INTERVAL_DIVISOR: u8 = 5;
+// 1 to 32 steps, shown from 1 like channels
+const STEPS_DIVISOR: u8 = 4;
+const RATE_DIVISOR: u8 = 16;
+// off, re-articulate, glide
+const LEGATO_DIVISOR: u8 = 43;
+// off, time, tempo synced
+const GATE_MODE_DIVISOR: u8 = 43;
+// off, notes, CC
+const INPUT_MODE_DIVISOR: u8 = 43;
+// 14 scale types, 12 keys
+const SCALE_TYPE_DIVISOR: u8 = 9;
+const SCALE_KEY_DIVISOR: u8 = 10;
+// up to 2 octaves up or down
+const RANDOM_PITCH_RANGE_DIVISOR: u8 = 5;
+const MAX_RANDOM_PITCH_RANGE: u8 = 24;
+// in seconds
+const MAX_OVERLAP: f32 = 1.;
+const MAX_GLIDE_TIME: f32 = 2.;
+const MAX_GATE_TIME: f32 = 4.;
+// about 300ms
+const DEFAULT_GATE_TIME: f32 = 0.3;
+const DURATION_FACTOR: f32 = 20.;
 
 pub struct NoteGeneratorPluginParameters {
-    pub host: Mutex<HostCallbackLock>,
     pub transfer: ParameterTransfer,
+    // read when the plugin is created, selected with the Scala file parameter
+    scala_scales: Vec<ScalaScale>,
+    // set when a preset is loaded, so the values it restores are not sent as changes
+    state_loaded: AtomicBool,
 }
 
-#[repr(i32)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StepParameter {
+    Pitch = 0,
+    Velocity,
+    Gate,
+    Active,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CcParameter {
+    // off, then controllers from 0
+    Number = 0,
+    // 14 bits, of which only the 7 most significant are sent unless high resolution
+    Value,
+    HighResolution,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Parameter {
-    Channel = 0,
+    Channel,
     Pitch,
     Velocity,
     NoteOffVelocity,
     Pressure,
     PitchBend,
     Trigger,
-    TriggeredPitch,
-    TriggeredChannel,
+    ChordType,
+    ChordInversion,
+    ChordSpread,
+    CustomInterval1,
+    CustomInterval2,
+    CustomInterval3,
+    CustomInterval4,
+    Sequencer,
+    SequencerSteps,
+    SequencerRate,
+    // step index from 0
+    Step(usize, StepParameter),
+    Legato,
+    LegatoOverlap,
+    GlideTime,
+    BendRange,
+    Timbre,
+    PolyAftertouch,
+    // off, then programs from 0
+    Program,
+    // slot index from 0
+    Cc(usize, CcParameter),
+    Gate,
+    GateTime,
+    GateRate,
+    InputTrigger,
+    InputCc,
+    InputThreshold,
+    RandomAmount,
+    RandomVelocity,
+    RandomPitchRange,
+    RandomNoteOffVelocity,
+    RandomSeed,
+    Scale,
+    ScaleKey,
+    // semitone above the key, from 0
+    CustomScale(usize),
+    ScalaFile,
 }
 
 impl From<i32> for Parameter {
@@ -34,8 +298,67 @@ impl From<i32> for Parameter {
             4 => Parameter::Pressure,
             5 => Parameter::PitchBend,
             6 => Parameter::Trigger,
-            7 => Parameter::TriggeredPitch,
-            8 => Parameter::TriggeredChannel,
+            7 => Parameter::ChordType,
+            8 => Parameter::ChordInversion,
+            9 => Parameter::ChordSpread,
+            10 => Parameter::CustomInterval1,
+            11 => Parameter::CustomInterval2,
+            12 => Parameter::CustomInterval3,
+            13 => Parameter::CustomInterval4,
+            14 => Parameter::Sequencer,
+            15 => Parameter::SequencerSteps,
+            16 => Parameter::SequencerRate,
+            i if (FIRST_STEP_PARAMETER..FIRST_APPENDED_PARAMETER).contains(&(i as usize)) => {
+                let step_parameter = i as usize - FIRST_STEP_PARAMETER;
+                let parameter = match step_parameter % STEP_PARAMETER_COUNT {
+                    0 => StepParameter::Pitch,
+                    1 => StepParameter::Velocity,
+                    2 => StepParameter::Gate,
+                    _ => StepParameter::Active,
+                };
+                Parameter::Step(step_parameter / STEP_PARAMETER_COUNT, parameter)
+            }
+            i if (FIRST_APPENDED_PARAMETER..FIRST_CC_PARAMETER).contains(&(i as usize)) => {
+                match i as usize - FIRST_APPENDED_PARAMETER {
+                    0 => Parameter::Legato,
+                    1 => Parameter::LegatoOverlap,
+                    2 => Parameter::GlideTime,
+                    3 => Parameter::BendRange,
+                    4 => Parameter::Timbre,
+                    5 => Parameter::PolyAftertouch,
+                    _ => Parameter::Program,
+                }
+            }
+            i if (FIRST_CC_PARAMETER..FIRST_LATE_PARAMETER).contains(&(i as usize)) => {
+                let cc_parameter = i as usize - FIRST_CC_PARAMETER;
+                let parameter = match cc_parameter % CC_PARAMETER_COUNT {
+                    0 => CcParameter::Number,
+                    1 => CcParameter::Value,
+                    _ => CcParameter::HighResolution,
+                };
+                Parameter::Cc(cc_parameter / CC_PARAMETER_COUNT, parameter)
+            }
+            i if (FIRST_LATE_PARAMETER..FIRST_CUSTOM_SCALE_PARAMETER).contains(&(i as usize)) => {
+                match i as usize - FIRST_LATE_PARAMETER {
+                    0 => Parameter::Gate,
+                    1 => Parameter::GateTime,
+                    2 => Parameter::GateRate,
+                    3 => Parameter::InputTrigger,
+                    4 => Parameter::InputCc,
+                    5 => Parameter::InputThreshold,
+                    6 => Parameter::RandomAmount,
+                    7 => Parameter::RandomVelocity,
+                    8 => Parameter::RandomPitchRange,
+                    9 => Parameter::RandomNoteOffVelocity,
+                    10 => Parameter::RandomSeed,
+                    11 => Parameter::Scale,
+                    _ => Parameter::ScaleKey,
+                }
+            }
+            i if (FIRST_CUSTOM_SCALE_PARAMETER..SCALA_FILE_PARAMETER).contains(&(i as usize)) => {
+                Parameter::CustomScale(i as usize - FIRST_CUSTOM_SCALE_PARAMETER)
+            }
+            i if i as usize == SCALA_FILE_PARAMETER => Parameter::ScalaFile,
             _ => panic!(format!("No such Parameter {}", i)),
         }
     }
@@ -43,7 +366,59 @@ impl From<i32> for Parameter {
 
 impl Into<i32> for Parameter {
     fn into(self) -> i32 {
-        self as i32
+        match self {
+            Parameter::Channel => 0,
+            Parameter::Pitch => 1,
+            Parameter::Velocity => 2,
+            Parameter::NoteOffVelocity => 3,
+            Parameter::Pressure => 4,
+            Parameter::PitchBend => 5,
+            Parameter::Trigger => 6,
+            Parameter::ChordType => 7,
+            Parameter::ChordInversion => 8,
+            Parameter::ChordSpread => 9,
+            Parameter::CustomInterval1 => 10,
+            Parameter::CustomInterval2 => 11,
+            Parameter::CustomInterval3 => 12,
+            Parameter::CustomInterval4 => 13,
+            Parameter::Sequencer => 14,
+            Parameter::SequencerSteps => 15,
+            Parameter::SequencerRate => 16,
+            Parameter::Step(step, parameter) => {
+                (FIRST_STEP_PARAMETER + step * STEP_PARAMETER_COUNT + parameter as usize) as i32
+            }
+            Parameter::Legato => FIRST_APPENDED_PARAMETER as i32,
+            Parameter::LegatoOverlap => (FIRST_APPENDED_PARAMETER + 1) as i32,
+            Parameter::GlideTime => (FIRST_APPENDED_PARAMETER + 2) as i32,
+            Parameter::BendRange => (FIRST_APPENDED_PARAMETER + 3) as i32,
+            Parameter::Timbre => (FIRST_APPENDED_PARAMETER + 4) as i32,
+            Parameter::PolyAftertouch => (FIRST_APPENDED_PARAMETER + 5) as i32,
+            Parameter::Program => (FIRST_APPENDED_PARAMETER + 6) as i32,
+            Parameter::Cc(slot, parameter) => (FIRST_CC_PARAMETER + slot * CC_PARAMETER_COUNT + parameter as usize) as i32,
+            Parameter::Gate => FIRST_LATE_PARAMETER as i32,
+            Parameter::GateTime => (FIRST_LATE_PARAMETER + 1) as i32,
+            Parameter::GateRate => (FIRST_LATE_PARAMETER + 2) as i32,
+            Parameter::InputTrigger => (FIRST_LATE_PARAMETER + 3) as i32,
+            Parameter::InputCc => (FIRST_LATE_PARAMETER + 4) as i32,
+            Parameter::InputThreshold => (FIRST_LATE_PARAMETER + 5) as i32,
+            Parameter::RandomAmount => (FIRST_LATE_PARAMETER + 6) as i32,
+            Parameter::RandomVelocity => (FIRST_LATE_PARAMETER + 7) as i32,
+            Parameter::RandomPitchRange => (FIRST_LATE_PARAMETER + 8) as i32,
+            Parameter::RandomNoteOffVelocity => (FIRST_LATE_PARAMETER + 9) as i32,
+            Parameter::RandomSeed => (FIRST_LATE_PARAMETER + 10) as i32,
+            Parameter::Scale => (FIRST_LATE_PARAMETER + 11) as i32,
+            Parameter::ScaleKey => (FIRST_LATE_PARAMETER + 12) as i32,
+            Parameter::CustomScale(semitone) => (FIRST_CUSTOM_SCALE_PARAMETER + semitone) as i32,
+            Parameter::ScalaFile => SCALA_FILE_PARAMETER as i32,
+        }
+    }
+}
+
+impl Parameter {
+    #[inline]
+    fn index(self) -> usize {
+        let index: i32 = self.into();
+        index as usize
     }
 }
 
@@ -53,7 +428,7 @@ impl ParameterConversion<Parameter> for NoteGeneratorPluginParameters {
     }
 
     fn get_parameter_count() -> usize {
-        9
+        PARAMETER_COUNT
     }
 }
 
@@ -64,12 +439,76 @@ impl NoteGeneratorPluginParameters {
         self.get_byte_parameter(Parameter::Channel) / 8 + 1
     }
 
-    fn get_pitch_label(&self) -> String {
-        format!(
-            "{}{}",
-            NOTE_NAMES[self.get_byte_parameter(Parameter::Pitch) as usize % 12],
-            ((self.get_byte_parameter(Parameter::Pitch) as i8) - C0) / 12
-        )
+    // the degree in the scale, unless chromatic
+    fn get_pitch_label(&self, pitch: u8) -> String {
+        if self.get_scale_type() == ScaleType::Chromatic {
+            return note_name(pitch);
+        }
+        match self.get_scale().degree(pitch) {
+            Some(degree) => format!("{}, degree {}", note_name(pitch), degree),
+            None => note_name(pitch),
+        }
+    }
+
+    // quantized to the scale
+    #[inline]
+    pub fn get_pitch(&self) -> u8 {
+        self.quantize_pitch(self.get_byte_parameter(Parameter::Pitch))
+    }
+
+    #[inline]
+    fn get_scale_type(&self) -> ScaleType {
+        ScaleType::from(self.get_byte_parameter(Parameter::Scale) / SCALE_TYPE_DIVISOR)
+    }
+
+    // from 0 for C
+    #[inline]
+    fn get_scale_key(&self) -> u8 {
+        (self.get_byte_parameter(Parameter::ScaleKey) / SCALE_KEY_DIVISOR).min(11)
+    }
+
+    fn get_custom_scale_mask(&self) -> u16 {
+        (0..12)
+            .filter(|&semitone| self.get_bool_parameter(Parameter::CustomScale(semitone)))
+            .fold(0, |mask, semitone| mask | 1 << semitone)
+    }
+
+    // the byte is spread over the loaded scales, so the whole range of the parameter selects one
+    fn get_scala_scale(&self) -> Option<&ScalaScale> {
+        let index = self.get_byte_parameter(Parameter::ScalaFile) as usize * self.scala_scales.len() / 128;
+        self.scala_scales.get(index)
+    }
+
+    // the lowest byte selecting the scale, None past the 128 scales the byte can select
+    fn scala_scale_byte(&self, index: usize) -> Option<u8> {
+        let count = self.scala_scales.len().max(1);
+        let byte = (index * 128 + count - 1) / count;
+        (byte < 128 && byte * count / 128 == index).then_some(byte as u8)
+    }
+
+    fn get_scala_file_label(&self) -> String {
+        match self.get_scala_scale() {
+            Some(scale) => scale.name.clone(),
+            None => "None".to_string(),
+        }
+    }
+
+    // a Scala file that is not there is chromatic
+    pub fn get_scale(&self) -> Scale {
+        let key = self.get_scale_key();
+        match self.get_scale_type() {
+            ScaleType::Custom => Scale::from_mask(key, self.get_custom_scale_mask()),
+            ScaleType::Scala => match self.get_scala_scale() {
+                Some(scale) => scale.scale(key),
+                None => Scale::chromatic(),
+            },
+            scale_type => Scale::from_mask(key, scale_type.mask().unwrap_or(CHROMATIC_MASK)),
+        }
+    }
+
+    #[inline]
+    pub fn quantize_pitch(&self, pitch: u8) -> u8 {
+        self.get_scale().quantize(pitch)
     }
 
     #[inline]
@@ -98,11 +537,314 @@ impl NoteGeneratorPluginParameters {
         self.get_bool_parameter(Parameter::Trigger)
     }
 
-    pub fn new(host: HostCallback) -> Self {
-        NoteGeneratorPluginParameters {
-            host: Mutex::new(HostCallbackLock { host }),
-            ..Default::default()
+    #[inline]
+    pub fn get_chord_type(&self) -> ChordType {
+        ChordType::from(self.get_byte_parameter(Parameter::ChordType) / CHORD_TYPE_DIVISOR)
+    }
+
+    #[inline]
+    fn get_chord_inversion(&self) -> u8 {
+        self.get_byte_parameter(Parameter::ChordInversion) / INVERSION_DIVISOR
+    }
+
+    #[inline]
+    fn get_chord_spread(&self) -> u8 {
+        self.get_byte_parameter(Parameter::ChordSpread) / SPREAD_DIVISOR
+    }
+
+    #[inline]
+    fn get_custom_interval(&self, parameter: Parameter) -> u8 {
+        self.get_byte_parameter(parameter) / INTERVAL_DIVISOR
+    }
+
+    fn get_custom_interval_label(&self, parameter: Parameter) -> String {
+        match self.get_custom_interval(parameter) {
+            0 => "Off".to_string(),
+            interval => format!("+{} semitones", interval),
+        }
+    }
+
+    // the pitches to play for the current chord parameters, the pitch parameter or the pitch of
+    // a step being the root
+    pub fn get_chord(&self, pitch: u8) -> Chord {
+        let inversion = self.get_chord_inversion();
+        let spread = self.get_chord_spread();
+
+        match self.get_chord_type() {
+            ChordType::Custom => {
+                let intervals = [
+                    self.get_custom_interval(Parameter::CustomInterval1),
+                    self.get_custom_interval(Parameter::CustomInterval2),
+                    self.get_custom_interval(Parameter::CustomInterval3),
+                    self.get_custom_interval(Parameter::CustomInterval4),
+                ];
+                let mut used = [0u8; 4];
+                let mut count = 0;
+                for interval in intervals.iter().filter(|&&interval| interval > 0) {
+                    used[count] = *interval;
+                    count += 1;
+                }
+                Chord::new(pitch, &used[..count], inversion, spread)
+            }
+            chord_type => Chord::new(pitch, chord_type.intervals(), inversion, spread),
+        }
+    }
+
+    #[inline]
+    pub fn get_sequencer(&self) -> bool {
+        self.get_bool_parameter(Parameter::Sequencer)
+    }
+
+    #[inline]
+    pub fn get_sequencer_steps(&self) -> usize {
+        (self.get_byte_parameter(Parameter::SequencerSteps) / STEPS_DIVISOR) as usize + 1
+    }
+
+    fn get_rate_index(&self, parameter: Parameter) -> usize {
+        ((self.get_byte_parameter(parameter) / RATE_DIVISOR) as usize).min(RATES.len() - 1)
+    }
+
+    // in quarter notes
+    #[inline]
+    pub fn get_step_length(&self) -> f64 {
+        RATES[self.get_rate_index(Parameter::SequencerRate)].1
+    }
+
+    // quantized to the scale
+    #[inline]
+    pub fn get_step_pitch(&self, step: usize) -> u8 {
+        self.quantize_pitch(self.get_byte_parameter(Parameter::Step(step, StepParameter::Pitch)))
+    }
+
+    #[inline]
+    pub fn get_step_velocity(&self, step: usize) -> u8 {
+        self.get_byte_parameter(Parameter::Step(step, StepParameter::Velocity))
+    }
+
+    // fraction of the step length
+    #[inline]
+    pub fn get_step_gate(&self, step: usize) -> f64 {
+        self.transfer.get_parameter(Parameter::Step(step, StepParameter::Gate).index()) as f64
+    }
+
+    #[inline]
+    pub fn get_step_active(&self, step: usize) -> bool {
+        self.get_bool_parameter(Parameter::Step(step, StepParameter::Active))
+    }
+
+    fn get_step_label(&self, step: usize, parameter: StepParameter) -> String {
+        match parameter {
+            StepParameter::Pitch => self.get_pitch_label(self.get_step_pitch(step)),
+            StepParameter::Velocity => format!("{}", self.get_step_velocity(step)),
+            StepParameter::Gate => format!("{:.0}%", self.get_step_gate(step) * 100.),
+            StepParameter::Active => format!("{}", self.get_step_active(step)),
+        }
+    }
+
+    #[inline]
+    pub fn get_legato(&self) -> LegatoMode {
+        LegatoMode::from(self.get_byte_parameter(Parameter::Legato) / LEGATO_DIVISOR)
+    }
+
+    // in seconds
+    #[inline]
+    pub fn get_legato_overlap(&self) -> f32 {
+        self.get_exponential_scale_parameter(Parameter::LegatoOverlap, MAX_OVERLAP, DURATION_FACTOR)
+    }
+
+    // in seconds
+    #[inline]
+    pub fn get_glide_time(&self) -> f32 {
+        self.get_exponential_scale_parameter(Parameter::GlideTime, MAX_GLIDE_TIME, DURATION_FACTOR)
+    }
+
+    // in semitones, up or down
+    #[inline]
+    pub fn get_bend_range(&self) -> u8 {
+        self.get_byte_parameter(Parameter::BendRange).max(1)
+    }
+
+    #[inline]
+    pub fn get_gate(&self) -> GateMode {
+        GateMode::from(self.get_byte_parameter(Parameter::Gate) / GATE_MODE_DIVISOR)
+    }
+
+    // in seconds
+    #[inline]
+    pub fn get_gate_time(&self) -> f32 {
+        self.get_exponential_scale_parameter(Parameter::GateTime, MAX_GATE_TIME, DURATION_FACTOR)
+    }
+
+    // in quarter notes
+    #[inline]
+    pub fn get_gate_length(&self) -> f64 {
+        RATES[self.get_rate_index(Parameter::GateRate)].1
+    }
+
+    #[inline]
+    pub fn get_input_mode(&self) -> InputMode {
+        InputMode::from(self.get_byte_parameter(Parameter::InputTrigger) / INPUT_MODE_DIVISOR)
+    }
+
+    // from 0 to 1
+    #[inline]
+    pub fn get_random_amount(&self) -> f64 {
+        self.transfer.get_parameter(Parameter::RandomAmount.index()) as f64
+    }
+
+    // in semitones, up or down
+    #[inline]
+    pub fn get_random_pitch_range(&self) -> u8 {
+        (self.get_byte_parameter(Parameter::RandomPitchRange) / RANDOM_PITCH_RANGE_DIVISOR).min(MAX_RANDOM_PITCH_RANGE)
+    }
+
+    fn get_random_pitch_range_label(&self) -> String {
+        match self.get_random_pitch_range() {
+            0 => "Off".to_string(),
+            range => format!("+/-{} semitones", range),
+        }
+    }
+
+    // none when off
+    #[inline]
+    pub fn get_program(&self) -> Option<u8> {
+        self.get_byte_parameter(Parameter::Program).checked_sub(1)
+    }
+
+    // none when the slot is off
+    #[inline]
+    pub fn get_cc_number(&self, slot: usize) -> Option<u8> {
+        self.get_byte_parameter(Parameter::Cc(slot, CcParameter::Number)).checked_sub(1)
+    }
+
+    #[inline]
+    pub fn get_cc_high_resolution(&self, slot: usize) -> bool {
+        self.get_bool_parameter(Parameter::Cc(slot, CcParameter::HighResolution))
+    }
+
+    // 14 bits when in high resolution, else 7
+    #[inline]
+    pub fn get_cc_value(&self, slot: usize) -> u16 {
+        let value = self.get_u14_parameter(Parameter::Cc(slot, CcParameter::Value));
+        if self.get_cc_high_resolution(slot) {
+            value
+        } else {
+            value >> 7
+        }
+    }
+
+    fn get_cc_label(&self, slot: usize, parameter: CcParameter) -> String {
+        match parameter {
+            CcParameter::Number => Self::get_off_label(self.get_cc_number(slot)),
+            CcParameter::Value => format!("{}", self.get_cc_value(slot)),
+            CcParameter::HighResolution => format!("{}", self.get_cc_high_resolution(slot)),
+        }
+    }
+
+    fn get_off_label(value: Option<u8>) -> String {
+        match value {
+            Some(value) => format!("{}", value),
+            None => "Off".to_string(),
+        }
+    }
+
+    fn get_duration_label(value: f32) -> String {
+        if value > 0.0 {
+            duration_display(value)
+        } else {
+            "Off".to_string()
+        }
+    }
+
+    // plain values as parsed by the definition, e.g. "500ms"
+    fn set_from_definition(&self, parameter: Parameter, text: &str) -> bool {
+        let definition = &PARAMETER_DEFINITIONS[parameter.index()];
+        match definition.parse(text) {
+            Some(value) => {
+                self.transfer.set_parameter(parameter.index(), definition.to_normalized(value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_bool_from_text(&self, parameter: Parameter, text: &str) -> bool {
+        match text.to_ascii_lowercase().as_ref() {
+            "0" | "off" | "false" | "" => {
+                self.set_bool_parameter(parameter, false);
+                true
+            }
+            "1" | "on" | "true" => {
+                self.set_bool_parameter(parameter, true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // as shown, the degree being ignored
+    fn set_pitch_from_text(&self, parameter: Parameter, text: &str) -> bool {
+        let text = text.split(',').next().unwrap_or_default().trim();
+        match NOTE_NAMES.iter().rposition(|&s| text.starts_with(s)) {
+            None => false,
+            Some(position) => {
+                match text[NOTE_NAMES[position].len()..text.len()].parse::<i8>() {
+                    Ok(octave) => {
+                        if (-2..=8).contains(&octave) {
+                            let pitch = octave as i16 * 12 + C0 as i16 + position as i16;
+                            if pitch < 128 {
+                                self.set_byte_parameter(parameter, self.quantize_pitch(pitch as u8));
+                                true
+                            } else {
+                                false
+                            }
+                        } else {
+                            false
+                        }
+                    }
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+
+    fn set_divided_parameter(&self, parameter: Parameter, text: &str, divisor: u8) -> bool {
+        match text.trim().parse::<u8>() {
+            Ok(n) if n <= 127 / divisor => {
+                self.set_byte_parameter(parameter, n * divisor);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // the taken flag is reset
+    pub fn take_state_loaded(&self) -> bool {
+        self.state_loaded.swap(false, Ordering::AcqRel)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = self.serialize_state();
+        for slot in 0..CC_SLOT_COUNT {
+            let value = self.get_u14_parameter(Parameter::Cc(slot, CcParameter::Value));
+            data.extend_from_slice(&[(value >> 7) as u8, (value & 0x7F) as u8]);
+        }
+        data
+    }
+
+    // presets written before the CC values were appended keep their 7 bits
+    fn load_state(&self, data: &[u8]) {
+        if data.len() == TRIGGERED_NOTE_PRESET_SIZE {
+            self.deserialize_state(&data[..Parameter::ChordType.index()])
+        } else {
+            self.deserialize_state(&data[..data.len().min(PARAMETER_COUNT)]);
+            let values = data.get(PARAMETER_COUNT..).unwrap_or_default();
+            for (slot, value) in values.chunks_exact(CC_VALUE_SIZE).take(CC_SLOT_COUNT).enumerate() {
+                let value = (value[0] as u16 & 0x7F) << 7 | value[1] as u16 & 0x7F;
+                self.set_u14_parameter(Parameter::Cc(slot, CcParameter::Value), value);
+            }
         }
+        self.state_loaded.store(true, Ordering::Release);
     }
 }
 
@@ -110,28 +852,54 @@ impl PluginParameters for NoteGeneratorPluginParameters {
     fn get_parameter_text(&self, index: i32) -> String {
         match Parameter::from(index as i32) {
             Parameter::Channel => format!("{}", self.get_displayable_channel()),
-            Parameter::Pitch => self.get_pitch_label(),
+            Parameter::Pitch => self.get_pitch_label(self.get_pitch()),
             Parameter::Velocity => format!("{}", self.get_velocity()),
             Parameter::NoteOffVelocity => format!("{}", self.get_note_off_velocity()),
             Parameter::Pressure => format!("{}", self.get_pressure()),
             Parameter::PitchBend => self.get_pitchbend_label(),
             Parameter::Trigger => format!("{}", self.get_trigger()),
-            _ => "".to_string(),
+            Parameter::ChordType => self.get_chord_type().name().to_string(),
+            Parameter::ChordInversion => format!("{}", self.get_chord_inversion()),
+            Parameter::ChordSpread => format!("{} octaves", self.get_chord_spread()),
+            parameter @ (Parameter::CustomInterval1
+            | Parameter::CustomInterval2
+            | Parameter::CustomInterval3
+            | Parameter::CustomInterval4) => self.get_custom_interval_label(parameter),
+            Parameter::Sequencer => format!("{}", self.get_sequencer()),
+            Parameter::SequencerSteps => format!("{}", self.get_sequencer_steps()),
+            parameter @ (Parameter::SequencerRate | Parameter::GateRate) => RATES[self.get_rate_index(parameter)].0.to_string(),
+            Parameter::Step(step, parameter) => self.get_step_label(step, parameter),
+            Parameter::Legato => self.get_legato().name().to_string(),
+            Parameter::LegatoOverlap => Self::get_duration_label(self.get_legato_overlap()),
+            Parameter::GlideTime => Self::get_duration_label(self.get_glide_time()),
+            Parameter::BendRange => format!("{} semitones", self.get_bend_range()),
+            Parameter::Timbre => format!("{}", self.get_byte_parameter(Parameter::Timbre)),
+            Parameter::PolyAftertouch => format!("{}", self.get_byte_parameter(Parameter::PolyAftertouch)),
+            Parameter::Program => Self::get_off_label(self.get_program()),
+            Parameter::Cc(slot, parameter) => self.get_cc_label(slot, parameter),
+            Parameter::Gate => self.get_gate().name().to_string(),
+            Parameter::GateTime => Self::get_duration_label(self.get_gate_time()),
+            Parameter::InputTrigger => self.get_input_mode().name().to_string(),
+            parameter @ (Parameter::InputCc | Parameter::InputThreshold | Parameter::RandomSeed) => {
+                format!("{}", self.get_byte_parameter(parameter))
+            }
+            Parameter::RandomAmount => format!("{:.0}%", self.get_random_amount() * 100.),
+            parameter @ (Parameter::RandomVelocity | Parameter::RandomNoteOffVelocity) => {
+                format!("{}", self.get_bool_parameter(parameter))
+            }
+            Parameter::RandomPitchRange => self.get_random_pitch_range_label(),
+            Parameter::Scale => self.get_scale_type().name().to_string(),
+            Parameter::ScaleKey => NOTE_NAMES[self.get_scale_key() as usize].to_string(),
+            Parameter::CustomScale(semitone) => format!("{}", self.get_bool_parameter(Parameter::CustomScale(semitone))),
+            Parameter::ScalaFile => self.get_scala_file_label(),
         }
     }
 
     fn get_parameter_name(&self, index: i32) -> String {
-        match Parameter::from(index as i32) {
-            Parameter::Channel => "Channel",
-            Parameter::Pitch => "Pitch",
-            Parameter::Velocity => "Velocity",
-            Parameter::NoteOffVelocity => "Note off velocity",
-            Parameter::Pressure => "Pressure",
-            Parameter::PitchBend => "Pitch Bend",
-            Parameter::Trigger => "Trigger generated note",
-            _ => "",
+        match PARAMETER_DEFINITIONS.get(index as usize) {
+            Some(definition) => definition.name.to_string(),
+            None => "".to_string(),
         }
-        .to_string()
     }
 
     fn get_parameter(&self, index: i32) -> f32 {
@@ -140,16 +908,35 @@ impl PluginParameters for NoteGeneratorPluginParameters {
 
     fn set_parameter(&self, index: i32, value: f32) {
         match Parameter::from(index as i32) {
-            Parameter::Trigger => {
+            parameter @ (Parameter::Trigger
+            | Parameter::Sequencer
+            | Parameter::Step(_, StepParameter::Active)
+            | Parameter::Cc(_, CcParameter::HighResolution)
+            | Parameter::RandomVelocity
+            | Parameter::RandomNoteOffVelocity
+            | Parameter::CustomScale(_)) => {
                 // boolean case: in order to ignore intermediary changes,
                 // don't just pass the unchanged f32
                 let new_value = f32_to_bool(value);
-                let old_value = self.get_bool_parameter(Parameter::Trigger);
+                let old_value = self.get_bool_parameter(parameter);
 
                 if new_value != old_value {
-                    self.set_bool_parameter(Parameter::Trigger, new_value)
+                    self.set_bool_parameter(parameter, new_value)
                 }
             }
+            parameter @ (Parameter::Pitch | Parameter::Step(_, StepParameter::Pitch)) => {
+                // snapped to the scale, so modulations only change it from degree to degree
+                let new_value = self.quantize_pitch(f32_to_byte(value));
+                let old_value = self.get_byte_parameter(parameter);
+
+                if new_value != old_value {
+                    self.set_byte_parameter(parameter, new_value)
+                }
+            }
+            // not reduced to a byte, for finer durations
+            Parameter::LegatoOverlap | Parameter::GlideTime | Parameter::GateTime => {
+                self.transfer.set_parameter(index as usize, value)
+            }
             Parameter::PitchBend => {
                 let new_value = f32_to_u14(value);
                 let old_value = self.get_u14_parameter(Parameter::PitchBend);
@@ -158,6 +945,20 @@ impl PluginParameters for NoteGeneratorPluginParameters {
                     self.set_u14_parameter(Parameter::PitchBend, new_value)
                 }
             }
+            parameter @ Parameter::Cc(slot, CcParameter::Value) => {
+                // compared on what is sent, so it is only sent when it changes
+                let new_value = f32_to_u14(value);
+                let old_value = self.get_u14_parameter(parameter);
+                let changed = if self.get_cc_high_resolution(slot) {
+                    new_value != old_value
+                } else {
+                    new_value >> 7 != old_value >> 7
+                };
+
+                if changed {
+                    self.set_u14_parameter(parameter, new_value)
+                }
+            }
             _ => {
                 // reduce to a byte and compare, so modulators don't generate tons of
                 // irrelevant changes
@@ -176,7 +977,7 @@ impl PluginParameters for NoteGeneratorPluginParameters {
             Parameter::Channel => match text.parse::<u8>() {
                 Ok(n) => {
                     if n > 0 && n <= 16 {
-                        self.set_byte_parameter(Parameter::from(index), n);
+                        self.set_byte_parameter(Parameter::from(index), (n - 1) * 8);
                         true
                     } else {
                         false
@@ -184,11 +985,19 @@ impl PluginParameters for NoteGeneratorPluginParameters {
                 }
                 Err(_) => false,
             },
-            Parameter::Velocity | Parameter::NoteOffVelocity | Parameter::Pressure => {
+            Parameter::Velocity
+            | Parameter::NoteOffVelocity
+            | Parameter::Pressure
+            | Parameter::Step(_, StepParameter::Velocity)
+            | Parameter::Timbre
+            | Parameter::PolyAftertouch
+            | Parameter::InputCc
+            | Parameter::InputThreshold
+            | Parameter::RandomSeed => {
                 match text.parse::<u8>() {
                     Ok(n) => {
                         if n < 128 {
-                            self.set_byte_parameter(Parameter::Velocity, n);
+                            self.set_byte_parameter(Parameter::from(index), n);
                             true
                         } else {
                             false
@@ -197,67 +1006,233 @@ impl PluginParameters for NoteGeneratorPluginParameters {
                     Err(_) => false,
                 }
             }
-            Parameter::Pitch => match NOTE_NAMES.iter().position(|&s| text.starts_with(s)) {
-                None => false,
-                Some(position) => {
-                    match text[NOTE_NAMES[position].len()..text.len()].parse::<i8>() {
-                        Ok(octave) => {
-                            if octave >= -2 && octave <= 8 {
-                                let pitch = octave as i16 * 12 + C0 as i16 + position as i16;
-                                if pitch < 128 {
-                                    self.set_byte_parameter(Parameter::Pitch, pitch as u8);
-                                    true
-                                } else {
-                                    false
-                                }
-                            } else {
-                                false
-                            }
+            parameter @ (Parameter::Program | Parameter::Cc(_, CcParameter::Number)) => {
+                if text.trim().eq_ignore_ascii_case("off") {
+                    self.set_byte_parameter(parameter, 0);
+                    true
+                } else {
+                    match text.trim().parse::<u8>() {
+                        Ok(n) if n < 127 => {
+                            self.set_byte_parameter(parameter, n + 1);
+                            true
                         }
-                        Err(_) => false,
+                        _ => false,
                     }
                 }
+            }
+            parameter @ (Parameter::Pitch | Parameter::Step(_, StepParameter::Pitch)) => {
+                self.set_pitch_from_text(parameter, &text)
+            }
+            parameter @ (Parameter::Trigger
+            | Parameter::Sequencer
+            | Parameter::Step(_, StepParameter::Active)
+            | Parameter::Cc(_, CcParameter::HighResolution)
+            | Parameter::RandomVelocity
+            | Parameter::RandomNoteOffVelocity
+            | Parameter::CustomScale(_)) => self.set_bool_from_text(parameter, &text),
+            parameter @ Parameter::Cc(slot, CcParameter::Value) => match text.trim().parse::<u16>() {
+                Ok(n) if self.get_cc_high_resolution(slot) && n <= 0x3FFF => {
+                    self.set_u14_parameter(parameter, n);
+                    true
+                }
+                Ok(n) if n < 128 => {
+                    self.set_u14_parameter(parameter, n << 7);
+                    true
+                }
+                _ => false,
             },
-            Parameter::Trigger => match text.to_ascii_lowercase().as_ref() {
-                "0" | "off" | "" => {
-                    self.set_bool_parameter(Parameter::Trigger, false);
+            Parameter::ChordType => match CHORD_TYPE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::ChordType, position as u8 * CHORD_TYPE_DIVISOR);
                     true
                 }
-                "1" | "on" => {
-                    self.set_bool_parameter(Parameter::Trigger, true);
+                None => self.set_divided_parameter(Parameter::ChordType, &text, CHORD_TYPE_DIVISOR),
+            },
+            Parameter::ChordInversion => self.set_divided_parameter(Parameter::ChordInversion, &text, INVERSION_DIVISOR),
+            Parameter::ChordSpread => self.set_divided_parameter(Parameter::ChordSpread, &text, SPREAD_DIVISOR),
+            parameter @ (Parameter::CustomInterval1
+            | Parameter::CustomInterval2
+            | Parameter::CustomInterval3
+            | Parameter::CustomInterval4) => {
+                let text = text.trim().trim_start_matches('+').trim_end_matches("semitones");
+                if text.eq_ignore_ascii_case("off") {
+                    self.set_byte_parameter(parameter, 0);
+                    true
+                } else {
+                    self.set_divided_parameter(parameter, text, INTERVAL_DIVISOR)
+                }
+            }
+            Parameter::SequencerSteps => match text.trim().parse::<u8>() {
+                Ok(n) if n > 0 && n as usize <= STEP_COUNT => {
+                    self.set_byte_parameter(Parameter::SequencerSteps, (n - 1) * STEPS_DIVISOR);
+                    true
+                }
+                _ => false,
+            },
+            parameter @ (Parameter::SequencerRate | Parameter::GateRate) => match RATES.iter().position(|(name, _)| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(parameter, position as u8 * RATE_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            Parameter::Legato => match LEGATO_MODE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::Legato, position as u8 * LEGATO_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            Parameter::Gate => match GATE_MODE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::Gate, position as u8 * GATE_MODE_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            parameter @ (Parameter::LegatoOverlap | Parameter::GlideTime | Parameter::GateTime) => {
+                self.set_from_definition(parameter, &text)
+            }
+            Parameter::InputTrigger => match INPUT_MODE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::InputTrigger, position as u8 * INPUT_MODE_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            Parameter::BendRange => match text.trim().trim_end_matches("semitones").trim().parse::<u8>() {
+                Ok(n) if n > 0 && n < 128 => {
+                    self.set_byte_parameter(Parameter::BendRange, n);
                     true
                 }
                 _ => false,
             },
+            parameter @ (Parameter::Step(_, StepParameter::Gate) | Parameter::RandomAmount) => {
+                match text.trim().trim_end_matches('%').trim().parse::<f32>() {
+                    Ok(percent) if (0. ..=100.).contains(&percent) => {
+                        self.transfer.set_parameter(parameter.index(), percent / 100.);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Parameter::Scale => match SCALE_TYPE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::Scale, position as u8 * SCALE_TYPE_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            Parameter::ScaleKey => match NOTE_NAMES.iter().position(|name| name.eq_ignore_ascii_case(text.trim())) {
+                Some(position) => {
+                    self.set_byte_parameter(Parameter::ScaleKey, position as u8 * SCALE_KEY_DIVISOR);
+                    true
+                }
+                None => false,
+            },
+            Parameter::ScalaFile => match self
+                .scala_scales
+                .iter()
+                .position(|scale| scale.name == text.trim())
+                .and_then(|position| self.scala_scale_byte(position))
+            {
+                Some(byte) => {
+                    self.set_byte_parameter(Parameter::ScalaFile, byte);
+                    true
+                }
+                None => false,
+            },
+            Parameter::RandomPitchRange => {
+                let text = text.trim().trim_start_matches("+/-").trim_end_matches("semitones").trim();
+                if text.eq_ignore_ascii_case("off") {
+                    self.set_byte_parameter(Parameter::RandomPitchRange, 0);
+                    true
+                } else {
+                    match text.parse::<u8>() {
+                        Ok(range) if range <= MAX_RANDOM_PITCH_RANGE => {
+                            self.set_byte_parameter(Parameter::RandomPitchRange, range * RANDOM_PITCH_RANGE_DIVISOR);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+            }
             _ => false,
         }
     }
 
     fn get_preset_data(&self) -> Vec<u8> {
-        self.serialize_state()
+        self.save_state()
     }
 
     fn get_bank_data(&self) -> Vec<u8> {
-        self.serialize_state()
+        self.save_state()
     }
 
     fn load_preset_data(&self, data: &[u8]) {
-        self.deserialize_state(data)
+        self.load_state(data)
     }
 
     fn load_bank_data(&self, data: &[u8]) {
-        self.deserialize_state(data)
+        self.load_state(data)
     }
 }
 
 impl Default for NoteGeneratorPluginParameters {
     fn default() -> Self {
         let parameters = NoteGeneratorPluginParameters {
-            host: Default::default(),
-            transfer: ParameterTransfer::new(9),
+            transfer: ParameterTransfer::new(PARAMETER_COUNT),
+            scala_scales: ScalaScale::from_env(),
+            state_loaded: AtomicBool::new(false),
         };
         parameters.set_byte_parameter(Parameter::Pitch, C0 as u8);
         parameters.set_byte_parameter(Parameter::Velocity, 64);
+        parameters.set_byte_parameter(Parameter::SequencerSteps, (STEP_COUNT as u8 - 1) * STEPS_DIVISOR);
+        parameters.set_byte_parameter(Parameter::SequencerRate, DEFAULT_RATE as u8 * RATE_DIVISOR);
+        for step in 0..STEP_COUNT {
+            parameters.set_byte_parameter(Parameter::Step(step, StepParameter::Pitch), C0 as u8);
+            parameters.set_byte_parameter(Parameter::Step(step, StepParameter::Velocity), 64);
+            parameters.transfer.set_parameter(Parameter::Step(step, StepParameter::Gate).index(), 0.5);
+            parameters.set_bool_parameter(Parameter::Step(step, StepParameter::Active), true);
+        }
+        parameters.set_byte_parameter(Parameter::BendRange, 48);
+        parameters.set_byte_parameter(Parameter::Timbre, ZEROVALUE);
+        parameters.transfer.set_parameter(Parameter::GateTime.index(), DEFAULT_GATE_TIME);
+        parameters.set_byte_parameter(Parameter::GateRate, DEFAULT_RATE as u8 * RATE_DIVISOR);
+        parameters.set_byte_parameter(Parameter::InputCc, DEFAULT_INPUT_CC);
+        parameters.set_byte_parameter(Parameter::InputThreshold, 64);
+        for semitone in 0..12 {
+            parameters.set_bool_parameter(Parameter::CustomScale(semitone), true);
+        }
+        // the defaults are not changes to send
+        for _ in parameters.transfer.iterate(true) {}
         parameters
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scala_files_are_spread_over_the_byte() {
+        let parameters = NoteGeneratorPluginParameters {
+            scala_scales: ["a", "b", "c"]
+                .iter()
+                .map(|name| ScalaScale::parse(name, "Octave\n1\n2/1\n").unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        let index = SCALA_FILE_PARAMETER as i32;
+
+        for (byte, name) in [(0, "a"), (42, "a"), (43, "b"), (85, "b"), (86, "c"), (127, "c")] {
+            parameters.set_byte_parameter(Parameter::ScalaFile, byte);
+            assert_eq!(parameters.get_parameter_text(index), name);
+        }
+        for name in ["a", "b", "c"] {
+            assert!(parameters.string_to_parameter(index, name.to_string()));
+            assert_eq!(parameters.get_parameter_text(index), name);
+        }
+        assert!(!parameters.string_to_parameter(index, "d".to_string()));
+    }
+}
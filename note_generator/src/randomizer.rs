@@ -0,0 +1,49 @@
+// randomization of the notes each time they are triggered: velocity, pitch and note off
+// velocity move away from their parameter, by up to the random amount. The generator is seeded
+// with the seed parameter, which is saved with the preset, and seeded again when the seed changes
+// and when the host transport starts, so renders play the same notes.
+
+use crate::parameters::{NoteGeneratorPluginParameters, Parameter};
+use util::parameters::ParameterConversion;
+use util::random::Random;
+
+#[derive(Default)]
+pub struct Randomizer {
+    random: Random,
+}
+
+impl Randomizer {
+    pub fn reseed(&mut self, seed: u8) {
+        self.random.reseed(seed as u64);
+    }
+
+    // in [-amount, amount)
+    fn offset(&mut self, parameters: &NoteGeneratorPluginParameters) -> f64 {
+        self.random.next_bipolar() * parameters.get_random_amount()
+    }
+
+    // at least 1, as a note on with a velocity of 0 is a note off
+    pub fn velocity(&mut self, parameters: &NoteGeneratorPluginParameters, velocity: u8) -> u8 {
+        if !parameters.get_bool_parameter(Parameter::RandomVelocity) {
+            return velocity;
+        }
+        (velocity as f64 + self.offset(parameters) * 127.).round().clamp(1., 127.) as u8
+    }
+
+    pub fn note_off_velocity(&mut self, parameters: &NoteGeneratorPluginParameters, velocity: u8) -> u8 {
+        if !parameters.get_bool_parameter(Parameter::RandomNoteOffVelocity) {
+            return velocity;
+        }
+        (velocity as f64 + self.offset(parameters) * 127.).round().clamp(0., 127.) as u8
+    }
+
+    // up or down by up to the pitch range, then quantized to the scale
+    pub fn pitch(&mut self, parameters: &NoteGeneratorPluginParameters, pitch: u8) -> u8 {
+        let range = parameters.get_random_pitch_range();
+        if range == 0 {
+            return pitch;
+        }
+        let pitch = (pitch as f64 + self.offset(parameters) * range as f64).round().clamp(0., 127.);
+        parameters.quantize_pitch(pitch as u8)
+    }
+}
@@ -0,0 +1,171 @@
+// the built-in step sequencer: while the host transport plays, steps are played in turn, one per
+// step length, aligned on the host position so they follow loops, locates and tempo changes. Each
+// step plays the chord of its pitch, at its sample in the block, and is stopped after its gate.
+
+use crate::chord::MAX_CHORD_NOTES;
+use crate::parameters::{NoteGeneratorPluginParameters, Parameter};
+use crate::randomizer::Randomizer;
+use util::constants::{NOTE_OFF, NOTE_ON};
+use util::midi_processor::{TimedMidiMessage, TransportInfo};
+use util::parameters::ParameterConversion;
+
+// in quarter notes, a block starting further than this from the end of the previous one means the
+// host jumped, e.g. looped
+const JUMP_TOLERANCE: f64 = 0.01;
+
+// the gate of a step is at most its length, so the notes of a step end before the next one starts.
+// Notes beyond are not played
+const MAX_SEQUENCED_NOTES: usize = 2 * MAX_CHORD_NOTES;
+
+#[derive(Clone, Copy)]
+struct SequencedNote {
+    channel: u8,
+    pitch: u8,
+    // in quarter notes
+    end: f64,
+    // drawn when the note starts, as the other random values
+    note_off_velocity: u8,
+}
+
+pub struct Sequencer {
+    // allocated up front for the audio thread, None for the free slots
+    notes: [Option<SequencedNote>; MAX_SEQUENCED_NOTES],
+    // steps are counted from the position 0, so a step is never played twice because of rounding
+    last_step: Option<i64>,
+    // where the next block starts if the transport goes on
+    next_position: Option<f64>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Sequencer {
+            notes: [None; MAX_SEQUENCED_NOTES],
+            last_step: None,
+            next_position: None,
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn process(
+        &mut self,
+        parameters: &NoteGeneratorPluginParameters,
+        randomizer: &mut Randomizer,
+        samples: usize,
+        sample_rate: f32,
+        transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        let (position, tempo) = match (transport.position, transport.tempo) {
+            (Some(position), Some(tempo)) if transport.playing && tempo > 0. && parameters.get_sequencer() => {
+                (position, tempo)
+            }
+            _ => {
+                self.stop_all(outputs);
+                self.last_step = None;
+                self.next_position = None;
+                return;
+            }
+        };
+
+        if samples == 0 {
+            return;
+        }
+        if self.next_position.is_some_and(|next| (position - next).abs() > JUMP_TOLERANCE) {
+            self.stop_all(outputs);
+            self.last_step = None;
+        }
+
+        // quarter notes per sample
+        let rate = tempo / 60. / sample_rate as f64;
+        self.next_position = Some(position + samples as f64 * rate);
+        let delta_of = |time: f64| ((time - position) / rate).round().max(0.) as usize;
+
+        let step_length = parameters.get_step_length();
+        // a step starting less than half a sample before the block is played at its start
+        let mut step = ((position - rate / 2.) / step_length).ceil() as i64;
+        loop {
+            let start = step as f64 * step_length;
+            let delta = delta_of(start);
+            if delta >= samples {
+                break;
+            }
+            // before the notes starting at the same time, that may have the same pitch
+            self.stop_ended(delta, &delta_of, outputs);
+            if self.last_step.map_or(true, |last_step| step > last_step) {
+                self.start_step(parameters, randomizer, step, start, delta, rate, outputs);
+                self.last_step = Some(step);
+            }
+            step += 1;
+        }
+        self.stop_ended(samples - 1, &delta_of, outputs);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn start_step(
+        &mut self,
+        parameters: &NoteGeneratorPluginParameters,
+        randomizer: &mut Randomizer,
+        step: i64,
+        start: f64,
+        delta: usize,
+        rate: f64,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        let index = step.rem_euclid(parameters.get_sequencer_steps() as i64) as usize;
+        if !parameters.get_step_active(index) {
+            return;
+        }
+
+        let channel = parameters.get_byte_parameter(Parameter::Channel) / 8;
+        let velocity = randomizer.velocity(parameters, parameters.get_step_velocity(index));
+        let root = randomizer.pitch(parameters, parameters.get_step_pitch(index));
+        let note_off_velocity =
+            randomizer.note_off_velocity(parameters, parameters.get_byte_parameter(Parameter::NoteOffVelocity));
+        // at least a sample, so the note off comes after the note on
+        let end = start + (parameters.get_step_gate(index) * parameters.get_step_length()).max(rate);
+        for &pitch in parameters.get_chord(root).pitches() {
+            let slot = match self.notes.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => slot,
+                None => return,
+            };
+            *slot = Some(SequencedNote {
+                channel,
+                pitch,
+                end,
+                note_off_velocity,
+            });
+            outputs.push(TimedMidiMessage::new([NOTE_ON + channel, pitch, velocity], delta));
+        }
+    }
+
+    // notes ending up to max_delta
+    fn stop_ended(
+        &mut self,
+        max_delta: usize,
+        delta_of: &dyn Fn(f64) -> usize,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        for slot in self.notes.iter_mut() {
+            let note = match slot {
+                Some(note) => note,
+                None => continue,
+            };
+            let delta = delta_of(note.end);
+            if delta > max_delta {
+                continue;
+            }
+            outputs.push(TimedMidiMessage::new(
+                [NOTE_OFF + note.channel, note.pitch, note.note_off_velocity],
+                delta,
+            ));
+            *slot = None;
+        }
+    }
+
+    fn stop_all(&mut self, outputs: &mut Vec<TimedMidiMessage>) {
+        for note in self.notes.iter_mut().filter_map(Option::take) {
+            outputs.push(TimedMidiMessage::new([NOTE_OFF + note.channel, note.pitch, note.note_off_velocity], 0));
+        }
+    }
+}
@@ -3,60 +3,63 @@ mod parameters;
 #[macro_use]
 extern crate vst;
 
-use std::cell::RefCell;
 use std::sync::Arc;
 
-use vst::buffer::{AudioBuffer, SendEventBuffer};
-use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters};
-use vst::event::Event;
-use vst::api::Events;
+use vst::api::Supported;
+use vst::plugin::{CanDo, PluginParameters};
 
 use parameters::NoteOffDelayPluginParameters;
-use parameters::Parameter;
+use parameters::{Parameter, PARAMETER_DEFINITIONS, STORED_PARAMETER_COUNT};
 use util::absolute_time_midi_message_vector::AbsoluteTimeMidiMessageVector;
-use util::debug::DebugSocket;
+use util::debug::{DebugLevel, DebugSocket};
+use util::debug_log;
 use util::delayed_message_consumer::{process_scheduled_events, MessageReason};
-use util::messages::format_event;
 use util::midi_message_type::MidiMessageType;
+use util::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
 use util::parameters::ParameterConversion;
+use util::vst_wrapper::VstWrapper;
 
-plugin_main!(NoteOffDelayPlugin);
+plugin_main!(VstWrapper<NoteOffDelayPlugin>);
+util::clap_main!(NoteOffDelayPlugin);
+util::lv2_main!(NoteOffDelayPlugin);
 
 pub struct NoteOffDelayPlugin {
     current_time_in_samples: usize,
     message_queue: AbsoluteTimeMidiMessageVector,
     parameters: Arc<NoteOffDelayPluginParameters>,
     sample_rate: f32,
-    send_buffer: RefCell<SendEventBuffer>,
 }
 
 impl Default for NoteOffDelayPlugin {
     fn default() -> Self {
+        DebugSocket::log(
+            DebugLevel::Info,
+            build_info::format!("{{{} v{} built with {} at {}}}", $.crate_info.name, $.crate_info.version, $.compiler, $.timestamp),
+        );
         NoteOffDelayPlugin {
-            send_buffer: Default::default(),
             parameters: Arc::new(Default::default()),
             sample_rate: 44100.0,
             current_time_in_samples: 0,
-            message_queue: Default::default()
+            message_queue: Default::default(),
         }
     }
 }
 
 impl NoteOffDelayPlugin {
-    fn send_events(&mut self, samples: usize) {
-        if let Ok(mut host_callback_lock) = self.parameters.host_mutex.lock() {
-            let (next_message_queue, events) = process_scheduled_events(
-                samples,
-                self.current_time_in_samples,
-                &self.message_queue,
-                self.parameters.get_max_notes(),
-                self.parameters.get_bool_parameter(Parameter::MaxNotesAppliesToDelayedNotesOnly),
-                self.parameters.get_parameter(Parameter::Delay.into()) > 0.0
-            );
+    fn send_events(&mut self, samples: usize, outputs: &mut Vec<TimedMidiMessage>) {
+        let (next_message_queue, events) = process_scheduled_events(
+            samples,
+            self.current_time_in_samples,
+            &self.message_queue,
+            self.parameters.get_max_notes(),
+            self.parameters.get_bool_parameter(Parameter::MaxNotesAppliesToDelayedNotesOnly),
+            self.parameters.get_parameter(Parameter::Delay.into()) > 0.0,
+            &self.parameters.statistics
+        );
 
-            self.message_queue = next_message_queue;
-            self.send_buffer.borrow_mut().send_events(events, &mut host_callback_lock.host);
-        }
+        self.message_queue = next_message_queue;
+        self.parameters.statistics.report(self.current_time_in_samples, self.seconds_to_samples(1.0));
+        outputs.extend(events);
     }
 
     #[allow(dead_code)]
@@ -68,12 +71,13 @@ impl NoteOffDelayPlugin {
         (seconds * self.sample_rate) as usize
     }
 
-    fn debug_events_in(&mut self, events: &Events) {
-        for e in events.events() {
-            DebugSocket::send(
-                &*(format_event(&e)
-                    + &*format!(" current time={}", self.current_time_in_samples)),
-            );
+    fn debug_events_in(&self, inputs: &[TimedMidiMessage]) {
+        if !DebugSocket::enabled(DebugLevel::Debug) {
+            return;
+        }
+
+        for e in inputs {
+            debug_log!(DebugLevel::Debug, "{} current time={}", e, self.current_time_in_samples);
         }
     }
 
@@ -81,48 +85,65 @@ impl NoteOffDelayPlugin {
         let new_time_in_samples = self.current_time_in_samples + samples;
         self.current_time_in_samples = new_time_in_samples;
     }
-}
 
-impl Plugin for NoteOffDelayPlugin {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Note Off Delay".to_string(),
-            vendor: "DJ Crontab".to_string(),
-            unique_id: 234213173,
-            parameters: 3,
-            category: Category::Effect,
-            initial_delay: 0,
-            version: 1,
-            inputs: 0,
-            outputs: 0,
-            midi_inputs: 1,
-            f64_precision: false,
-            presets: 1,
-            midi_outputs: 1,
-            preset_chunks: true,
-            silent_when_stopped: true,
+    fn queue_events(&mut self, inputs: &[TimedMidiMessage]) {
+        let note_off_delay = self.seconds_to_samples(self.parameters
+            .get_exponential_scale_parameter(Parameter::Delay, 10., 20.));
+
+        for midi_event in inputs {
+            // TODO: minimum time, maximum time ( with delay )
+
+            match MidiMessageType::from(&midi_event.data) {
+                MidiMessageType::NoteOffMessage(_) => {
+                    self.message_queue.insert_message(
+                        midi_event.data,
+                        midi_event.delta_frames + self.current_time_in_samples, MessageReason::Live,
+                    );
+
+                    if note_off_delay > 0 {
+                        // send two times the note off, the live one will be only used to mark the note on as delayed
+                        self.message_queue.insert_message(
+                            midi_event.data,
+                            note_off_delay + midi_event.delta_frames + self.current_time_in_samples,
+                            MessageReason::Delayed,
+                        );
+                    }
+
+                }
+                MidiMessageType::Unsupported => {
+                    continue;
+                }
+                _ => {
+                    self.message_queue.insert_message(
+                        midi_event.data,
+                        midi_event.delta_frames + self.current_time_in_samples, MessageReason::Live,
+                    );
+                }
+            };
         }
     }
+}
 
-    fn new(host: HostCallback) -> Self {
-        let parameters = NoteOffDelayPluginParameters::new(host);
-        DebugSocket::send(
-            build_info::format!("{{{} v{} built with {} at {}}}", $.crate_info.name, $.crate_info.version, $.compiler, $.timestamp),
-        );
-        NoteOffDelayPlugin {
-            current_time_in_samples: 0,
-            message_queue: Default::default(),
-            parameters: Arc::new(parameters),
-            sample_rate: 44100.0,
-            send_buffer: Default::default(),
+impl MidiProcessor for NoteOffDelayPlugin {
+    type Parameters = NoteOffDelayPluginParameters;
+
+    fn info() -> ProcessorInfo {
+        ProcessorInfo {
+            name: "Note Off Delay",
+            id: "note_off_delay",
+            vendor: "DJ Crontab",
+            unique_id: 234213173,
+            version: 1,
+            parameters: STORED_PARAMETER_COUNT as i32,
+            parameter_definitions: &PARAMETER_DEFINITIONS,
         }
     }
 
-    fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = rate
+    fn parameters(&self) -> &Arc<NoteOffDelayPluginParameters> {
+        &self.parameters
     }
 
-    fn can_do(&self, can_do: CanDo) -> vst::api::Supported {
+    fn can_do(can_do: CanDo) -> Supported {
         use vst::api::Supported::*;
         use vst::plugin::CanDo::*;
 
@@ -143,68 +164,101 @@ impl Plugin for NoteOffDelayPlugin {
                 // bug here, or bitwig ends up being confused about midi events coming out of VSTs,
                 // and some notes end up still running. As it's not really useful in this context,
                 // let the feature off.
-                // if s == "MPE" {
-                //     Yes
-                // } else {
-                //     DebugSocket::send(&*s);
-                //     No
-                // }
                 Maybe
             }
         }
     }
 
-    fn process(&mut self, audio_buffer: &mut AudioBuffer<f32>) {
-        self.send_events(audio_buffer.samples());
-        self.increase_time_in_samples(audio_buffer.samples());
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate
     }
 
-    fn process_events(&mut self, events: &Events) {
-        self.debug_events_in(events);
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        _transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    ) {
+        self.debug_events_in(inputs);
+        self.queue_events(inputs);
+        self.send_events(samples, outputs);
+        self.increase_time_in_samples(samples);
+    }
+}
 
-        let note_off_delay = self.seconds_to_samples(self.parameters
-            .get_exponential_scale_parameter(Parameter::Delay, 10., 20.));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for event in events.events() {
-            let midi_event = if let Event::Midi(midi_event) = event {
-                midi_event
-            } else {
-                continue
-            };
+    const BLOCK_SIZE: usize = 64;
 
-            // TODO: minimum time, maximum time ( with delay )
+    // inputs and outputs delta frames are from the start of the first block
+    fn play(plugin: &mut NoteOffDelayPlugin, inputs: &[TimedMidiMessage], blocks: usize) -> Vec<TimedMidiMessage> {
+        let mut played = vec![];
+        for block in 0..blocks {
+            let block_start = block * BLOCK_SIZE;
+            let block_inputs: Vec<TimedMidiMessage> = inputs
+                .iter()
+                .filter(|input| (block_start..block_start + BLOCK_SIZE).contains(&input.delta_frames))
+                .map(|input| TimedMidiMessage::new(input.data, input.delta_frames - block_start))
+                .collect();
+            let mut outputs = vec![];
+            plugin.process(&block_inputs, BLOCK_SIZE, &TransportInfo::default(), &mut outputs);
+            played.extend(
+                outputs
+                    .iter()
+                    .map(|output| TimedMidiMessage::new(output.data, output.delta_frames + block_start)),
+            );
+        }
+        played
+    }
 
-            match MidiMessageType::from(&midi_event.data) {
-                MidiMessageType::NoteOffMessage(_) => {
-                    self.message_queue.insert_message(
-                        midi_event.data,
-                        midi_event.delta_frames as usize + self.current_time_in_samples, MessageReason::Live,
-                    );
+    fn plugin_with_delay(value: f32) -> (NoteOffDelayPlugin, usize) {
+        let mut plugin = NoteOffDelayPlugin::default();
+        plugin.set_sample_rate(1000.);
+        plugin.parameters.set_parameter(Parameter::Delay as i32, value);
+        let delay =
+            plugin.seconds_to_samples(plugin.parameters.get_exponential_scale_parameter(Parameter::Delay, 10., 20.));
+        (plugin, delay)
+    }
 
-                    if note_off_delay > 0 {
-                        // send two times the note off, the live one will be only used to mark the note on as delayed
-                        self.message_queue.insert_message(
-                            midi_event.data,
-                            note_off_delay + midi_event.delta_frames as usize + self.current_time_in_samples,
-                            MessageReason::Delayed,
-                        );
-                    }
+    #[test]
+    fn note_offs_are_delayed() {
+        let (mut plugin, delay) = plugin_with_delay(0.1);
+        assert!(delay > BLOCK_SIZE);
 
-                }
-                MidiMessageType::Unsupported => {
-                    continue;
-                }
-                _ => {
-                    self.message_queue.insert_message(
-                        midi_event.data,
-                        midi_event.delta_frames as usize + self.current_time_in_samples, MessageReason::Live,
-                    );
-                }
-            };
-        }
+        let inputs = [
+            TimedMidiMessage::new([0x90, 60, 100], 0),
+            TimedMidiMessage::new([0xB0, 1, 64], 5),
+            TimedMidiMessage::new([0x80, 60, 0], 10),
+        ];
+        assert_eq!(
+            play(&mut plugin, &inputs, (10 + delay) / BLOCK_SIZE + 2),
+            vec![inputs[0], inputs[1], TimedMidiMessage::new([0x80, 60, 0], 10 + delay)]
+        );
     }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn vst::plugin::PluginParameters> {
-        Arc::clone(&self.parameters) as Arc<dyn vst::plugin::PluginParameters>
+    // a new note stops the delayed ones beyond the maximum
+    #[test]
+    fn max_notes() {
+        let (mut plugin, delay) = plugin_with_delay(0.1);
+        plugin.parameters.set_max_notes(1);
+
+        let inputs = [
+            TimedMidiMessage::new([0x90, 60, 100], 0),
+            TimedMidiMessage::new([0x80, 60, 0], 10),
+            TimedMidiMessage::new([0x90, 62, 100], 20),
+            TimedMidiMessage::new([0x80, 62, 0], 30),
+        ];
+        assert_eq!(
+            play(&mut plugin, &inputs, (30 + delay) / BLOCK_SIZE + 2),
+            vec![
+                inputs[0],
+                TimedMidiMessage::new([0x80, 60, 0], 20),
+                inputs[2],
+                TimedMidiMessage::new([0x80, 62, 0], 30 + delay),
+            ]
+        );
     }
 }
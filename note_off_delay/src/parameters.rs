@@ -1,25 +1,36 @@
-use std::sync::Mutex;
-
-use vst::plugin::HostCallback ;
 use vst::util::ParameterTransfer;
 
-use util::debug::DebugSocket;
+use util::debug::DebugLevel;
+use util::debug_log;
 use util::parameter_value_conversion::{f32_to_byte, f32_to_bool};
-use util::{HostCallbackLock, duration_display};
+use util::duration_display;
+use util::parameter_definition::{ParameterDefinition, ParameterScale};
 use util::parameters::ParameterConversion;
+use util::scheduler_statistics::SchedulerStatistics;
+
+pub const PARAMETER_COUNT: usize = 4;
+// the statistics come last, they are not stored in presets nor shown to VST2 hosts, which have no
+// read-only parameters
+pub const STORED_PARAMETER_COUNT: usize = PARAMETER_COUNT - 1;
 
-const PARAMETER_COUNT: usize = 3;
+pub const PARAMETER_DEFINITIONS: [ParameterDefinition; PARAMETER_COUNT] = [
+    ParameterDefinition::new("Delay", ParameterScale::Exponential { max: 10., factor: 20. }),
+    ParameterDefinition::new("Max Notes", ParameterScale::Byte { divisor: 4 }),
+    ParameterDefinition::new("Apply max notes to delayed notes only", ParameterScale::Bool),
+    ParameterDefinition::new("Statistics", ParameterScale::Normalized).read_only(),
+];
 
 pub struct NoteOffDelayPluginParameters {
-    pub host_mutex: Mutex<HostCallbackLock>,
     pub transfer: ParameterTransfer,
+    pub statistics: SchedulerStatistics,
 }
 
 #[repr(i32)]
 pub enum Parameter {
     Delay = 0,
     MaxNotes,
-    MaxNotesAppliesToDelayedNotesOnly
+    MaxNotesAppliesToDelayedNotesOnly,
+    Statistics,
 }
 
 impl From<i32> for Parameter {
@@ -28,6 +39,7 @@ impl From<i32> for Parameter {
             0 => Parameter::Delay,
             1 => Parameter::MaxNotes,
             2 => Parameter::MaxNotesAppliesToDelayedNotesOnly,
+            3 => Parameter::Statistics,
             _ => panic!("no such parameter {}", i),
         }
     }
@@ -47,19 +59,12 @@ impl ParameterConversion<Parameter> for NoteOffDelayPluginParameters {
     }
 
     fn get_parameter_count() -> usize {
-        PARAMETER_COUNT
+        STORED_PARAMETER_COUNT
     }
 }
 
 
 impl NoteOffDelayPluginParameters {
-    pub fn new(host: HostCallback) -> Self {
-        NoteOffDelayPluginParameters {
-            host_mutex: Mutex::new(HostCallbackLock { host }),
-            ..Default::default()
-        }
-    }
-
     pub fn get_max_notes(&self) -> u8 {
         self.get_byte_parameter(Parameter::MaxNotes) / 4
     }
@@ -73,8 +78,8 @@ impl NoteOffDelayPluginParameters {
 impl Default for NoteOffDelayPluginParameters {
     fn default() -> Self {
         NoteOffDelayPluginParameters {
-            host_mutex: Default::default(),
             transfer: ParameterTransfer::new(PARAMETER_COUNT),
+            statistics: Default::default(),
         }
     }
 }
@@ -107,6 +112,8 @@ impl vst::plugin::PluginParameters for NoteOffDelayPluginParameters {
                     "Off"
                 }.to_string()
             }
+
+            Parameter::Statistics => self.statistics.snapshot().summary(),
         }
     }
 
@@ -115,6 +122,7 @@ impl vst::plugin::PluginParameters for NoteOffDelayPluginParameters {
             Parameter::Delay => "Delay",
             Parameter::MaxNotes => "Max Notes",
             Parameter::MaxNotesAppliesToDelayedNotesOnly => "Apply max notes to delayed notes only",
+            Parameter::Statistics => "Statistics",
         }
         .to_string()
     }
@@ -126,7 +134,7 @@ impl vst::plugin::PluginParameters for NoteOffDelayPluginParameters {
     fn set_parameter(&self, index: i32, value: f32) {
         match index.into() {
             Parameter::Delay => {
-                DebugSocket::send(&*format!("Parameter {} set to {}", index, value));
+                debug_log!(DebugLevel::Debug, "Parameter {} set to {}", index, value);
                 let old_value = self.get_parameter(index);
                 if (value - old_value).abs() > 0.0001 {
                     self.transfer.set_parameter(index as usize, value)
@@ -143,6 +151,9 @@ impl vst::plugin::PluginParameters for NoteOffDelayPluginParameters {
                 self.set_bool_parameter(Parameter::MaxNotesAppliesToDelayedNotesOnly,
                                         f32_to_bool(value))
             }
+            Parameter::Statistics => {
+                // read-only, only its text is meaningful
+            }
         }
     }
 
@@ -0,0 +1,158 @@
+// checks that the CLAP build of a plugin sends the same midi messages as its VST2 build, both
+// being exported by the same library.
+//
+// usage: clap_check <plugin library or crate name> [--input events.txt|file.mid] [--set Name=value]...
+//                   [--sample-rate 44100] [--block-size 512] [--length seconds]
+//
+// parameters are set on the VST2 instance, and its preset is loaded into the CLAP instance through
+// the state extension, which checks saving and loading on the way. Parameter changes within the
+// input are not supported, as the formats don't apply them the same way. Differences are printed,
+// and the exit code is 1 if there are any.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use util::midi_file::MidiFile;
+
+use tools::clap_host::compare_formats;
+use tools::event_list::{parse_event_list, ScriptEvent};
+use tools::offline_host::resolve_library;
+
+const DEFAULT_TAIL_IN_SECONDS: f32 = 10.0;
+
+struct Options {
+    plugin: String,
+    input: Option<PathBuf>,
+    parameters: Vec<(String, String)>,
+    sample_rate: f32,
+    block_size: usize,
+    length_in_seconds: Option<f32>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut args = args.iter();
+        let plugin = args.next().ok_or_else(|| "missing plugin".to_string())?;
+
+        let mut options = Options {
+            plugin: plugin.clone(),
+            input: None,
+            parameters: vec![],
+            sample_rate: 44100.0,
+            block_size: 512,
+            length_in_seconds: None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            let invalid = |value: &String| format!("invalid value {} for {}", value, arg);
+            match arg.as_ref() {
+                "--input" => options.input = Some(PathBuf::from(value()?)),
+                "--set" => {
+                    let setting = value()?;
+                    let separator = setting.find('=').ok_or_else(|| invalid(setting))?;
+                    options.parameters.push((
+                        setting[..separator].trim().to_string(),
+                        setting[separator + 1..].trim().to_string(),
+                    ));
+                }
+                "--sample-rate" => {
+                    let rate = value()?;
+                    options.sample_rate = rate.parse().ok().filter(|rate| *rate > 0.0).ok_or_else(|| invalid(rate))?;
+                }
+                "--block-size" => {
+                    let size = value()?;
+                    options.block_size = size.parse().ok().filter(|size| *size > 0).ok_or_else(|| invalid(size))?;
+                }
+                "--length" => {
+                    let length = value()?;
+                    options.length_in_seconds = Some(length.parse().map_err(|_| invalid(length))?);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn read_input(options: &Options) -> Result<Vec<ScriptEvent>, String> {
+    let path = match &options.input {
+        Some(path) => path,
+        None => return Ok(vec![]),
+    };
+
+    let is_midi_file = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi"));
+    let script: Vec<ScriptEvent> = if is_midi_file {
+        MidiFile::read(path, options.sample_rate)?.messages.into_iter().map(ScriptEvent::Message).collect()
+    } else {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        parse_event_list(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+    };
+
+    for event in &script {
+        if let ScriptEvent::Parameter { name, .. } = event {
+            return Err(format!("{}: parameter change of {} in the input, use --set instead", path.display(), name));
+        }
+    }
+    Ok(script)
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let script = read_input(&options)?;
+    let path = resolve_library(&options.plugin, Path::new(""));
+
+    let length = match options.length_in_seconds {
+        Some(seconds) => (seconds * options.sample_rate) as usize,
+        None => {
+            let last_position = script.last().map(ScriptEvent::position).unwrap_or(0);
+            last_position + (DEFAULT_TAIL_IN_SECONDS * options.sample_rate) as usize
+        }
+    };
+
+    let comparison = compare_formats(
+        &path,
+        &options.parameters,
+        &script,
+        options.sample_rate,
+        options.block_size,
+        length,
+    )?;
+    if !comparison.same_state {
+        println!("the CLAP state differs from the VST2 preset it was loaded from");
+    }
+
+    if comparison.vst_output != comparison.clap_output {
+        let vst_lines: Vec<&str> = comparison.vst_output.lines().collect();
+        let clap_lines: Vec<&str> = comparison.clap_output.lines().collect();
+        for line in vst_lines.iter().filter(|line| !clap_lines.contains(line)) {
+            println!("vst2 {}", line);
+        }
+        for line in clap_lines.iter().filter(|line| !vst_lines.contains(line)) {
+            println!("clap {}", line);
+        }
+        println!("{} events from VST2, {} from CLAP", vst_lines.len(), clap_lines.len());
+    } else {
+        println!("{}: {} events, same output", path.display(), comparison.vst_output.lines().count());
+    }
+
+    Ok(comparison.is_same())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match Options::parse(&args).and_then(run) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
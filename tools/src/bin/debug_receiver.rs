@@ -0,0 +1,203 @@
+// listens to the lines sent by util::debug::DebugSocket, and prints them tagged with the source port
+// of the sending process, midi messages decoded.
+//
+// usage: debug_receiver [--listen 127.0.0.1:5555] [--plugin note_off_delay]... [--type note]...
+//                       [--output debug.log]
+//
+// --plugin and --type can be repeated, types are note, cc, pressure, pitchbend, other and text
+// ( lines without any midi message ).
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::UdpSocket;
+use std::process;
+use std::str::FromStr;
+
+use util::debug::DEFAULT_DESTINATION;
+use util::messages::describe_message;
+use util::midi_message_type::MidiMessageType;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MessageKind {
+    Note,
+    CC,
+    Pressure,
+    PitchBend,
+    Other,
+    Text,
+}
+
+impl FromStr for MessageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_ref() {
+            "note" => Ok(MessageKind::Note),
+            "cc" => Ok(MessageKind::CC),
+            "pressure" => Ok(MessageKind::Pressure),
+            "pitchbend" => Ok(MessageKind::PitchBend),
+            "other" => Ok(MessageKind::Other),
+            "text" => Ok(MessageKind::Text),
+            _ => Err(format!("unknown message type {}", s)),
+        }
+    }
+}
+
+impl From<&[u8; 3]> for MessageKind {
+    fn from(data: &[u8; 3]) -> Self {
+        match MidiMessageType::from(data) {
+            MidiMessageType::NoteOnMessage(_) | MidiMessageType::NoteOffMessage(_) => MessageKind::Note,
+            MidiMessageType::CCMessage(_) => MessageKind::CC,
+            MidiMessageType::PressureMessage(_) => MessageKind::Pressure,
+            MidiMessageType::PitchBendMessage(_) => MessageKind::PitchBend,
+            _ => MessageKind::Other,
+        }
+    }
+}
+
+struct Options {
+    listen: String,
+    plugins: Vec<String>,
+    types: Vec<MessageKind>,
+    output: Option<File>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            listen: DEFAULT_DESTINATION.to_string(),
+            plugins: vec![],
+            types: vec![],
+            output: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            match arg.as_ref() {
+                "--listen" => options.listen = value()?.clone(),
+                "--plugin" => options.plugins.push(value()?.clone()),
+                "--type" => options.types.push(value()?.parse()?),
+                "--output" => {
+                    let path = value()?;
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .map_err(|e| format!("cannot open {}: {}", path, e))?;
+                    options.output = Some(file);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+// lines tagged by a debug session start with "<plugin>#<instance>"
+fn split_tag(line: &str) -> (Option<&str>, &str) {
+    match line.find(' ') {
+        Some(position) if line[..position].contains('#') => (Some(&line[..position]), &line[position + 1..]),
+        _ => (None, line),
+    }
+}
+
+// finds the "[0x90 0x3C 0x40]" groups of a line
+fn find_messages(line: &str) -> Vec<[u8; 3]> {
+    let mut messages = vec![];
+    let mut rest = line;
+
+    while let Some(start) = rest.find('[') {
+        let inside = &rest[start + 1..];
+        let end = match inside.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let bytes = inside[..end]
+            .split_whitespace()
+            .map(|byte| byte.strip_prefix("0x").and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>();
+
+        if let Some(bytes) = bytes {
+            if bytes.len() == 3 {
+                messages.push([bytes[0], bytes[1], bytes[2]]);
+            }
+        }
+        rest = &inside[end + 1..];
+    }
+
+    messages
+}
+
+fn find_reason(line: &str) -> Option<&str> {
+    line.split_whitespace().find_map(|word| word.strip_prefix("reason="))
+}
+
+fn format_line(options: &Options, port: u16, line: &str) -> Option<String> {
+    let (tag, text) = split_tag(line);
+
+    if !options.plugins.is_empty() {
+        let plugin = tag.and_then(|tag| tag.split('#').next());
+        if !options.plugins.iter().any(|name| Some(name.as_ref()) == plugin) {
+            return None;
+        }
+    }
+
+    let messages = find_messages(text);
+    if !options.types.is_empty() {
+        let matches = if messages.is_empty() {
+            options.types.contains(&MessageKind::Text)
+        } else {
+            messages
+                .iter()
+                .any(|message| options.types.contains(&MessageKind::from(message)))
+        };
+        if !matches {
+            return None;
+        }
+    }
+
+    let mut formatted = format!("{:5} {:20} {}", port, tag.unwrap_or("-"), text);
+    for message in &messages {
+        formatted += &*format!("\n{:27}=> {}", "", describe_message(message));
+    }
+    if let Some(reason) = find_reason(text) {
+        formatted += &*format!(" ({})", reason);
+    }
+    Some(formatted)
+}
+
+fn receive(mut options: Options) -> Result<(), String> {
+    let socket = UdpSocket::bind(&options.listen)
+        .map_err(|e| format!("cannot listen on {}: {}", options.listen, e))?;
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let (length, from) = socket
+            .recv_from(&mut buffer)
+            .map_err(|e| format!("cannot receive: {}", e))?;
+
+        let datagram = String::from_utf8_lossy(&buffer[..length]);
+        for line in datagram.lines().filter(|line| !line.trim().is_empty()) {
+            if let Some(formatted) = format_line(&options, from.port(), line) {
+                println!("{}", formatted);
+                if let Some(output) = options.output.as_mut() {
+                    writeln!(output, "{}", formatted).map_err(|e| format!("cannot write: {}", e))?;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = Options::parse(&args).and_then(receive);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+}
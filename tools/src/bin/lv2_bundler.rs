@@ -0,0 +1,56 @@
+// writes LV2 bundles for plugin libraries: the library is copied in <crate>.lv2, next to the
+// manifest.ttl and plugin .ttl written by the library itself, from its parameter definitions.
+//
+// usage: lv2_bundler [--output-directory artefact] [library or crate name]...
+//
+// without any library, bundles are written for all the plugins of the workspace.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tools::lv2_host::write_bundle;
+use tools::offline_host::resolve_library;
+
+const PLUGINS: [&str; 6] = [
+    "note_generator",
+    "note_off_delay",
+    "filter_out_non_note",
+    "note_fan_out",
+    "midi_delay",
+    "max_note_duration",
+];
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut output_directory = PathBuf::from("artefact");
+    let mut plugins = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--output-directory" => {
+                output_directory = PathBuf::from(args.next().ok_or_else(|| format!("missing value for {}", arg))?)
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown argument {}", arg)),
+            _ => plugins.push(arg.clone()),
+        }
+    }
+    if plugins.is_empty() {
+        plugins = PLUGINS.iter().map(|plugin| plugin.to_string()).collect();
+    }
+
+    for plugin in &plugins {
+        let bundle = write_bundle(&resolve_library(plugin, Path::new("")), &output_directory)?;
+        println!("Created bundle {}", bundle.display());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,401 @@
+// runs plugins as a JACK client, between a keyboard and a synth, without a DAW. Only built with the
+// jack-client feature, as it needs the JACK library.
+//
+// usage: note-fx-jack (--plugin <library or crate name> [--set Name=value]...)... | --chain setup.chain
+//                     [--name note-fx] [--connect-input port]... [--connect-output port]...
+//                     [--test events.txt [--expect expected.txt] [--length seconds]]
+//
+// plugins given with --plugin are chained in order, each --set applying to the plugin before it, a
+// .chain file ( see tools::plugin_graph ) can be used instead, and holds the parameters. The client
+// has a midi_in and a midi_out port, connected to the given ports once running, and runs until
+// interrupted.
+//
+// --test checks the setup on any JACK server, like one started with "jackd -d dummy": a player
+// client plays the event list into midi_in, a recorder client records midi_out, and what was
+// recorded is printed, or compared to --expect, the exit code being 1 if they differ.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use jack::{
+    AsyncClient, Client, ClientOptions, ClosureProcessHandler, Control, MidiIn, MidiOut, Port, PortSpec, ProcessScope,
+    RawMidi,
+};
+use vst::event::MidiEvent;
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+use util::{make_midi_message, message_length};
+
+use tools::event_list::{format_event_list, parse_event_list, timed_message, ScriptEvent};
+use tools::offline_host::{resolve_library, BlockProcessor, OfflinePlugin};
+use tools::plugin_graph::PluginGraph;
+
+const DEFAULT_TEST_TAIL_IN_SECONDS: f32 = 2.0;
+
+struct PluginOptions {
+    library: String,
+    parameters: Vec<(String, String)>,
+}
+
+struct Options {
+    plugins: Vec<PluginOptions>,
+    chain: Option<PathBuf>,
+    name: String,
+    connect_inputs: Vec<String>,
+    connect_outputs: Vec<String>,
+    test: Option<PathBuf>,
+    expect: Option<PathBuf>,
+    length_in_seconds: Option<f32>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            plugins: vec![],
+            chain: None,
+            name: "note-fx".to_string(),
+            connect_inputs: vec![],
+            connect_outputs: vec![],
+            test: None,
+            expect: None,
+            length_in_seconds: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            let invalid = |value: &String| format!("invalid value {} for {}", value, arg);
+            match arg.as_ref() {
+                "--plugin" => options.plugins.push(PluginOptions {
+                    library: value()?.clone(),
+                    parameters: vec![],
+                }),
+                "--set" => {
+                    let setting = value()?;
+                    let separator = setting.find('=').ok_or_else(|| invalid(setting))?;
+                    let plugin = options
+                        .plugins
+                        .last_mut()
+                        .ok_or_else(|| format!("--set {} must follow a --plugin", setting))?;
+                    plugin.parameters.push((
+                        setting[..separator].trim().to_string(),
+                        setting[separator + 1..].trim().to_string(),
+                    ));
+                }
+                "--chain" => options.chain = Some(PathBuf::from(value()?)),
+                "--name" => options.name = value()?.clone(),
+                "--connect-input" => options.connect_inputs.push(value()?.clone()),
+                "--connect-output" => options.connect_outputs.push(value()?.clone()),
+                "--test" => options.test = Some(PathBuf::from(value()?)),
+                "--expect" => options.expect = Some(PathBuf::from(value()?)),
+                "--length" => {
+                    let length = value()?;
+                    options.length_in_seconds =
+                        Some(length.parse().ok().filter(|length| *length > 0.0).ok_or_else(|| invalid(length))?);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        if options.plugins.is_empty() == options.chain.is_none() {
+            return Err("expected either --plugin or --chain".to_string());
+        }
+        if options.test.is_none() && (options.expect.is_some() || options.length_in_seconds.is_some()) {
+            return Err("--expect and --length only apply to --test".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn load_processor(options: &Options) -> Result<Box<dyn BlockProcessor>, String> {
+    if let Some(chain) = &options.chain {
+        return Ok(Box::new(PluginGraph::load(chain)?));
+    }
+
+    let mut plugins = vec![];
+    for plugin_options in &options.plugins {
+        let plugin = OfflinePlugin::load(&resolve_library(&plugin_options.library, Path::new("")))?;
+        for (name, value) in &plugin_options.parameters {
+            plugin.set_parameter_by_name(name, value)?;
+        }
+        plugins.push(plugin);
+    }
+    Ok(Box::new(PluginGraph::chain(plugins)))
+}
+
+// the processor is only used by the process callback, once the client is activated
+struct SendProcessor(Box<dyn BlockProcessor>);
+
+unsafe impl Send for SendProcessor {}
+
+fn read_midi(bytes: &[u8]) -> Option<[u8; 3]> {
+    if bytes.is_empty() || bytes.len() > 3 || bytes[0] < 0x80 || bytes[0] >= 0xF0 {
+        return None;
+    }
+    let mut data = [0u8; 3];
+    data[..bytes.len()].copy_from_slice(bytes);
+    Some(data)
+}
+
+fn client(name: &str) -> Result<Client, String> {
+    Client::new(name, ClientOptions::NO_START_SERVER)
+        .map(|(client, _status)| client)
+        .map_err(|e| format!("cannot open JACK client {}: {:?}", name, e))
+}
+
+fn port_name<T: PortSpec>(port: &Port<T>) -> Result<String, String> {
+    port.name().map_err(|e| format!("{:?}", e))
+}
+
+fn connect(client: &Client, source: &str, destination: &str) -> Result<(), String> {
+    client
+        .connect_ports_by_name(source, destination)
+        .map_err(|e| format!("cannot connect {} to {}: {:?}", source, destination, e))
+}
+
+type ProcessHandler = ClosureProcessHandler<Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>>;
+
+struct Filter {
+    client: AsyncClient<(), ProcessHandler>,
+    input: String,
+    output: String,
+}
+
+// the client running the plugins, midi_in to midi_out
+fn start_filter(options: &Options) -> Result<Filter, String> {
+    let client = client(&options.name)?;
+    let midi_in = client.register_port("midi_in", MidiIn::default()).map_err(|e| format!("{:?}", e))?;
+    let mut midi_out = client.register_port("midi_out", MidiOut::default()).map_err(|e| format!("{:?}", e))?;
+    let input = port_name(&midi_in)?;
+    let output = port_name(&midi_out)?;
+
+    let mut processor = load_processor(options)?;
+    processor.set_sample_rate(client.sample_rate() as f32);
+    processor.set_block_size(client.buffer_size() as usize);
+    processor.resume();
+    let mut processor = SendProcessor(processor);
+    let mut inputs: Vec<MidiEvent> = Vec::with_capacity(256);
+
+    let process: Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send> = Box::new(move |_, scope| {
+        let samples = scope.n_frames();
+        inputs.clear();
+        for raw in midi_in.iter(scope) {
+            if let Some(data) = read_midi(raw.bytes) {
+                inputs.push(make_midi_message(data, raw.time as i32));
+            }
+        }
+
+        let mut outputs = processor.0.process_block(&inputs, samples as usize);
+        outputs.sort_by_key(|event| event.delta_frames);
+        let mut writer = midi_out.writer(scope);
+        for event in &outputs {
+            let _ = writer.write(&RawMidi {
+                time: (event.delta_frames.max(0) as u32).min(samples.saturating_sub(1)),
+                bytes: &event.data[..message_length(event.data[0])],
+            });
+        }
+        Control::Continue
+    });
+
+    let client = client
+        .activate_async((), ClosureProcessHandler::new(process))
+        .map_err(|e| format!("cannot activate {}: {:?}", options.name, e))?;
+
+    for source in &options.connect_inputs {
+        connect(client.as_client(), source, &input)?;
+    }
+    for destination in &options.connect_outputs {
+        connect(client.as_client(), &output, destination)?;
+    }
+
+    Ok(Filter { client, input, output })
+}
+
+fn run_daemon(options: &Options) -> Result<bool, String> {
+    let filter = start_filter(options)?;
+    println!("{} running, {} -> {}", options.name, filter.input, filter.output);
+
+    let (sender, receiver) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = sender.send(());
+    })
+    .map_err(|e| e.to_string())?;
+    let _ = receiver.recv();
+
+    filter.client.deactivate().map_err(|e| format!("{:?}", e))?;
+    Ok(true)
+}
+
+// positions are relative to the first cycle of the player once everything is connected, the same
+// cycle for the recorder as the player, the filter and the recorder are processed in that order
+#[derive(Default)]
+struct TestClock {
+    connected: AtomicBool,
+    started: AtomicBool,
+    start: AtomicU32,
+    // last position recorded, to know when to stop
+    position: AtomicU32,
+}
+
+fn run_test(options: &Options, path: &Path) -> Result<bool, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let mut script: Vec<(u32, [u8; 3])> = vec![];
+    for event in parse_event_list(&text).map_err(|e| format!("{}: {}", path.display(), e))? {
+        match event {
+            ScriptEvent::Message(message) => script.push((message.play_time_in_samples as u32, message.data.into())),
+            ScriptEvent::Parameter { name, .. } => {
+                let error = format!("{}: parameter change of {} in the input, use --set instead", path.display(), name);
+                return Err(error);
+            }
+        }
+    }
+
+    let filter = start_filter(options)?;
+    let clock = Arc::new(TestClock::default());
+    let recorded = Arc::new(Mutex::new(vec![]));
+
+    let player = client(&format!("{}-player", options.name))?;
+    let sample_rate = player.sample_rate() as f32;
+    let mut player_out = player.register_port("out", MidiOut::default()).map_err(|e| format!("{:?}", e))?;
+    let player_port = port_name(&player_out)?;
+    let player_clock = Arc::clone(&clock);
+    let mut next = 0;
+    let play: Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send> = Box::new(move |_, scope| {
+        if !player_clock.connected.load(Ordering::Acquire) {
+            return Control::Continue;
+        }
+        if !player_clock.started.load(Ordering::Acquire) {
+            player_clock.start.store(scope.last_frame_time(), Ordering::Release);
+            player_clock.started.store(true, Ordering::Release);
+        }
+        let block_start = scope.last_frame_time().wrapping_sub(player_clock.start.load(Ordering::Acquire));
+        let block_end = block_start + scope.n_frames();
+
+        let mut writer = player_out.writer(scope);
+        while next < script.len() && script[next].0 < block_end {
+            let (position, data) = script[next];
+            let _ = writer.write(&RawMidi {
+                time: position.saturating_sub(block_start),
+                bytes: &data[..message_length(data[0])],
+            });
+            next += 1;
+        }
+        Control::Continue
+    });
+    let player = player
+        .activate_async((), ClosureProcessHandler::new(play))
+        .map_err(|e| format!("cannot activate the player: {:?}", e))?;
+
+    let recorder = client(&format!("{}-recorder", options.name))?;
+    let recorder_in = recorder.register_port("in", MidiIn::default()).map_err(|e| format!("{:?}", e))?;
+    let recorder_port = port_name(&recorder_in)?;
+    let recorder_clock = Arc::clone(&clock);
+    let recorder_messages = Arc::clone(&recorded);
+    let record: Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send> = Box::new(move |_, scope| {
+        if !recorder_clock.started.load(Ordering::Acquire) {
+            return Control::Continue;
+        }
+        let block_start = scope.last_frame_time().wrapping_sub(recorder_clock.start.load(Ordering::Acquire));
+        if let Ok(mut recorded) = recorder_messages.lock() {
+            for raw in recorder_in.iter(scope) {
+                if let Some(data) = read_midi(raw.bytes) {
+                    recorded.push(timed_message(data, (block_start + raw.time) as usize));
+                }
+            }
+        }
+        recorder_clock.position.store(block_start + scope.n_frames(), Ordering::Release);
+        Control::Continue
+    });
+    let recorder = recorder
+        .activate_async((), ClosureProcessHandler::new(record))
+        .map_err(|e| format!("cannot activate the recorder: {:?}", e))?;
+
+    connect(player.as_client(), &player_port, &filter.input)?;
+    connect(recorder.as_client(), &filter.output, &recorder_port)?;
+    clock.connected.store(true, Ordering::Release);
+
+    let length = match options.length_in_seconds {
+        Some(seconds) => (seconds * sample_rate) as u32,
+        None => {
+            let last_position = script.last().map_or(0, |(position, _)| *position);
+            last_position + (DEFAULT_TEST_TAIL_IN_SECONDS * sample_rate) as u32
+        }
+    };
+    while clock.position.load(Ordering::Acquire) < length {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    player.deactivate().map_err(|e| format!("{:?}", e))?;
+    recorder.deactivate().map_err(|e| format!("{:?}", e))?;
+    filter.client.deactivate().map_err(|e| format!("{:?}", e))?;
+
+    let mut recorded: Vec<AbsoluteTimeMidiMessage> = match recorded.lock() {
+        Ok(recorded) => recorded
+            .iter()
+            .filter(|message| (message.play_time_in_samples as u32) < length)
+            .cloned()
+            .collect(),
+        Err(_) => vec![],
+    };
+    recorded.sort_by_key(|message| message.play_time_in_samples);
+    let output = format_event_list(&recorded);
+
+    let expect = match &options.expect {
+        Some(expect) => expect,
+        None => {
+            print!("{}", output);
+            return Ok(true);
+        }
+    };
+    let text = fs::read_to_string(expect).map_err(|e| format!("cannot read {}: {}", expect.display(), e))?;
+    let expected: Vec<AbsoluteTimeMidiMessage> = parse_event_list(&text)
+        .map_err(|e| format!("{}: {}", expect.display(), e))?
+        .into_iter()
+        .filter_map(|event| match event {
+            ScriptEvent::Message(message) => Some(message),
+            ScriptEvent::Parameter { .. } => None,
+        })
+        .collect();
+    let expected = format_event_list(&expected);
+
+    if expected == output {
+        return Ok(true);
+    }
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let output_lines: Vec<&str> = output.lines().collect();
+    for line in expected_lines.iter().filter(|line| !output_lines.contains(line)) {
+        println!("- {}", line);
+    }
+    for line in output_lines.iter().filter(|line| !expected_lines.contains(line)) {
+        println!("+ {}", line);
+    }
+    Ok(false)
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    match &options.test {
+        Some(path) => run_test(&options, path),
+        None => run_daemon(&options),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match Options::parse(&args).and_then(run) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
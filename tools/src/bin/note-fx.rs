@@ -0,0 +1,184 @@
+// applies plugins to midi files, without a DAW.
+//
+// usage: note-fx <input.mid>... (--plugin <library or crate name> [--set Name=value]...)...
+//                [--chain setup.chain] [--output out.mid | --output-directory directory]
+//                [--sample-rate 44100] [--block-size 512] [--tail seconds]
+//
+// plugins given with --plugin are chained in order, each --set applying to the plugin before it.
+// A .chain file ( see tools::plugin_graph ) can be used instead. Without --output, each input is
+// written next to itself as <name>.fx.mid. Processing goes on for --tail seconds ( 10 by default )
+// after the last event of the file, so delayed note offs are not lost.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use util::midi_file::MidiFile;
+
+use tools::event_list::ScriptEvent;
+use tools::offline_host::{resolve_library, run_script, BlockProcessor, OfflinePlugin};
+use tools::plugin_graph::PluginGraph;
+
+const DEFAULT_TAIL_IN_SECONDS: f32 = 10.0;
+
+struct PluginOptions {
+    library: String,
+    parameters: Vec<(String, String)>,
+}
+
+struct Options {
+    inputs: Vec<PathBuf>,
+    plugins: Vec<PluginOptions>,
+    chain: Option<PathBuf>,
+    output: Option<PathBuf>,
+    output_directory: Option<PathBuf>,
+    sample_rate: f32,
+    block_size: usize,
+    tail_in_seconds: f32,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            inputs: vec![],
+            plugins: vec![],
+            chain: None,
+            output: None,
+            output_directory: None,
+            sample_rate: 44100.0,
+            block_size: 512,
+            tail_in_seconds: DEFAULT_TAIL_IN_SECONDS,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            let invalid = |value: &String| format!("invalid value {} for {}", value, arg);
+            match arg.as_ref() {
+                "--plugin" => options.plugins.push(PluginOptions {
+                    library: value()?.clone(),
+                    parameters: vec![],
+                }),
+                "--set" => {
+                    let setting = value()?;
+                    let separator = setting.find('=').ok_or_else(|| invalid(setting))?;
+                    let plugin = options
+                        .plugins
+                        .last_mut()
+                        .ok_or_else(|| format!("--set {} must follow a --plugin", setting))?;
+                    plugin.parameters.push((
+                        setting[..separator].trim().to_string(),
+                        setting[separator + 1..].trim().to_string(),
+                    ));
+                }
+                "--chain" => options.chain = Some(PathBuf::from(value()?)),
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                "--output-directory" => options.output_directory = Some(PathBuf::from(value()?)),
+                "--sample-rate" => {
+                    let rate = value()?;
+                    options.sample_rate = rate.parse().ok().filter(|rate| *rate > 0.0).ok_or_else(|| invalid(rate))?;
+                }
+                "--block-size" => {
+                    let size = value()?;
+                    options.block_size = size.parse().ok().filter(|size| *size > 0).ok_or_else(|| invalid(size))?;
+                }
+                "--tail" => {
+                    let tail = value()?;
+                    options.tail_in_seconds = tail.parse().ok().filter(|tail| *tail >= 0.0).ok_or_else(|| invalid(tail))?;
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown argument {}", arg)),
+                _ => options.inputs.push(PathBuf::from(arg)),
+            }
+        }
+
+        if options.inputs.is_empty() {
+            return Err("no input file".to_string());
+        }
+        if options.plugins.is_empty() == options.chain.is_none() {
+            return Err("expected either --plugin or --chain".to_string());
+        }
+        if options.output.is_some() && (options.inputs.len() > 1 || options.output_directory.is_some()) {
+            return Err("--output takes a single input, use --output-directory instead".to_string());
+        }
+
+        Ok(options)
+    }
+
+    fn output_path(&self, input: &Path) -> PathBuf {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+
+        match &self.output_directory {
+            Some(directory) => directory.join(input.file_name().unwrap_or(input.as_os_str())),
+            None => {
+                let mut file_name = input.file_stem().map(OsString::from).unwrap_or_default();
+                file_name.push(".fx.mid");
+                input.with_file_name(file_name)
+            }
+        }
+    }
+}
+
+// loaded again for each file, so every file starts from a fresh state
+fn load_processor(options: &Options) -> Result<Box<dyn BlockProcessor>, String> {
+    if let Some(chain) = &options.chain {
+        return Ok(Box::new(PluginGraph::load(chain)?));
+    }
+
+    let mut plugins = vec![];
+    for plugin_options in &options.plugins {
+        let plugin = OfflinePlugin::load(&resolve_library(&plugin_options.library, Path::new("")))?;
+        for (name, value) in &plugin_options.parameters {
+            plugin.set_parameter_by_name(name, value)?;
+        }
+        plugins.push(plugin);
+    }
+    Ok(Box::new(PluginGraph::chain(plugins)))
+}
+
+fn process_file(options: &Options, input: &Path) -> Result<(), String> {
+    let midi_file = MidiFile::read(input, options.sample_rate)?;
+
+    let mut processor = load_processor(options)?;
+    processor.set_sample_rate(options.sample_rate);
+    processor.set_block_size(options.block_size);
+    processor.resume();
+
+    let last_position = midi_file
+        .messages
+        .last()
+        .map_or(0, |message| message.play_time_in_samples);
+    let length = last_position + (options.tail_in_seconds * options.sample_rate) as usize;
+
+    let script: Vec<ScriptEvent> = midi_file.messages.into_iter().map(ScriptEvent::Message).collect();
+    let messages = run_script(&mut *processor, &script, options.block_size, length)?;
+
+    let output = options.output_path(input);
+    let processed = MidiFile {
+        tempo_map: midi_file.tempo_map,
+        messages,
+    };
+    processed.write(&output, options.sample_rate)?;
+
+    println!("{} -> {}: {} events", input.display(), output.display(), processed.messages.len());
+    Ok(())
+}
+
+fn run(options: Options) -> Result<(), String> {
+    for input in &options.inputs {
+        process_file(&options, input)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = Options::parse(&args).and_then(run);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+}
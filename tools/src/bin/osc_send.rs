@@ -0,0 +1,77 @@
+// sends an OSC message to the plugins listening with util::osc, to try addresses without an OSC
+// application.
+//
+// usage: osc_send [--port 9000] <address> <value>
+//
+// the port defaults to NOTE_FX_OSC_PORT. Integers are sent as int arguments, other numbers as
+// float and anything else as a string, e.g. osc_send /note_off_delay/delay 500ms
+
+use std::env;
+use std::net::UdpSocket;
+use std::process;
+
+use util::osc::PORT_VARIABLE;
+
+// nul terminated, padded to 4 bytes
+fn push_string(packet: &mut Vec<u8>, text: &str) {
+    packet.extend_from_slice(text.as_bytes());
+    packet.push(0);
+    while packet.len() % 4 != 0 {
+        packet.push(0);
+    }
+}
+
+fn encode(address: &str, value: &str) -> Vec<u8> {
+    let mut packet = vec![];
+    push_string(&mut packet, address);
+    if let Ok(value) = value.parse::<i32>() {
+        push_string(&mut packet, ",i");
+        packet.extend_from_slice(&value.to_be_bytes());
+    } else if let Ok(value) = value.parse::<f32>() {
+        push_string(&mut packet, ",f");
+        packet.extend_from_slice(&value.to_be_bytes());
+    } else {
+        push_string(&mut packet, ",s");
+        push_string(&mut packet, value);
+    }
+    packet
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut port = env::var(PORT_VARIABLE).ok();
+    let mut positional = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--port" => port = Some(args.next().ok_or_else(|| format!("missing value for {}", arg))?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown argument {}", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let (address, value) = match positional.as_slice() {
+        [address, value] if address.starts_with('/') => (address, value),
+        _ => return Err("usage: osc_send [--port 9000] <address> <value>".to_string()),
+    };
+    let port = port
+        .ok_or_else(|| format!("no port given, and {} is not set", PORT_VARIABLE))?
+        .trim()
+        .parse::<u16>()
+        .map_err(|e| format!("invalid port: {}", e))?;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    socket
+        .send_to(&encode(address, value), ("127.0.0.1", port))
+        .map_err(|e| format!("cannot send to port {}: {}", port, e))?;
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,213 @@
+// runs a plugin library, an LV2 bundle, or a combination of plugins described in a .chain file, on
+// a midi file or an event list, and writes what it produced.
+//
+// usage: plugin_runner <plugin library, crate name, Bundle.lv2 or setup.chain>
+//                      [--input events.txt|file.mid] [--output events.txt|file.mid]
+//                      [--expect events.txt] [--set Name=value]...
+//                      [--sample-rate 44100] [--block-size 512] [--length seconds]
+//
+// event lists are described in tools::event_list and .chain files in tools::plugin_graph, where
+// --set takes "<plugin name>.<parameter>=value". LV2 control ports take plain values, as shown by
+// LV2 hosts, and can also be named by their symbol. The output is printed as an event list when
+// --output is missing. Without --length, the run stops 10 seconds after the last input event.
+// With --expect, the output is compared to an event list, and the exit code is 1 if they differ.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+use util::midi_file::{MidiFile, TempoMap};
+
+use tools::event_list::{format_event_list, parse_event_list, ScriptEvent};
+use tools::lv2_host::Lv2Plugin;
+use tools::offline_host::{resolve_library, run_script, BlockProcessor, OfflinePlugin};
+use tools::plugin_graph::PluginGraph;
+
+const DEFAULT_TAIL_IN_SECONDS: f32 = 10.0;
+
+struct Options {
+    plugin: String,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    expect: Option<PathBuf>,
+    parameters: Vec<(String, String)>,
+    sample_rate: f32,
+    block_size: usize,
+    length_in_seconds: Option<f32>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut args = args.iter();
+        let plugin = args.next().ok_or_else(|| "missing plugin".to_string())?;
+
+        let mut options = Options {
+            plugin: plugin.clone(),
+            input: None,
+            output: None,
+            expect: None,
+            parameters: vec![],
+            sample_rate: 44100.0,
+            block_size: 512,
+            length_in_seconds: None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            let invalid = |value: &String| format!("invalid value {} for {}", value, arg);
+            match arg.as_ref() {
+                "--input" => options.input = Some(PathBuf::from(value()?)),
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                "--expect" => options.expect = Some(PathBuf::from(value()?)),
+                "--set" => {
+                    let setting = value()?;
+                    let separator = setting.find('=').ok_or_else(|| invalid(setting))?;
+                    options.parameters.push((
+                        setting[..separator].trim().to_string(),
+                        setting[separator + 1..].trim().to_string(),
+                    ));
+                }
+                "--sample-rate" => {
+                    let rate = value()?;
+                    options.sample_rate = rate.parse().ok().filter(|rate| *rate > 0.0).ok_or_else(|| invalid(rate))?;
+                }
+                "--block-size" => {
+                    let size = value()?;
+                    options.block_size = size.parse().ok().filter(|size| *size > 0).ok_or_else(|| invalid(size))?;
+                }
+                "--length" => {
+                    let length = value()?;
+                    options.length_in_seconds = Some(length.parse().map_err(|_| invalid(length))?);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn is_midi_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi"),
+        None => false,
+    }
+}
+
+// the tempo map of a midi file input is kept for a midi file output
+fn read_input(options: &Options) -> Result<(Vec<ScriptEvent>, TempoMap), String> {
+    let path = match &options.input {
+        Some(path) => path,
+        None => return Ok((vec![], TempoMap::default())),
+    };
+
+    if is_midi_file(path) {
+        let midi_file = MidiFile::read(path, options.sample_rate)?;
+        let script = midi_file.messages.into_iter().map(ScriptEvent::Message).collect();
+        Ok((script, midi_file.tempo_map))
+    } else {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let script = parse_event_list(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok((script, TempoMap::default()))
+    }
+}
+
+fn load_processor(plugin: &str) -> Result<Box<dyn BlockProcessor>, String> {
+    let path = Path::new(plugin);
+    if path.extension().is_some_and(|extension| extension == "chain") {
+        Ok(Box::new(PluginGraph::load(path)?))
+    } else if path.extension().is_some_and(|extension| extension == "lv2") {
+        Ok(Box::new(Lv2Plugin::load(path)?))
+    } else {
+        Ok(Box::new(OfflinePlugin::load(&resolve_library(plugin, Path::new("")))?))
+    }
+}
+
+// comments and blank lines are ignored, so expected files can be annotated
+fn compare(outputs: &[AbsoluteTimeMidiMessage], path: &Path) -> Result<bool, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let expected = parse_event_list(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let expected: Vec<AbsoluteTimeMidiMessage> = expected
+        .into_iter()
+        .filter_map(|event| match event {
+            ScriptEvent::Message(message) => Some(message),
+            ScriptEvent::Parameter { .. } => None,
+        })
+        .collect();
+
+    let expected_text = format_event_list(&expected);
+    let output_text = format_event_list(outputs);
+    if expected_text == output_text {
+        return Ok(true);
+    }
+
+    let expected_lines: Vec<&str> = expected_text.lines().collect();
+    let output_lines: Vec<&str> = output_text.lines().collect();
+    for line in expected_lines.iter().filter(|line| !output_lines.contains(line)) {
+        println!("- {}", line);
+    }
+    for line in output_lines.iter().filter(|line| !expected_lines.contains(line)) {
+        println!("+ {}", line);
+    }
+    if expected_lines.len() != output_lines.len() {
+        println!("expected {} events, got {}", expected_lines.len(), output_lines.len());
+    }
+    Ok(false)
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let (script, tempo_map) = read_input(&options)?;
+
+    let mut plugin = load_processor(&options.plugin)?;
+    plugin.set_sample_rate(options.sample_rate);
+    plugin.set_block_size(options.block_size);
+    plugin.resume();
+
+    for (name, value) in &options.parameters {
+        plugin.set_parameter_by_name(name, value)?;
+    }
+
+    let length = match options.length_in_seconds {
+        Some(seconds) => (seconds * options.sample_rate) as usize,
+        None => {
+            let last_position = script.last().map(ScriptEvent::position).unwrap_or(0);
+            last_position + (DEFAULT_TAIL_IN_SECONDS * options.sample_rate) as usize
+        }
+    };
+
+    let outputs = run_script(&mut *plugin, &script, options.block_size, length)?;
+
+    match &options.output {
+        Some(path) if is_midi_file(path) => {
+            let midi_file = MidiFile {
+                tempo_map,
+                messages: outputs.clone(),
+            };
+            midi_file.write(path, options.sample_rate)?
+        }
+        Some(path) => fs::write(path, format_event_list(&outputs))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?,
+        None if options.expect.is_none() => print!("{}", format_event_list(&outputs)),
+        None => {}
+    }
+
+    match &options.expect {
+        Some(path) => compare(&outputs, path),
+        None => Ok(true),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match Options::parse(&args).and_then(run) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
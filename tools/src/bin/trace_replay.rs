@@ -0,0 +1,146 @@
+// replays a trace recorded with NOTE_FX_TRACE_DIRECTORY and compares the produced events with the
+// recorded ones, block by block.
+//
+// usage: trace_replay <trace file> [plugin library]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use vst::event::MidiEvent;
+
+use tools::offline_host::{library_path, OfflinePlugin};
+use util::messages::format_midi_event;
+use util::trace::{read_trace, TraceRecord};
+
+struct Block {
+    index: usize,
+    position: usize,
+    recorded: Vec<String>,
+    replayed: Vec<String>,
+}
+
+impl Block {
+    // reports lines missing from either side, keeping their order
+    fn report_differences(&self) -> bool {
+        let mut replayed = self.replayed.clone();
+        let mut missing = vec![];
+
+        for line in &self.recorded {
+            match replayed.iter().position(|replayed_line| replayed_line == line) {
+                Some(position) => {
+                    replayed.remove(position);
+                }
+                None => missing.push(line),
+            }
+        }
+
+        if missing.is_empty() && replayed.is_empty() {
+            return false;
+        }
+
+        println!("block {} at sample {} differs:", self.index, self.position);
+        for line in missing {
+            println!("  - {}", line);
+        }
+        for line in replayed {
+            println!("  + {}", line);
+        }
+        true
+    }
+}
+
+fn format_events(events: &[MidiEvent]) -> Vec<String> {
+    events.iter().map(format_midi_event).collect()
+}
+
+fn replay(trace_path: &Path, library: Option<PathBuf>) -> Result<bool, String> {
+    let trace = read_trace(trace_path)?;
+    let plugin_name = trace.plugin;
+
+    let library = library.unwrap_or_else(|| library_path(&plugin_name));
+    let mut plugin = OfflinePlugin::load(&library)?;
+    plugin.resume();
+
+    let mut pending_inputs: Vec<MidiEvent> = vec![];
+    let mut remaining_inputs = 0;
+    let mut current_block: Option<Block> = None;
+    let mut block_count = 0;
+    let mut differences = 0;
+
+    for record in trace.records {
+        match record {
+            TraceRecord::SampleRate(rate) => plugin.set_sample_rate(rate),
+            TraceRecord::Parameter(index, value) => plugin.parameters().set_parameter(index, value),
+            TraceRecord::Events(count) => {
+                remaining_inputs = count;
+                if count == 0 {
+                    plugin.process_events(&[]);
+                }
+            }
+            TraceRecord::Input(e) => {
+                pending_inputs.push(e);
+                remaining_inputs = remaining_inputs.saturating_sub(1);
+                if remaining_inputs == 0 {
+                    plugin.process_events(&pending_inputs);
+                    pending_inputs.clear();
+                }
+            }
+            TraceRecord::Block { position, samples } => {
+                if let Some(block) = current_block.take() {
+                    if block.report_differences() {
+                        differences += 1;
+                    }
+                }
+
+                current_block = Some(Block {
+                    index: block_count,
+                    position,
+                    recorded: vec![],
+                    replayed: format_events(&plugin.process(samples)),
+                });
+                block_count += 1;
+            }
+            TraceRecord::Output(e) => {
+                if let Some(block) = current_block.as_mut() {
+                    block.recorded.push(format_midi_event(&e));
+                }
+            }
+            TraceRecord::Lost(count) => {
+                return Err(format!(
+                    "{} records were lost after block {}, the trace cannot be replayed",
+                    count, block_count
+                ));
+            }
+        }
+    }
+
+    if let Some(block) = current_block.take() {
+        if block.report_differences() {
+            differences += 1;
+        }
+    }
+
+    println!(
+        "{}: {} blocks replayed, {} differ",
+        plugin_name, block_count, differences
+    );
+    Ok(differences == 0)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <trace file> [plugin library]", args[0]);
+        process::exit(2);
+    }
+
+    match replay(Path::new(&args[1]), args.get(2).map(PathBuf::from)) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,489 @@
+// a minimal CLAP host, enough to run the CLAP build of the plugins like OfflinePlugin runs the VST2
+// one: no audio, one note port, parameters and state. Everything happens on the calling thread,
+// which acts as both the main and the audio thread.
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::ptr;
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_midi, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_MIDI, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::params::{clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::clap_plugin;
+use clap_sys::process::clap_process;
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+use libloading::Library;
+use vst::event::MidiEvent;
+
+use util::make_midi_message;
+
+use super::event_list::{format_event_list, ScriptEvent};
+use super::offline_host::{run_script, BlockProcessor, OfflinePlugin};
+
+const HOST_NAME: &[u8] = b"tools\0";
+const HOST_VERSION: &[u8] = b"0.1.0\0";
+
+// the outputs of the VST2 and CLAP builds of a library for the same script, as event lists
+pub struct FormatComparison {
+    // the CLAP state saved back is the VST2 preset it was loaded from
+    pub same_state: bool,
+    pub vst_output: String,
+    pub clap_output: String,
+}
+
+impl FormatComparison {
+    pub fn is_same(&self) -> bool {
+        self.same_state && self.vst_output == self.clap_output
+    }
+}
+
+// parameters are set on the VST2 instance, and its preset is loaded into the CLAP instance through
+// the state extension, which checks saving and loading on the way. The script is played for length
+// samples, and must not change parameters, as the formats don't apply them the same way.
+pub fn compare_formats(
+    path: &Path,
+    parameters: &[(String, String)],
+    script: &[ScriptEvent],
+    sample_rate: f32,
+    block_size: usize,
+    length: usize,
+) -> Result<FormatComparison, String> {
+    let mut vst_plugin = OfflinePlugin::load(path)?;
+    for (name, value) in parameters {
+        vst_plugin.set_parameter_by_name(name, value)?;
+    }
+    // loaded back, so the VST2 instance uses the values as stored in the preset, like the CLAP one
+    let preset = vst_plugin.parameters().get_preset_data();
+    vst_plugin.parameters().load_preset_data(&preset);
+
+    let mut clap_plugin = ClapPlugin::load(path)?;
+    clap_plugin.load_state(&preset)?;
+    let same_state = clap_plugin.save_state()? == preset;
+
+    let processors: [&mut dyn BlockProcessor; 2] = [&mut vst_plugin, &mut clap_plugin];
+    let mut outputs = vec![];
+    for processor in processors {
+        processor.set_sample_rate(sample_rate);
+        processor.set_block_size(block_size);
+        processor.resume();
+        outputs.push(format_event_list(&run_script(processor, script, block_size, length)?));
+    }
+
+    let clap_output = outputs.pop().unwrap_or_default();
+    let vst_output = outputs.pop().unwrap_or_default();
+    Ok(FormatComparison {
+        same_state,
+        vst_output,
+        clap_output,
+    })
+}
+
+// input events of a block, in the order they were added
+#[derive(Default)]
+struct InputEvents {
+    parameters: Vec<clap_event_param_value>,
+    messages: Vec<clap_event_midi>,
+    // rebuilt before each use, as the vectors above may have moved
+    headers: Vec<*const clap_event_header>,
+}
+
+impl InputEvents {
+    fn header(size: usize, event_type: u16, time: u32) -> clap_event_header {
+        clap_event_header {
+            size: size as u32,
+            time,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: event_type,
+            flags: 0,
+        }
+    }
+
+    // parameter changes come first, at the start of the block
+    fn collect_headers(&mut self) {
+        self.headers.clear();
+        self.headers.extend(self.parameters.iter().map(|event| &event.header as *const clap_event_header));
+        self.headers.extend(self.messages.iter().map(|event| &event.header as *const clap_event_header));
+    }
+
+    fn clear(&mut self) {
+        self.parameters.clear();
+        self.messages.clear();
+        self.headers.clear();
+    }
+
+    fn list(&mut self) -> clap_input_events {
+        self.collect_headers();
+        clap_input_events {
+            ctx: self as *mut InputEvents as *mut c_void,
+            size: Some(input_events_size),
+            get: Some(input_events_get),
+        }
+    }
+}
+
+unsafe extern "C" fn input_events_size(list: *const clap_input_events) -> u32 {
+    (*((*list).ctx as *const InputEvents)).headers.len() as u32
+}
+
+unsafe extern "C" fn input_events_get(list: *const clap_input_events, index: u32) -> *const clap_event_header {
+    let events = &*((*list).ctx as *const InputEvents);
+    events.headers.get(index as usize).copied().unwrap_or(ptr::null())
+}
+
+// only midi messages are kept
+unsafe extern "C" fn output_events_try_push(list: *const clap_output_events, event: *const clap_event_header) -> bool {
+    let outputs = &mut *((*list).ctx as *mut Vec<MidiEvent>);
+    if (*event).space_id == CLAP_CORE_EVENT_SPACE_ID && (*event).type_ == CLAP_EVENT_MIDI {
+        let event = &*(event as *const clap_event_midi);
+        outputs.push(make_midi_message(event.data, event.header.time as i32));
+    }
+    true
+}
+
+unsafe extern "C" fn host_get_extension(_host: *const clap_host, _extension_id: *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+unsafe extern "C" fn ostream_write(stream: *const clap_ostream, buffer: *const c_void, size: u64) -> i64 {
+    let data = &mut *((*stream).ctx as *mut Vec<u8>);
+    data.extend_from_slice(std::slice::from_raw_parts(buffer as *const u8, size as usize));
+    size as i64
+}
+
+struct ReadState<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+unsafe extern "C" fn istream_read(stream: *const clap_istream, buffer: *mut c_void, size: u64) -> i64 {
+    let state = &mut *((*stream).ctx as *mut ReadState);
+    let length = (size as usize).min(state.data.len() - state.position);
+    ptr::copy_nonoverlapping(state.data[state.position..].as_ptr(), buffer as *mut u8, length);
+    state.position += length;
+    length as i64
+}
+
+pub struct ClapParameter {
+    pub id: clap_id,
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+pub struct ClapPlugin {
+    // boxed, the plugin keeps the pointer
+    _host: Box<clap_host>,
+    entry: *const clap_plugin_entry,
+    plugin: *const clap_plugin,
+    parameters: Vec<ClapParameter>,
+    sample_rate: f32,
+    block_size: usize,
+    active: bool,
+    inputs: InputEvents,
+    steady_time: i64,
+    // last, so the library is unloaded after everything else
+    _library: Library,
+}
+
+impl ClapPlugin {
+    // loads the first plugin of the library
+    pub fn load(path: &Path) -> Result<ClapPlugin, String> {
+        let library = unsafe { Library::new(path) }.map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+        let entry: *const clap_plugin_entry = unsafe {
+            *library
+                .get::<*const clap_plugin_entry>(b"clap_entry\0")
+                .map_err(|e| format!("{} is not a CLAP plugin: {}", path.display(), e))?
+        };
+
+        let host = Box::new(clap_host {
+            clap_version: CLAP_VERSION,
+            host_data: ptr::null_mut(),
+            name: HOST_NAME.as_ptr() as *const c_char,
+            vendor: HOST_NAME.as_ptr() as *const c_char,
+            url: HOST_NAME.as_ptr() as *const c_char,
+            version: HOST_VERSION.as_ptr() as *const c_char,
+            get_extension: Some(host_get_extension),
+            request_restart: Some(host_request),
+            request_process: Some(host_request),
+            request_callback: Some(host_request),
+        });
+
+        unsafe {
+            let path_text = CString::new(path.to_string_lossy().as_bytes()).unwrap_or_default();
+            let init = (*entry).init.ok_or("missing init in clap_entry")?;
+            if !init(path_text.as_ptr()) {
+                return Err(format!("Failed to initialize {}", path.display()));
+            }
+
+            let get_factory = (*entry).get_factory.ok_or("missing get_factory in clap_entry")?;
+            let factory = get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory;
+            let descriptor = match factory.as_ref().and_then(|factory| factory.get_plugin_descriptor) {
+                Some(get_plugin_descriptor) => get_plugin_descriptor(factory, 0),
+                None => ptr::null(),
+            };
+            let create_plugin = factory.as_ref().and_then(|factory| factory.create_plugin);
+            let plugin = match (descriptor.as_ref(), create_plugin) {
+                (Some(descriptor), Some(create_plugin)) => create_plugin(factory, &*host, descriptor.id),
+                _ => ptr::null(),
+            };
+            if plugin.is_null() || !(*plugin).init.is_some_and(|init| init(plugin)) {
+                if let Some(deinit) = (*entry).deinit {
+                    deinit();
+                }
+                return Err(format!("Failed to instantiate plugin: {}", path.display()));
+            }
+
+            let mut clap_plugin = ClapPlugin {
+                _host: host,
+                entry,
+                plugin,
+                parameters: vec![],
+                sample_rate: 44100.0,
+                block_size: 512,
+                active: false,
+                inputs: Default::default(),
+                steady_time: 0,
+                _library: library,
+            };
+            clap_plugin.parameters = clap_plugin.read_parameters();
+            Ok(clap_plugin)
+        }
+    }
+
+    unsafe fn extension<T>(&self, id: &CStr) -> Option<&T> {
+        let get_extension = (*self.plugin).get_extension?;
+        (get_extension(self.plugin, id.as_ptr()) as *const T).as_ref()
+    }
+
+    fn read_parameters(&self) -> Vec<ClapParameter> {
+        unsafe {
+            let params = match self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS) {
+                Some(params) => params,
+                None => return vec![],
+            };
+            let (count, get_info) = match (params.count, params.get_info) {
+                (Some(count), Some(get_info)) => (count, get_info),
+                _ => return vec![],
+            };
+
+            let mut parameters = vec![];
+            for index in 0..count(self.plugin) {
+                let mut info: clap_param_info = mem::zeroed();
+                if get_info(self.plugin, index, &mut info) {
+                    parameters.push(ClapParameter {
+                        id: info.id,
+                        name: CStr::from_ptr(info.name.as_ptr()).to_string_lossy().into_owned(),
+                        min: info.min_value,
+                        max: info.max_value,
+                    });
+                }
+            }
+            parameters
+        }
+    }
+
+    pub fn parameters(&self) -> &[ClapParameter] {
+        &self.parameters
+    }
+
+    // parameter names are matched ignoring case
+    pub fn find_parameter(&self, name: &str) -> Option<&ClapParameter> {
+        self.parameters.iter().find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+    }
+
+    // the value is parsed by the plugin, the change is sent with the next block
+    pub fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parameter = self
+            .find_parameter(name)
+            .ok_or_else(|| format!("plugin has no parameter named {}", name))?;
+        let (id, min, max) = (parameter.id, parameter.min, parameter.max);
+
+        let text = CString::new(value).map_err(|_| format!("invalid value {} for parameter {}", value, name))?;
+        let mut plain = 0.0;
+        let parsed = unsafe {
+            match self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS).and_then(|params| params.text_to_value) {
+                Some(text_to_value) => text_to_value(self.plugin, id, text.as_ptr(), &mut plain),
+                None => false,
+            }
+        };
+        if !parsed || plain < min || plain > max {
+            return Err(format!("invalid value {} for parameter {}", value, name));
+        }
+
+        self.inputs.parameters.push(clap_event_param_value {
+            header: InputEvents::header(mem::size_of::<clap_event_param_value>(), CLAP_EVENT_PARAM_VALUE, 0),
+            param_id: id,
+            cookie: ptr::null_mut(),
+            note_id: -1,
+            port_index: -1,
+            channel: -1,
+            key: -1,
+            value: plain,
+        });
+        Ok(())
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>, String> {
+        let mut data: Vec<u8> = vec![];
+        let stream = clap_ostream {
+            ctx: &mut data as *mut Vec<u8> as *mut c_void,
+            write: Some(ostream_write),
+        };
+        let saved = unsafe {
+            match self.extension::<clap_plugin_state>(CLAP_EXT_STATE).and_then(|state| state.save) {
+                Some(save) => save(self.plugin, &stream),
+                None => false,
+            }
+        };
+        if saved {
+            Ok(data)
+        } else {
+            Err("the plugin could not save its state".to_string())
+        }
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut read_state = ReadState { data, position: 0 };
+        let stream = clap_istream {
+            ctx: &mut read_state as *mut ReadState as *mut c_void,
+            read: Some(istream_read),
+        };
+        let loaded = unsafe {
+            match self.extension::<clap_plugin_state>(CLAP_EXT_STATE).and_then(|state| state.load) {
+                Some(load) => load(self.plugin, &stream),
+                None => false,
+            }
+        };
+        if loaded {
+            Ok(())
+        } else {
+            Err("the plugin could not load the state".to_string())
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        unsafe {
+            if let Some(stop_processing) = (*self.plugin).stop_processing {
+                stop_processing(self.plugin);
+            }
+            if let Some(deactivate) = (*self.plugin).deactivate {
+                deactivate(self.plugin);
+            }
+        }
+        self.active = false;
+    }
+
+    // activates with the current sample rate and block size
+    pub fn resume(&mut self) {
+        self.deactivate();
+        unsafe {
+            let activated = match (*self.plugin).activate {
+                Some(activate) => activate(self.plugin, self.sample_rate as f64, 1, self.block_size as u32),
+                None => false,
+            };
+            if activated {
+                if let Some(start_processing) = (*self.plugin).start_processing {
+                    start_processing(self.plugin);
+                }
+                self.active = true;
+            }
+        }
+    }
+
+    // returns the events the plugin sent during that block
+    pub fn process(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        for event in inputs {
+            self.inputs.messages.push(clap_event_midi {
+                header: InputEvents::header(
+                    mem::size_of::<clap_event_midi>(),
+                    CLAP_EVENT_MIDI,
+                    event.delta_frames.max(0) as u32,
+                ),
+                port_index: 0,
+                data: event.data,
+            });
+        }
+        // events are expected in time order, parameters are all at 0
+        self.inputs.messages.sort_by_key(|event| event.header.time);
+
+        let mut outputs: Vec<MidiEvent> = vec![];
+        let in_events = self.inputs.list();
+        let out_events = clap_output_events {
+            ctx: &mut outputs as *mut Vec<MidiEvent> as *mut c_void,
+            try_push: Some(output_events_try_push),
+        };
+
+        let process = clap_process {
+            steady_time: self.steady_time,
+            frames_count: samples as u32,
+            transport: ptr::null(),
+            audio_inputs: ptr::null(),
+            audio_outputs: ptr::null_mut(),
+            audio_inputs_count: 0,
+            audio_outputs_count: 0,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+
+        unsafe {
+            if let Some(process_block) = (*self.plugin).process {
+                process_block(self.plugin, &process);
+            }
+        }
+
+        self.inputs.clear();
+        self.steady_time += samples as i64;
+        outputs
+    }
+}
+
+impl Drop for ClapPlugin {
+    fn drop(&mut self) {
+        self.deactivate();
+        unsafe {
+            if let Some(destroy) = (*self.plugin).destroy {
+                destroy(self.plugin);
+            }
+            if let Some(deinit) = (*self.entry).deinit {
+                deinit();
+            }
+        }
+    }
+}
+
+impl BlockProcessor for ClapPlugin {
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+    }
+
+    fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size;
+    }
+
+    fn resume(&mut self) {
+        ClapPlugin::resume(self)
+    }
+
+    fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        ClapPlugin::set_parameter_by_name(self, name, value)
+    }
+
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        self.process(inputs, samples)
+    }
+}
@@ -0,0 +1,96 @@
+// text format used to script plugin runs, one event per line:
+//
+//   # comments start with '#'
+//   0 [0x90 0x3C 0x40]
+//   22050 [0x80 0x3C 0x00]
+//   44100 Trigger generated note=1
+//
+// the first column is the position in samples. It is followed either by a midi message, or by a
+// parameter name and its value, set at the start of the block containing that position.
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+use util::delayed_message_consumer::MessageReason;
+use util::messages::{describe_message, parse_message_bytes};
+
+pub enum ScriptEvent {
+    Message(AbsoluteTimeMidiMessage),
+    Parameter {
+        position: usize,
+        name: String,
+        value: String,
+    },
+}
+
+impl ScriptEvent {
+    pub fn position(&self) -> usize {
+        match self {
+            ScriptEvent::Message(message) => message.play_time_in_samples,
+            ScriptEvent::Parameter { position, .. } => *position,
+        }
+    }
+}
+
+pub fn timed_message(data: [u8; 3], play_time_in_samples: usize) -> AbsoluteTimeMidiMessage {
+    AbsoluteTimeMidiMessage {
+        data: data.into(),
+        id: 0,
+        reason: MessageReason::Live,
+        play_time_in_samples,
+    }
+}
+
+// events are sorted by position, keeping the file order for equal positions
+pub fn parse_event_list(text: &str) -> Result<Vec<ScriptEvent>, String> {
+    let mut events = vec![];
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || format!("line {}: cannot parse '{}'", line_number + 1, line);
+        let (position, rest) = match line.find(char::is_whitespace) {
+            Some(separator) => (&line[..separator], line[separator..].trim()),
+            None => return Err(invalid()),
+        };
+        let position: usize = position.parse().map_err(|_| invalid())?;
+
+        if rest.starts_with('[') {
+            let (data, _) = parse_message_bytes(rest).ok_or_else(invalid)?;
+            events.push(ScriptEvent::Message(timed_message(data, position)));
+        } else {
+            let separator = rest.find('=').ok_or_else(invalid)?;
+            events.push(ScriptEvent::Parameter {
+                position,
+                name: rest[..separator].trim().to_string(),
+                value: rest[separator + 1..].trim().to_string(),
+            });
+        }
+    }
+
+    events.sort_by_key(ScriptEvent::position);
+    Ok(events)
+}
+
+// output can be read back by parse_event_list, descriptions are comments
+pub fn format_event_list(messages: &[AbsoluteTimeMidiMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let data: [u8; 3] = message.data.into();
+            format!(
+                "{} [{:#04X} {:#04X} {:#04X}] # {}\n",
+                message.play_time_in_samples,
+                data[0],
+                data[1],
+                data[2],
+                describe_message(&data)
+            )
+        })
+        .collect()
+}
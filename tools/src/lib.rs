@@ -0,0 +1,5 @@
+pub mod clap_host;
+pub mod event_list;
+pub mod lv2_host;
+pub mod offline_host;
+pub mod plugin_graph;
@@ -0,0 +1,398 @@
+// a minimal headless LV2 host, for the bundles written by lv2_bundler: it reads the subset of
+// turtle written by util::lv2_wrapper to find the binary and the control ports, and maps URIDs
+// itself. Control values are plain values, as shown in an LV2 host.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use libloading::Library;
+use vst::event::MidiEvent;
+
+use util::lv2_wrapper::{
+    atom_padded_size, Atom, AtomEvent, AtomSequence, Lv2Descriptor, Lv2Feature, Lv2Handle, Lv2UridMap,
+    LV2_ATOM_CHUNK, LV2_ATOM_SEQUENCE, LV2_MIDI_EVENT, LV2_URID_MAP, MIDI_IN_PORT, MIDI_OUT_PORT,
+};
+use util::{make_midi_message, message_length};
+
+use super::offline_host::BlockProcessor;
+
+// in u64, to keep atoms aligned
+const ATOM_BUFFER_SIZE: usize = 4096;
+
+// the bundle of a plugin library, <crate>.lv2 in output_directory, with a copy of the library and
+// the ttl files it writes from its parameter definitions
+pub fn write_bundle(library_path: &Path, output_directory: &Path) -> Result<PathBuf, String> {
+    let file_name = library_path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or_else(|| format!("invalid library {}", library_path.display()))?
+        .to_string();
+    let stem = library_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.strip_prefix(env::consts::DLL_PREFIX).unwrap_or(stem))
+        .ok_or_else(|| format!("invalid library {}", library_path.display()))?;
+
+    let bundle = output_directory.join(format!("{}.lv2", stem));
+    fs::create_dir_all(&bundle).map_err(|e| format!("cannot create {}: {}", bundle.display(), e))?;
+    fs::copy(library_path, bundle.join(&file_name))
+        .map_err(|e| format!("cannot copy {}: {}", library_path.display(), e))?;
+
+    let library = unsafe { Library::new(library_path) }
+        .map_err(|e| format!("Failed to load {}: {}", library_path.display(), e))?;
+    let directory = CString::new(bundle.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+    let binary = CString::new(file_name).map_err(|e| e.to_string())?;
+    let written = unsafe {
+        let lv2_write_bundle = library
+            .get::<unsafe extern "C" fn(*const c_char, *const c_char) -> bool>(b"lv2_write_bundle\0")
+            .map_err(|e| format!("{} does not export LV2 bundles: {}", library_path.display(), e))?;
+        lv2_write_bundle(directory.as_ptr(), binary.as_ptr())
+    };
+
+    if written {
+        Ok(bundle)
+    } else {
+        Err(format!("cannot write the ttl files of {}", bundle.display()))
+    }
+}
+
+pub struct ControlPort {
+    pub index: u32,
+    pub symbol: String,
+    pub name: String,
+    pub default: f32,
+    pub minimum: f32,
+    pub maximum: f32,
+    pub toggled: bool,
+}
+
+#[derive(Default)]
+struct UridMap {
+    urids: HashMap<String, u32>,
+}
+
+unsafe extern "C" fn map_uri(handle: *mut c_void, uri: *const c_char) -> u32 {
+    let map = &mut *(handle as *mut UridMap);
+    let uri = CStr::from_ptr(uri).to_string_lossy().into_owned();
+    let next = map.urids.len() as u32 + 1;
+    *map.urids.entry(uri).or_insert(next)
+}
+
+// the value following a key, on a line like "lv2:index 2 ;"
+fn ttl_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let rest = line.trim().strip_prefix(key)?;
+    Some(rest.trim().trim_end_matches([';', '.', ',']).trim().trim_matches('"'))
+}
+
+// <file> in a line like "lv2:binary <libnote_off_delay.so> ;"
+fn ttl_link(text: &str, key: &str) -> Option<String> {
+    text.lines()
+        .filter_map(|line| ttl_value(line, key))
+        .map(|value| value.trim_start_matches('<').trim_end_matches('>').to_string())
+        .next()
+}
+
+fn read_control_ports(text: &str) -> Vec<ControlPort> {
+    let mut ports = vec![];
+    for block in text.split('[').skip(1) {
+        let block = block.split(']').next().unwrap_or("");
+        if !block.contains("lv2:ControlPort") {
+            continue;
+        }
+        let value = |key: &str| block.lines().find_map(|line| ttl_value(line, key));
+        let number = |key: &str| value(key).and_then(|value| value.parse::<f32>().ok());
+
+        let index = value("lv2:index").and_then(|index| index.parse().ok());
+        if let (Some(index), Some(symbol)) = (index, value("lv2:symbol")) {
+            ports.push(ControlPort {
+                index,
+                symbol: symbol.to_string(),
+                name: value("lv2:name").unwrap_or(symbol).to_string(),
+                default: number("lv2:default").unwrap_or(0.0),
+                minimum: number("lv2:minimum").unwrap_or(0.0),
+                maximum: number("lv2:maximum").unwrap_or(1.0),
+                toggled: block.contains("lv2:toggled"),
+            });
+        }
+    }
+    ports.sort_by_key(|port| port.index);
+    ports
+}
+
+pub struct Lv2Plugin {
+    bundle: PathBuf,
+    descriptor: *const Lv2Descriptor,
+    handle: Lv2Handle,
+    // boxed, the plugin keeps pointers to them
+    urid_map: Box<UridMap>,
+    urid_map_feature: Box<Lv2UridMap>,
+    ports: Vec<ControlPort>,
+    // connected to the control ports, at the same positions as ports
+    values: Vec<f32>,
+    input: Vec<u64>,
+    output: Vec<u64>,
+    sample_rate: f32,
+    // last, so the library is unloaded after everything else
+    _library: Library,
+}
+
+impl Lv2Plugin {
+    // a bundle directory, with a manifest.ttl
+    pub fn load(bundle: &Path) -> Result<Lv2Plugin, String> {
+        let read = |file: &str| {
+            let path = bundle.join(file);
+            fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))
+        };
+        let manifest = read("manifest.ttl")?;
+        let binary = ttl_link(&manifest, "lv2:binary")
+            .ok_or_else(|| format!("{}: no lv2:binary in manifest.ttl", bundle.display()))?;
+        let plugin_file = ttl_link(&manifest, "rdfs:seeAlso")
+            .ok_or_else(|| format!("{}: no rdfs:seeAlso in manifest.ttl", bundle.display()))?;
+        let ports = read_control_ports(&read(&plugin_file)?);
+        let values = ports.iter().map(|port| port.default).collect();
+
+        let library_path = bundle.join(&binary);
+        let library = unsafe { Library::new(&library_path) }
+            .map_err(|e| format!("Failed to load {}: {}", library_path.display(), e))?;
+        let descriptor = unsafe {
+            let lv2_descriptor = library
+                .get::<unsafe extern "C" fn(u32) -> *const Lv2Descriptor>(b"lv2_descriptor\0")
+                .map_err(|e| format!("{} is not an LV2 plugin: {}", library_path.display(), e))?;
+            lv2_descriptor(0)
+        };
+        if descriptor.is_null() {
+            return Err(format!("{} has no plugin", library_path.display()));
+        }
+
+        let mut urid_map = Box::new(UridMap::default());
+        let urid_map_feature = Box::new(Lv2UridMap {
+            handle: &mut *urid_map as *mut UridMap as *mut c_void,
+            map: Some(map_uri),
+        });
+
+        Ok(Lv2Plugin {
+            bundle: bundle.to_path_buf(),
+            descriptor,
+            handle: ptr::null_mut(),
+            urid_map,
+            urid_map_feature,
+            ports,
+            values,
+            input: vec![0; ATOM_BUFFER_SIZE],
+            output: vec![0; ATOM_BUFFER_SIZE],
+            sample_rate: 44100.0,
+            _library: library,
+        })
+    }
+
+    pub fn ports(&self) -> &[ControlPort] {
+        &self.ports
+    }
+
+    fn urid(&mut self, uri: &str) -> u32 {
+        let uri = CString::new(uri).unwrap_or_default();
+        unsafe { map_uri(&mut *self.urid_map as *mut UridMap as *mut c_void, uri.as_ptr()) }
+    }
+
+    // names and symbols are matched ignoring case, values are plain values, or on/off
+    pub fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let position = self
+            .ports
+            .iter()
+            .position(|port| port.name.eq_ignore_ascii_case(name) || port.symbol.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("plugin has no control port named {}", name))?;
+        let port = &self.ports[position];
+
+        let parsed = match value.to_ascii_lowercase().as_ref() {
+            "on" if port.toggled => Some(1.0),
+            "off" if port.toggled => Some(0.0),
+            value => value.parse::<f32>().ok(),
+        };
+        match parsed {
+            Some(parsed) if parsed >= port.minimum && parsed <= port.maximum => {
+                self.values[position] = parsed;
+                Ok(())
+            }
+            _ => Err(format!(
+                "invalid value {} for {}, expected {} to {}",
+                value, name, port.minimum, port.maximum
+            )),
+        }
+    }
+
+    fn cleanup(&mut self) {
+        if self.handle.is_null() {
+            return;
+        }
+        unsafe {
+            if let Some(deactivate) = (*self.descriptor).deactivate {
+                deactivate(self.handle);
+            }
+            if let Some(cleanup) = (*self.descriptor).cleanup {
+                cleanup(self.handle);
+            }
+        }
+        self.handle = ptr::null_mut();
+    }
+
+    // instantiates again, with the current sample rate
+    pub fn resume(&mut self) -> Result<(), String> {
+        self.cleanup();
+
+        let uri = CString::new(LV2_URID_MAP).unwrap_or_default();
+        let bundle = CString::new(format!("{}/", self.bundle.display())).unwrap_or_default();
+        let feature = Lv2Feature {
+            uri: uri.as_ptr(),
+            data: &mut *self.urid_map_feature as *mut Lv2UridMap as *mut c_void,
+        };
+        let features = [&feature as *const Lv2Feature, ptr::null()];
+
+        unsafe {
+            let instantiate = (*self.descriptor).instantiate.ok_or("missing instantiate")?;
+            self.handle = instantiate(self.descriptor, self.sample_rate as f64, bundle.as_ptr(), features.as_ptr());
+            if self.handle.is_null() {
+                return Err(format!("Failed to instantiate {}", self.bundle.display()));
+            }
+
+            let connect_port = (*self.descriptor).connect_port.ok_or("missing connect_port")?;
+            connect_port(self.handle, MIDI_IN_PORT, self.input.as_mut_ptr() as *mut c_void);
+            connect_port(self.handle, MIDI_OUT_PORT, self.output.as_mut_ptr() as *mut c_void);
+            for (port, value) in self.ports.iter().zip(self.values.iter_mut()) {
+                connect_port(self.handle, port.index, value as *mut f32 as *mut c_void);
+            }
+            if let Some(activate) = (*self.descriptor).activate {
+                activate(self.handle);
+            }
+        }
+        Ok(())
+    }
+
+    // delta frames and bytes of each message
+    fn write_input(&mut self, inputs: &[(usize, &[u8])]) {
+        let sequence_type = self.urid(LV2_ATOM_SEQUENCE);
+        let midi_event = self.urid(LV2_MIDI_EVENT);
+        let capacity = self.input.len() * mem::size_of::<u64>();
+        let buffer = self.input.as_mut_ptr() as *mut u8;
+
+        unsafe {
+            let sequence = buffer as *mut AtomSequence;
+            (*sequence).atom = Atom {
+                size: (mem::size_of::<AtomSequence>() - mem::size_of::<Atom>()) as u32,
+                type_: sequence_type,
+            };
+            (*sequence).unit = 0;
+            (*sequence).pad = 0;
+
+            let mut offset = mem::size_of::<AtomSequence>();
+            for &(delta_frames, bytes) in inputs {
+                let length = bytes.len();
+                let event_size = atom_padded_size(mem::size_of::<AtomEvent>() + length);
+                if offset + event_size > capacity {
+                    break;
+                }
+                let event = buffer.add(offset) as *mut AtomEvent;
+                (*event).time_frames = delta_frames as i64;
+                (*event).body = Atom {
+                    size: length as u32,
+                    type_: midi_event,
+                };
+                ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.add(offset + mem::size_of::<AtomEvent>()), length);
+                offset += event_size;
+                (*sequence).atom.size += event_size as u32;
+            }
+        }
+    }
+
+    fn read_output(&mut self) -> Vec<MidiEvent> {
+        let midi_event = self.urid(LV2_MIDI_EVENT);
+        let buffer = self.output.as_ptr() as *const u8;
+        let mut outputs = vec![];
+
+        unsafe {
+            let sequence = buffer as *const AtomSequence;
+            let end = (mem::size_of::<Atom>() + (*sequence).atom.size as usize).min(self.output.len() * 8);
+            let mut offset = mem::size_of::<AtomSequence>();
+            while offset + mem::size_of::<AtomEvent>() <= end {
+                let event = buffer.add(offset) as *const AtomEvent;
+                let size = (*event).body.size as usize;
+                let body = buffer.add(offset + mem::size_of::<AtomEvent>());
+                // a message of another size than its status gives would be misread by a host
+                // passing raw midi on, it is dropped
+                if (*event).body.type_ == midi_event && size > 0 && size == message_length(*body) {
+                    let bytes = std::slice::from_raw_parts(body, size);
+                    let mut data = [0u8; 3];
+                    data[..bytes.len()].copy_from_slice(bytes);
+                    outputs.push(make_midi_message(data, (*event).time_frames as i32));
+                }
+                offset += atom_padded_size(mem::size_of::<AtomEvent>() + size);
+            }
+        }
+        outputs
+    }
+
+    // returns the events the plugin sent during that block
+    pub fn process(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        let mut inputs = inputs.to_vec();
+        inputs.sort_by_key(|event| event.delta_frames);
+        let raw_inputs: Vec<(usize, &[u8])> = inputs
+            .iter()
+            .map(|input| (input.delta_frames.max(0) as usize, &input.data[..message_length(input.data[0])]))
+            .collect();
+        self.process_raw(&raw_inputs, samples)
+    }
+
+    // inputs are delta frames and bytes, sorted, e.g. for SysEx messages
+    pub fn process_raw(&mut self, inputs: &[(usize, &[u8])], samples: usize) -> Vec<MidiEvent> {
+        if self.handle.is_null() {
+            return vec![];
+        }
+        self.write_input(inputs);
+
+        // the plugin is told how much it can write
+        let chunk = self.urid(LV2_ATOM_CHUNK);
+        unsafe {
+            (*(self.output.as_mut_ptr() as *mut Atom)) = Atom {
+                size: (self.output.len() * mem::size_of::<u64>()) as u32,
+                type_: chunk,
+            };
+            if let Some(run) = (*self.descriptor).run {
+                run(self.handle, samples as u32);
+            }
+        }
+        self.read_output()
+    }
+}
+
+impl Drop for Lv2Plugin {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl BlockProcessor for Lv2Plugin {
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+    }
+
+    fn set_block_size(&mut self, _block_size: usize) {}
+
+    fn resume(&mut self) {
+        if let Err(e) = Lv2Plugin::resume(self) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        Lv2Plugin::set_parameter_by_name(self, name, value)
+    }
+
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        self.process(inputs, samples)
+    }
+}
@@ -0,0 +1,234 @@
+use std::env;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use vst::api;
+use vst::buffer::SendEventBuffer;
+use vst::event::{Event, MidiEvent};
+use vst::host::{Host, HostBuffer, PluginInstance, PluginLoader};
+use vst::plugin::{Info, Plugin, PluginParameters};
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+
+use super::event_list::{timed_message, ScriptEvent};
+
+// plugin libraries built from this workspace are next to the tools binaries, whatever the profile,
+// otherwise they are looked for in target/debug
+pub fn library_path(crate_name: &str) -> PathBuf {
+    let file_name = library_file_name(crate_name);
+
+    let next_to_executable = env::current_exe()
+        .ok()
+        .and_then(|executable| executable.parent().map(|directory| directory.join(&file_name)));
+    match next_to_executable {
+        Some(path) if path.exists() => path,
+        _ => Path::new("target").join("debug").join(file_name),
+    }
+}
+
+fn library_file_name(crate_name: &str) -> String {
+    format!("{}{}{}", DLL_PREFIX, crate_name, DLL_SUFFIX)
+}
+
+// anything that is not an existing file is taken as a crate name of this workspace, whose library
+// is looked for in directory first
+pub fn resolve_library(plugin: &str, directory: &Path) -> PathBuf {
+    let path = directory.join(plugin);
+    let library = directory.join(library_file_name(plugin));
+    if path.is_file() {
+        path
+    } else if library.is_file() {
+        library
+    } else {
+        library_path(plugin)
+    }
+}
+
+// a plugin, or a combination of plugins, that can be driven block by block
+pub trait BlockProcessor {
+    fn set_sample_rate(&mut self, rate: f32);
+    fn set_block_size(&mut self, block_size: usize);
+    fn resume(&mut self);
+    fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String>;
+    // inputs and outputs delta frames are relative to the start of the block
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent>;
+}
+
+// plays a script until length samples have been processed. Parameter changes apply at the start
+// of the block containing their position, outputs are sorted by position.
+pub fn run_script(
+    processor: &mut dyn BlockProcessor,
+    script: &[ScriptEvent],
+    block_size: usize,
+    length: usize,
+) -> Result<Vec<AbsoluteTimeMidiMessage>, String> {
+    let mut outputs = vec![];
+    let mut script = script.iter().peekable();
+    let mut block_start = 0;
+
+    while block_start < length {
+        let samples = block_size.min(length - block_start);
+        let mut inputs = vec![];
+
+        while let Some(event) = script.next_if(|event| event.position() < block_start + samples) {
+            match event {
+                ScriptEvent::Message(message) => inputs.push(message.new_midi_event(block_start)),
+                ScriptEvent::Parameter { name, value, .. } => processor.set_parameter_by_name(name, value)?,
+            }
+        }
+
+        for event in processor.process_block(&inputs, samples) {
+            outputs.push(timed_message(event.data, block_start + event.delta_frames.max(0) as usize));
+        }
+
+        block_start += samples;
+    }
+
+    outputs.sort_by_key(|message| message.play_time_in_samples);
+    Ok(outputs)
+}
+
+#[derive(Default)]
+pub struct OfflineHost {
+    received_events: Mutex<Vec<MidiEvent>>,
+}
+
+impl Host for OfflineHost {
+    fn process_events(&self, events: &api::Events) {
+        if let Ok(mut received_events) = self.received_events.lock() {
+            for e in events.events() {
+                if let Event::Midi(e) = e {
+                    received_events.push(e);
+                }
+            }
+        }
+    }
+}
+
+// runs a plugin library outside of any DAW, one block at a time
+pub struct OfflinePlugin {
+    host: Arc<Mutex<OfflineHost>>,
+    instance: PluginInstance,
+    parameters: Arc<dyn PluginParameters>,
+    info: Info,
+    send_buffer: SendEventBuffer,
+    host_buffer: HostBuffer<f32>,
+}
+
+impl OfflinePlugin {
+    pub fn load(path: &Path) -> Result<OfflinePlugin, String> {
+        let host = Arc::new(Mutex::new(OfflineHost::default()));
+
+        let mut loader = PluginLoader::load(path, Arc::clone(&host))
+            .map_err(|e| format!("Failed to load plugin: {} {}", e, path.display()))?;
+        let mut instance = loader
+            .instance()
+            .map_err(|e| format!("Failed to instantiate plugin: {} {}", e, path.display()))?;
+
+        instance.init();
+        let info = instance.get_info();
+        let parameters = instance.get_parameter_object();
+
+        Ok(OfflinePlugin {
+            host,
+            instance,
+            parameters,
+            info,
+            send_buffer: Default::default(),
+            host_buffer: HostBuffer::new(1, 1),
+        })
+    }
+
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    pub fn parameters(&self) -> &Arc<dyn PluginParameters> {
+        &self.parameters
+    }
+
+    // parameter names are matched ignoring case
+    pub fn find_parameter(&self, name: &str) -> Option<i32> {
+        (0..self.info.parameters).find(|index| self.parameters.get_parameter_name(*index).eq_ignore_ascii_case(name))
+    }
+
+    // the value is first parsed by the plugin, as if typed in a DAW, then as a normalized 0..1 value
+    pub fn set_parameter_by_name(&self, name: &str, value: &str) -> Result<(), String> {
+        let index = self
+            .find_parameter(name)
+            .ok_or_else(|| format!("{} has no parameter named {}", self.info.name, name))?;
+
+        if self.parameters.string_to_parameter(index, value.to_string()) {
+            return Ok(());
+        }
+        match value.parse::<f32>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => {
+                self.parameters.set_parameter(index, value);
+                Ok(())
+            }
+            _ => Err(format!("invalid value {} for parameter {}", value, name)),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, rate: f32) {
+        self.instance.set_sample_rate(rate);
+    }
+
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.instance.set_block_size(block_size as i64);
+    }
+
+    pub fn resume(&mut self) {
+        self.instance.resume();
+    }
+
+    pub fn process_events(&mut self, events: &[MidiEvent]) {
+        self.send_buffer.send_events_to_plugin(events, &mut self.instance);
+    }
+
+    // returns the events the plugin sent during that block
+    pub fn process(&mut self, samples: usize) -> Vec<MidiEvent> {
+        let inputs = vec![vec![0.0; samples]];
+        let mut outputs = vec![vec![0.0; samples]];
+        let mut audio_buffer = self.host_buffer.bind(&inputs, &mut outputs);
+
+        self.instance.process(&mut audio_buffer);
+        self.take_received_events()
+    }
+
+    fn take_received_events(&self) -> Vec<MidiEvent> {
+        match self.host.lock() {
+            Ok(host) => match host.received_events.lock() {
+                Ok(mut received_events) => received_events.drain(..).collect(),
+                Err(_) => vec![],
+            },
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl BlockProcessor for OfflinePlugin {
+    fn set_sample_rate(&mut self, rate: f32) {
+        OfflinePlugin::set_sample_rate(self, rate)
+    }
+
+    fn set_block_size(&mut self, block_size: usize) {
+        OfflinePlugin::set_block_size(self, block_size)
+    }
+
+    fn resume(&mut self) {
+        OfflinePlugin::resume(self)
+    }
+
+    fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        OfflinePlugin::set_parameter_by_name(self, name, value)
+    }
+
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        if !inputs.is_empty() {
+            self.process_events(inputs);
+        }
+        self.process(samples)
+    }
+}
@@ -0,0 +1,222 @@
+// serial chains and parallel layers of plugins, described in a text file to reproduce DAW setups:
+//
+//   # four fan out copies in a layer, each taking one step, followed by a note off delay
+//   layer
+//     chain
+//       plugin note_fan_out as first
+//       set Steps=4
+//       set Selection=0
+//     end
+//     ...
+//   end
+//   plugin note_off_delay
+//   set Delay=0.5
+//
+// the file itself is a chain. Within a block, events produced by a plugin are given to the next
+// plugin of the chain, and every branch of a layer receives the same events, their outputs being
+// merged by time. `set` lines apply to the last declared plugin, and take the same values as
+// plugin_runner --set. Plugins are named after their crate or file unless renamed with `as`, so
+// parameters can be addressed from event lists as "<name>.<parameter>".
+
+use std::env::consts::DLL_PREFIX;
+use std::fs;
+use std::path::Path;
+
+use vst::event::MidiEvent;
+
+use super::offline_host::{resolve_library, BlockProcessor, OfflinePlugin};
+
+enum Node {
+    Plugin { name: String, plugin: Box<OfflinePlugin> },
+    Chain(Vec<Node>),
+    Layer(Vec<Node>),
+}
+
+pub struct PluginGraph {
+    root: Node,
+}
+
+impl Node {
+    fn for_each_plugin(&mut self, f: &mut dyn FnMut(&str, &mut OfflinePlugin)) {
+        match self {
+            Node::Plugin { name, plugin } => f(name, plugin),
+            Node::Chain(nodes) | Node::Layer(nodes) => {
+                for node in nodes {
+                    node.for_each_plugin(f);
+                }
+            }
+        }
+    }
+
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        match self {
+            Node::Plugin { plugin, .. } => plugin.process_block(inputs, samples),
+            Node::Chain(nodes) => {
+                let mut events = inputs.to_vec();
+                for node in nodes {
+                    events = node.process_block(&events, samples);
+                }
+                events
+            }
+            Node::Layer(nodes) => {
+                let mut events: Vec<MidiEvent> = nodes
+                    .iter_mut()
+                    .flat_map(|node| node.process_block(inputs, samples))
+                    .collect();
+                // stable, branches keep their order for events at the same time
+                events.sort_by_key(|e| e.delta_frames);
+                events
+            }
+        }
+    }
+}
+
+impl PluginGraph {
+    // a serial chain, plugins are named after their position, starting at 1
+    pub fn chain(plugins: Vec<OfflinePlugin>) -> PluginGraph {
+        let nodes = plugins
+            .into_iter()
+            .enumerate()
+            .map(|(index, plugin)| Node::Plugin {
+                name: (index + 1).to_string(),
+                plugin: Box::new(plugin),
+            })
+            .collect();
+        PluginGraph {
+            root: Node::Chain(nodes),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<PluginGraph, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        Self::parse(&text, directory).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // plugin paths are relative to directory
+    pub fn parse(text: &str, directory: &Path) -> Result<PluginGraph, String> {
+        // the root chain is at the bottom of the stack
+        let mut stack = vec![Node::Chain(vec![])];
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", line_number + 1, message);
+            let (keyword, rest) = match line.find(char::is_whitespace) {
+                Some(separator) => (&line[..separator], line[separator..].trim()),
+                None => (line, ""),
+            };
+
+            match keyword {
+                "chain" => stack.push(Node::Chain(vec![])),
+                "layer" => stack.push(Node::Layer(vec![])),
+                "end" => {
+                    if stack.len() < 2 {
+                        return Err(error("end without chain or layer"));
+                    }
+                    let node = stack.pop().unwrap();
+                    push_node(&mut stack, node);
+                }
+                "plugin" => {
+                    let mut words = rest.split_whitespace();
+                    let library = words.next().ok_or_else(|| error("missing plugin"))?;
+                    let name = match (words.next(), words.next(), words.next()) {
+                        (None, _, _) => plugin_name(library),
+                        (Some("as"), Some(name), None) => name.to_string(),
+                        _ => return Err(error("expected plugin <library> [as <name>]")),
+                    };
+
+                    let plugin = OfflinePlugin::load(&resolve_library(library, directory)).map_err(|e| error(&e))?;
+                    push_node(&mut stack, Node::Plugin { name, plugin: Box::new(plugin) });
+                }
+                "set" => {
+                    let separator = rest.find('=').ok_or_else(|| error("expected set <parameter>=<value>"))?;
+                    let plugin = match last_node(&mut stack) {
+                        Some(Node::Plugin { plugin, .. }) => plugin,
+                        _ => return Err(error("set must follow a plugin")),
+                    };
+                    plugin
+                        .set_parameter_by_name(rest[..separator].trim(), rest[separator + 1..].trim())
+                        .map_err(|e| error(&e))?;
+                }
+                _ => return Err(error(&format!("unknown keyword {}", keyword))),
+            }
+        }
+
+        if stack.len() > 1 {
+            return Err("missing end".to_string());
+        }
+        Ok(PluginGraph {
+            root: stack.pop().unwrap(),
+        })
+    }
+}
+
+fn push_node(stack: &mut [Node], node: Node) {
+    if let Some(Node::Chain(nodes) | Node::Layer(nodes)) = stack.last_mut() {
+        nodes.push(node);
+    }
+}
+
+fn last_node(stack: &mut [Node]) -> Option<&mut Node> {
+    match stack.last_mut() {
+        Some(Node::Chain(nodes) | Node::Layer(nodes)) => nodes.last_mut(),
+        _ => None,
+    }
+}
+
+fn plugin_name(library: &str) -> String {
+    let stem = Path::new(library)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(library);
+    // libnote_off_delay.so is named note_off_delay
+    stem.strip_prefix(DLL_PREFIX).unwrap_or(stem).to_string()
+}
+
+impl BlockProcessor for PluginGraph {
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.root.for_each_plugin(&mut |_, plugin| plugin.set_sample_rate(rate));
+    }
+
+    fn set_block_size(&mut self, block_size: usize) {
+        self.root.for_each_plugin(&mut |_, plugin| plugin.set_block_size(block_size));
+    }
+
+    fn resume(&mut self) {
+        self.root.for_each_plugin(&mut |_, plugin| plugin.resume());
+    }
+
+    // "<plugin name>.<parameter>" sets the parameter of every plugin with that name
+    fn set_parameter_by_name(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let separator = name
+            .find('.')
+            .ok_or_else(|| format!("{} should be <plugin name>.<parameter>", name))?;
+        let (plugin_name, parameter) = (&name[..separator], &name[separator + 1..]);
+
+        let mut found = false;
+        let mut result = Ok(());
+        self.root.for_each_plugin(&mut |name, plugin| {
+            if name == plugin_name && result.is_ok() {
+                found = true;
+                result = plugin.set_parameter_by_name(parameter, value);
+            }
+        });
+
+        if !found {
+            return Err(format!("no plugin named {}", plugin_name));
+        }
+        result
+    }
+
+    fn process_block(&mut self, inputs: &[MidiEvent], samples: usize) -> Vec<MidiEvent> {
+        self.root.process_block(inputs, samples)
+    }
+}
@@ -0,0 +1,67 @@
+// runs the setups of tests/chains, each <name>.chain playing <name>.events, and compares the
+// output with <name>.expected
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+
+use tools::event_list::{format_event_list, parse_event_list, ScriptEvent};
+use tools::offline_host::{run_script, BlockProcessor};
+use tools::plugin_graph::PluginGraph;
+
+const SAMPLE_RATE: f32 = 44100.0;
+const BLOCK_SIZE: usize = 512;
+// after the last input event
+const TAIL: usize = 2 * 44100;
+
+fn chain_file(name: &str, extension: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("chains")
+        .join(format!("{}.{}", name, extension))
+}
+
+fn read_events(name: &str, extension: &str) -> Vec<ScriptEvent> {
+    let path = chain_file(name, extension);
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    parse_event_list(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn messages(events: Vec<ScriptEvent>) -> Vec<AbsoluteTimeMidiMessage> {
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            ScriptEvent::Message(message) => Some(message),
+            ScriptEvent::Parameter { .. } => None,
+        })
+        .collect()
+}
+
+fn check_chain(name: &str) {
+    let path = chain_file(name, "chain");
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    let mut graph = PluginGraph::parse(&text, common::plugin_directory()).unwrap_or_else(|e| panic!("{}", e));
+    graph.set_sample_rate(SAMPLE_RATE);
+    graph.set_block_size(BLOCK_SIZE);
+    graph.resume();
+
+    let script = read_events(name, "events");
+    let length = script.last().map(ScriptEvent::position).unwrap_or(0) + TAIL;
+    let outputs = run_script(&mut graph, &script, BLOCK_SIZE, length).unwrap_or_else(|e| panic!("{}", e));
+
+    let expected = messages(read_events(name, "expected"));
+    assert_eq!(format_event_list(&outputs), format_event_list(&expected), "{}", name);
+}
+
+#[test]
+fn fan_out_layer() {
+    check_chain("fan_out_layer");
+}
+
+#[test]
+fn delay_max_duration() {
+    check_chain("delay_max_duration");
+}
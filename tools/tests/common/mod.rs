@@ -0,0 +1,44 @@
+#![allow(dead_code)]
+
+// plugin libraries for the integration tests, built once per test binary. They get their own
+// target directory, as the one of the tests stays locked while they run.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+pub const PLUGINS: [&str; 6] = [
+    "filter_out_non_note",
+    "max_note_duration",
+    "midi_delay",
+    "note_fan_out",
+    "note_generator",
+    "note_off_delay",
+];
+
+pub fn workspace_directory() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("tools is in the workspace")
+        .to_path_buf()
+}
+
+pub fn plugin_directory() -> &'static Path {
+    static DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+    DIRECTORY.get_or_init(|| {
+        let target = workspace_directory().join("target").join("plugin-tests");
+        let mut command = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()));
+        command
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(workspace_directory().join("Cargo.toml"))
+            .env("CARGO_TARGET_DIR", &target);
+        for plugin in PLUGINS.iter() {
+            command.arg("-p").arg(plugin);
+        }
+        let status = command.status().expect("cannot run cargo");
+        assert!(status.success(), "cannot build the plugins");
+        target.join("debug")
+    })
+}
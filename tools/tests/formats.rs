@@ -0,0 +1,75 @@
+// the CLAP build of each plugin has to send what its VST2 build sends, for the same input and
+// parameters, see the clap_check tool
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use tools::clap_host::{compare_formats, ClapPlugin};
+use tools::event_list::parse_event_list;
+use tools::offline_host::resolve_library;
+
+const SAMPLE_RATE: f32 = 44100.0;
+const BLOCK_SIZE: usize = 512;
+const LENGTH: usize = 3 * 44100;
+
+fn check_plugin(plugin: &str, parameters: &[(&str, &str)]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("formats").join("input.events");
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    let script = parse_event_list(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let parameters: Vec<(String, String)> =
+        parameters.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+    let library = resolve_library(plugin, common::plugin_directory());
+    let comparison = compare_formats(&library, &parameters, &script, SAMPLE_RATE, BLOCK_SIZE, LENGTH)
+        .unwrap_or_else(|e| panic!("{}: {}", plugin, e));
+
+    assert!(comparison.same_state, "{}: the CLAP state differs from the VST2 preset", plugin);
+    assert!(!comparison.vst_output.is_empty(), "{}: no output", plugin);
+    assert_eq!(comparison.vst_output, comparison.clap_output, "{}", plugin);
+}
+
+#[test]
+fn filter_out_non_note() {
+    check_plugin("filter_out_non_note", &[]);
+}
+
+#[test]
+fn max_note_duration() {
+    check_plugin("max_note_duration", &[("Maximum duration", "0.1")]);
+}
+
+#[test]
+fn midi_delay() {
+    check_plugin("midi_delay", &[("Delay", "0.3")]);
+}
+
+#[test]
+fn note_fan_out() {
+    check_plugin("note_fan_out", &[("Steps", "2"), ("Selection", "1")]);
+}
+
+#[test]
+fn note_generator() {
+    check_plugin("note_generator", &[("Trigger generated note", "1")]);
+}
+
+#[test]
+fn note_off_delay() {
+    check_plugin("note_off_delay", &[("Delay", "0.2")]);
+}
+
+#[test]
+fn invalid_states_are_refused() {
+    let library = resolve_library("midi_delay", common::plugin_directory());
+    let mut plugin = ClapPlugin::load(&library).unwrap_or_else(|e| panic!("{}", e));
+    let state = plugin.save_state().unwrap_or_else(|e| panic!("{}", e));
+
+    let mut longer = state.clone();
+    longer.extend_from_slice(&[64; 8]);
+    assert!(plugin.load_state(&longer).is_err());
+    assert_eq!(plugin.save_state().unwrap_or_else(|e| panic!("{}", e)), state);
+
+    plugin.load_state(&state).unwrap_or_else(|e| panic!("{}", e));
+}
@@ -0,0 +1,80 @@
+// runs note-fx-jack --test against a dummy JACK server of its own, skipped when jackd is not
+// installed. Only built with the jack-client feature, like note-fx-jack.
+#![cfg(feature = "jack-client")]
+
+mod common;
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use tools::offline_host::resolve_library;
+
+// a server name of its own, so a server already running is left alone
+const SERVER: &str = "note-fx-test";
+// note-fx-jack exits with 2 on errors, like when the server is not ready yet
+const ATTEMPTS: usize = 20;
+
+struct DummyServer(Child);
+
+impl DummyServer {
+    fn start() -> Option<DummyServer> {
+        let server = Command::new("jackd")
+            .args(["--no-realtime", "-n", SERVER, "-d", "dummy", "-r", "48000", "-p", "256"])
+            .spawn();
+        match server {
+            Ok(server) => Some(DummyServer(server)),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => panic!("cannot start jackd: {}", e),
+        }
+    }
+}
+
+impl Drop for DummyServer {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn fixture(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("jack").join(file)
+}
+
+#[test]
+fn note_off_delay_on_a_dummy_server() {
+    let _server = match DummyServer::start() {
+        Some(server) => server,
+        None => {
+            eprintln!("jackd is not installed, skipped");
+            return;
+        }
+    };
+    let library = resolve_library("note_off_delay", common::plugin_directory());
+
+    for _ in 0..ATTEMPTS {
+        let output = Command::new(env!("CARGO_BIN_EXE_note-fx-jack"))
+            .env("JACK_DEFAULT_SERVER", SERVER)
+            .env("JACK_NO_START_SERVER", "1")
+            .arg("--plugin")
+            .arg(&library)
+            .args(["--set", "Delay=0.5", "--name", "note-fx-test"])
+            .arg("--test")
+            .arg(fixture("notes.events"))
+            .arg("--expect")
+            .arg(fixture("note_off_delay.expected"))
+            .output()
+            .expect("cannot run note-fx-jack");
+
+        match output.status.code() {
+            Some(2) => thread::sleep(Duration::from_millis(250)),
+            _ => {
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+                return;
+            }
+        }
+    }
+    panic!("the dummy JACK server did not start");
+}
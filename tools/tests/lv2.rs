@@ -0,0 +1,82 @@
+// runs the LV2 build of the plugins in the headless host of lv2_host, from bundles written like
+// lv2_bundler does
+
+mod common;
+
+use std::fs;
+
+use util::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+
+use tools::event_list::{format_event_list, parse_event_list, ScriptEvent};
+use tools::lv2_host::{write_bundle, Lv2Plugin};
+use tools::offline_host::{resolve_library, run_script, BlockProcessor, OfflinePlugin};
+
+const SAMPLE_RATE: f32 = 44100.0;
+const BLOCK_SIZE: usize = 512;
+const LENGTH: usize = 44100;
+
+// the program change and channel pressure have a single data byte
+const INPUT: &str = "
+0 [0x90 0x3C 0x64]
+10 [0xC0 0x05 0x00]
+600 [0xD0 0x30 0x00]
+700 [0xB0 0x01 0x40]
+800 [0xE0 0x00 0x50]
+1000 [0xA0 0x3C 0x20]
+2000 [0x80 0x3C 0x00]
+";
+
+fn load(plugin: &str) -> Lv2Plugin {
+    let library = resolve_library(plugin, common::plugin_directory());
+    let directory = common::plugin_directory().join("lv2");
+    fs::create_dir_all(&directory).unwrap_or_else(|e| panic!("cannot create {}: {}", directory.display(), e));
+    let bundle = write_bundle(&library, &directory).unwrap_or_else(|e| panic!("{}", e));
+    Lv2Plugin::load(&bundle).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn play(processor: &mut dyn BlockProcessor, script: &[ScriptEvent]) -> String {
+    processor.set_sample_rate(SAMPLE_RATE);
+    processor.set_block_size(BLOCK_SIZE);
+    processor.resume();
+    format_event_list(&run_script(processor, script, BLOCK_SIZE, LENGTH).unwrap_or_else(|e| panic!("{}", e)))
+}
+
+#[test]
+fn messages_keep_their_size() {
+    let script = parse_event_list(INPUT).unwrap();
+    let messages: Vec<AbsoluteTimeMidiMessage> = script
+        .iter()
+        .filter_map(|event| match event {
+            ScriptEvent::Message(message) => Some(*message),
+            ScriptEvent::Parameter { .. } => None,
+        })
+        .collect();
+
+    // without steps, note_fan_out passes everything as is
+    assert_eq!(play(&mut load("note_fan_out"), &script), format_event_list(&messages));
+}
+
+#[test]
+fn same_output_as_vst2() {
+    let script = parse_event_list(INPUT).unwrap();
+    for plugin in ["filter_out_non_note", "midi_delay", "note_off_delay"] {
+        let library = resolve_library(plugin, common::plugin_directory());
+        let mut vst_plugin = OfflinePlugin::load(&library).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(play(&mut load(plugin), &script), play(&mut vst_plugin, &script), "{}", plugin);
+    }
+}
+
+#[test]
+fn messages_of_another_size_are_dropped() {
+    // without steps, note_fan_out passes everything as is
+    let mut plugin = load("note_fan_out");
+    plugin.set_sample_rate(SAMPLE_RATE);
+    plugin.set_block_size(BLOCK_SIZE);
+    plugin.resume().unwrap_or_else(|e| panic!("{}", e));
+
+    let sysex: &[u8] = &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+    let note_on: &[u8] = &[0x90, 0x3C, 0x64];
+    let outputs = plugin.process_raw(&[(0, sysex), (10, &note_on[..2]), (20, note_on)], BLOCK_SIZE);
+    let outputs: Vec<([u8; 3], i32)> = outputs.iter().map(|output| (output.data, output.delta_frames)).collect();
+    assert_eq!(outputs, vec![([0x90, 0x3C, 0x64], 20)]);
+}
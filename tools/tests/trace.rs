@@ -0,0 +1,59 @@
+// a plugin recording a trace, then the trace replayed against the same plugin library
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use tools::offline_host::{resolve_library, OfflinePlugin};
+use util::make_midi_message;
+use util::trace::{read_trace, TraceRecord, TRACE_DIRECTORY_VARIABLE};
+
+const SAMPLE_RATE: f32 = 44100.0;
+const BLOCK_SIZE: usize = 512;
+
+#[test]
+fn recorded_traces_replay_without_differences() {
+    let library = resolve_library("note_off_delay", common::plugin_directory());
+    let directory = env::temp_dir().join(format!("note-fx-trace-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    // read when the plugin is created
+    env::set_var(TRACE_DIRECTORY_VARIABLE, &directory);
+    let mut plugin = OfflinePlugin::load(&library).unwrap();
+    env::remove_var(TRACE_DIRECTORY_VARIABLE);
+
+    plugin.set_sample_rate(SAMPLE_RATE);
+    plugin.set_block_size(BLOCK_SIZE);
+    plugin.resume();
+    let mut outputs = 0;
+    for block in 0..40 {
+        if block == 10 {
+            plugin.set_parameter_by_name("Delay", "0.2").unwrap();
+        }
+        let pitch = 60 + (block % 12) as u8;
+        plugin.process_events(&[
+            make_midi_message([0x90, pitch, 100], 10),
+            make_midi_message([0x80, pitch, 0], 300),
+        ]);
+        outputs += plugin.process(BLOCK_SIZE).len();
+    }
+    // stops the writer thread, which writes what is left
+    drop(plugin);
+    assert!(outputs > 0);
+
+    let path = fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+    let trace = read_trace(&path).unwrap();
+    assert_eq!(trace.plugin, "note_off_delay");
+    let count = |matches: fn(&TraceRecord) -> bool| trace.records.iter().filter(|record| matches(record)).count();
+    assert_eq!(count(|record| matches!(record, TraceRecord::Block { .. })), 40);
+    assert_eq!(count(|record| matches!(record, TraceRecord::Input(_))), 80);
+    assert_eq!(count(|record| matches!(record, TraceRecord::Output(_))), outputs);
+
+    let replay = Command::new(env!("CARGO_BIN_EXE_trace_replay")).arg(&path).arg(&library).output().unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    let stdout = String::from_utf8_lossy(&replay.stdout);
+    assert!(replay.status.success(), "{}{}", stdout, String::from_utf8_lossy(&replay.stderr));
+    assert!(stdout.contains("40 blocks replayed, 0 differ"), "{}", stdout);
+}
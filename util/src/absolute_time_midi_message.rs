@@ -8,6 +8,7 @@ use super::raw_message::RawMessage;
 use vst::event::MidiEvent;
 use std::cmp::max;
 use crate::delayed_message_consumer::MessageReason;
+use crate::midi_processor::TimedMidiMessage;
 
 #[derive(Copy)]
 pub struct AbsoluteTimeMidiMessage {
@@ -31,6 +32,14 @@ impl AbsoluteTimeMidiMessage {
         }
     }
 
+    // relative to the start of the block at current_time_in_samples
+    pub fn new_timed_message(&self, current_time_in_samples: usize) -> TimedMidiMessage {
+        TimedMidiMessage::new(
+            self.data.into(),
+            self.play_time_in_samples.saturating_sub(current_time_in_samples),
+        )
+    }
+
     pub fn get_channel(&self) -> u8 {
         assert!(self.data[0] >= 0x80 && self.data[0] <= 0x9F);
         self.data[0] & 0x0F
@@ -63,9 +72,10 @@ impl Clone for AbsoluteTimeMidiMessage {
 
 impl Display for AbsoluteTimeMidiMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&*format!(
-            "{} {} [{:#04X} {:#04X} {:#04X}]", self.play_time_in_samples,
-            self.id, self.data[0], self.data[1], self.data[2]
-        ))
+        write!(
+            f,
+            "{} {} [{:#04X} {:#04X} {:#04X}] reason={:?}", self.play_time_in_samples,
+            self.id, self.data[0], self.data[1], self.data[2], self.reason
+        )
     }
 }
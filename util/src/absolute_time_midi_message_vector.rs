@@ -3,7 +3,8 @@ use std::ops::{Deref, DerefMut};
 use global_counter::primitive::exact::CounterUsize;
 
 use super::absolute_time_midi_message::AbsoluteTimeMidiMessage;
-use super::debug::DebugSocket;
+use super::debug::DebugLevel;
+use crate::debug_log;
 use super::delayed_message_consumer::MessageReason;
 use super::midi_message_type::MidiMessageType;
 use super::raw_message::RawMessage;
@@ -80,7 +81,7 @@ impl AbsoluteTimeMidiMessageVector {
             reason
         };
 
-        DebugSocket::send(&*format!("Inserting {}", message));
+        debug_log!(DebugLevel::Trace, "Inserting {}", message);
         if let Some(insert_point) = insert_point {
             self.insert(insert_point, message);
         } else {
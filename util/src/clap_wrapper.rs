@@ -0,0 +1,636 @@
+// CLAP glue for a MidiProcessor, the counterpart of vst_wrapper: one note port in and one out
+// using midi messages, parameters described by ProcessorInfo::parameter_definitions with their
+// index as id, the state saved in the same format as VST2 presets, and the transport of each
+// block given to the processor.
+//
+// a plugin crate exports it next to the VST2 entry point with clap_main!(ProcessorType);
+
+use std::cell::UnsafeCell;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+
+pub use clap_sys;
+
+use clap_sys::events::{
+    clap_event_header, clap_event_midi, clap_event_note, clap_event_param_value, clap_event_transport,
+    clap_input_events, clap_output_events, CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_MIDI, CLAP_EVENT_NOTE_OFF,
+    CLAP_EVENT_NOTE_ON, CLAP_EVENT_PARAM_VALUE, CLAP_TRANSPORT_HAS_BEATS_TIMELINE, CLAP_TRANSPORT_HAS_TEMPO,
+    CLAP_TRANSPORT_HAS_TIME_SIGNATURE, CLAP_TRANSPORT_IS_PLAYING,
+};
+use clap_sys::ext::note_ports::{
+    clap_note_port_info, clap_plugin_note_ports, CLAP_EXT_NOTE_PORTS, CLAP_NOTE_DIALECT_CLAP, CLAP_NOTE_DIALECT_MIDI,
+};
+use clap_sys::ext::params::{
+    clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS, CLAP_PARAM_IS_AUTOMATABLE, CLAP_PARAM_IS_HIDDEN,
+    CLAP_PARAM_IS_READONLY, CLAP_PARAM_IS_STEPPED,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::fixedpoint::CLAP_BEATTIME_FACTOR;
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::plugin_features::{CLAP_PLUGIN_FEATURE_NOTE_EFFECT, CLAP_PLUGIN_FEATURE_UTILITY};
+use clap_sys::process::{clap_process, clap_process_status, CLAP_PROCESS_CONTINUE, CLAP_PROCESS_ERROR};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+use vst::plugin::PluginParameters;
+
+use super::debug::{DebugSession, DebugSocket};
+use super::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
+use super::osc::OscReceiver;
+use super::parameter_definition::ParameterDefinition;
+
+#[macro_export]
+macro_rules! clap_main {
+    ($processor:ty) => {
+        static CLAP_FACTORY: $crate::clap_wrapper::ClapFactory = $crate::clap_wrapper::ClapFactory::new::<$processor>();
+
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static clap_entry: $crate::clap_wrapper::clap_sys::entry::clap_plugin_entry =
+            $crate::clap_wrapper::clap_sys::entry::clap_plugin_entry {
+                clap_version: $crate::clap_wrapper::clap_sys::version::CLAP_VERSION,
+                init: Some($crate::clap_wrapper::entry_init),
+                deinit: Some($crate::clap_wrapper::entry_deinit),
+                get_factory: Some(clap_entry_get_factory),
+            };
+
+        unsafe extern "C" fn clap_entry_get_factory(
+            factory_id: *const std::os::raw::c_char,
+        ) -> *const std::os::raw::c_void {
+            if factory_id.is_null() {
+                return std::ptr::null();
+            }
+            CLAP_FACTORY.get(std::ffi::CStr::from_ptr(factory_id))
+        }
+    };
+}
+
+// the descriptor points to strings built from ProcessorInfo, kept alive next to it
+pub struct ClapDescriptor {
+    descriptor: clap_plugin_descriptor,
+    _strings: Vec<CString>,
+    _features: Vec<*const c_char>,
+}
+
+// only read after being built, and what it points to is never modified
+unsafe impl Send for ClapDescriptor {}
+unsafe impl Sync for ClapDescriptor {}
+
+impl ClapDescriptor {
+    fn new(info: &ProcessorInfo) -> ClapDescriptor {
+        let strings: Vec<CString> = [
+            format!("com.djcrontab.{}", info.id),
+            info.name.to_string(),
+            info.vendor.to_string(),
+            String::new(),
+            info.version.to_string(),
+        ]
+        .iter()
+        .map(|text| CString::new(text.as_str()).unwrap_or_default())
+        .collect();
+
+        let features = vec![
+            CLAP_PLUGIN_FEATURE_NOTE_EFFECT.as_ptr(),
+            CLAP_PLUGIN_FEATURE_UTILITY.as_ptr(),
+            ptr::null(),
+        ];
+
+        ClapDescriptor {
+            descriptor: clap_plugin_descriptor {
+                clap_version: CLAP_VERSION,
+                id: strings[0].as_ptr(),
+                name: strings[1].as_ptr(),
+                vendor: strings[2].as_ptr(),
+                url: strings[3].as_ptr(),
+                manual_url: strings[3].as_ptr(),
+                support_url: strings[3].as_ptr(),
+                version: strings[4].as_ptr(),
+                description: strings[1].as_ptr(),
+                features: features.as_ptr(),
+            },
+            _strings: strings,
+            _features: features,
+        }
+    }
+}
+
+// a factory for a single plugin. The clap_plugin_factory comes first, so the pointer given to the
+// host can be turned back into the whole struct
+#[repr(C)]
+pub struct ClapFactory {
+    factory: clap_plugin_factory,
+    descriptor: OnceLock<ClapDescriptor>,
+    info: fn() -> ProcessorInfo,
+    create: unsafe fn(*const clap_host, &'static ClapDescriptor) -> *const clap_plugin,
+}
+
+impl ClapFactory {
+    pub const fn new<P: MidiProcessor + 'static>() -> ClapFactory {
+        ClapFactory {
+            factory: clap_plugin_factory {
+                get_plugin_count: Some(factory_get_plugin_count),
+                get_plugin_descriptor: Some(factory_get_plugin_descriptor),
+                create_plugin: Some(factory_create_plugin),
+            },
+            descriptor: OnceLock::new(),
+            info: P::info,
+            create: ClapWrapper::<P>::create,
+        }
+    }
+
+    fn descriptor(&'static self) -> &'static ClapDescriptor {
+        self.descriptor.get_or_init(|| ClapDescriptor::new(&(self.info)()))
+    }
+
+    pub fn get(&'static self, factory_id: &CStr) -> *const c_void {
+        if factory_id == CLAP_PLUGIN_FACTORY_ID {
+            &self.factory as *const clap_plugin_factory as *const c_void
+        } else {
+            ptr::null()
+        }
+    }
+}
+
+pub extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+pub extern "C" fn entry_deinit() {}
+
+unsafe fn factory<'a>(factory: *const clap_plugin_factory) -> &'a ClapFactory {
+    &*(factory as *const ClapFactory)
+}
+
+unsafe extern "C" fn factory_get_plugin_count(_factory: *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_get_plugin_descriptor(
+    factory_pointer: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    if index == 0 {
+        &factory(factory_pointer).descriptor().descriptor
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_create_plugin(
+    factory_pointer: *const clap_plugin_factory,
+    host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    let factory = factory(factory_pointer);
+    let descriptor = factory.descriptor();
+    if plugin_id.is_null() || CStr::from_ptr(plugin_id) != CStr::from_ptr(descriptor.descriptor.id) {
+        return ptr::null();
+    }
+    (factory.create)(host, descriptor)
+}
+
+// only touched from the audio thread, or while not processing
+struct AudioState<P: MidiProcessor> {
+    processor: P,
+    inputs: Vec<TimedMidiMessage>,
+    outputs: Vec<TimedMidiMessage>,
+}
+
+// the extensions are part of each instance, so the pointers given to the host stay valid as long
+// as the plugin lives
+struct ClapWrapper<P: MidiProcessor> {
+    plugin: clap_plugin,
+    params: clap_plugin_params,
+    note_ports: clap_plugin_note_ports,
+    state: clap_plugin_state,
+    parameters: Arc<P::Parameters>,
+    definitions: &'static [ParameterDefinition],
+    audio: UnsafeCell<AudioState<P>>,
+    debug_session: DebugSession,
+    osc: OscReceiver,
+}
+
+impl<P: MidiProcessor + 'static> ClapWrapper<P> {
+    unsafe fn create(_host: *const clap_host, descriptor: &'static ClapDescriptor) -> *const clap_plugin {
+        let info = P::info();
+        let debug_session = DebugSocket::open(info.id);
+        let processor = {
+            let _scope = debug_session.scope();
+            P::default()
+        };
+
+        let wrapper = Box::into_raw(Box::new(ClapWrapper {
+            plugin: clap_plugin {
+                desc: &descriptor.descriptor,
+                plugin_data: ptr::null_mut(),
+                init: Some(Self::init),
+                destroy: Some(Self::destroy),
+                activate: Some(Self::activate),
+                deactivate: Some(Self::deactivate),
+                start_processing: Some(Self::start_processing),
+                stop_processing: Some(Self::stop_processing),
+                reset: Some(Self::reset),
+                process: Some(Self::process),
+                get_extension: Some(Self::get_extension),
+                on_main_thread: Some(Self::on_main_thread),
+            },
+            params: clap_plugin_params {
+                count: Some(Self::params_count),
+                get_info: Some(Self::params_get_info),
+                get_value: Some(Self::params_get_value),
+                value_to_text: Some(Self::params_value_to_text),
+                text_to_value: Some(Self::params_text_to_value),
+                flush: Some(Self::params_flush),
+            },
+            note_ports: clap_plugin_note_ports {
+                count: Some(Self::note_ports_count),
+                get: Some(Self::note_ports_get),
+            },
+            state: clap_plugin_state {
+                save: Some(Self::state_save),
+                load: Some(Self::state_load),
+            },
+            parameters: Arc::clone(processor.parameters()),
+            definitions: info.parameter_definitions,
+            audio: UnsafeCell::new(AudioState {
+                processor,
+                inputs: vec![],
+                outputs: vec![],
+            }),
+            debug_session,
+            osc: OscReceiver::from_env(&info),
+        }));
+        (*wrapper).plugin.plugin_data = wrapper as *mut c_void;
+        &(*wrapper).plugin
+    }
+
+    unsafe fn from_plugin<'a>(plugin: *const clap_plugin) -> &'a ClapWrapper<P> {
+        &*((*plugin).plugin_data as *const ClapWrapper<P>)
+    }
+
+    // the host guarantees a single thread processes at a time
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn audio_state(&self) -> &mut AudioState<P> {
+        &mut *self.audio.get()
+    }
+
+    fn definition(&self, id: clap_id) -> Option<&'static ParameterDefinition> {
+        self.definitions.get(id as usize)
+    }
+
+    fn set_parameter(&self, id: clap_id, plain: f64) {
+        if let Some(definition) = self.definition(id) {
+            if !definition.read_only {
+                self.parameters.set_parameter(id as i32, definition.to_normalized(plain));
+            }
+        }
+    }
+
+    // parameter changes are applied before the block, midi and note events are kept for it
+    unsafe fn read_events(&self, events: *const clap_input_events, inputs: &mut Vec<TimedMidiMessage>) {
+        if events.is_null() {
+            return;
+        }
+        let events = &*events;
+        let (size, get) = match (events.size, events.get) {
+            (Some(size), Some(get)) => (size, get),
+            _ => return,
+        };
+
+        for index in 0..size(events) {
+            let header = get(events, index);
+            if header.is_null() || (*header).space_id != CLAP_CORE_EVENT_SPACE_ID {
+                continue;
+            }
+            let delta_frames = (*header).time as usize;
+
+            match (*header).type_ {
+                CLAP_EVENT_PARAM_VALUE => {
+                    let event = &*(header as *const clap_event_param_value);
+                    self.set_parameter(event.param_id, event.value);
+                }
+                CLAP_EVENT_MIDI => {
+                    let event = &*(header as *const clap_event_midi);
+                    inputs.push(TimedMidiMessage::new(event.data, delta_frames));
+                }
+                event_type @ (CLAP_EVENT_NOTE_ON | CLAP_EVENT_NOTE_OFF) => {
+                    let event = &*(header as *const clap_event_note);
+                    // wildcards only make sense for note expressions and chokes
+                    if event.channel < 0 || event.key < 0 {
+                        continue;
+                    }
+                    let status = if event_type == CLAP_EVENT_NOTE_ON { 0x90 } else { 0x80 };
+                    let velocity = (event.velocity * 127.).round().clamp(0., 127.) as u8;
+                    inputs.push(TimedMidiMessage::new(
+                        [status | (event.channel as u8 & 0x0F), event.key as u8 & 0x7F, velocity],
+                        delta_frames,
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    unsafe fn transport(transport: *const clap_event_transport) -> TransportInfo {
+        if transport.is_null() {
+            return Default::default();
+        }
+        let transport = &*transport;
+        let valid = |flag: u32| transport.flags & flag != 0;
+        let beats = |time: i64| time as f64 / CLAP_BEATTIME_FACTOR as f64;
+
+        TransportInfo {
+            playing: valid(CLAP_TRANSPORT_IS_PLAYING),
+            tempo: Some(transport.tempo).filter(|_| valid(CLAP_TRANSPORT_HAS_TEMPO)),
+            position: Some(beats(transport.song_pos_beats)).filter(|_| valid(CLAP_TRANSPORT_HAS_BEATS_TIMELINE)),
+            bar_start_position: Some(beats(transport.bar_start)).filter(|_| valid(CLAP_TRANSPORT_HAS_BEATS_TIMELINE)),
+            time_signature: Some((transport.tsig_num as i32, transport.tsig_denom as i32))
+                .filter(|_| valid(CLAP_TRANSPORT_HAS_TIME_SIGNATURE)),
+            // CLAP gives seconds, not samples
+            sample_position: None,
+        }
+    }
+
+    unsafe extern "C" fn init(_plugin: *const clap_plugin) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn destroy(plugin: *const clap_plugin) {
+        drop(Box::from_raw((*plugin).plugin_data as *mut ClapWrapper<P>));
+    }
+
+    unsafe extern "C" fn activate(
+        plugin: *const clap_plugin,
+        sample_rate: f64,
+        _min_frames_count: u32,
+        max_frames_count: u32,
+    ) -> bool {
+        let wrapper = Self::from_plugin(plugin);
+        let audio = wrapper.audio_state();
+        audio.processor.set_sample_rate(sample_rate as f32);
+        audio.processor.set_block_size(max_frames_count as usize);
+        true
+    }
+
+    unsafe extern "C" fn deactivate(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn start_processing(_plugin: *const clap_plugin) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn stop_processing(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn reset(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn process(plugin: *const clap_plugin, process: *const clap_process) -> clap_process_status {
+        if process.is_null() {
+            return CLAP_PROCESS_ERROR;
+        }
+        let wrapper = Self::from_plugin(plugin);
+        let _scope = wrapper.debug_session.scope();
+        let process = &*process;
+        let audio = wrapper.audio_state();
+
+        wrapper.osc.apply(&*wrapper.parameters);
+        wrapper.read_events(process.in_events, &mut audio.inputs);
+        let transport = Self::transport(process.transport);
+
+        audio.inputs.sort_by_key(|message| message.delta_frames);
+        audio.processor.process(&audio.inputs, process.frames_count as usize, &transport, &mut audio.outputs);
+        audio.inputs.clear();
+
+        // the host expects the events in time order
+        audio.outputs.sort_by_key(|message| message.delta_frames);
+        let out_events = process.out_events;
+        let try_push = if out_events.is_null() { None } else { (*out_events).try_push };
+        for message in audio.outputs.drain(..) {
+            if let Some(try_push) = try_push {
+                let event = clap_event_midi {
+                    header: clap_event_header {
+                        size: mem::size_of::<clap_event_midi>() as u32,
+                        time: message.delta_frames as u32,
+                        space_id: CLAP_CORE_EVENT_SPACE_ID,
+                        type_: CLAP_EVENT_MIDI,
+                        flags: 0,
+                    },
+                    port_index: 0,
+                    data: message.data,
+                };
+                try_push(out_events, &event.header);
+            }
+        }
+
+        CLAP_PROCESS_CONTINUE
+    }
+
+    unsafe extern "C" fn get_extension(plugin: *const clap_plugin, id: *const c_char) -> *const c_void {
+        if id.is_null() {
+            return ptr::null();
+        }
+        let wrapper = Self::from_plugin(plugin);
+        let id = CStr::from_ptr(id);
+
+        if id == CLAP_EXT_PARAMS {
+            &wrapper.params as *const clap_plugin_params as *const c_void
+        } else if id == CLAP_EXT_NOTE_PORTS {
+            &wrapper.note_ports as *const clap_plugin_note_ports as *const c_void
+        } else if id == CLAP_EXT_STATE {
+            &wrapper.state as *const clap_plugin_state as *const c_void
+        } else {
+            ptr::null()
+        }
+    }
+
+    unsafe extern "C" fn on_main_thread(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn params_count(plugin: *const clap_plugin) -> u32 {
+        Self::from_plugin(plugin).definitions.len() as u32
+    }
+
+    unsafe extern "C" fn params_get_info(plugin: *const clap_plugin, index: u32, info: *mut clap_param_info) -> bool {
+        let definition = match Self::from_plugin(plugin).definition(index) {
+            Some(definition) if !info.is_null() => definition,
+            _ => return false,
+        };
+
+        let mut flags = 0;
+        if definition.is_stepped() {
+            flags |= CLAP_PARAM_IS_STEPPED;
+        }
+        if definition.hidden {
+            flags |= CLAP_PARAM_IS_HIDDEN;
+        }
+        if definition.read_only {
+            flags |= CLAP_PARAM_IS_READONLY;
+        } else if !definition.hidden {
+            flags |= CLAP_PARAM_IS_AUTOMATABLE;
+        }
+
+        let (min, max) = definition.range();
+        let info = &mut *info;
+        info.id = index;
+        info.flags = flags;
+        info.cookie = ptr::null_mut();
+        write_text(&mut info.name, definition.name);
+        write_text(&mut info.module, "");
+        info.min_value = min;
+        info.max_value = max;
+        info.default_value = definition.to_plain(definition.default);
+        true
+    }
+
+    unsafe extern "C" fn params_get_value(plugin: *const clap_plugin, id: clap_id, value: *mut f64) -> bool {
+        let wrapper = Self::from_plugin(plugin);
+        match wrapper.definition(id) {
+            Some(definition) if !value.is_null() => {
+                *value = definition.to_plain(wrapper.parameters.get_parameter(id as i32));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // the plugin texts only describe the current value, other values get a generic text
+    unsafe extern "C" fn params_value_to_text(
+        plugin: *const clap_plugin,
+        id: clap_id,
+        value: f64,
+        display: *mut c_char,
+        size: u32,
+    ) -> bool {
+        let wrapper = Self::from_plugin(plugin);
+        let definition = match wrapper.definition(id) {
+            Some(definition) if !display.is_null() && size > 0 => definition,
+            _ => return false,
+        };
+
+        let current = definition.to_plain(wrapper.parameters.get_parameter(id as i32));
+        let text = if definition.read_only || (current - value).abs() < 1e-6 {
+            wrapper.parameters.get_parameter_text(id as i32)
+        } else {
+            definition.format(value)
+        };
+        write_text(std::slice::from_raw_parts_mut(display, size as usize), &text);
+        true
+    }
+
+    unsafe extern "C" fn params_text_to_value(
+        plugin: *const clap_plugin,
+        id: clap_id,
+        display: *const c_char,
+        value: *mut f64,
+    ) -> bool {
+        let definition = match Self::from_plugin(plugin).definition(id) {
+            Some(definition) if !display.is_null() && !value.is_null() => definition,
+            _ => return false,
+        };
+
+        match CStr::from_ptr(display).to_str().ok().and_then(|text| definition.parse(text)) {
+            Some(plain) => {
+                *value = plain;
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe extern "C" fn params_flush(
+        plugin: *const clap_plugin,
+        in_events: *const clap_input_events,
+        _out_events: *const clap_output_events,
+    ) {
+        // only parameter changes are expected here, anything else is dropped
+        let mut ignored = vec![];
+        Self::from_plugin(plugin).read_events(in_events, &mut ignored);
+    }
+
+    unsafe extern "C" fn note_ports_count(_plugin: *const clap_plugin, _is_input: bool) -> u32 {
+        1
+    }
+
+    unsafe extern "C" fn note_ports_get(
+        _plugin: *const clap_plugin,
+        index: u32,
+        is_input: bool,
+        info: *mut clap_note_port_info,
+    ) -> bool {
+        if index != 0 || info.is_null() {
+            return false;
+        }
+        let info = &mut *info;
+        info.id = 0;
+        // clap notes received are turned into midi messages, only midi is sent
+        info.supported_dialects = if is_input {
+            CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_CLAP
+        } else {
+            CLAP_NOTE_DIALECT_MIDI
+        };
+        info.preferred_dialect = CLAP_NOTE_DIALECT_MIDI;
+        write_text(&mut info.name, if is_input { "Notes in" } else { "Notes out" });
+        true
+    }
+
+    unsafe extern "C" fn state_save(plugin: *const clap_plugin, stream: *const clap_ostream) -> bool {
+        let write = match stream.as_ref().and_then(|stream| stream.write) {
+            Some(write) => write,
+            None => return false,
+        };
+
+        let data = Self::from_plugin(plugin).parameters.get_preset_data();
+        let mut written = 0;
+        while written < data.len() {
+            let remaining = &data[written..];
+            let result = write(stream, remaining.as_ptr() as *const c_void, remaining.len() as u64);
+            if result <= 0 {
+                return false;
+            }
+            written += result as usize;
+        }
+        true
+    }
+
+    unsafe extern "C" fn state_load(plugin: *const clap_plugin, stream: *const clap_istream) -> bool {
+        let read = match stream.as_ref().and_then(|stream| stream.read) {
+            Some(read) => read,
+            None => return false,
+        };
+
+        let mut data = vec![];
+        let mut buffer = [0u8; 256];
+        loop {
+            let result = read(stream, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u64);
+            if result < 0 {
+                return false;
+            }
+            if result == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..result as usize]);
+        }
+
+        // a state longer than the plugin saves is not one of its states. Panics of the plugin on
+        // other invalid data must not unwind into the host
+        let parameters = &Self::from_plugin(plugin).parameters;
+        if data.len() > parameters.get_preset_data().len() {
+            return false;
+        }
+        panic::catch_unwind(AssertUnwindSafe(|| parameters.load_preset_data(&data))).is_ok()
+    }
+}
+
+// copies as much of the text as fits, always null terminated
+fn write_text(destination: &mut [c_char], text: &str) {
+    let length = text.len().min(destination.len().saturating_sub(1));
+    for (destination, byte) in destination.iter_mut().zip(text.bytes().take(length)) {
+        *destination = byte as c_char;
+    }
+    if let Some(terminator) = destination.get_mut(length) {
+        *terminator = 0;
+    }
+}
@@ -5,7 +5,8 @@ pub const CC: u8 = 0xB0;
 pub const TIMBRECC: u8 = 0x4A;
 pub const NOTE_OFF: u8 = 0x80;
 pub const NOTE_ON: u8 = 0x90;
-pub const AFTERTOUCH: u8 = 0x90;
+pub const AFTERTOUCH: u8 = 0xA0;
+pub const PROGRAM_CHANGE: u8 = 0xC0;
 pub const C0: i8 = 0x18;
 
 pub static NOTE_NAMES: &[&str; 12] = &[
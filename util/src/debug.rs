@@ -1,46 +1,369 @@
-use std::net::{SocketAddr, UdpSocket};
+// debug lines are sent as UDP datagrams, but never from the calling thread: lines are formatted
+// into fixed-size buffers, without allocating, and pushed into a lock-free ring buffer that a
+// background thread drains to the socket. Everything is compiled
+// out unless the `debug` feature is enabled, and at runtime the level is read from
+// NOTE_FX_DEBUG_LEVEL ( off, error, info, debug, trace ) and the destination from
+// NOTE_FX_DEBUG_DESTINATION ( defaults to 127.0.0.1:5555 ).
+//
+// lines logged while a session scope is active are prefixed with the plugin name and instance
+// number, e.g. "note_off_delay#2 Inserting ...", so a receiver can tell instances apart.
+//
+// debug_log! takes a format string and its arguments, which are only formatted when the level is
+// enabled.
 
-pub struct DebugSocket {
-    socket: UdpSocket,
-    to: SocketAddr,
+use core::fmt;
+use core::str::FromStr;
+
+use global_counter::primitive::exact::CounterUsize;
+
+pub const LEVEL_VARIABLE: &str = "NOTE_FX_DEBUG_LEVEL";
+pub const DESTINATION_VARIABLE: &str = "NOTE_FX_DEBUG_DESTINATION";
+pub const DEFAULT_DESTINATION: &str = "127.0.0.1:5555";
+
+static DEBUG_INSTANCE_ID: CounterUsize = CounterUsize::new(0);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum DebugLevel {
+    Off = 0,
+    Error,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for DebugLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_ref() {
+            "off" | "0" => Ok(DebugLevel::Off),
+            "error" | "1" => Ok(DebugLevel::Error),
+            "info" | "2" => Ok(DebugLevel::Info),
+            "debug" | "3" => Ok(DebugLevel::Debug),
+            "trace" | "4" => Ok(DebugLevel::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+// keeps the background thread alive. The thread is stopped and joined when the last session is
+// dropped, so it never outlives the plugin library.
+#[cfg_attr(not(feature = "debug"), allow(dead_code))]
+pub struct DebugSession {
+    plugin_name: &'static str,
+    instance: usize,
+}
+
+impl DebugSession {
+    // tags lines logged from the current thread with this instance, until the scope is dropped
+    pub fn scope(&self) -> DebugScope {
+        #[cfg(feature = "debug")]
+        {
+            DebugScope {
+                previous: channel::set_current_instance(Some((self.plugin_name, self.instance))),
+            }
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            DebugScope {}
+        }
+    }
+}
+
+pub struct DebugScope {
+    #[cfg(feature = "debug")]
+    previous: Option<(&'static str, usize)>,
+}
+
+impl Drop for DebugScope {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        channel::set_current_instance(self.previous);
+    }
+}
+
+impl Drop for DebugSession {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        channel::close();
+    }
+}
+
+#[macro_export]
+macro_rules! debug_log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::debug::DebugSocket::enabled($level) {
+            $crate::debug::DebugSocket::log_args($level, format_args!($($arg)+));
+        }
+    };
 }
 
+pub struct DebugSocket;
+
 impl DebugSocket {
-    fn create() {
-        let socket_result = (10000..20000)
-            .map(|port| UdpSocket::bind(format!("127.0.0.1:{}", port)))
-            .filter_map(Result::ok)
-            .next();
+    // the only way to start the background thread: to be called outside of the audio thread,
+    // typically when the plugin is created, and kept while the plugin is alive
+    pub fn open(plugin_name: &'static str) -> DebugSession {
+        #[cfg(feature = "debug")]
+        channel::open();
+        DebugSession {
+            plugin_name,
+            instance: DEBUG_INSTANCE_ID.inc(),
+        }
+    }
 
-        let socket = socket_result.unwrap();
-        socket.set_nonblocking(true).unwrap();
+    // allows callers to skip formatting when the line would be discarded anyway
+    #[inline]
+    pub fn enabled(level: DebugLevel) -> bool {
+        #[cfg(feature = "debug")]
+        {
+            level != DebugLevel::Off && level as u8 <= channel::level()
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            let _ = level;
+            false
+        }
+    }
+
+    pub fn log(level: DebugLevel, debug_str: &str) {
+        if !debug_str.is_empty() {
+            Self::log_args(level, format_args!("{}", debug_str));
+        }
+    }
 
-        unsafe {
-            DEBUG_SOCKET = Some(DebugSocket {
-                socket,
-                to: "127.0.0.1:5555".parse().unwrap(),
-            })
+    // does not allocate, lines longer than the queued buffers are cut
+    pub fn log_args(level: DebugLevel, args: fmt::Arguments) {
+        #[cfg(feature = "debug")]
+        {
+            if Self::enabled(level) {
+                channel::push(args);
+            }
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            let _ = (level, args);
         }
     }
 
     pub fn send(debug_str: &str) {
-        if debug_str.is_empty() {
-            return
+        Self::log(DebugLevel::Debug, debug_str)
+    }
+}
+
+#[cfg(feature = "debug")]
+mod channel {
+    use std::cell::Cell;
+    use std::env;
+    use std::fmt;
+    use std::fmt::Write;
+    use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+    use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+    use std::thread;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use crossbeam_queue::ArrayQueue;
+
+    use super::{DebugLevel, DEFAULT_DESTINATION, DESTINATION_VARIABLE, LEVEL_VARIABLE};
+
+    const QUEUE_CAPACITY: usize = 4096;
+    const DRAIN_INTERVAL: Duration = Duration::from_millis(20);
+    // including the line feed
+    const LINE_SIZE: usize = 256;
+
+    // written on the stack of the logging thread, and copied into the queue
+    pub struct DebugLine {
+        length: usize,
+        bytes: [u8; LINE_SIZE],
+    }
+
+    impl DebugLine {
+        pub fn new(args: fmt::Arguments) -> Self {
+            let mut line = DebugLine {
+                length: 0,
+                bytes: [0; LINE_SIZE],
+            };
+            // writing never fails, what does not fit is dropped
+            let _ = match current_instance() {
+                Some((plugin_name, instance)) => write!(line, "{}#{} {}", plugin_name, instance, args),
+                None => line.write_fmt(args),
+            };
+            line.bytes[line.length] = b'\n';
+            line.length += 1;
+            line
+        }
+
+        pub fn as_bytes(&self) -> &[u8] {
+            &self.bytes[..self.length]
         }
-        let debug_string = debug_str.to_owned() + "\n";
-        unsafe {
-            if DEBUG_SOCKET.is_none() {
-                Self::create()
+    }
+
+    impl Write for DebugLine {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            // keeps room for the line feed, and cuts between characters
+            let available = LINE_SIZE - 1 - self.length;
+            let mut end = s.len().min(available);
+            while !s.is_char_boundary(end) {
+                end -= 1;
             }
+            self.bytes[self.length..self.length + end].copy_from_slice(&s.as_bytes()[..end]);
+            self.length += end;
+            Ok(())
+        }
+    }
+
+    struct Worker {
+        sessions: usize,
+        stop: Option<Arc<AtomicBool>>,
+        handle: Option<JoinHandle<()>>,
+    }
+
+    static QUEUE: OnceLock<ArrayQueue<DebugLine>> = OnceLock::new();
+    static LEVEL: AtomicU8 = AtomicU8::new(DebugLevel::Off as u8);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static WORKER: Mutex<Worker> = Mutex::new(Worker {
+        sessions: 0,
+        stop: None,
+        handle: None,
+    });
+
+    thread_local! {
+        static CURRENT_INSTANCE: Cell<Option<(&'static str, usize)>> = const { Cell::new(None) };
+    }
+
+    // returns the previous value, so scopes can be nested
+    pub fn set_current_instance(instance: Option<(&'static str, usize)>) -> Option<(&'static str, usize)> {
+        CURRENT_INSTANCE.with(|current| current.replace(instance))
+    }
+
+    pub fn current_instance() -> Option<(&'static str, usize)> {
+        CURRENT_INSTANCE.with(|current| current.get())
+    }
+
+    fn lock_worker() -> MutexGuard<'static, Worker> {
+        // a panic while holding this lock leaves nothing inconsistent, keep going
+        WORKER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 
-            if let Some(debug_socket) = &DEBUG_SOCKET {
-                debug_socket
-                    .socket
-                    .send_to(debug_string.as_bytes(), debug_socket.to)
-                    .unwrap();
+    #[inline]
+    pub fn level() -> u8 {
+        LEVEL.load(Ordering::Relaxed)
+    }
+
+    pub fn push(args: fmt::Arguments) {
+        if let Some(queue) = QUEUE.get() {
+            if queue.push(DebugLine::new(args)).is_err() {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
-}
 
-static mut DEBUG_SOCKET: Option<DebugSocket> = None;
+    pub fn open() {
+        let mut worker = lock_worker();
+        worker.sessions += 1;
+        if worker.sessions == 1 {
+            start(&mut worker);
+        }
+    }
+
+    pub fn close() {
+        let mut worker = lock_worker();
+        worker.sessions = worker.sessions.saturating_sub(1);
+        if worker.sessions > 0 {
+            return;
+        }
+
+        LEVEL.store(DebugLevel::Off as u8, Ordering::Relaxed);
+        if let Some(stop) = worker.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        if let Some(handle) = worker.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn start(worker: &mut Worker) {
+        let level = env::var(LEVEL_VARIABLE)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DebugLevel::Debug);
+
+        if level == DebugLevel::Off {
+            return;
+        }
+
+        let (socket, to) = match connect() {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        QUEUE.get_or_init(|| ArrayQueue::new(QUEUE_CAPACITY));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let spawned = thread::Builder::new()
+            .name("note fx debug".to_string())
+            .spawn(move || drain(socket, to, &thread_stop));
+
+        if let Ok(handle) = spawned {
+            worker.stop = Some(stop);
+            worker.handle = Some(handle);
+            LEVEL.store(level as u8, Ordering::Relaxed);
+        }
+    }
+
+    fn connect() -> Option<(UdpSocket, SocketAddr)> {
+        let destination =
+            env::var(DESTINATION_VARIABLE).unwrap_or_else(|_| DEFAULT_DESTINATION.to_string());
+        let to = destination.to_socket_addrs().ok()?.next()?;
+        let bind_address = if to.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_address).ok()?;
+        Some((socket, to))
+    }
+
+    fn drain(socket: UdpSocket, to: SocketAddr, stop: &AtomicBool) {
+        let queue = match QUEUE.get() {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        loop {
+            // send errors are ignored: nobody listening is the normal case
+            while let Some(line) = queue.pop() {
+                let _ = socket.send_to(line.as_bytes(), to);
+            }
+
+            let dropped = DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                let _ = socket.send_to(format!("{} debug lines dropped\n", dropped).as_bytes(), to);
+            }
+
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            thread::sleep(DRAIN_INTERVAL);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn lines_are_tagged_and_cut() {
+            assert_eq!(DebugLine::new(format_args!("{} {}", "note", 60)).as_bytes(), b"note 60\n");
+
+            let previous = set_current_instance(Some(("note_off_delay", 2)));
+            assert_eq!(DebugLine::new(format_args!("Inserting")).as_bytes(), b"note_off_delay#2 Inserting\n");
+            set_current_instance(previous);
+
+            // the two bytes character does not fit, and is not cut in half
+            let long = "a".repeat(LINE_SIZE - 2) + "\u{e9}";
+            let line = DebugLine::new(format_args!("{}", long));
+            assert_eq!(line.as_bytes().len(), LINE_SIZE - 1);
+            assert!(line.as_bytes().ends_with(b"a\n"));
+        }
+    }
+}
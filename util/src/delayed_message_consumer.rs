@@ -2,12 +2,13 @@ use core::iter::Iterator;
 use std::collections::HashMap;
 use std::collections::hash_map::RandomState;
 use std::ops::{DerefMut, Deref};
-use vst::event::MidiEvent;
 
 use super::absolute_time_midi_message::AbsoluteTimeMidiMessage;
 use super::absolute_time_midi_message_vector::AbsoluteTimeMidiMessageVector;
 use super::midi_message_type::MidiMessageType;
 use super::messages::NoteOff;
+use super::midi_processor::TimedMidiMessage;
+use super::scheduler_statistics::SchedulerStatistics;
 
 
 #[derive(Hash, Clone, Copy, PartialEq, Eq)]
@@ -16,7 +17,7 @@ struct PlayingNoteIndex {
     pitch: u8,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MessageReason {
     Live,
     Delayed,   // the same event will exist live and delayed
@@ -59,12 +60,13 @@ impl PlayingNotes {
 
 pub fn process_scheduled_events(samples: usize, current_time_in_samples: usize,
                                 messages: &AbsoluteTimeMidiMessageVector, max_notes: u8,
-                                apply_max_notes_to_delayed_notes_only: bool, delay_is_active: bool
-) -> (AbsoluteTimeMidiMessageVector, Vec<MidiEvent>) {
+                                apply_max_notes_to_delayed_notes_only: bool, delay_is_active: bool,
+                                statistics: &SchedulerStatistics
+) -> (AbsoluteTimeMidiMessageVector, Vec<TimedMidiMessage>) {
     let mut playing_notes: PlayingNotes = PlayingNotes::default();
     let mut queued_messages = AbsoluteTimeMidiMessageVector::default();
     let mut notes_on_to_requeue : HashMap<usize, AbsoluteTimeMidiMessage> = HashMap::new();
-    let mut events: Vec<MidiEvent> = vec![];
+    let mut events: Vec<TimedMidiMessage> = vec![];
 
     let mut add_event = |event: AbsoluteTimeMidiMessage, playing_notes: &mut PlayingNotes| {
         // note: playing_notes cannot be captured by closure because the method also uses it, causing the borrow checker
@@ -76,7 +78,11 @@ pub fn process_scheduled_events(samples: usize, current_time_in_samples: usize,
             if event.play_time_in_samples >= current_time_in_samples {
                 if let MidiMessageType::NoteOffMessage(_) = MidiMessageType::from(event) {
                     let note_on = notes_on_to_requeue.get_mut( & event.id);
-                    if note_on.is_none() { return }  // no such note running, skip
+                    if note_on.is_none() {
+                        // no such note running, skip
+                        statistics.count_skipped_note_off();
+                        return
+                    }
                     let note_on = note_on.unwrap();
 
                     if event.reason == MessageReason::Live && delay_is_active {
@@ -97,7 +103,8 @@ pub fn process_scheduled_events(samples: usize, current_time_in_samples: usize,
                     playing_notes.remove(&PlayingNoteIndex { pitch: event.get_pitch(), channel: event.get_channel() });
                     notes_on_to_requeue.remove(&event.id);
                 }
-                events.push(event.new_midi_event(current_time_in_samples));
+                statistics.count_event(event.reason);
+                events.push(event.new_timed_message(current_time_in_samples));
             }
 
             if let MidiMessageType::NoteOnMessage(_) = MidiMessageType::from(event) {
@@ -110,11 +117,18 @@ pub fn process_scheduled_events(samples: usize, current_time_in_samples: usize,
         }
     };
 
+    statistics.update_queue_depth(messages.len());
+
     for mut message in messages.iter().copied() {
         if message.play_time_in_samples < current_time_in_samples {
             match MidiMessageType::from(message) {
                 MidiMessageType::NoteOnMessage(_) => {}
-                _ => { panic!("Only pending note on are expected to be found in the past") }
+                _ => {
+                    // only pending note on are expected to be found in the past. Anything else
+                    // missed its block, send it as soon as possible
+                    statistics.count_late_event();
+                    message.play_time_in_samples = current_time_in_samples;
+                }
             }
         };
 
@@ -172,11 +186,13 @@ pub fn process_scheduled_events(samples: usize, current_time_in_samples: usize,
                             // this note was interrupted earlier already, don't send that
                             // note off or we may interrupt a new note with that delayed note
                             // off
+                            statistics.count_skipped_note_off();
                             continue;
                         }
                     }
                     None => {
                         // was not playing at all, skip
+                        statistics.count_skipped_note_off();
                         continue;
                     }
                 };
@@ -1,23 +1,29 @@
 extern crate global_counter;
 
 use vst::event::MidiEvent;
-use vst::plugin::HostCallback;
 
 pub mod constants;
 pub mod debug;
 pub mod parameter_value_conversion;
 pub mod parameters;
+pub mod parameter_definition;
 pub mod messages;
 pub mod absolute_time_midi_message;
 pub mod midi_message_type;
 pub mod raw_message;
 pub mod absolute_time_midi_message_vector;
 pub mod delayed_message_consumer;
-
-#[derive(Default)]
-pub struct HostCallbackLock {
-    pub host: HostCallback,
-}
+pub mod trace;
+pub mod scheduler_statistics;
+pub mod midi_file;
+pub mod midi_processor;
+pub mod osc;
+pub mod random;
+pub mod scale;
+pub mod step_counter;
+pub mod vst_wrapper;
+pub mod clap_wrapper;
+pub mod lv2_wrapper;
 
 pub fn make_midi_message(bytes: [u8; 3], delta_frames: i32) -> MidiEvent {
     MidiEvent {
@@ -31,6 +37,15 @@ pub fn make_midi_message(bytes: [u8; 3], delta_frames: i32) -> MidiEvent {
     }
 }
 
+// bytes of a channel message on the wire: program change and channel pressure have a single data
+// byte, system messages are not supported
+pub fn message_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 2,
+        _ => 3,
+    }
+}
+
 pub fn duration_display(value: f32) -> String {
     let mut out = String::new();
     let mut _value = value;
@@ -0,0 +1,631 @@
+// LV2 glue for a MidiProcessor, the counterpart of vst_wrapper and clap_wrapper. Each plugin is a
+// MIDI plugin with an atom sequence port in ( midi events and time:Position ) and one out ( midi
+// events ), followed by one control port per parameter, neither hidden nor read-only, in the order
+// of ProcessorInfo::parameter_definitions. Control ports hold plain values, as in the definitions.
+//
+// a plugin crate exports it with lv2_main!(ProcessorType), which also exports lv2_write_bundle,
+// used by the lv2_bundler tool to write the .ttl files of a bundle from the parameter definitions.
+//
+// the few LV2 types needed are declared here, following the C headers.
+
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::fs;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::ptr;
+use std::sync::OnceLock;
+
+use vst::plugin::PluginParameters;
+
+use super::debug::{DebugSession, DebugSocket};
+use super::message_length;
+use super::midi_processor::{MidiProcessor, ProcessorInfo, TimedMidiMessage, TransportInfo};
+use super::osc::OscReceiver;
+use super::parameter_definition::{ParameterDefinition, ParameterScale};
+
+pub const LV2_URID_MAP: &str = "http://lv2plug.in/ns/ext/urid#map";
+pub const LV2_ATOM_SEQUENCE: &str = "http://lv2plug.in/ns/ext/atom#Sequence";
+pub const LV2_ATOM_CHUNK: &str = "http://lv2plug.in/ns/ext/atom#Chunk";
+pub const LV2_MIDI_EVENT: &str = "http://lv2plug.in/ns/ext/midi#MidiEvent";
+const LV2_ATOM_OBJECT: &str = "http://lv2plug.in/ns/ext/atom#Object";
+const LV2_ATOM_BLANK: &str = "http://lv2plug.in/ns/ext/atom#Blank";
+const LV2_ATOM_INT: &str = "http://lv2plug.in/ns/ext/atom#Int";
+const LV2_ATOM_LONG: &str = "http://lv2plug.in/ns/ext/atom#Long";
+const LV2_ATOM_FLOAT: &str = "http://lv2plug.in/ns/ext/atom#Float";
+const LV2_ATOM_DOUBLE: &str = "http://lv2plug.in/ns/ext/atom#Double";
+const LV2_TIME_POSITION: &str = "http://lv2plug.in/ns/ext/time#Position";
+const LV2_TIME_BAR: &str = "http://lv2plug.in/ns/ext/time#bar";
+const LV2_TIME_BAR_BEAT: &str = "http://lv2plug.in/ns/ext/time#barBeat";
+const LV2_TIME_BEATS_PER_BAR: &str = "http://lv2plug.in/ns/ext/time#beatsPerBar";
+const LV2_TIME_BEAT_UNIT: &str = "http://lv2plug.in/ns/ext/time#beatUnit";
+const LV2_TIME_BEATS_PER_MINUTE: &str = "http://lv2plug.in/ns/ext/time#beatsPerMinute";
+const LV2_TIME_SPEED: &str = "http://lv2plug.in/ns/ext/time#speed";
+
+// the atom ports come first, then the control ports
+pub const MIDI_IN_PORT: u32 = 0;
+pub const MIDI_OUT_PORT: u32 = 1;
+pub const FIRST_CONTROL_PORT: u32 = 2;
+
+pub type Lv2Handle = *mut c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Lv2Descriptor {
+    pub uri: *const c_char,
+    pub instantiate: Option<
+        unsafe extern "C" fn(*const Lv2Descriptor, f64, *const c_char, *const *const Lv2Feature) -> Lv2Handle,
+    >,
+    pub connect_port: Option<unsafe extern "C" fn(Lv2Handle, u32, *mut c_void)>,
+    pub activate: Option<unsafe extern "C" fn(Lv2Handle)>,
+    pub run: Option<unsafe extern "C" fn(Lv2Handle, u32)>,
+    pub deactivate: Option<unsafe extern "C" fn(Lv2Handle)>,
+    pub cleanup: Option<unsafe extern "C" fn(Lv2Handle)>,
+    pub extension_data: Option<unsafe extern "C" fn(*const c_char) -> *const c_void>,
+}
+
+#[repr(C)]
+pub struct Lv2Feature {
+    pub uri: *const c_char,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct Lv2UridMap {
+    pub handle: *mut c_void,
+    pub map: Option<unsafe extern "C" fn(*mut c_void, *const c_char) -> u32>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Atom {
+    pub size: u32,
+    pub type_: u32,
+}
+
+#[repr(C)]
+pub struct AtomSequence {
+    pub atom: Atom,
+    // time unit of the events, 0 for frames, and padding
+    pub unit: u32,
+    pub pad: u32,
+}
+
+// followed by body.size bytes, then padding to 8 bytes
+#[repr(C)]
+pub struct AtomEvent {
+    pub time_frames: i64,
+    pub body: Atom,
+}
+
+#[repr(C)]
+struct AtomObjectBody {
+    id: u32,
+    otype: u32,
+}
+
+#[repr(C)]
+struct AtomPropertyBody {
+    key: u32,
+    context: u32,
+    value: Atom,
+}
+
+pub fn atom_padded_size(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+// the control ports, with the index of their parameter
+pub fn control_parameters(
+    definitions: &'static [ParameterDefinition],
+) -> impl Iterator<Item = (usize, &'static ParameterDefinition)> {
+    definitions
+        .iter()
+        .enumerate()
+        .filter(|(_, definition)| !definition.hidden && !definition.read_only)
+}
+
+pub fn plugin_uri(info: &ProcessorInfo) -> String {
+    format!("urn:djcrontab:{}", info.id)
+}
+
+#[macro_export]
+macro_rules! lv2_main {
+    ($processor:ty) => {
+        static LV2_EXPORT: $crate::lv2_wrapper::Lv2Export = $crate::lv2_wrapper::Lv2Export::new::<$processor>();
+
+        #[no_mangle]
+        pub extern "C" fn lv2_descriptor(index: u32) -> *const $crate::lv2_wrapper::Lv2Descriptor {
+            LV2_EXPORT.descriptor(index)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn lv2_write_bundle(
+            directory: *const std::os::raw::c_char,
+            binary: *const std::os::raw::c_char,
+        ) -> bool {
+            if directory.is_null() || binary.is_null() {
+                return false;
+            }
+            LV2_EXPORT.write_bundle(std::ffi::CStr::from_ptr(directory), std::ffi::CStr::from_ptr(binary))
+        }
+    };
+}
+
+struct ExportedDescriptor {
+    descriptor: Lv2Descriptor,
+    _uri: CString,
+}
+
+// the descriptor of a single plugin, its uri built on first use
+pub struct Lv2Export {
+    template: Lv2Descriptor,
+    descriptor: OnceLock<ExportedDescriptor>,
+    info: fn() -> ProcessorInfo,
+}
+
+// the only pointer is the uri, owned by the export and never modified
+unsafe impl Send for Lv2Export {}
+unsafe impl Sync for Lv2Export {}
+
+impl Lv2Export {
+    pub const fn new<P: MidiProcessor + 'static>() -> Lv2Export {
+        Lv2Export {
+            template: Lv2Descriptor {
+                uri: ptr::null(),
+                instantiate: Some(Lv2Wrapper::<P>::instantiate),
+                connect_port: Some(Lv2Wrapper::<P>::connect_port),
+                activate: Some(Lv2Wrapper::<P>::activate),
+                run: Some(Lv2Wrapper::<P>::run),
+                deactivate: Some(Lv2Wrapper::<P>::deactivate),
+                cleanup: Some(Lv2Wrapper::<P>::cleanup),
+                extension_data: Some(extension_data),
+            },
+            descriptor: OnceLock::new(),
+            info: P::info,
+        }
+    }
+
+    pub fn descriptor(&self, index: u32) -> *const Lv2Descriptor {
+        if index != 0 {
+            return ptr::null();
+        }
+        let exported = self.descriptor.get_or_init(|| {
+            let uri = CString::new(plugin_uri(&(self.info)())).unwrap_or_default();
+            ExportedDescriptor {
+                descriptor: Lv2Descriptor {
+                    uri: uri.as_ptr(),
+                    ..self.template
+                },
+                _uri: uri,
+            }
+        });
+        &exported.descriptor
+    }
+
+    pub fn write_bundle(&self, directory: &CStr, binary: &CStr) -> bool {
+        let directory = directory.to_string_lossy().into_owned();
+        let binary = binary.to_string_lossy().into_owned();
+        let info = (self.info)();
+
+        let plugin_file = format!("{}.ttl", info.id);
+        let written = fs::write(Path::new(&directory).join("manifest.ttl"), manifest_ttl(&info, &binary, &plugin_file))
+            .and_then(|_| fs::write(Path::new(&directory).join(&plugin_file), plugin_ttl(&info)));
+        written.is_ok()
+    }
+}
+
+unsafe extern "C" fn extension_data(_uri: *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+pub fn manifest_ttl(info: &ProcessorInfo, binary: &str, plugin_file: &str) -> String {
+    format!(
+        "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+         @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+         \n\
+         <{}>\n    a lv2:Plugin ;\n    lv2:binary <{}> ;\n    rdfs:seeAlso <{}> .\n",
+        plugin_uri(info),
+        binary,
+        plugin_file
+    )
+}
+
+pub fn plugin_ttl(info: &ProcessorInfo) -> String {
+    let mut ttl = String::new();
+    ttl += "@prefix atom: <http://lv2plug.in/ns/ext/atom#> .\n";
+    ttl += "@prefix doap: <http://usefulinc.com/ns/doap#> .\n";
+    ttl += "@prefix foaf: <http://xmlns.com/foaf/0.1/> .\n";
+    ttl += "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n";
+    ttl += "@prefix midi: <http://lv2plug.in/ns/ext/midi#> .\n";
+    ttl += "@prefix time: <http://lv2plug.in/ns/ext/time#> .\n";
+    ttl += "@prefix units: <http://lv2plug.in/ns/extensions/units#> .\n";
+    ttl += "@prefix urid: <http://lv2plug.in/ns/ext/urid#> .\n\n";
+
+    let _ = write!(
+        ttl,
+        "<{}>\n    a lv2:Plugin , lv2:MIDIPlugin ;\n    doap:name \"{}\" ;\n    \
+         doap:maintainer [ foaf:name \"{}\" ] ;\n    lv2:minorVersion 0 ;\n    lv2:microVersion {} ;\n    \
+         lv2:requiredFeature urid:map ;\n    lv2:optionalFeature lv2:hardRTCapable ;\n",
+        plugin_uri(info),
+        info.name,
+        info.vendor,
+        info.version
+    );
+
+    let _ = write!(
+        ttl,
+        "    lv2:port [\n        a lv2:InputPort , atom:AtomPort ;\n        atom:bufferType atom:Sequence ;\n        \
+         atom:supports midi:MidiEvent , time:Position ;\n        lv2:designation lv2:control ;\n        \
+         lv2:index {} ;\n        lv2:symbol \"midi_in\" ;\n        lv2:name \"MIDI in\"\n    ] , [\n        \
+         a lv2:OutputPort , atom:AtomPort ;\n        atom:bufferType atom:Sequence ;\n        \
+         atom:supports midi:MidiEvent ;\n        lv2:index {} ;\n        lv2:symbol \"midi_out\" ;\n        \
+         lv2:name \"MIDI out\"\n    ]",
+        MIDI_IN_PORT, MIDI_OUT_PORT
+    );
+
+    for (port, (_, definition)) in control_parameters(info.parameter_definitions).enumerate() {
+        let (minimum, maximum) = definition.range();
+        let _ = write!(
+            ttl,
+            " , [\n        a lv2:InputPort , lv2:ControlPort ;\n        lv2:index {} ;\n        lv2:symbol \"{}\" ;\n        \
+             lv2:name \"{}\" ;\n        lv2:default {:?} ;\n        lv2:minimum {:?} ;\n        lv2:maximum {:?}",
+            FIRST_CONTROL_PORT as usize + port,
+            definition.symbol(),
+            definition.name,
+            definition.to_plain(definition.default),
+            minimum,
+            maximum
+        );
+        match definition.scale {
+            ParameterScale::Byte { .. } | ParameterScale::U14 => ttl += " ;\n        lv2:portProperty lv2:integer",
+            ParameterScale::Bool => ttl += " ;\n        lv2:portProperty lv2:toggled",
+            ParameterScale::Exponential { .. } => ttl += " ;\n        units:unit units:s",
+            ParameterScale::Normalized => {}
+        }
+        ttl += "\n    ]";
+    }
+    ttl += " .\n";
+    ttl
+}
+
+struct Urids {
+    sequence: u32,
+    midi_event: u32,
+    object: u32,
+    blank: u32,
+    int: u32,
+    long: u32,
+    float: u32,
+    double: u32,
+    position: u32,
+    bar: u32,
+    bar_beat: u32,
+    beats_per_bar: u32,
+    beat_unit: u32,
+    beats_per_minute: u32,
+    speed: u32,
+}
+
+impl Urids {
+    unsafe fn new(map: &Lv2UridMap) -> Option<Urids> {
+        let map_function = map.map?;
+        let urid = |uri: &str| {
+            let uri = CString::new(uri).unwrap_or_default();
+            map_function(map.handle, uri.as_ptr())
+        };
+        Some(Urids {
+            sequence: urid(LV2_ATOM_SEQUENCE),
+            midi_event: urid(LV2_MIDI_EVENT),
+            object: urid(LV2_ATOM_OBJECT),
+            blank: urid(LV2_ATOM_BLANK),
+            int: urid(LV2_ATOM_INT),
+            long: urid(LV2_ATOM_LONG),
+            float: urid(LV2_ATOM_FLOAT),
+            double: urid(LV2_ATOM_DOUBLE),
+            position: urid(LV2_TIME_POSITION),
+            bar: urid(LV2_TIME_BAR),
+            bar_beat: urid(LV2_TIME_BAR_BEAT),
+            beats_per_bar: urid(LV2_TIME_BEATS_PER_BAR),
+            beat_unit: urid(LV2_TIME_BEAT_UNIT),
+            beats_per_minute: urid(LV2_TIME_BEATS_PER_MINUTE),
+            speed: urid(LV2_TIME_SPEED),
+        })
+    }
+
+    unsafe fn number(&self, atom: *const Atom) -> Option<f64> {
+        let body = (atom as *const u8).add(mem::size_of::<Atom>());
+        let type_ = (*atom).type_;
+        if type_ == self.int {
+            Some(ptr::read_unaligned(body as *const i32) as f64)
+        } else if type_ == self.long {
+            Some(ptr::read_unaligned(body as *const i64) as f64)
+        } else if type_ == self.float {
+            Some(ptr::read_unaligned(body as *const f32) as f64)
+        } else if type_ == self.double {
+            Some(ptr::read_unaligned(body as *const f64))
+        } else {
+            None
+        }
+    }
+}
+
+// hosts only send the position when it changes, so it is kept and moved forward with each block
+#[derive(Default)]
+struct Position {
+    known: bool,
+    bar: f64,
+    bar_beat: f64,
+    beats_per_bar: f64,
+    beat_unit: f64,
+    // in beat units
+    beats_per_minute: f64,
+    speed: f64,
+}
+
+impl Position {
+    fn advance(&mut self, samples: usize, sample_rate: f64) {
+        if !self.known || self.beats_per_bar <= 0.0 {
+            return;
+        }
+        self.bar_beat += samples as f64 / sample_rate * self.beats_per_minute / 60. * self.speed;
+        while self.bar_beat >= self.beats_per_bar {
+            self.bar_beat -= self.beats_per_bar;
+            self.bar += 1.0;
+        }
+    }
+
+    // LV2 beats are of beat_unit length, the processors count in quarter notes
+    fn transport(&self) -> TransportInfo {
+        if !self.known || self.beat_unit <= 0.0 {
+            return Default::default();
+        }
+        let quarter_notes = 4. / self.beat_unit;
+        let bar_start = self.bar * self.beats_per_bar * quarter_notes;
+        TransportInfo {
+            playing: self.speed != 0.0,
+            tempo: Some(self.beats_per_minute * quarter_notes),
+            position: Some(bar_start + self.bar_beat * quarter_notes),
+            bar_start_position: Some(bar_start),
+            time_signature: Some((self.beats_per_bar as i32, self.beat_unit as i32)),
+            sample_position: None,
+        }
+    }
+}
+
+struct ControlPort {
+    parameter: usize,
+    definition: &'static ParameterDefinition,
+    port: *const f32,
+    // so values are only applied when the host changes them
+    last_value: Option<f32>,
+}
+
+struct Lv2Wrapper<P: MidiProcessor> {
+    processor: P,
+    urids: Urids,
+    sample_rate: f64,
+    input: *const AtomSequence,
+    output: *mut AtomSequence,
+    controls: Vec<ControlPort>,
+    position: Position,
+    inputs: Vec<TimedMidiMessage>,
+    outputs: Vec<TimedMidiMessage>,
+    debug_session: DebugSession,
+    osc: OscReceiver,
+}
+
+impl<P: MidiProcessor + 'static> Lv2Wrapper<P> {
+    unsafe fn from_handle<'a>(handle: Lv2Handle) -> &'a mut Lv2Wrapper<P> {
+        &mut *(handle as *mut Lv2Wrapper<P>)
+    }
+
+    unsafe extern "C" fn instantiate(
+        _descriptor: *const Lv2Descriptor,
+        sample_rate: f64,
+        _bundle_path: *const c_char,
+        features: *const *const Lv2Feature,
+    ) -> Lv2Handle {
+        let mut urids = None;
+        let mut feature = features;
+        while !feature.is_null() && !(*feature).is_null() {
+            let uri = CStr::from_ptr((**feature).uri);
+            if uri.to_bytes() == LV2_URID_MAP.as_bytes() && !(**feature).data.is_null() {
+                urids = Urids::new(&*((**feature).data as *const Lv2UridMap));
+            }
+            feature = feature.add(1);
+        }
+        // required, as declared in the ttl
+        let urids = match urids {
+            Some(urids) => urids,
+            None => return ptr::null_mut(),
+        };
+
+        let info = P::info();
+        let debug_session = DebugSocket::open(info.id);
+        let mut processor = {
+            let _scope = debug_session.scope();
+            P::default()
+        };
+        processor.set_sample_rate(sample_rate as f32);
+
+        let controls = control_parameters(info.parameter_definitions)
+            .map(|(parameter, definition)| ControlPort {
+                parameter,
+                definition,
+                port: ptr::null(),
+                last_value: None,
+            })
+            .collect();
+
+        Box::into_raw(Box::new(Lv2Wrapper {
+            processor,
+            urids,
+            sample_rate,
+            input: ptr::null(),
+            output: ptr::null_mut(),
+            controls,
+            position: Default::default(),
+            inputs: vec![],
+            outputs: vec![],
+            debug_session,
+            osc: OscReceiver::from_env(&info),
+        })) as Lv2Handle
+    }
+
+    unsafe extern "C" fn connect_port(handle: Lv2Handle, port: u32, data: *mut c_void) {
+        let wrapper = Self::from_handle(handle);
+        match port {
+            MIDI_IN_PORT => wrapper.input = data as *const AtomSequence,
+            MIDI_OUT_PORT => wrapper.output = data as *mut AtomSequence,
+            _ => {
+                if let Some(control) = wrapper.controls.get_mut((port - FIRST_CONTROL_PORT) as usize) {
+                    control.port = data as *const f32;
+                }
+            }
+        }
+    }
+
+    unsafe extern "C" fn activate(_handle: Lv2Handle) {}
+
+    unsafe extern "C" fn deactivate(_handle: Lv2Handle) {}
+
+    unsafe extern "C" fn cleanup(handle: Lv2Handle) {
+        drop(Box::from_raw(handle as *mut Lv2Wrapper<P>));
+    }
+
+    fn apply_controls(&mut self) {
+        let parameters = self.processor.parameters();
+        for control in &mut self.controls {
+            if control.port.is_null() {
+                continue;
+            }
+            let value = unsafe { *control.port };
+            if control.last_value != Some(value) {
+                parameters.set_parameter(control.parameter as i32, control.definition.to_normalized(value as f64));
+                control.last_value = Some(value);
+            }
+        }
+    }
+
+    unsafe fn read_position(&mut self, object: *const Atom) {
+        let body = (object as *const u8).add(mem::size_of::<Atom>()) as *const AtomObjectBody;
+        if (*body).otype != self.urids.position {
+            return;
+        }
+
+        let end = mem::size_of::<Atom>() + (*object).size as usize;
+        let mut offset = mem::size_of::<Atom>() + mem::size_of::<AtomObjectBody>();
+        while offset + mem::size_of::<AtomPropertyBody>() <= end {
+            let property = (object as *const u8).add(offset) as *const AtomPropertyBody;
+            let value = &(*property).value as *const Atom;
+            if let Some(number) = self.urids.number(value) {
+                let position = &mut self.position;
+                let key = (*property).key;
+                if key == self.urids.bar {
+                    position.bar = number;
+                } else if key == self.urids.bar_beat {
+                    position.bar_beat = number;
+                } else if key == self.urids.beats_per_bar {
+                    position.beats_per_bar = number;
+                } else if key == self.urids.beat_unit {
+                    position.beat_unit = number;
+                } else if key == self.urids.beats_per_minute {
+                    position.beats_per_minute = number;
+                } else if key == self.urids.speed {
+                    position.speed = number;
+                }
+                position.known = true;
+            }
+            offset += atom_padded_size(
+                mem::size_of::<u32>() * 2 + mem::size_of::<Atom>() + (*value).size as usize,
+            );
+        }
+    }
+
+    // midi events are kept for the block, positions are applied at its start
+    unsafe fn read_input(&mut self) {
+        if self.input.is_null() {
+            return;
+        }
+        let sequence = self.input;
+        let end = mem::size_of::<Atom>() + (*sequence).atom.size as usize;
+        let mut offset = mem::size_of::<AtomSequence>();
+
+        while offset + mem::size_of::<AtomEvent>() <= end {
+            let event = (sequence as *const u8).add(offset) as *const AtomEvent;
+            let body = &(*event).body as *const Atom;
+            let size = (*body).size as usize;
+            let type_ = (*body).type_;
+
+            if type_ == self.urids.midi_event && size > 0 {
+                let bytes = std::slice::from_raw_parts((body as *const u8).add(mem::size_of::<Atom>()), size);
+                // SysEx and messages of another size than their status gives are not passed on
+                if size == message_length(bytes[0]) {
+                    let mut data = [0u8; 3];
+                    data[..size].copy_from_slice(bytes);
+                    self.inputs.push(TimedMidiMessage::new(data, (*event).time_frames.max(0) as usize));
+                }
+            } else if type_ == self.urids.object || type_ == self.urids.blank {
+                self.read_position(body);
+            }
+
+            offset += atom_padded_size(mem::size_of::<AtomEvent>() + size);
+        }
+    }
+
+    // the host gives the capacity of the buffer in atom.size, events that don't fit are dropped
+    unsafe fn write_output(&mut self) {
+        if self.output.is_null() {
+            self.outputs.clear();
+            return;
+        }
+        let sequence = self.output;
+        let capacity = (*sequence).atom.size as usize;
+        (*sequence).atom.type_ = self.urids.sequence;
+        (*sequence).atom.size = (mem::size_of::<AtomSequence>() - mem::size_of::<Atom>()) as u32;
+        (*sequence).unit = 0;
+        (*sequence).pad = 0;
+
+        let mut offset = mem::size_of::<AtomSequence>();
+        for message in self.outputs.drain(..) {
+            let length = message_length(message.data[0]);
+            let event_size = atom_padded_size(mem::size_of::<AtomEvent>() + length);
+            if offset + event_size > capacity {
+                break;
+            }
+            let event = (sequence as *mut u8).add(offset) as *mut AtomEvent;
+            (*event).time_frames = message.delta_frames as i64;
+            (*event).body = Atom {
+                size: length as u32,
+                type_: self.urids.midi_event,
+            };
+            let data = (event as *mut u8).add(mem::size_of::<AtomEvent>());
+            ptr::copy_nonoverlapping(message.data.as_ptr(), data, length);
+
+            offset += event_size;
+            (*sequence).atom.size += event_size as u32;
+        }
+        self.outputs.clear();
+    }
+
+    unsafe extern "C" fn run(handle: Lv2Handle, sample_count: u32) {
+        let wrapper = Self::from_handle(handle);
+        let _scope = wrapper.debug_session.scope();
+
+        wrapper.apply_controls();
+        wrapper.osc.apply(&**wrapper.processor.parameters());
+        wrapper.read_input();
+        let transport = wrapper.position.transport();
+
+        wrapper.inputs.sort_by_key(|message| message.delta_frames);
+        wrapper.processor.process(&wrapper.inputs, sample_count as usize, &transport, &mut wrapper.outputs);
+        wrapper.inputs.clear();
+
+        // events of a sequence are in time order
+        wrapper.outputs.sort_by_key(|message| message.delta_frames);
+        wrapper.write_output();
+        wrapper.position.advance(sample_count as usize, wrapper.sample_rate);
+    }
+}
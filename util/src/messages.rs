@@ -1,9 +1,44 @@
 use vst::event::Event::Midi;
 use vst::event::{Event, MidiEvent};
 
-use super::constants::{NOTE_ON, NOTE_OFF, PRESSURE, PITCHBEND};
+use super::constants::{C0, NOTE_NAMES, NOTE_ON, NOTE_OFF, PRESSURE, PITCHBEND};
+use super::midi_message_type::MidiMessageType;
 use super::raw_message::RawMessage;
 
+pub fn note_name(pitch: u8) -> String {
+    format!(
+        "{}{}",
+        NOTE_NAMES[pitch as usize % 12],
+        (pitch as i16 - C0 as i16).div_euclid(12)
+    )
+}
+
+// human readable version of a midi message, channels displayed from 1
+pub fn describe_message(data: &[u8; 3]) -> String {
+    let channel = (data[0] & 0x0F) + 1;
+    match MidiMessageType::from(data) {
+        MidiMessageType::NoteOnMessage(m) => {
+            format!("note on {} velocity {} channel {}", note_name(m.pitch), m.velocity, channel)
+        }
+        MidiMessageType::NoteOffMessage(m) => {
+            format!("note off {} velocity {} channel {}", note_name(m.pitch), m.velocity, channel)
+        }
+        MidiMessageType::CCMessage(_) => format!("cc {} value {} channel {}", data[1], data[2], channel),
+        MidiMessageType::PressureMessage(_) => format!("pressure {} channel {}", data[1], channel),
+        MidiMessageType::PitchBendMessage(_) => format!(
+            "pitch bend {} channel {}",
+            ((data[2] as i32) << 7 | data[1] as i32) - 0x2000,
+            channel
+        ),
+        MidiMessageType::UnsupportedChannelMessage(_) => match data[0] & 0xF0 {
+            0xA0 => format!("poly aftertouch {} {} channel {}", note_name(data[1]), data[2], channel),
+            0xC0 => format!("program change {} channel {}", data[1], channel),
+            _ => format!("system [{:#04X} {:#04X} {:#04X}]", data[0], data[1], data[2]),
+        },
+        MidiMessageType::Unsupported => format!("[{:#04X} {:#04X} {:#04X}]", data[0], data[1], data[2]),
+    }
+}
+
 pub fn format_midi_event(e: &MidiEvent) -> String {
     format!(
         "[{:#04X} {:#04X} {:#04X}] delta_frames={}",
@@ -11,6 +46,25 @@ pub fn format_midi_event(e: &MidiEvent) -> String {
     )
 }
 
+// parses "[0x90 0x3C 0x40]" at the start of s, returns the bytes and what follows
+pub fn parse_message_bytes(s: &str) -> Option<([u8; 3], &str)> {
+    let s = s.trim();
+    if !s.starts_with('[') {
+        return None;
+    }
+    let end = s.find(']')?;
+
+    let bytes = s[1..end]
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte.trim_start_matches("0x").trim_start_matches("0X"), 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    if bytes.len() != 3 {
+        return None;
+    }
+
+    Some(([bytes[0], bytes[1], bytes[2]], &s[end + 1..]))
+}
+
 pub fn format_event(e: &Event) -> String {
     // notice bitwig only gives midi events anyway
     match e {
@@ -0,0 +1,489 @@
+// standard midi files, format 0 and 1. Reading merges all tracks into one stream of messages timed
+// in samples, following the tempo changes of the file, writing produces a format 0 file using the
+// same tempo map, so bars and beats line up with the file that was read.
+//
+// sysex and meta events other than tempo changes are skipped, SMPTE based timing is not supported.
+
+use std::fs;
+use std::path::Path;
+
+use super::absolute_time_midi_message::AbsoluteTimeMidiMessage;
+use super::delayed_message_consumer::MessageReason;
+
+// microseconds per quarter note, 120 bpm
+pub const DEFAULT_TEMPO: u32 = 500_000;
+pub const DEFAULT_TICKS_PER_QUARTER_NOTE: u16 = 960;
+
+const META_EVENT: u8 = 0xFF;
+const META_TEMPO: u8 = 0x51;
+const META_END_OF_TRACK: u8 = 0x2F;
+
+#[derive(Clone, Copy)]
+struct TempoChange {
+    tick: u64,
+    tempo: u32,
+    // time of the change, cached to avoid walking the whole map on each conversion
+    seconds: f64,
+}
+
+#[derive(Clone)]
+pub struct TempoMap {
+    ticks_per_quarter_note: u16,
+    changes: Vec<TempoChange>,
+}
+
+pub struct MidiFile {
+    pub tempo_map: TempoMap,
+    pub messages: Vec<AbsoluteTimeMidiMessage>,
+}
+
+impl TempoMap {
+    // changes are (tick, microseconds per quarter note), the tempo is 120 bpm until the first one
+    pub fn new(ticks_per_quarter_note: u16, mut changes: Vec<(u64, u32)>) -> TempoMap {
+        changes.sort_by_key(|(tick, _)| *tick);
+
+        let mut tempo_map = TempoMap {
+            ticks_per_quarter_note: ticks_per_quarter_note.max(1),
+            changes: vec![TempoChange {
+                tick: 0,
+                tempo: DEFAULT_TEMPO,
+                seconds: 0.0,
+            }],
+        };
+
+        for (tick, tempo) in changes {
+            let seconds = tempo_map.tick_to_seconds(tick);
+            // a change at the same tick replaces the previous one
+            if let Some(last) = tempo_map.changes.last_mut().filter(|last| last.tick == tick) {
+                last.tempo = tempo;
+            } else {
+                tempo_map.changes.push(TempoChange { tick, tempo, seconds });
+            }
+        }
+
+        tempo_map
+    }
+
+    pub fn ticks_per_quarter_note(&self) -> u16 {
+        self.ticks_per_quarter_note
+    }
+
+    fn seconds_per_tick(&self, change: &TempoChange) -> f64 {
+        change.tempo as f64 / 1_000_000.0 / self.ticks_per_quarter_note as f64
+    }
+
+    pub fn tick_to_seconds(&self, tick: u64) -> f64 {
+        let change = self
+            .changes
+            .iter()
+            .rev()
+            .find(|change| change.tick <= tick)
+            .unwrap_or(&self.changes[0]);
+        change.seconds + (tick - change.tick) as f64 * self.seconds_per_tick(change)
+    }
+
+    pub fn seconds_to_tick(&self, seconds: f64) -> u64 {
+        let change = self
+            .changes
+            .iter()
+            .rev()
+            .find(|change| change.seconds <= seconds)
+            .unwrap_or(&self.changes[0]);
+        change.tick + ((seconds - change.seconds).max(0.0) / self.seconds_per_tick(change)).round() as u64
+    }
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        TempoMap::new(DEFAULT_TICKS_PER_QUARTER_NOTE, vec![])
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < length {
+            return Err(format!("unexpected end of data at byte {}", self.position));
+        }
+        let taken = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(taken)
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.bytes
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| format!("unexpected end of data at byte {}", self.position))
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // at most 4 bytes, 7 bits each, the high bit set on all bytes but the last
+    fn read_variable_length(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.read_u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!("invalid variable length quantity at byte {}", self.position))
+    }
+}
+
+fn write_variable_length(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+// program change and channel pressure have a single data byte
+fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+enum TrackEvent {
+    Tempo(u32),
+    Message([u8; 3]),
+}
+
+fn parse_track(reader: &mut Reader, events: &mut Vec<(u64, TrackEvent)>) -> Result<(), String> {
+    let mut tick = 0u64;
+    let mut running_status: Option<u8> = None;
+
+    while !reader.is_empty() {
+        tick += reader.read_variable_length()? as u64;
+
+        let status = if reader.peek()? < 0x80 {
+            running_status.ok_or_else(|| format!("data byte without status at byte {}", reader.position))?
+        } else {
+            reader.read_u8()?
+        };
+
+        match status {
+            META_EVENT => {
+                running_status = None;
+                let meta_type = reader.read_u8()?;
+                let length = reader.read_variable_length()? as usize;
+                let data = reader.take(length)?;
+
+                match meta_type {
+                    META_TEMPO if length == 3 => {
+                        let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                        events.push((tick, TrackEvent::Tempo(tempo)));
+                    }
+                    META_END_OF_TRACK => break,
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let length = reader.read_variable_length()? as usize;
+                reader.take(length)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let mut data = [status, 0, 0];
+                for byte in data.iter_mut().skip(1).take(data_length(status)) {
+                    *byte = reader.read_u8()? & 0x7F;
+                }
+                events.push((tick, TrackEvent::Message(data)));
+            }
+            _ => return Err(format!("unexpected status {:#04X} at byte {}", status, reader.position)),
+        }
+    }
+
+    Ok(())
+}
+
+impl MidiFile {
+    pub fn read(path: &Path, sample_rate: f32) -> Result<MidiFile, String> {
+        let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&bytes, sample_rate).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(bytes: &[u8], sample_rate: f32) -> Result<MidiFile, String> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(4)? != b"MThd" {
+            return Err("not a standard midi file".to_string());
+        }
+        let header_length = reader.read_u32()? as usize;
+        let mut header = Reader {
+            bytes: reader.take(header_length)?,
+            position: 0,
+        };
+        let format = header.read_u16()?;
+        let _track_count = header.read_u16()?;
+        let division = header.read_u16()?;
+
+        if format > 1 {
+            return Err(format!("format {} files are not supported", format));
+        }
+        if division & 0x8000 != 0 {
+            return Err("SMPTE timing is not supported".to_string());
+        }
+
+        let mut events = vec![];
+        while !reader.is_empty() {
+            let chunk_type = reader.take(4)?;
+            let length = reader.read_u32()? as usize;
+            let chunk = reader.take(length)?;
+            // unknown chunks are to be ignored
+            if chunk_type == b"MTrk" {
+                parse_track(&mut Reader { bytes: chunk, position: 0 }, &mut events)?;
+            }
+        }
+        // stable, so events at the same tick keep the order of the tracks
+        events.sort_by_key(|(tick, _)| *tick);
+
+        let tempo_changes = events
+            .iter()
+            .filter_map(|(tick, event)| match event {
+                TrackEvent::Tempo(tempo) => Some((*tick, *tempo)),
+                TrackEvent::Message(_) => None,
+            })
+            .collect();
+        let tempo_map = TempoMap::new(division, tempo_changes);
+
+        let messages = events
+            .iter()
+            .filter_map(|(tick, event)| match event {
+                TrackEvent::Message(data) => Some(AbsoluteTimeMidiMessage {
+                    data: (*data).into(),
+                    id: 0,
+                    reason: MessageReason::Live,
+                    play_time_in_samples: (tempo_map.tick_to_seconds(*tick) * sample_rate as f64).round() as usize,
+                }),
+                TrackEvent::Tempo(_) => None,
+            })
+            .collect();
+
+        Ok(MidiFile { tempo_map, messages })
+    }
+
+    // messages are expected to be sorted by time, system messages are skipped
+    pub fn encode(&self, sample_rate: f32) -> Vec<u8> {
+        let mut events: Vec<(u64, TrackEvent)> = self
+            .tempo_map
+            .changes
+            .iter()
+            .map(|change| (change.tick, TrackEvent::Tempo(change.tempo)))
+            .collect();
+
+        for message in &self.messages {
+            if message.data[0] < 0x80 || message.data[0] >= 0xF0 {
+                continue;
+            }
+            let seconds = message.play_time_in_samples as f64 / sample_rate as f64;
+            events.push((self.tempo_map.seconds_to_tick(seconds), TrackEvent::Message(message.data.into())));
+        }
+        events.sort_by_key(|(tick, _)| *tick);
+
+        let mut track = vec![];
+        let mut last_tick = 0u64;
+        for (tick, event) in events {
+            write_variable_length(&mut track, (tick - last_tick).min(0x0FFF_FFFF) as u32);
+            last_tick = tick;
+
+            match event {
+                TrackEvent::Tempo(tempo) => {
+                    track.extend([META_EVENT, META_TEMPO, 3]);
+                    track.extend(&tempo.to_be_bytes()[1..]);
+                }
+                TrackEvent::Message(data) => track.extend(&data[..1 + data_length(data[0])]),
+            }
+        }
+        track.extend([0, META_EVENT, META_END_OF_TRACK, 0]);
+
+        let mut bytes = vec![];
+        bytes.extend(b"MThd");
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend(0u16.to_be_bytes());
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(self.tempo_map.ticks_per_quarter_note.to_be_bytes());
+        bytes.extend(b"MTrk");
+        bytes.extend((track.len() as u32).to_be_bytes());
+        bytes.extend(track);
+        bytes
+    }
+
+    pub fn write(&self, path: &Path, sample_rate: f32) -> Result<(), String> {
+        fs::write(path, self.encode(sample_rate)).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn header(format: u16, track_count: u16, division: u16) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend(format.to_be_bytes());
+        bytes.extend(track_count.to_be_bytes());
+        bytes.extend(division.to_be_bytes());
+        bytes
+    }
+
+    fn track(events: &[u8]) -> Vec<u8> {
+        let mut bytes = b"MTrk".to_vec();
+        bytes.extend((events.len() as u32 + 4).to_be_bytes());
+        bytes.extend(events);
+        bytes.extend([0, META_EVENT, META_END_OF_TRACK, 0]);
+        bytes
+    }
+
+    fn timed_data(midi_file: &MidiFile) -> Vec<(usize, [u8; 3])> {
+        midi_file
+            .messages
+            .iter()
+            .map(|message| (message.play_time_in_samples, message.data.into()))
+            .collect()
+    }
+
+    #[test]
+    fn format_0_with_running_status() {
+        let mut bytes = header(0, 1, 96);
+        bytes.extend(track(&[
+            0x00, 0x90, 0x3C, 0x40, // note on
+            0x60, 0x3C, 0x00, // running status note on, velocity 0, a quarter note later
+            0x00, 0xC1, 0x05, // program change, a single data byte
+            0x00, 0x07, // running status program change
+            0x00, 0xD1, 0x20, // channel pressure
+        ]));
+
+        let midi_file = MidiFile::parse(&bytes, SAMPLE_RATE).unwrap();
+        assert_eq!(
+            timed_data(&midi_file),
+            vec![
+                (0, [0x90, 0x3C, 0x40]),
+                (22050, [0x90, 0x3C, 0x00]),
+                (22050, [0xC1, 0x05, 0x00]),
+                (22050, [0xC1, 0x07, 0x00]),
+                (22050, [0xD1, 0x20, 0x00]),
+            ]
+        );
+    }
+
+    #[test]
+    fn running_status_is_cancelled_by_meta_events() {
+        let mut bytes = header(0, 1, 96);
+        bytes.extend(track(&[0x00, 0x90, 0x3C, 0x40, 0x00, META_EVENT, 0x01, 0x00, 0x00, 0x3C, 0x00]));
+        assert!(MidiFile::parse(&bytes, SAMPLE_RATE).is_err());
+    }
+
+    #[test]
+    fn format_1_merges_tracks_with_their_tempo_map() {
+        let mut bytes = header(1, 2, 96);
+        // 60 bpm, then 120 bpm after a quarter note
+        bytes.extend(track(&[
+            0x00, META_EVENT, META_TEMPO, 3, 0x0F, 0x42, 0x40, // 1 000 000 microseconds
+            0x60, META_EVENT, META_TEMPO, 3, 0x07, 0xA1, 0x20, // 500 000 microseconds
+        ]));
+        bytes.extend(track(&[
+            0x00, 0x90, 0x3C, 0x40, // 0 s
+            0x60, 0x80, 0x3C, 0x00, // 1 s
+            0x60, 0x90, 0x3E, 0x40, // 1.5 s
+        ]));
+
+        let midi_file = MidiFile::parse(&bytes, SAMPLE_RATE).unwrap();
+        assert_eq!(
+            timed_data(&midi_file),
+            vec![(0, [0x90, 0x3C, 0x40]), (44100, [0x80, 0x3C, 0x00]), (66150, [0x90, 0x3E, 0x40])]
+        );
+        assert_eq!(midi_file.tempo_map.ticks_per_quarter_note(), 96);
+    }
+
+    #[test]
+    fn unsupported_files() {
+        let mut format_2 = header(2, 1, 96);
+        format_2.extend(track(&[]));
+        assert!(MidiFile::parse(&format_2, SAMPLE_RATE).is_err());
+
+        let mut smpte = header(0, 1, 0xE728);
+        smpte.extend(track(&[]));
+        assert!(MidiFile::parse(&smpte, SAMPLE_RATE).is_err());
+
+        assert!(MidiFile::parse(b"RIFF", SAMPLE_RATE).is_err());
+    }
+
+    #[test]
+    fn tempo_map() {
+        let tempo_map = TempoMap::new(480, vec![(960, 250_000), (480, 1_000_000), (960, 1_000_000)]);
+
+        // 120 bpm for a quarter note, 60 bpm for a quarter note, then 60 bpm again as the last
+        // change at a tick replaces the previous one
+        assert_eq!(tempo_map.tick_to_seconds(480), 0.5);
+        assert_eq!(tempo_map.tick_to_seconds(960), 1.5);
+        assert_eq!(tempo_map.tick_to_seconds(1440), 2.5);
+
+        for tick in [0, 1, 479, 480, 700, 960, 5000] {
+            assert_eq!(tempo_map.seconds_to_tick(tempo_map.tick_to_seconds(tick)), tick);
+        }
+        assert_eq!(TempoMap::default().tick_to_seconds(DEFAULT_TICKS_PER_QUARTER_NOTE as u64), 0.5);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let messages = [
+            (0, [0x90, 0x3C, 0x40]),
+            (11025, [0xB0, 0x07, 0x64]),
+            (22050, [0xC2, 0x0A, 0x00]),
+            (33075, [0xD2, 0x30, 0x00]),
+            (44100, [0x80, 0x3C, 0x00]),
+            (88200, [0xE0, 0x00, 0x40]),
+        ];
+        let midi_file = MidiFile {
+            tempo_map: TempoMap::new(960, vec![(1920, 1_000_000)]),
+            messages: messages
+                .iter()
+                .map(|(position, data)| AbsoluteTimeMidiMessage {
+                    data: (*data).into(),
+                    id: 0,
+                    reason: MessageReason::Live,
+                    play_time_in_samples: *position,
+                })
+                .collect(),
+        };
+
+        let bytes = midi_file.encode(SAMPLE_RATE);
+        let read = MidiFile::parse(&bytes, SAMPLE_RATE).unwrap();
+        assert_eq!(timed_data(&read), messages.to_vec());
+        assert_eq!(read.tempo_map.ticks_per_quarter_note(), 960);
+        assert_eq!(read.tempo_map.tick_to_seconds(2880), 2.0);
+        // same bytes when written again
+        assert_eq!(read.encode(SAMPLE_RATE), bytes);
+    }
+}
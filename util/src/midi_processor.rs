@@ -0,0 +1,97 @@
+// the logic of a plugin, independent of any plugin format: it receives the midi messages of a
+// block and returns the ones to send. Formats are handled by wrappers, see vst_wrapper and
+// clap_wrapper.
+
+use core::fmt;
+use core::fmt::Display;
+use std::sync::Arc;
+
+use vst::api::Supported;
+use vst::plugin::{CanDo, PluginParameters};
+
+use super::parameter_definition::ParameterDefinition;
+
+pub struct ProcessorInfo {
+    pub name: &'static str,
+    // crate name, used to name traces and debug lines
+    pub id: &'static str,
+    pub vendor: &'static str,
+    pub unique_id: i32,
+    pub version: i32,
+    // parameters shown to the host
+    pub parameters: i32,
+    // every parameter, including the ones hidden from the host, indexed like the parameters
+    // object. Read-only ones come after the ones shown to VST2 hosts, and are not stored
+    pub parameter_definitions: &'static [ParameterDefinition],
+}
+
+// delta_frames is relative to the start of the block
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimedMidiMessage {
+    pub data: [u8; 3],
+    pub delta_frames: usize,
+}
+
+// what the host knows about the transport, at the start of the block
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TransportInfo {
+    pub playing: bool,
+    // beats per minute
+    pub tempo: Option<f64>,
+    // in quarter notes
+    pub position: Option<f64>,
+    pub bar_start_position: Option<f64>,
+    // numerator, denominator
+    pub time_signature: Option<(i32, i32)>,
+    // of the start of the block, the same for every plugin of a host cycle
+    pub sample_position: Option<i64>,
+}
+
+pub trait MidiProcessor: Default + Send {
+    type Parameters: PluginParameters + Send + 'static;
+
+    fn info() -> ProcessorInfo;
+
+    fn parameters(&self) -> &Arc<Self::Parameters>;
+
+    fn set_sample_rate(&mut self, _rate: f32) {}
+
+    fn set_block_size(&mut self, _block_size: usize) {}
+
+    // the answers of the VST2 plugin to the host
+    fn can_do(can_do: CanDo) -> Supported {
+        use vst::api::Supported::*;
+        use vst::plugin::CanDo::*;
+
+        match can_do {
+            SendEvents | SendMidiEvent | ReceiveEvents | ReceiveMidiEvent | Offline | Bypass => Yes,
+            MidiProgramNames | ReceiveSysExEvent | MidiSingleNoteTuningChange => No,
+            _ => Maybe,
+        }
+    }
+
+    // inputs are sorted by delta frames, outputs are appended to outputs
+    fn process(
+        &mut self,
+        inputs: &[TimedMidiMessage],
+        samples: usize,
+        transport: &TransportInfo,
+        outputs: &mut Vec<TimedMidiMessage>,
+    );
+}
+
+impl TimedMidiMessage {
+    pub fn new(data: [u8; 3], delta_frames: usize) -> TimedMidiMessage {
+        TimedMidiMessage { data, delta_frames }
+    }
+}
+
+impl Display for TimedMidiMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:#04X} {:#04X} {:#04X}] delta_frames={}",
+            self.data[0], self.data[1], self.data[2], self.delta_frames
+        )
+    }
+}
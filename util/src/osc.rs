@@ -0,0 +1,480 @@
+// parameters can be set with OSC messages sent over UDP to localhost, when NOTE_FX_OSC_PORT is set
+// to the port to listen on. Addresses are derived from the parameter names, see
+// ParameterDefinition::symbol: "/note_generator/pitch 60" sets the pitch of every note generator
+// instance, "/pitch 60" of every instance having a pitch parameter. A unique prefix ending before a
+// "_" is enough, so "/trigger 1" is "/trigger_generated_note 1". Hidden and read-only parameters
+// can't be set.
+//
+// values are plain values, as shown by CLAP and LV2 hosts ( 60 for a pitch, 0.5 for half a second ),
+// given as int, float, double, int64, true / false or string arguments, strings being parsed as the
+// host texts ( "500ms", "on" ). Bundles are accepted, their time tag is ignored.
+//
+// a single background thread receives the messages for all the instances of the process, and
+// queues the normalized values per instance. The audio thread applies them at the start of each
+// block, so nothing is locked there. The thread is started with the first instance and stopped
+// when the last one is dropped.
+
+use std::convert::TryInto;
+use std::env;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crossbeam_queue::ArrayQueue;
+use vst::plugin::PluginParameters;
+
+use super::debug::{DebugLevel, DebugSocket};
+use super::midi_processor::ProcessorInfo;
+use super::parameter_definition::ParameterDefinition;
+use crate::debug_log;
+
+pub const PORT_VARIABLE: &str = "NOTE_FX_OSC_PORT";
+
+const QUEUE_CAPACITY: usize = 256;
+// how long the thread waits for a message before checking if it has to stop
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+struct Endpoint {
+    plugin: &'static str,
+    definitions: &'static [ParameterDefinition],
+    // parameter index and normalized value
+    queue: ArrayQueue<(usize, f32)>,
+}
+
+struct Listener {
+    sessions: usize,
+    stop: Option<Arc<AtomicBool>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+// separate from the listener, as the thread reads the endpoints while it is being joined
+static ENDPOINTS: Mutex<Vec<Arc<Endpoint>>> = Mutex::new(Vec::new());
+static LISTENER: Mutex<Listener> = Mutex::new(Listener {
+    sessions: 0,
+    stop: None,
+    handle: None,
+});
+
+// a panic while holding these locks leaves nothing inconsistent, keep going
+fn lock_endpoints() -> MutexGuard<'static, Vec<Arc<Endpoint>>> {
+    ENDPOINTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn lock_listener() -> MutexGuard<'static, Listener> {
+    LISTENER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Default)]
+pub struct OscReceiver {
+    endpoint: Option<Arc<Endpoint>>,
+}
+
+impl OscReceiver {
+    // to be called outside of the audio thread, typically when the plugin is created. Does nothing
+    // unless NOTE_FX_OSC_PORT is set
+    pub fn from_env(info: &ProcessorInfo) -> OscReceiver {
+        let port = match env::var(PORT_VARIABLE)
+            .ok()
+            .and_then(|port| port.trim().parse::<u16>().ok())
+        {
+            Some(port) => port,
+            None => return Default::default(),
+        };
+
+        let endpoint = Arc::new(Endpoint {
+            plugin: info.id,
+            definitions: info.parameter_definitions,
+            queue: ArrayQueue::new(QUEUE_CAPACITY),
+        });
+        lock_endpoints().push(Arc::clone(&endpoint));
+
+        let mut listener = lock_listener();
+        listener.sessions += 1;
+        if listener.handle.is_none() {
+            start(&mut listener, port);
+        }
+
+        OscReceiver {
+            endpoint: Some(endpoint),
+        }
+    }
+
+    // sets the parameters received since the last call, from the audio thread
+    pub fn apply<T: PluginParameters + ?Sized>(&self, parameters: &T) {
+        if let Some(endpoint) = &self.endpoint {
+            while let Some((index, value)) = endpoint.queue.pop() {
+                parameters.set_parameter(index as i32, value);
+            }
+        }
+    }
+}
+
+impl Drop for OscReceiver {
+    fn drop(&mut self) {
+        let endpoint = match self.endpoint.take() {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+        lock_endpoints().retain(|registered| !Arc::ptr_eq(registered, &endpoint));
+
+        let mut listener = lock_listener();
+        listener.sessions = listener.sessions.saturating_sub(1);
+        if listener.sessions > 0 {
+            return;
+        }
+        if let Some(stop) = listener.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        if let Some(handle) = listener.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn start(listener: &mut Listener, port: u16) {
+    let socket = match UdpSocket::bind(("127.0.0.1", port)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug_log!(DebugLevel::Error, "cannot listen to OSC on port {}: {}", port, e);
+            return;
+        }
+    };
+    if socket.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+        return;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+    let spawned = thread::Builder::new()
+        .name("note fx osc".to_string())
+        .spawn(move || receive(socket, &thread_stop));
+
+    if let Ok(handle) = spawned {
+        listener.stop = Some(stop);
+        listener.handle = Some(handle);
+    }
+}
+
+fn receive(socket: UdpSocket, stop: &AtomicBool) {
+    let mut buffer = vec![0u8; 65536];
+    let mut messages = vec![];
+
+    while !stop.load(Ordering::Relaxed) {
+        // timeouts are the normal case, other errors ( e.g. a too large datagram ) only lose it
+        let size = match socket.recv_from(&mut buffer) {
+            Ok((size, _)) => size,
+            Err(_) => continue,
+        };
+
+        if parse_packet(&buffer[..size], &mut messages).is_none() {
+            DebugSocket::log(DebugLevel::Info, "ignoring malformed OSC packet");
+        }
+        for (address, value) in messages.drain(..) {
+            dispatch(&address, &value);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum OscValue {
+    Number(f64),
+    Text(String),
+}
+
+// a nul terminated string, padded to 4 bytes
+fn read_string<'a>(data: &mut &'a [u8]) -> Option<&'a str> {
+    let length = data.iter().position(|&byte| byte == 0)?;
+    let text = std::str::from_utf8(&data[..length]).ok()?;
+    let padded = (length + 4) & !3;
+    *data = data.get(padded..)?;
+    Some(text)
+}
+
+fn read_bytes<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+    let bytes = data.get(..N)?.try_into().ok()?;
+    *data = &data[N..];
+    Some(bytes)
+}
+
+fn parse_packet(mut data: &[u8], messages: &mut Vec<(String, OscValue)>) -> Option<()> {
+    if data.starts_with(b"#bundle\0") {
+        data = &data[8..];
+        // time tag
+        read_bytes::<8>(&mut data)?;
+        while !data.is_empty() {
+            let size = u32::from_be_bytes(read_bytes(&mut data)?) as usize;
+            parse_packet(data.get(..size)?, messages)?;
+            data = &data[size..];
+        }
+        return Some(());
+    }
+
+    let address = read_string(&mut data)?.to_string();
+    let type_tags = read_string(&mut data)?;
+    // only the first argument is used
+    let value = match type_tags.strip_prefix(',')?.chars().next()? {
+        'i' => OscValue::Number(i32::from_be_bytes(read_bytes(&mut data)?) as f64),
+        'f' => OscValue::Number(f32::from_be_bytes(read_bytes(&mut data)?) as f64),
+        'd' => OscValue::Number(f64::from_be_bytes(read_bytes(&mut data)?)),
+        'h' => OscValue::Number(i64::from_be_bytes(read_bytes(&mut data)?) as f64),
+        'T' => OscValue::Number(1.0),
+        'F' => OscValue::Number(0.0),
+        's' => OscValue::Text(read_string(&mut data)?.to_string()),
+        _ => return None,
+    };
+    messages.push((address, value));
+    Some(())
+}
+
+// index of the parameter matching the last segment of an address, exactly or by a unique prefix
+fn find_parameter(definitions: &[ParameterDefinition], name: &str) -> Option<usize> {
+    let settable = || {
+        definitions
+            .iter()
+            .enumerate()
+            .filter(|(_, definition)| !definition.hidden && !definition.read_only)
+            .map(|(index, definition)| (index, definition.symbol()))
+    };
+
+    if let Some((index, _)) = settable().find(|(_, symbol)| symbol == name) {
+        return Some(index);
+    }
+    let mut candidates =
+        settable().filter(|(_, symbol)| symbol.starts_with(name) && symbol[name.len()..].starts_with('_'));
+    match (candidates.next(), candidates.next()) {
+        (Some((index, _)), None) => Some(index),
+        _ => None,
+    }
+}
+
+// "/plugin/name" or "/name", lowercase
+fn parse_address(address: &str) -> Option<(Option<String>, String)> {
+    let mut segments = address
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_ascii_lowercase());
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some(name), None, None) => Some((None, name)),
+        (Some(plugin), Some(name), None) => Some((Some(plugin), name)),
+        _ => None,
+    }
+}
+
+// queues the value for every endpoint having the parameter, returns false if none has it
+fn queue_value(endpoints: &[Arc<Endpoint>], plugin: Option<&str>, name: &str, address: &str, value: &OscValue) -> bool {
+    let mut matched = false;
+    for endpoint in endpoints {
+        if plugin.is_some_and(|plugin| plugin != endpoint.plugin) {
+            continue;
+        }
+        let index = match find_parameter(endpoint.definitions, name) {
+            Some(index) => index,
+            None => continue,
+        };
+        let definition = &endpoint.definitions[index];
+        let plain = match value {
+            OscValue::Number(number) => Some(*number),
+            OscValue::Text(text) => definition.parse(text),
+        };
+        matched = true;
+        match plain {
+            Some(plain) => {
+                if endpoint.queue.push((index, definition.to_normalized(plain))).is_err() {
+                    debug_log!(DebugLevel::Info, "dropping OSC message {}", address);
+                }
+            }
+            None => debug_log!(DebugLevel::Info, "invalid value for {}", address),
+        }
+    }
+    matched
+}
+
+fn dispatch(address: &str, value: &OscValue) {
+    let (plugin, name) = match parse_address(address) {
+        Some(segments) => segments,
+        None => {
+            debug_log!(DebugLevel::Info, "ignoring OSC address {}", address);
+            return;
+        }
+    };
+
+    // also the case of every message meant for the other plugins of a setup, so only a debug line
+    if !queue_value(&lock_endpoints(), plugin.as_deref(), &name, address, value) {
+        debug_log!(DebugLevel::Debug, "no parameter for OSC address {}", address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter_definition::ParameterScale;
+
+    const DEFINITIONS: [ParameterDefinition; 6] = [
+        ParameterDefinition::new("Pitch", ParameterScale::Byte { divisor: 1 }),
+        ParameterDefinition::new("Pitch range", ParameterScale::Byte { divisor: 1 }),
+        ParameterDefinition::new("Note off velocity", ParameterScale::Byte { divisor: 1 }),
+        ParameterDefinition::new("Note length", ParameterScale::Exponential { max: 10., factor: 20. }),
+        ParameterDefinition::new("Trigger generated note", ParameterScale::Bool),
+        ParameterDefinition::new("Triggered", ParameterScale::Bool).read_only(),
+    ];
+    const DELAY_DEFINITIONS: [ParameterDefinition; 2] = [
+        ParameterDefinition::new("Delay", ParameterScale::Exponential { max: 10., factor: 20. }),
+        ParameterDefinition::new("Pitch", ParameterScale::Byte { divisor: 1 }).hidden(),
+    ];
+
+    // nul terminated and padded to 4 bytes
+    fn padded(text: &str) -> Vec<u8> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize((text.len() + 4) & !3, 0);
+        bytes
+    }
+
+    fn message(address: &str, type_tags: &str, arguments: &[u8]) -> Vec<u8> {
+        [padded(address), padded(type_tags), arguments.to_vec()].concat()
+    }
+
+    fn parse(packet: &[u8]) -> Option<Vec<(String, OscValue)>> {
+        let mut messages = vec![];
+        parse_packet(packet, &mut messages).map(|_| messages)
+    }
+
+    fn endpoint(plugin: &'static str, definitions: &'static [ParameterDefinition]) -> Arc<Endpoint> {
+        Arc::new(Endpoint {
+            plugin,
+            definitions,
+            queue: ArrayQueue::new(QUEUE_CAPACITY),
+        })
+    }
+
+    fn queued(endpoint: &Endpoint) -> Vec<(usize, f32)> {
+        std::iter::from_fn(|| endpoint.queue.pop()).collect()
+    }
+
+    #[test]
+    fn strings_are_padded_to_4_bytes() {
+        let data = [padded("abc"), padded("abcd"), vec![1]].concat();
+        let mut rest = data.as_slice();
+        assert_eq!(read_string(&mut rest), Some("abc"));
+        assert_eq!(rest.len(), 9);
+        assert_eq!(read_string(&mut rest), Some("abcd"));
+        assert_eq!(rest, &[1]);
+
+        // without the padding or the nul
+        assert_eq!(read_string(&mut &b"abcd\0"[..]), None);
+        assert_eq!(read_string(&mut &b"abcd"[..]), None);
+    }
+
+    #[test]
+    fn each_type_tag_gives_a_value() {
+        let cases: [(&str, Vec<u8>, OscValue); 7] = [
+            (",i", (-3i32).to_be_bytes().to_vec(), OscValue::Number(-3.)),
+            (",f", 0.5f32.to_be_bytes().to_vec(), OscValue::Number(0.5)),
+            (",d", 0.25f64.to_be_bytes().to_vec(), OscValue::Number(0.25)),
+            (
+                ",h",
+                1_000_000_000_000i64.to_be_bytes().to_vec(),
+                OscValue::Number(1e12),
+            ),
+            (",T", vec![], OscValue::Number(1.)),
+            (",F", vec![], OscValue::Number(0.)),
+            (",s", padded("500ms"), OscValue::Text("500ms".to_string())),
+        ];
+        for (type_tags, arguments, value) in cases {
+            assert_eq!(
+                parse(&message("/pitch", type_tags, &arguments)),
+                Some(vec![("/pitch".to_string(), value)]),
+                "{}",
+                type_tags
+            );
+        }
+
+        // only the first argument is used
+        let two = [1i32.to_be_bytes(), 2i32.to_be_bytes()].concat();
+        assert_eq!(
+            parse(&message("/pitch", ",ii", &two)),
+            Some(vec![("/pitch".to_string(), OscValue::Number(1.))])
+        );
+
+        assert_eq!(parse(&message("/pitch", ",b", &[0, 0, 0, 0])), None);
+        assert_eq!(parse(&message("/pitch", ",", &[])), None);
+        assert_eq!(parse(&message("/pitch", "i", &1i32.to_be_bytes())), None);
+        assert_eq!(parse(&message("/pitch", ",i", &[0, 1])), None);
+    }
+
+    #[test]
+    fn bundles_hold_messages_and_bundles() {
+        let element = |content: Vec<u8>| [(content.len() as u32).to_be_bytes().to_vec(), content].concat();
+        let bundle =
+            |elements: Vec<Vec<u8>>| [b"#bundle\0".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 1], elements.concat()].concat();
+
+        let inner = bundle(vec![element(message("/b", ",T", &[]))]);
+        let outer = bundle(vec![element(message("/a", ",i", &7i32.to_be_bytes())), element(inner)]);
+        assert_eq!(
+            parse(&outer),
+            Some(vec![
+                ("/a".to_string(), OscValue::Number(7.)),
+                ("/b".to_string(), OscValue::Number(1.))
+            ])
+        );
+
+        assert_eq!(parse(&bundle(vec![])), Some(vec![]));
+        // an element longer than the bundle
+        let mut cut = bundle(vec![element(message("/a", ",T", &[]))]);
+        cut.truncate(cut.len() - 4);
+        assert_eq!(parse(&cut), None);
+        // without its time tag
+        assert_eq!(parse(b"#bundle\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn parameters_are_found_by_symbol_or_unique_prefix() {
+        assert_eq!(find_parameter(&DEFINITIONS, "pitch"), Some(0));
+        assert_eq!(find_parameter(&DEFINITIONS, "pitch_range"), Some(1));
+        assert_eq!(find_parameter(&DEFINITIONS, "trigger"), Some(4));
+        assert_eq!(find_parameter(&DEFINITIONS, "note_off"), Some(2));
+        // ambiguous, "note_off_velocity" or "note_length"
+        assert_eq!(find_parameter(&DEFINITIONS, "note"), None);
+        // prefixes end before a "_"
+        assert_eq!(find_parameter(&DEFINITIONS, "trig"), None);
+        assert_eq!(find_parameter(&DEFINITIONS, "pitch_r"), None);
+        // read-only and hidden parameters can't be set
+        assert_eq!(find_parameter(&DEFINITIONS, "triggered"), None);
+        assert_eq!(find_parameter(&DELAY_DEFINITIONS, "pitch"), None);
+    }
+
+    #[test]
+    fn addresses_name_the_plugin_or_not() {
+        assert_eq!(parse_address("/Pitch"), Some((None, "pitch".to_string())));
+        assert_eq!(
+            parse_address("/note_generator/pitch/"),
+            Some((Some("note_generator".to_string()), "pitch".to_string()))
+        );
+        assert_eq!(parse_address("/a/b/c"), None);
+        assert_eq!(parse_address("/"), None);
+
+        let generator = endpoint("note_generator", &DEFINITIONS);
+        let delay = endpoint("note_off_delay", &DELAY_DEFINITIONS);
+        let endpoints = [Arc::clone(&generator), Arc::clone(&delay)];
+        let queue = |plugin: Option<&str>, name: &str, value: OscValue| {
+            queue_value(&endpoints, plugin, name, "/address", &value)
+        };
+
+        assert!(queue(None, "pitch", OscValue::Number(60.)));
+        assert_eq!(queued(&generator), vec![(0, 60.5 / 127.)]);
+        assert!(queue(None, "delay", OscValue::Text("0".to_string())));
+        assert_eq!(queued(&delay), vec![(0, 0.)]);
+
+        assert!(queue(Some("note_generator"), "trigger", OscValue::Number(1.)));
+        assert!(!queue(Some("note_off_delay"), "trigger", OscValue::Number(1.)));
+        assert!(!queue(Some("max_note_duration"), "pitch", OscValue::Number(1.)));
+        assert_eq!(queued(&generator), vec![(4, 1.)]);
+
+        // found, but nothing to queue
+        assert!(queue(None, "note_length", OscValue::Text("long".to_string())));
+        assert_eq!(queued(&generator), vec![]);
+        assert!(queued(&delay).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+// static description of the parameters of a processor, for plugin formats that expose plain
+// values and ranges to the host ( CLAP ) instead of the normalized 0..1 values of VST2.
+// The normalized value stays the one stored by the parameters object, these definitions only
+// convert to and from what the host shows.
+
+use super::duration_display;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParameterScale {
+    // stored as a byte, divided to get the value ( 0..=127 / divisor )
+    Byte { divisor: u8 },
+    // 0..=0x3FFF
+    U14,
+    Bool,
+    // seconds, see ParameterConversion::get_exponential_scale_parameter
+    Exponential { max: f32, factor: f32 },
+    // the normalized value itself, when the plugin has its own mapping
+    Normalized,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ParameterDefinition {
+    pub name: &'static str,
+    pub scale: ParameterScale,
+    // normalized
+    pub default: f32,
+    // stored in presets, but not shown to the user
+    pub hidden: bool,
+    // only there to show information
+    pub read_only: bool,
+}
+
+impl ParameterDefinition {
+    pub const fn new(name: &'static str, scale: ParameterScale) -> ParameterDefinition {
+        ParameterDefinition {
+            name,
+            scale,
+            default: 0.0,
+            hidden: false,
+            read_only: false,
+        }
+    }
+
+    pub const fn with_default(mut self, default: f32) -> ParameterDefinition {
+        self.default = default;
+        self
+    }
+
+    pub const fn hidden(mut self) -> ParameterDefinition {
+        self.hidden = true;
+        self
+    }
+
+    pub const fn read_only(mut self) -> ParameterDefinition {
+        self.read_only = true;
+        self
+    }
+
+    // lowercase, anything else than letters and digits replaced by _, e.g. "note_off_velocity".
+    // Used for LV2 port symbols and OSC addresses
+    pub fn symbol(&self) -> String {
+        self.name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect()
+    }
+
+    // plain values
+    pub fn range(&self) -> (f64, f64) {
+        match self.scale {
+            ParameterScale::Byte { divisor } => (0.0, (127 / divisor) as f64),
+            ParameterScale::U14 => (0.0, 0x3FFF as f64),
+            ParameterScale::Bool | ParameterScale::Normalized => (0.0, 1.0),
+            ParameterScale::Exponential { max, .. } => (0.0, max as f64),
+        }
+    }
+
+    pub fn is_stepped(&self) -> bool {
+        match self.scale {
+            ParameterScale::Byte { .. } | ParameterScale::U14 | ParameterScale::Bool => true,
+            ParameterScale::Exponential { .. } | ParameterScale::Normalized => false,
+        }
+    }
+
+    pub fn to_plain(&self, normalized: f32) -> f64 {
+        let normalized = normalized.clamp(0.0, 1.0);
+        match self.scale {
+            ParameterScale::Byte { divisor } => ((normalized * 127.) as u8 / divisor) as f64,
+            ParameterScale::U14 => (normalized * 0x3FFF as f32) as u16 as f64,
+            ParameterScale::Bool => if normalized > 0.5 { 1.0 } else { 0.0 },
+            ParameterScale::Exponential { max, factor } => ((factor.powf(normalized) - 1.) * max / (factor - 1.)) as f64,
+            ParameterScale::Normalized => normalized as f64,
+        }
+    }
+
+    pub fn to_normalized(&self, plain: f64) -> f32 {
+        let (min, max) = self.range();
+        let plain = plain.max(min).min(max);
+        match self.scale {
+            // half a step more, so the truncation when reading it back gives the same value
+            ParameterScale::Byte { divisor } => ((plain.round() as f32 * divisor as f32 + 0.5) / 127.).min(1.0),
+            ParameterScale::U14 => ((plain.round() as f32 + 0.5) / 0x3FFF as f32).min(1.0),
+            ParameterScale::Bool => if plain > 0.5 { 1.0 } else { 0.0 },
+            ParameterScale::Exponential { max, factor } => {
+                ((plain as f32 * (factor - 1.) / max + 1.).ln() / factor.ln()).clamp(0.0, 1.0)
+            }
+            ParameterScale::Normalized => plain as f32,
+        }
+    }
+
+    // used when the plugin can't tell, as its texts are only for the current value
+    pub fn format(&self, plain: f64) -> String {
+        match self.scale {
+            ParameterScale::Byte { .. } | ParameterScale::U14 => format!("{}", plain as i64),
+            ParameterScale::Bool => if plain > 0.5 { "On" } else { "Off" }.to_string(),
+            ParameterScale::Exponential { .. } if plain > 0.0 => duration_display(plain as f32),
+            ParameterScale::Exponential { .. } => "Off".to_string(),
+            ParameterScale::Normalized => format!("{:.2}", plain),
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        let text = text.trim();
+        match self.scale {
+            ParameterScale::Bool if text.eq_ignore_ascii_case("on") => Some(1.0),
+            ParameterScale::Bool if text.eq_ignore_ascii_case("off") => Some(0.0),
+            ParameterScale::Exponential { .. } if text.eq_ignore_ascii_case("off") => Some(0.0),
+            ParameterScale::Exponential { .. } if text.ends_with("ms") => {
+                text[..text.len() - 2].trim().parse::<f64>().ok().map(|value| value / 1000.)
+            }
+            ParameterScale::Exponential { .. } => text.trim_end_matches('s').trim().parse().ok(),
+            _ => text.parse().ok(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// a small seeded pseudo random generator ( xorshift64* ), so plugins randomizing notes play the
+// same ones from the same seed, e.g. at each render. Not for anything needing real randomness.
+
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        let mut random = Random { state: 0 };
+        random.reseed(seed);
+        random
+    }
+
+    // close seeds are spread with splitmix64, and the state is never 0, where xorshift stays
+    pub fn reseed(&mut self, seed: u64) {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        self.state = if z == 0 { 1 } else { z };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // in [-1, 1)
+    pub fn next_bipolar(&mut self) -> f64 {
+        self.next_f64() * 2. - 1.
+    }
+
+    // in [0, count), 0 when count is 0
+    pub fn below(&mut self, count: usize) -> usize {
+        ((self.next_f64() * count as f64) as usize).min(count.saturating_sub(1))
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new(0)
+    }
+}
@@ -0,0 +1,375 @@
+// scales to quantize pitches to: the modes of the major scale, harmonic and melodic minor,
+// pentatonics, custom masks of the 12 semitones above the key, and Scala files ( .scl ), whose
+// degrees are rounded to the nearest semitone.
+//
+// Scala files are read from the directory given by NOTE_FX_SCALA_DIR, sorted by file name, when a
+// plugin is created, see ScalaScale::from_env.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use super::debug::DebugLevel;
+use crate::debug_log;
+
+pub const SCALA_DIRECTORY_VARIABLE: &str = "NOTE_FX_SCALA_DIR";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScaleType {
+    // every pitch, no quantization
+    Chromatic = 0,
+    Major,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Minor,
+    Locrian,
+    HarmonicMinor,
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    // a mask of the semitones above the key
+    Custom,
+    // a Scala file
+    Scala,
+}
+
+pub const SCALE_TYPE_NAMES: [&str; 14] = [
+    "Chromatic",
+    "Major",
+    "Dorian",
+    "Phrygian",
+    "Lydian",
+    "Mixolydian",
+    "Minor",
+    "Locrian",
+    "Harmonic minor",
+    "Melodic minor",
+    "Major pentatonic",
+    "Minor pentatonic",
+    "Custom",
+    "Scala file",
+];
+
+impl From<u8> for ScaleType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ScaleType::Chromatic,
+            1 => ScaleType::Major,
+            2 => ScaleType::Dorian,
+            3 => ScaleType::Phrygian,
+            4 => ScaleType::Lydian,
+            5 => ScaleType::Mixolydian,
+            6 => ScaleType::Minor,
+            7 => ScaleType::Locrian,
+            8 => ScaleType::HarmonicMinor,
+            9 => ScaleType::MelodicMinor,
+            10 => ScaleType::MajorPentatonic,
+            11 => ScaleType::MinorPentatonic,
+            12 => ScaleType::Custom,
+            _ => ScaleType::Scala,
+        }
+    }
+}
+
+// bit n is set when the semitone n above the key is in the scale
+pub const CHROMATIC_MASK: u16 = 0xFFF;
+
+const fn mask(semitones: &[u8]) -> u16 {
+    let mut mask = 0;
+    let mut index = 0;
+    while index < semitones.len() {
+        mask |= 1 << semitones[index];
+        index += 1;
+    }
+    mask
+}
+
+impl ScaleType {
+    pub fn name(self) -> &'static str {
+        SCALE_TYPE_NAMES[self as usize]
+    }
+
+    // None for custom and Scala scales, that depend on more than the type
+    pub fn mask(self) -> Option<u16> {
+        match self {
+            ScaleType::Chromatic => Some(CHROMATIC_MASK),
+            ScaleType::Major => Some(mask(&[0, 2, 4, 5, 7, 9, 11])),
+            ScaleType::Dorian => Some(mask(&[0, 2, 3, 5, 7, 9, 10])),
+            ScaleType::Phrygian => Some(mask(&[0, 1, 3, 5, 7, 8, 10])),
+            ScaleType::Lydian => Some(mask(&[0, 2, 4, 6, 7, 9, 11])),
+            ScaleType::Mixolydian => Some(mask(&[0, 2, 4, 5, 7, 9, 10])),
+            ScaleType::Minor => Some(mask(&[0, 2, 3, 5, 7, 8, 10])),
+            ScaleType::Locrian => Some(mask(&[0, 1, 3, 5, 6, 8, 10])),
+            ScaleType::HarmonicMinor => Some(mask(&[0, 2, 3, 5, 7, 8, 11])),
+            ScaleType::MelodicMinor => Some(mask(&[0, 2, 3, 5, 7, 9, 11])),
+            ScaleType::MajorPentatonic => Some(mask(&[0, 2, 4, 7, 9])),
+            ScaleType::MinorPentatonic => Some(mask(&[0, 3, 5, 7, 10])),
+            ScaleType::Custom | ScaleType::Scala => None,
+        }
+    }
+}
+
+// the degree of each midi pitch, from 1 at the key, 0 when the pitch is not in the scale
+#[derive(Clone, Copy)]
+pub struct Scale {
+    degrees: [u8; 128],
+}
+
+impl Scale {
+    pub fn chromatic() -> Scale {
+        Scale::from_mask(0, CHROMATIC_MASK)
+    }
+
+    // an empty mask is chromatic, so there is always a pitch to quantize to
+    pub fn from_mask(key: u8, mask: u16) -> Scale {
+        let mask = if mask & CHROMATIC_MASK == 0 { CHROMATIC_MASK } else { mask & CHROMATIC_MASK };
+        let mut scale = Scale { degrees: [0; 128] };
+        for pitch in 0..128u8 {
+            let semitone = (pitch as i16 - key as i16).rem_euclid(12) as u16;
+            if mask & (1 << semitone) != 0 {
+                scale.degrees[pitch as usize] = (mask & ((1 << semitone) - 1)).count_ones() as u8 + 1;
+            }
+        }
+        scale
+    }
+
+    // the degree from 1, None when the pitch is not in the scale
+    pub fn degree(&self, pitch: u8) -> Option<u8> {
+        match self.degrees[pitch as usize & 0x7F] {
+            0 => None,
+            degree => Some(degree),
+        }
+    }
+
+    // the nearest pitch of the scale, the lower one when two are as near
+    pub fn quantize(&self, pitch: u8) -> u8 {
+        let pitch = pitch.min(127);
+        for distance in 0..128u8 {
+            if let Some(lower) = pitch.checked_sub(distance) {
+                if self.degrees[lower as usize] != 0 {
+                    return lower;
+                }
+            }
+            let upper = pitch as usize + distance as usize;
+            if upper < 128 && self.degrees[upper] != 0 {
+                return upper as u8;
+            }
+        }
+        pitch
+    }
+}
+
+// offsets from the key covered by a Scala scale, from -127 to 127
+const RELATIVE_RANGE: usize = 255;
+const MIDDLE_C: usize = 60;
+
+pub struct ScalaScale {
+    // the file name, without the extension
+    pub name: String,
+    pub description: String,
+    // degrees of each offset from the key, offset 0 being at index 127
+    relative_degrees: [u8; RELATIVE_RANGE],
+}
+
+impl ScalaScale {
+    // the scales of the directory in NOTE_FX_SCALA_DIR, files that can't be read are skipped
+    pub fn from_env() -> Vec<ScalaScale> {
+        match env::var(SCALA_DIRECTORY_VARIABLE) {
+            Ok(directory) => ScalaScale::load_directory(Path::new(&directory)),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn load_directory(directory: &Path) -> Vec<ScalaScale> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug_log!(DebugLevel::Error, "cannot read scala directory {}: {}", directory.display(), e);
+                return vec![];
+            }
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("scl"))
+            })
+            .collect();
+        paths.sort();
+
+        paths
+            .iter()
+            .filter_map(|path| match ScalaScale::load(path) {
+                Ok(scale) => Some(scale),
+                Err(e) => {
+                    debug_log!(DebugLevel::Error, "cannot load {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<ScalaScale, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        ScalaScale::parse(&name, &text)
+    }
+
+    // the description line, the number of degrees, then a pitch per degree, in cents when it has
+    // a period, else as a ratio. Lines starting with ! are comments
+    pub fn parse(name: &str, text: &str) -> Result<ScalaScale, String> {
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        let description = lines.next().ok_or("missing description")?.trim().to_string();
+        let count = lines
+            .next()
+            .ok_or("missing number of notes")?
+            .split_whitespace()
+            .next()
+            .ok_or("missing number of notes")?
+            .parse::<usize>()
+            .map_err(|e| format!("invalid number of notes: {}", e))?;
+        if count == 0 {
+            return Err("no notes".to_string());
+        }
+
+        let mut cents = Vec::with_capacity(count);
+        for line in lines.take(count) {
+            let pitch = line.split_whitespace().next().ok_or("empty pitch line")?;
+            cents.push(ScalaScale::parse_pitch(pitch)?);
+        }
+        if cents.len() < count {
+            return Err(format!("{} notes instead of {}", cents.len(), count));
+        }
+
+        Ok(ScalaScale {
+            name: name.to_string(),
+            description,
+            relative_degrees: ScalaScale::relative_degrees(&cents)?,
+        })
+    }
+
+    fn parse_pitch(pitch: &str) -> Result<f64, String> {
+        let invalid = || format!("invalid pitch {}", pitch);
+        if pitch.contains('.') {
+            return pitch.parse::<f64>().map_err(|_| invalid());
+        }
+        let (numerator, denominator) = match pitch.split_once('/') {
+            Some((numerator, denominator)) => (numerator, denominator),
+            None => (pitch, "1"),
+        };
+        let numerator = numerator.parse::<f64>().map_err(|_| invalid())?;
+        let denominator = denominator.parse::<f64>().map_err(|_| invalid())?;
+        if numerator <= 0. || denominator <= 0. {
+            return Err(invalid());
+        }
+        Ok(1200. * (numerator / denominator).log2())
+    }
+
+    // the last pitch is the period the scale repeats at, usually the octave
+    fn relative_degrees(cents: &[f64]) -> Result<[u8; RELATIVE_RANGE], String> {
+        let (&period, degrees) = cents.split_last().ok_or("no notes")?;
+        if period < 1. {
+            return Err(format!("invalid period of {} cents", period));
+        }
+
+        let mut relative_degrees = [0; RELATIVE_RANGE];
+        let lowest = -(RELATIVE_RANGE as f64 / 2.) * 100.;
+        let periods = (-lowest / period).ceil() as i64 + 1;
+        for repetition in -periods..=periods {
+            let start = repetition as f64 * period;
+            for (degree, &offset) in [0.].iter().chain(degrees).enumerate() {
+                let index = ((start + offset) / 100.).round() as i64 + RELATIVE_RANGE as i64 / 2;
+                if (0..RELATIVE_RANGE as i64).contains(&index) && relative_degrees[index as usize] == 0 {
+                    relative_degrees[index as usize] = (degree + 1).min(u8::MAX as usize) as u8;
+                }
+            }
+        }
+        Ok(relative_degrees)
+    }
+
+    // the degrees are counted from the key in the octave of the middle C, which matters for scales
+    // not repeating at the octave
+    pub fn scale(&self, key: u8) -> Scale {
+        let anchor = MIDDLE_C + (key % 12) as usize;
+        let mut scale = Scale { degrees: [0; 128] };
+        for pitch in 0..128usize {
+            scale.degrees[pitch] = self.relative_degrees[pitch + RELATIVE_RANGE / 2 - anchor];
+        }
+        scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn degrees(scale: &Scale, pitches: std::ops::Range<u8>) -> Vec<Option<u8>> {
+        pitches.map(|pitch| scale.degree(pitch)).collect()
+    }
+
+    #[test]
+    fn masks_give_degrees_from_the_key() {
+        let major = Scale::from_mask(2, ScaleType::Major.mask().unwrap());
+        assert_eq!(
+            degrees(&major, 62..75),
+            vec![Some(1), None, Some(2), None, Some(3), Some(4), None, Some(5), None, Some(6), None, Some(7), Some(1)]
+        );
+        // below the key, in the octave before
+        assert_eq!(degrees(&major, 0..2), vec![None, Some(7)]);
+
+        let empty = Scale::from_mask(0, 0);
+        assert_eq!(degrees(&empty, 0..12), degrees(&Scale::chromatic(), 0..12));
+    }
+
+    #[test]
+    fn quantize_takes_the_nearest_pitch_then_the_lower_one() {
+        let pentatonic = Scale::from_mask(0, ScaleType::MajorPentatonic.mask().unwrap());
+        // C D E G A
+        assert_eq!(pentatonic.quantize(60), 60);
+        assert_eq!(pentatonic.quantize(61), 60);
+        assert_eq!(pentatonic.quantize(65), 64);
+        assert_eq!(pentatonic.quantize(66), 67);
+        assert_eq!(pentatonic.quantize(70), 69);
+        assert_eq!(pentatonic.quantize(71), 72);
+
+        let top = Scale::from_mask(0, mask(&[0]));
+        assert_eq!(top.quantize(127), 120);
+        assert_eq!(top.quantize(200), 120);
+    }
+
+    #[test]
+    fn scala_pitches_are_cents_or_ratios() {
+        let text = "! comment\nJust\n 4\n!\n9/8\n5/4 third\n701.955\n2\n";
+        let scale = ScalaScale::parse("just", text).unwrap();
+        assert_eq!(scale.description, "Just");
+        // 204 and 386 cents round to 2 and 4 semitones
+        assert_eq!(
+            degrees(&scale.scale(0), 60..73),
+            vec![Some(1), None, Some(2), None, Some(3), None, None, Some(4), None, None, None, None, Some(1)]
+        );
+        assert_eq!(degrees(&scale.scale(3), 63..65), vec![Some(1), None]);
+
+        assert!(ScalaScale::parse("empty", "Empty\n0\n").is_err());
+        assert!(ScalaScale::parse("short", "Short\n3\n100.0\n2/1\n").is_err());
+        assert!(ScalaScale::parse("invalid", "Invalid\n1\n-3/2\n").is_err());
+        assert!(ScalaScale::parse("unison", "Unison\n1\n1/1\n").is_err());
+    }
+
+    #[test]
+    fn scala_scales_repeat_at_their_period() {
+        // a period of 1900 cents, 19 semitones, with a degree in the middle
+        let scale = ScalaScale::parse("tritave", "Tritave\n2\n950.\n3/1\n").unwrap();
+        let tritave = scale.scale(0);
+        assert_eq!(tritave.degree(60), Some(1));
+        assert_eq!(tritave.degree(70), Some(2));
+        assert_eq!(tritave.degree(79), Some(1));
+        assert_eq!(tritave.degree(72), None);
+        assert_eq!(tritave.degree(41), Some(1));
+        assert_eq!(tritave.degree(50), Some(2));
+    }
+}
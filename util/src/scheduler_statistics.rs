@@ -0,0 +1,156 @@
+use core::fmt;
+use core::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::debug::{DebugLevel, DebugSocket};
+use crate::debug_log;
+use super::delayed_message_consumer::MessageReason;
+
+// counters updated by process_scheduled_events. Atomics, so they can be displayed from the
+// parameters object while the audio thread updates them.
+#[derive(Default)]
+pub struct SchedulerStatistics {
+    live: AtomicUsize,
+    delayed: AtomicUsize,
+    max_notes: AtomicUsize,
+    retrigger: AtomicUsize,
+    skipped_note_offs: AtomicUsize,
+    late_events: AtomicUsize,
+    queue_high_water_mark: AtomicUsize,
+    last_report: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct StatisticsSnapshot {
+    pub live: usize,
+    pub delayed: usize,
+    // notes stolen to respect the maximum amount of playing notes
+    pub max_notes: usize,
+    pub retrigger: usize,
+    // note offs of notes that were not playing, or already interrupted
+    pub skipped_note_offs: usize,
+    // events that should have been sent during a previous block
+    pub late_events: usize,
+    pub queue_high_water_mark: usize,
+}
+
+impl SchedulerStatistics {
+    #[inline]
+    pub fn count_event(&self, reason: MessageReason) {
+        let counter = match reason {
+            MessageReason::Live => &self.live,
+            MessageReason::Delayed => &self.delayed,
+            MessageReason::MaxNotes => &self.max_notes,
+            MessageReason::Retrigger => &self.retrigger,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn count_skipped_note_off(&self) {
+        self.skipped_note_offs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn count_late_event(&self) {
+        self.late_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn update_queue_depth(&self, depth: usize) {
+        self.queue_high_water_mark.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatisticsSnapshot {
+        StatisticsSnapshot {
+            live: self.live.load(Ordering::Relaxed),
+            delayed: self.delayed.load(Ordering::Relaxed),
+            max_notes: self.max_notes.load(Ordering::Relaxed),
+            retrigger: self.retrigger.load(Ordering::Relaxed),
+            skipped_note_offs: self.skipped_note_offs.load(Ordering::Relaxed),
+            late_events: self.late_events.load(Ordering::Relaxed),
+            queue_high_water_mark: self.queue_high_water_mark.load(Ordering::Relaxed),
+        }
+    }
+
+    // sends the counters to the debug channel, at most once per interval
+    pub fn report(&self, current_time_in_samples: usize, interval_in_samples: usize) {
+        if !DebugSocket::enabled(DebugLevel::Info) {
+            return;
+        }
+
+        let last_report = self.last_report.load(Ordering::Relaxed);
+        if current_time_in_samples < last_report + interval_in_samples {
+            return;
+        }
+        self.last_report.store(current_time_in_samples, Ordering::Relaxed);
+
+        debug_log!(DebugLevel::Info, "scheduler statistics: {}", self.snapshot());
+    }
+}
+
+impl StatisticsSnapshot {
+    // short enough to be displayed as a parameter value
+    pub fn summary(&self) -> String {
+        format!(
+            "{} stolen {} retrig {} dropped",
+            self.max_notes,
+            self.retrigger,
+            self.skipped_note_offs + self.late_events
+        )
+    }
+}
+
+impl Display for StatisticsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "live={} delayed={} max_notes={} retrigger={} skipped_note_offs={} late_events={} queue_high_water_mark={}",
+            self.live,
+            self.delayed,
+            self.max_notes,
+            self.retrigger,
+            self.skipped_note_offs,
+            self.late_events,
+            self.queue_high_water_mark
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_counted_per_reason() {
+        let statistics = SchedulerStatistics::default();
+        for reason in [MessageReason::Live, MessageReason::Delayed, MessageReason::Delayed, MessageReason::MaxNotes] {
+            statistics.count_event(reason);
+        }
+        statistics.count_event(MessageReason::Retrigger);
+        statistics.count_skipped_note_off();
+        statistics.count_late_event();
+        statistics.count_late_event();
+
+        let snapshot = statistics.snapshot();
+        assert_eq!(
+            (snapshot.live, snapshot.delayed, snapshot.max_notes, snapshot.retrigger),
+            (1, 2, 1, 1)
+        );
+        assert_eq!((snapshot.skipped_note_offs, snapshot.late_events), (1, 2));
+        assert_eq!(snapshot.summary(), "1 stolen 1 retrig 3 dropped");
+    }
+
+    #[test]
+    fn queue_depth_keeps_its_highest_value() {
+        let statistics = SchedulerStatistics::default();
+        for depth in [3, 12, 5, 0] {
+            statistics.update_queue_depth(depth);
+        }
+        assert_eq!(statistics.snapshot().queue_high_water_mark, 12);
+        assert_eq!(
+            statistics.snapshot().to_string(),
+            "live=0 delayed=0 max_notes=0 retrigger=0 skipped_note_offs=0 late_events=0 queue_high_water_mark=12"
+        );
+    }
+}
@@ -0,0 +1,419 @@
+// note counters shared by the instances of a plugin in the same group, so copies that each take
+// one note out of N agree on the position of every note, even when one of them missed notes while
+// bypassed, was added while playing, or receives the notes of a block in another order.
+//
+// the instances of a group process one block each per host cycle. When the host gives the sample
+// position of the block, a cycle is the blocks at the same position, so an instance coming back
+// from bypass starts a new cycle instead of joining the last one. Otherwise a new cycle starts when
+// an instance processes a second block. The first instance to count a note in a cycle gives it the
+// next position, the others find that position from the delta frames, channel and pitch of the
+// note, and how many times they already counted the same note in the block. Group 0 is not shared,
+// each instance counting its own notes.
+//
+// nothing is locked, as the instances may run on parallel audio threads. The cycle and the position
+// of its first note are swapped together, and the notes of a cycle are written in the slots of the
+// group in order, each in the first free one: the instances looking for a note all go through the
+// slot it was written to, so a note never gets two positions. Notes past the slots of a cycle are
+// numbered in the order each instance counts them, and the next cycle starts after the most any
+// instance counted, so copies agree again from there. The host position of a cycle is published
+// just after the cycle starts: an instance not finding it yet is in the same host cycle, host
+// cycles following each other.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const LOCAL_GROUP: u8 = 0;
+
+// the group parameter is a byte
+const GROUP_COUNT: usize = 128;
+
+// positions shared in a cycle
+const NOTES_PER_CYCLE: usize = 128;
+
+// the state of a group and its slots start with the cycle, so the slots written in previous cycles
+// are free. Cycle 0 is the state before the first block, notes are never counted in it
+const CYCLE_BITS: u32 = 24;
+const CYCLE_SHIFT: u32 = 64 - CYCLE_BITS;
+const CYCLE_MASK: u64 = (1 << CYCLE_BITS) - 1;
+const POSITION_MASK: u64 = (1 << CYCLE_SHIFT) - 1;
+// the host position of a cycle started without one
+const NO_HOST_POSITION: u64 = POSITION_MASK;
+
+#[derive(Clone, Copy, PartialEq)]
+struct NoteKey {
+    delta_frames: usize,
+    channel: u8,
+    pitch: u8,
+    // the same note already counted in the block
+    occurrence: usize,
+}
+
+impl NoteKey {
+    // 32 bits, blocks being shorter than 65536 frames
+    fn packed(&self) -> u64 {
+        (self.delta_frames as u64 & 0xFFFF) << 16
+            | (self.occurrence.min(0x1F) as u64) << 11
+            | (self.channel as u64 & 0xF) << 7
+            | self.pitch as u64 & 0x7F
+    }
+}
+
+struct Group {
+    // the cycle, then the position of its first note
+    state: AtomicU64,
+    // the cycle, then the sample position of its blocks
+    host_position: AtomicU64,
+    // the cycle, then the most notes an instance counted past the slots
+    overflow: AtomicU64,
+    // the cycle, then the key of the note given the position of the slot in the cycle
+    notes: [AtomicU64; NOTES_PER_CYCLE],
+}
+
+impl Group {
+    // the slots are written in order, the ones of cycle 0 are only zeroed
+    fn counted(&self, cycle: u64) -> u64 {
+        if cycle == 0 {
+            return 0;
+        }
+        self.notes
+            .iter()
+            .take_while(|note| note.load(Ordering::Acquire) >> CYCLE_SHIFT == cycle)
+            .count() as u64
+    }
+
+    fn overflow(&self, cycle: u64) -> u64 {
+        let overflow = self.overflow.load(Ordering::Acquire);
+        if overflow >> CYCLE_SHIFT == cycle {
+            overflow & POSITION_MASK
+        } else {
+            0
+        }
+    }
+
+    fn count_overflow(&self, cycle: u64, count: u64) {
+        let _ = self
+            .overflow
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |overflow| {
+                if overflow >> CYCLE_SHIFT != cycle || overflow & POSITION_MASK < count {
+                    Some(cycle << CYCLE_SHIFT | count)
+                } else {
+                    None
+                }
+            });
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_NOTE: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NEW_GROUP: Group = Group {
+    state: AtomicU64::new(0),
+    host_position: AtomicU64::new(0),
+    overflow: AtomicU64::new(0),
+    notes: [FREE_NOTE; NOTES_PER_CYCLE],
+};
+
+static GROUPS: [Group; GROUP_COUNT] = [NEW_GROUP; GROUP_COUNT];
+
+fn next_cycle(cycle: u64) -> u64 {
+    match (cycle + 1) & CYCLE_MASK {
+        0 => 1,
+        next => next,
+    }
+}
+
+pub struct StepCounter {
+    group: u8,
+    // the cycle of the group when this instance processed its last block, 0 before
+    cycle: u64,
+    first_position: u64,
+    local_position: u64,
+    // notes counted in the current block
+    counted: Vec<NoteKey>,
+    // notes of the current block past the slots
+    overflow: u64,
+}
+
+impl Default for StepCounter {
+    fn default() -> Self {
+        StepCounter {
+            group: LOCAL_GROUP,
+            cycle: 0,
+            first_position: 0,
+            local_position: 0,
+            counted: Vec::with_capacity(NOTES_PER_CYCLE),
+            overflow: 0,
+        }
+    }
+}
+
+impl StepCounter {
+    pub fn group(&self) -> u8 {
+        self.group
+    }
+
+    pub fn set_group(&mut self, group: u8) {
+        if group != self.group {
+            self.group = group;
+            self.cycle = 0;
+        }
+    }
+
+    fn shared(&self) -> Option<&'static Group> {
+        match self.group {
+            LOCAL_GROUP => None,
+            group => GROUPS.get(group as usize),
+        }
+    }
+
+    // to be called before counting the notes of a block. restart starts the positions over from 0,
+    // e.g. when the transport starts. host_position is the sample position of the block, if known
+    pub fn start_block(&mut self, restart: bool, host_position: Option<i64>) {
+        self.counted.clear();
+        self.overflow = 0;
+        let shared = match self.shared() {
+            Some(shared) => shared,
+            None => {
+                if restart {
+                    self.local_position = 0;
+                }
+                return;
+            }
+        };
+        let host_position = host_position.map_or(NO_HOST_POSITION, |position| position as u64 & POSITION_MASK);
+
+        let mut state = shared.state.load(Ordering::Acquire);
+        loop {
+            let cycle = state >> CYCLE_SHIFT;
+            // joins the cycle of the instances that already processed this block, or starts the
+            // next one after the notes counted in the cycle it was in
+            if cycle != self.cycle {
+                let published = shared.host_position.load(Ordering::Acquire);
+                let current = shared.state.load(Ordering::Acquire);
+                if current != state {
+                    state = current;
+                    continue;
+                }
+                if published >> CYCLE_SHIFT != cycle
+                    || host_position == NO_HOST_POSITION
+                    || published & POSITION_MASK == host_position
+                {
+                    break;
+                }
+            }
+
+            let first_position = if restart {
+                0
+            } else {
+                (state & POSITION_MASK) + shared.counted(cycle) + shared.overflow(cycle)
+            };
+            let next_cycle = next_cycle(cycle);
+            let next = next_cycle << CYCLE_SHIFT | first_position & POSITION_MASK;
+            match shared
+                .state
+                .compare_exchange(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    shared
+                        .host_position
+                        .store(next_cycle << CYCLE_SHIFT | host_position, Ordering::Release);
+                    state = next;
+                    break;
+                }
+                Err(current) => state = current,
+            }
+        }
+        self.cycle = state >> CYCLE_SHIFT;
+        self.first_position = state & POSITION_MASK;
+    }
+
+    // the position of a note on, counted from 0, the same for every instance of the group
+    pub fn count(&mut self, delta_frames: usize, channel: u8, pitch: u8) -> u64 {
+        let occurrence = self
+            .counted
+            .iter()
+            .filter(|key| key.delta_frames == delta_frames && key.channel == channel && key.pitch == pitch)
+            .count();
+        let key = NoteKey {
+            delta_frames,
+            channel,
+            pitch,
+            occurrence,
+        };
+        self.counted.push(key);
+
+        let shared = match self.shared() {
+            Some(shared) => shared,
+            None => {
+                let position = self.local_position;
+                self.local_position += 1;
+                return position;
+            }
+        };
+
+        let tagged = self.cycle << CYCLE_SHIFT | key.packed();
+        for (slot, note) in shared.notes.iter().enumerate() {
+            let mut current = note.load(Ordering::Acquire);
+            loop {
+                if current == tagged {
+                    return self.first_position + slot as u64;
+                }
+                if current >> CYCLE_SHIFT == self.cycle {
+                    // another note of the cycle
+                    break;
+                }
+                match note.compare_exchange(current, tagged, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => return self.first_position + slot as u64,
+                    Err(written) => current = written,
+                }
+            }
+        }
+        self.overflow += 1;
+        shared.count_overflow(self.cycle, self.overflow);
+        self.first_position + NOTES_PER_CYCLE as u64 + self.overflow - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread;
+
+    // the notes of a block, as delta frames, channel and pitch
+    fn count_block(counter: &mut StepCounter, notes: &[(usize, u8, u8)]) -> Vec<u64> {
+        count_block_at(counter, None, notes)
+    }
+
+    fn count_block_at(counter: &mut StepCounter, host_position: Option<i64>, notes: &[(usize, u8, u8)]) -> Vec<u64> {
+        counter.start_block(false, host_position);
+        notes
+            .iter()
+            .map(|&(delta_frames, channel, pitch)| counter.count(delta_frames, channel, pitch))
+            .collect()
+    }
+
+    #[test]
+    fn local_counters_count_their_own_notes() {
+        let mut first = StepCounter::default();
+        let mut second = StepCounter::default();
+        assert_eq!(count_block(&mut first, &[(0, 0, 60), (10, 0, 62)]), vec![0, 1]);
+        assert_eq!(count_block(&mut second, &[(0, 0, 60)]), vec![0]);
+        assert_eq!(count_block(&mut first, &[(0, 0, 64)]), vec![2]);
+        first.start_block(true, None);
+        assert_eq!(first.count(0, 0, 60), 0);
+    }
+
+    #[test]
+    fn instances_of_a_group_agree() {
+        let mut first = StepCounter::default();
+        let mut second = StepCounter::default();
+        first.set_group(1);
+        second.set_group(1);
+
+        let block = [(0, 0, 60), (0, 0, 60), (5, 1, 62)];
+        assert_eq!(count_block(&mut first, &block), vec![0, 1, 2]);
+        // in another order
+        assert_eq!(
+            count_block(&mut second, &[(5, 1, 62), (0, 0, 60), (0, 0, 60)]),
+            vec![2, 0, 1]
+        );
+
+        // the second missed a note, and gets the position of the next one anyway
+        assert_eq!(count_block(&mut first, &[(0, 0, 60), (3, 0, 64)]), vec![3, 4]);
+        assert_eq!(count_block(&mut second, &[(3, 0, 64)]), vec![4]);
+
+        first.start_block(true, None);
+        second.start_block(true, None);
+        assert_eq!(second.count(7, 0, 60), 0);
+        assert_eq!(first.count(7, 0, 60), 0);
+    }
+
+    // with the host positions of the blocks or not, in its own group
+    fn count_on_parallel_threads(group: u8, host_positions: bool) {
+        const INSTANCES: usize = 4;
+        const BLOCKS: usize = 200;
+        let barrier = Barrier::new(INSTANCES);
+        let positions: Vec<Vec<Vec<u64>>> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..INSTANCES)
+                .map(|instance| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        let mut counter = StepCounter::default();
+                        counter.set_group(group);
+                        (0..BLOCKS)
+                            .map(|block| {
+                                // every instance processes each block before the next one starts
+                                barrier.wait();
+                                let mut notes: Vec<(usize, u8, u8)> =
+                                    (0..8).map(|note| (note, 0, (block % 100) as u8)).collect();
+                                notes.rotate_left(instance);
+                                let host_position = Some(block as i64 * 64).filter(|_| host_positions);
+                                let mut block_positions = count_block_at(&mut counter, host_position, &notes);
+                                block_positions.rotate_right(instance);
+                                barrier.wait();
+                                block_positions
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+
+        // in the order the instances happened to count the notes
+        for (block, block_positions) in positions[0].iter().enumerate() {
+            let mut sorted = block_positions.clone();
+            sorted.sort_unstable();
+            let expected: Vec<u64> = (0..8).map(|note| (block * 8 + note) as u64).collect();
+            assert_eq!(sorted, expected);
+        }
+        assert!(positions
+            .iter()
+            .all(|instance_positions| *instance_positions == positions[0]));
+    }
+
+    #[test]
+    fn instances_on_parallel_threads_agree() {
+        count_on_parallel_threads(2, false);
+        count_on_parallel_threads(5, true);
+    }
+
+    #[test]
+    fn instances_back_from_bypass_start_a_new_cycle() {
+        let mut first = StepCounter::default();
+        let mut second = StepCounter::default();
+        first.set_group(3);
+        second.set_group(3);
+
+        assert_eq!(count_block_at(&mut first, Some(0), &[(0, 0, 60)]), vec![0]);
+        assert_eq!(count_block_at(&mut second, Some(0), &[(0, 0, 60)]), vec![0]);
+        // the second is bypassed
+        assert_eq!(count_block_at(&mut first, Some(64), &[(0, 0, 62)]), vec![1]);
+        assert_eq!(count_block_at(&mut first, Some(128), &[(0, 0, 64)]), vec![2]);
+        // and processes first when it comes back
+        assert_eq!(count_block_at(&mut second, Some(192), &[(0, 0, 64)]), vec![3]);
+        assert_eq!(count_block_at(&mut first, Some(192), &[(0, 0, 64)]), vec![3]);
+
+        // a loop as long as a block gives every cycle the same position
+        assert_eq!(count_block_at(&mut first, Some(192), &[(0, 0, 64)]), vec![4]);
+        assert_eq!(count_block_at(&mut second, Some(192), &[(0, 0, 64)]), vec![4]);
+    }
+
+    #[test]
+    fn notes_past_the_slots_are_numbered_the_same() {
+        let mut first = StepCounter::default();
+        let mut second = StepCounter::default();
+        first.set_group(4);
+        second.set_group(4);
+
+        let block: Vec<(usize, u8, u8)> = (0..NOTES_PER_CYCLE + 3).map(|note| (note, 0, 60)).collect();
+        let expected: Vec<u64> = (0..block.len() as u64).collect();
+        assert_eq!(count_block(&mut first, &block), expected);
+        assert_eq!(count_block(&mut second, &block), expected);
+
+        // the next cycle starts after them, even for an instance that missed some
+        let next = NOTES_PER_CYCLE as u64 + 3;
+        assert_eq!(count_block(&mut second, &[(0, 0, 62)]), vec![next]);
+        assert_eq!(count_block(&mut first, &[(0, 0, 60), (0, 0, 62)]), vec![next + 1, next]);
+    }
+}
@@ -0,0 +1,388 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam_queue::ArrayQueue;
+use global_counter::primitive::exact::CounterUsize;
+use vst::api::Events;
+use vst::event::{Event, MidiEvent};
+use vst::plugin::PluginParameters;
+
+use super::make_midi_message;
+
+// when this variable is set, each plugin instance records its inputs, parameter changes, block
+// boundaries and outputs to a file in that directory. The audio thread copies records into a queue
+// allocated with the recorder, and a writer thread drains it to the file, so recording neither
+// allocates nor blocks.
+pub const TRACE_DIRECTORY_VARIABLE: &str = "NOTE_FX_TRACE_DIRECTORY";
+
+// a trace file starts with the magic bytes, the length of the plugin name on a byte and the name,
+// followed by records of RECORD_SIZE bytes: a tag, then the values in little endian
+const TRACE_MAGIC: &[u8; 4] = b"NFXT";
+const RECORD_SIZE: usize = 16;
+
+const SAMPLE_RATE_TAG: u8 = 1;
+const PARAMETER_TAG: u8 = 2;
+const EVENTS_TAG: u8 = 3;
+const INPUT_TAG: u8 = 4;
+const BLOCK_TAG: u8 = 5;
+const OUTPUT_TAG: u8 = 6;
+const LOST_TAG: u8 = 7;
+
+// records, about a second of a busy instance, the writer drains it far more often
+const QUEUE_CAPACITY: usize = 16384;
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+static TRACE_ID: CounterUsize = CounterUsize::new(0);
+
+#[derive(Clone, Copy)]
+pub enum TraceRecord {
+    SampleRate(f32),
+    Parameter(i32, f32),
+    // announces how many input events are passed in one process_events call
+    Events(usize),
+    Input(MidiEvent),
+    Block { position: usize, samples: usize },
+    Output(MidiEvent),
+    // records dropped because the queue was full, the trace cannot be replayed past them
+    Lost(usize),
+}
+
+impl TraceRecord {
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut put = |tag: u8, values: &[&[u8]]| {
+            bytes[0] = tag;
+            let mut offset = 1;
+            for value in values {
+                bytes[offset..offset + value.len()].copy_from_slice(value);
+                offset += value.len();
+            }
+        };
+        match *self {
+            TraceRecord::SampleRate(rate) => put(SAMPLE_RATE_TAG, &[&rate.to_le_bytes()]),
+            TraceRecord::Parameter(index, value) => put(PARAMETER_TAG, &[&index.to_le_bytes(), &value.to_le_bytes()]),
+            TraceRecord::Events(count) => put(EVENTS_TAG, &[&(count as u32).to_le_bytes()]),
+            TraceRecord::Input(e) => put(INPUT_TAG, &[&e.data, &e.delta_frames.to_le_bytes()]),
+            TraceRecord::Block { position, samples } => put(
+                BLOCK_TAG,
+                &[&(position as u64).to_le_bytes(), &(samples as u32).to_le_bytes()],
+            ),
+            TraceRecord::Output(e) => put(OUTPUT_TAG, &[&e.data, &e.delta_frames.to_le_bytes()]),
+            TraceRecord::Lost(count) => put(LOST_TAG, &[&(count as u32).to_le_bytes()]),
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8; RECORD_SIZE]) -> Result<Self, String> {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+        };
+        let event = || make_midi_message([bytes[1], bytes[2], bytes[3]], u32_at(4) as i32);
+        match bytes[0] {
+            SAMPLE_RATE_TAG => Ok(TraceRecord::SampleRate(f32::from_bits(u32_at(1)))),
+            PARAMETER_TAG => Ok(TraceRecord::Parameter(u32_at(1) as i32, f32::from_bits(u32_at(5)))),
+            EVENTS_TAG => Ok(TraceRecord::Events(u32_at(1) as usize)),
+            INPUT_TAG => Ok(TraceRecord::Input(event())),
+            BLOCK_TAG => Ok(TraceRecord::Block {
+                position: (u32_at(1) as u64 | (u32_at(5) as u64) << 32) as usize,
+                samples: u32_at(9) as usize,
+            }),
+            OUTPUT_TAG => Ok(TraceRecord::Output(event())),
+            LOST_TAG => Ok(TraceRecord::Lost(u32_at(1) as usize)),
+            tag => Err(format!("invalid trace record tag {}", tag)),
+        }
+    }
+}
+
+pub struct Trace {
+    pub plugin: String,
+    pub records: Vec<TraceRecord>,
+}
+
+fn trace_header(plugin_name: &str) -> Vec<u8> {
+    // names are crate names, far shorter than a byte allows
+    let name = &plugin_name.as_bytes()[..plugin_name.len().min(u8::MAX as usize)];
+    let mut header = TRACE_MAGIC.to_vec();
+    header.push(name.len() as u8);
+    header.extend_from_slice(name);
+    header
+}
+
+pub fn parse_trace(bytes: &[u8]) -> Result<Trace, String> {
+    let rest = bytes
+        .strip_prefix(TRACE_MAGIC.as_slice())
+        .ok_or_else(|| "not a trace file".to_string())?;
+    let (&name_length, rest) = rest
+        .split_first()
+        .ok_or_else(|| "the trace has no plugin name".to_string())?;
+    if rest.len() < name_length as usize {
+        return Err("the trace has no plugin name".to_string());
+    }
+    let (name, rest) = rest.split_at(name_length as usize);
+    let plugin = String::from_utf8(name.to_vec()).map_err(|_| "invalid plugin name in the trace".to_string())?;
+
+    // the last record may be cut if the host was killed while it was written
+    let records = rest
+        .chunks_exact(RECORD_SIZE)
+        .map(|record| TraceRecord::decode(record.try_into().expect("records are RECORD_SIZE bytes")))
+        .collect::<Result<Vec<TraceRecord>, String>>()?;
+
+    Ok(Trace { plugin, records })
+}
+
+pub fn read_trace(path: &Path) -> Result<Trace, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    parse_trace(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[derive(Default)]
+pub struct TraceRecorder {
+    queue: Option<Arc<ArrayQueue<TraceRecord>>>,
+    dropped: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
+    position: usize,
+    // the last recorded values, allocated with the recorder
+    parameters: Vec<f32>,
+}
+
+impl TraceRecorder {
+    // to be called outside of the audio thread, as the queue and the writer thread are created here
+    pub fn from_env(plugin_name: &str, parameter_count: usize) -> Self {
+        match env::var_os(TRACE_DIRECTORY_VARIABLE) {
+            Some(directory) => Self::create(Path::new(&directory), plugin_name, parameter_count),
+            None => Default::default(),
+        }
+    }
+
+    pub fn create(directory: &Path, plugin_name: &str, parameter_count: usize) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or(0);
+        let path = directory.join(format!("{}-{}-{}.trace", plugin_name, timestamp, TRACE_ID.inc()));
+
+        let mut file = match File::create(&path) {
+            Ok(file) => BufWriter::new(file),
+            Err(_) => return Default::default(),
+        };
+        if file.write_all(&trace_header(plugin_name)).is_err() {
+            return Default::default();
+        }
+
+        let queue = Arc::new(ArrayQueue::new(QUEUE_CAPACITY));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (queue, dropped, stop) = (Arc::clone(&queue), Arc::clone(&dropped), Arc::clone(&stop));
+            thread::Builder::new()
+                .name("note fx trace".to_string())
+                .spawn(move || write_records(file, &queue, &dropped, &stop))
+        };
+
+        match writer {
+            Ok(writer) => TraceRecorder {
+                queue: Some(queue),
+                dropped,
+                stop,
+                writer: Some(writer),
+                position: 0,
+                parameters: vec![f32::NAN; parameter_count],
+            },
+            Err(_) => Default::default(),
+        }
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    fn write(&self, record: TraceRecord) {
+        if let Some(queue) = &self.queue {
+            if queue.push(record).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn record_sample_rate(&self, rate: f32) {
+        self.write(TraceRecord::SampleRate(rate));
+    }
+
+    // only records parameters that changed since the last call
+    pub fn record_parameters(&mut self, parameters: &dyn PluginParameters) {
+        if !self.is_recording() {
+            return;
+        }
+
+        for index in 0..self.parameters.len() {
+            let value = parameters.get_parameter(index as i32);
+            if value != self.parameters[index] {
+                self.parameters[index] = value;
+                self.write(TraceRecord::Parameter(index as i32, value));
+            }
+        }
+    }
+
+    pub fn record_input(&self, events: &Events) {
+        if !self.is_recording() {
+            return;
+        }
+
+        let midi_events = || {
+            events.events().filter_map(|e| match e {
+                Event::Midi(e) => Some(e),
+                _ => None,
+            })
+        };
+        self.write(TraceRecord::Events(midi_events().count()));
+        for e in midi_events() {
+            self.write(TraceRecord::Input(e));
+        }
+    }
+
+    pub fn record_block(&mut self, samples: usize) {
+        if !self.is_recording() {
+            return;
+        }
+
+        self.write(TraceRecord::Block {
+            position: self.position,
+            samples,
+        });
+        self.position += samples;
+    }
+
+    pub fn record_output(&self, events: &[MidiEvent]) {
+        for e in events {
+            self.write(TraceRecord::Output(*e));
+        }
+    }
+}
+
+impl Drop for TraceRecorder {
+    fn drop(&mut self) {
+        // the writer drains what is left, then stops
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_records(mut file: BufWriter<File>, queue: &ArrayQueue<TraceRecord>, dropped: &AtomicUsize, stop: &AtomicBool) {
+    loop {
+        // read before draining, so the records pushed before the recorder was dropped are written
+        let stopping = stop.load(Ordering::Relaxed);
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        let records = std::iter::from_fn(|| queue.pop()).chain((lost > 0).then_some(TraceRecord::Lost(lost)));
+        for record in records {
+            // there is nothing to do about write errors but stop recording
+            if file.write_all(&record.encode()).is_err() {
+                return;
+            }
+        }
+        let _ = file.flush();
+
+        if stopping {
+            break;
+        }
+        thread::sleep(DRAIN_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn records_are_read_back() {
+        let directory = env::temp_dir().join(format!("note-fx-trace-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let first_outputs = [make_midi_message([0x80, 60, 0], 1_000)];
+        let second_outputs = [
+            make_midi_message([0x90, 60, 100], 3),
+            make_midi_message([0xB0, 1, 64], 10),
+        ];
+        {
+            let mut recorder = TraceRecorder::create(&directory, "note_off_delay", 0);
+            assert!(recorder.is_recording());
+            recorder.record_sample_rate(44100.);
+            recorder.record_block(512);
+            recorder.record_output(&first_outputs);
+            recorder.record_block(256);
+            recorder.record_output(&second_outputs);
+        }
+
+        let path = fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        let trace = read_trace(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(trace.plugin, "note_off_delay");
+        let described: Vec<String> = trace
+            .records
+            .iter()
+            .map(|record| match record {
+                TraceRecord::SampleRate(rate) => format!("rate {}", rate),
+                TraceRecord::Block { position, samples } => format!("block {} {}", position, samples),
+                TraceRecord::Output(e) => format!("out {:?} {}", e.data, e.delta_frames),
+                _ => "other".to_string(),
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                "rate 44100",
+                "block 0 512",
+                "out [128, 60, 0] 1000",
+                "block 512 256",
+                "out [144, 60, 100] 3",
+                "out [176, 1, 64] 10",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_record_fits() {
+        let records = [
+            TraceRecord::SampleRate(48000.),
+            TraceRecord::Parameter(3, 0.25),
+            TraceRecord::Events(2),
+            TraceRecord::Input(make_midi_message([0x90, 60, 100], 511)),
+            TraceRecord::Block {
+                position: 5_000_000_000,
+                samples: 1024,
+            },
+            TraceRecord::Output(make_midi_message([0x80, 60, 0], 0)),
+            TraceRecord::Lost(12),
+        ];
+        let mut bytes = trace_header("midi_delay");
+        for record in records.iter() {
+            bytes.extend_from_slice(&record.encode());
+        }
+        // a record cut by a crash is ignored
+        bytes.extend_from_slice(&[OUTPUT_TAG, 0x80]);
+
+        let trace = parse_trace(&bytes).unwrap();
+        assert_eq!(trace.plugin, "midi_delay");
+        assert_eq!(trace.records.len(), records.len());
+        for (read, written) in trace.records.iter().zip(records.iter()) {
+            assert_eq!(read.encode(), written.encode());
+        }
+        assert!(parse_trace(b"plugin note_off_delay\n").is_err());
+    }
+}
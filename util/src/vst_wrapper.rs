@@ -0,0 +1,163 @@
+// VST2 glue for a MidiProcessor: collects the events received before a block, hands them to the
+// processor with the transport state, and sends what it returned. Traces, debug sessions and OSC
+// parameter changes are handled here too, so processors don't have to care.
+
+use std::sync::Arc;
+
+use vst::api;
+use vst::api::{Supported, TimeInfoFlags};
+use vst::buffer::{AudioBuffer, SendEventBuffer};
+use vst::event::{Event, MidiEvent};
+use vst::host::Host;
+use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters};
+
+use super::debug::{DebugSession, DebugSocket};
+use super::make_midi_message;
+use super::midi_processor::{MidiProcessor, TimedMidiMessage, TransportInfo};
+use super::osc::OscReceiver;
+use super::trace::TraceRecorder;
+
+pub struct VstWrapper<P: MidiProcessor> {
+    processor: P,
+    host: HostCallback,
+    inputs: Vec<TimedMidiMessage>,
+    outputs: Vec<TimedMidiMessage>,
+    events: Vec<MidiEvent>,
+    send_buffer: SendEventBuffer,
+    trace: TraceRecorder,
+    // none when built with Default, e.g. in tests, so no background thread is started
+    debug_session: Option<DebugSession>,
+    osc: OscReceiver,
+}
+
+impl<P: MidiProcessor> VstWrapper<P> {
+    // traces and debug sessions are only for instances created by a host
+    fn create(host: HostCallback, hosted: bool) -> Self {
+        let info = P::info();
+        let id = info.id;
+        // opened before the processor is created, so it can log from there, and the background
+        // thread is started outside of the audio thread
+        let debug_session = if hosted { Some(DebugSocket::open(id)) } else { None };
+        let processor = {
+            let _scope = debug_session.as_ref().map(DebugSession::scope);
+            P::default()
+        };
+
+        VstWrapper {
+            processor,
+            host,
+            inputs: vec![],
+            outputs: vec![],
+            events: vec![],
+            send_buffer: Default::default(),
+            trace: if hosted { TraceRecorder::from_env(id, info.parameter_definitions.len()) } else { Default::default() },
+            debug_session,
+            osc: OscReceiver::from_env(&info),
+        }
+    }
+
+    fn transport(&self) -> TransportInfo {
+        let mask = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::PPQ_POS_VALID | TimeInfoFlags::BARS_VALID
+            | TimeInfoFlags::TIME_SIG_VALID;
+
+        let time_info = match self.host.get_time_info(mask.bits()) {
+            Some(time_info) => time_info,
+            None => return Default::default(),
+        };
+        let flags = TimeInfoFlags::from_bits_truncate(time_info.flags);
+        let valid = |flag: TimeInfoFlags| flags.contains(flag);
+
+        TransportInfo {
+            playing: valid(TimeInfoFlags::TRANSPORT_PLAYING),
+            tempo: Some(time_info.tempo).filter(|_| valid(TimeInfoFlags::TEMPO_VALID)),
+            position: Some(time_info.ppq_pos).filter(|_| valid(TimeInfoFlags::PPQ_POS_VALID)),
+            bar_start_position: Some(time_info.bar_start_pos).filter(|_| valid(TimeInfoFlags::BARS_VALID)),
+            time_signature: Some((time_info.time_sig_numerator, time_info.time_sig_denominator))
+                .filter(|_| valid(TimeInfoFlags::TIME_SIG_VALID)),
+            // always valid
+            sample_position: Some(time_info.sample_pos as i64),
+        }
+    }
+}
+
+impl<P: MidiProcessor> Default for VstWrapper<P> {
+    fn default() -> Self {
+        Self::create(Default::default(), false)
+    }
+}
+
+impl<P: MidiProcessor> Plugin for VstWrapper<P> {
+    fn get_info(&self) -> Info {
+        let info = P::info();
+        Info {
+            name: info.name.to_string(),
+            vendor: info.vendor.to_string(),
+            unique_id: info.unique_id,
+            parameters: info.parameters,
+            category: Category::Effect,
+            initial_delay: 0,
+            version: info.version,
+            inputs: 0,
+            outputs: 0,
+            midi_inputs: 1,
+            f64_precision: false,
+            presets: 1,
+            midi_outputs: 1,
+            preset_chunks: true,
+            silent_when_stopped: true,
+        }
+    }
+
+    fn new(host: HostCallback) -> Self {
+        Self::create(host, true)
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        P::can_do(can_do)
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.trace.record_sample_rate(rate);
+        self.processor.set_sample_rate(rate);
+    }
+
+    fn set_block_size(&mut self, block_size: i64) {
+        self.processor.set_block_size(block_size as usize);
+    }
+
+    fn process(&mut self, audio_buffer: &mut AudioBuffer<f32>) {
+        let _scope = self.debug_session.as_ref().map(DebugSession::scope);
+        let samples = audio_buffer.samples();
+        self.osc.apply(&**self.processor.parameters());
+        self.trace.record_parameters(&**self.processor.parameters());
+        self.trace.record_block(samples);
+
+        let transport = self.transport();
+        self.inputs.sort_by_key(|message| message.delta_frames);
+        self.processor.process(&self.inputs, samples, &transport, &mut self.outputs);
+        self.inputs.clear();
+
+        for message in self.outputs.drain(..) {
+            self.events.push(make_midi_message(message.data, message.delta_frames as i32));
+        }
+        self.trace.record_output(&self.events);
+        self.send_buffer.send_events(&self.events, &mut self.host);
+        self.events.clear();
+    }
+
+    fn process_events(&mut self, events: &api::Events) {
+        let _scope = self.debug_session.as_ref().map(DebugSession::scope);
+        self.trace.record_parameters(&**self.processor.parameters());
+        self.trace.record_input(events);
+
+        for e in events.events() {
+            if let Event::Midi(e) = e {
+                self.inputs.push(TimedMidiMessage::new(e.data, e.delta_frames.max(0) as usize));
+            }
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(self.processor.parameters()) as Arc<dyn PluginParameters>
+    }
+}